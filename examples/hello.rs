@@ -31,20 +31,10 @@ impl Handler<HttpStream> for Hello {
 
 fn main() {
     env_logger::init().unwrap();
- 
-    let listener = HttpListener::bind(&"127.0.0.1:3000".parse().unwrap()).unwrap();
-    let mut handles = Vec::new();
 
-    for _ in 0..num_cpus::get() {
-        let listener = listener.try_clone().unwrap();
-        handles.push(::std::thread::spawn(move || {
-            Server::new(listener)
-                .handle(|_| Hello).unwrap();
-        }));
-    }
+    let listener = HttpListener::bind(&"127.0.0.1:3000".parse().unwrap()).unwrap();
+    let (_listening, server) = Server::new(listener)
+        .handle_threads(num_cpus::get(), || |_| Hello).unwrap();
     println!("Listening on http://127.0.0.1:3000");
-
-    for handle in handles {
-        handle.join().unwrap();
-    }
+    server.run();
 }
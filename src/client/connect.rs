@@ -1,14 +1,25 @@
+use std::collections::VecDeque;
 use std::collections::hash_map::{HashMap, Entry};
+use std::error::Error as StdError;
 use std::hash::Hash;
 use std::fmt;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use rotor::mio::tcp::TcpStream;
 use url::Url;
 
+use header::Headers;
 use net::{HttpStream, HttpsStream, Transport, SslClient};
-use super::dns::Dns;
+#[cfg(unix)]
+use net::UnixStream;
+use super::dns::{Dns, Resolve, ThreadResolver};
+use super::proxy::{self, Proxy, ProxyStream};
+use super::socks::Target;
 use super::Registration;
 
 /// A connector creates a Transport to a remote address..
@@ -23,6 +34,41 @@ pub trait Connect {
     fn connect(&mut self, &Url) -> io::Result<Self::Key>;
     /// Returns a connected socket and associated host.
     fn connected(&mut self) -> Option<(Self::Key, io::Result<Self::Output>)>;
+    /// Returns the headers to send with a request for the Url, if it is
+    /// sent to a proxy to forward, in absolute-form.
+    ///
+    /// The default never forwards requests.
+    fn forwarded(&self, _url: &Url) -> Option<Headers> {
+        None
+    }
+    /// Learns the id of the race that the socket `connected` just handed
+    /// out for the key is in. It comes with every `attempted` and `raced`
+    /// for that socket and the ones racing it, so connects to the same key
+    /// that overlap can be told apart.
+    ///
+    /// The default does nothing.
+    fn joined(&mut self, _key: &Self::Key, _race: usize) {}
+    /// Learns how connecting a socket of the race went, returning the error
+    /// for the key once there is nothing left to try.
+    ///
+    /// Connectors can start more sockets for the race from here, to other
+    /// addresses, and hand them out from `raced`. The default gives up when
+    /// the socket fails.
+    fn attempted(&mut self, _key: &Self::Key, _race: usize, attempt: Attempt) -> Option<io::Error> {
+        match attempt {
+            Attempt::Failed(err) => Some(err),
+            _ => None
+        }
+    }
+    /// Takes a socket that `attempted` just started for the race, to race
+    /// the one it heard about. The first of them to connect is used, and
+    /// the others are closed, which the connector hears as
+    /// `Attempt::Cancelled`.
+    ///
+    /// The default never races sockets.
+    fn raced(&mut self, _key: &Self::Key, _race: usize) -> Option<io::Result<Self::Output>> {
+        None
+    }
     #[doc(hidden)]
     /// Configure number of dns workers to use.
     fn dns_workers(&mut self, usize);
@@ -33,15 +79,203 @@ pub trait Connect {
 type Scheme = String;
 type Port = u16;
 
+/// How connecting a socket went.
+#[derive(Debug)]
+pub enum Attempt {
+    /// The socket connected.
+    Connected,
+    /// The socket is still connecting after `Config::connect_attempt_delay`,
+    /// so another address may race it.
+    Slow,
+    /// The socket failed to connect, or timed out.
+    Failed(io::Error),
+    /// Another socket racing for the same request connected first, so this
+    /// one was closed.
+    Cancelled,
+    /// The client gave up on the socket for reasons of its own, such as
+    /// having no room for more sockets. Other addresses won't do better.
+    Aborted(io::Error),
+}
+
+/// Connecting to every address of a host failed.
+///
+/// The `io::Error` given to the `Handler` wraps this, and can be
+/// downcast to it.
+#[derive(Debug)]
+pub struct ConnectError {
+    addrs: Vec<SocketAddr>,
+    errors: Vec<io::Error>,
+}
+
+impl ConnectError {
+    /// The error for a host that none of `addrs` could be connected to.
+    #[doc(hidden)]
+    pub fn new(addrs: Vec<SocketAddr>, errors: Vec<io::Error>) -> ConnectError {
+        ConnectError {
+            addrs: addrs,
+            errors: errors,
+        }
+    }
+
+    /// The addresses tried, in order.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// The errors connecting, as they happened.
+    pub fn errors(&self) -> &[io::Error] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str("failed to connect to "));
+        for (i, addr) in self.addrs.iter().enumerate() {
+            if i > 0 {
+                try!(f.write_str(", "));
+            }
+            try!(write!(f, "{}", addr));
+        }
+        match self.errors.last() {
+            Some(err) => write!(f, ": {}", err),
+            None => Ok(())
+        }
+    }
+}
+
+impl StdError for ConnectError {
+    fn description(&self) -> &str {
+        "failed to connect to any address"
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        self.errors.last().map(|err| err as &StdError)
+    }
+}
+
+/// Connection attempts to the addresses of a host, which race each other.
+#[derive(Debug)]
+struct Attempts {
+    port: u16,
+    /// Addresses not tried yet.
+    addrs: VecDeque<IpAddr>,
+    tried: Vec<SocketAddr>,
+    errors: Vec<io::Error>,
+    /// Sockets still connecting.
+    pending: usize,
+    connected: bool,
+    /// Whether the client gave up on these attempts by itself.
+    aborted: bool,
+}
+
+impl Attempts {
+    fn new(addrs: VecDeque<IpAddr>, port: u16) -> Attempts {
+        Attempts {
+            port: port,
+            addrs: addrs,
+            tried: Vec::new(),
+            errors: Vec::new(),
+            pending: 0,
+            connected: false,
+            aborted: false,
+        }
+    }
+
+    /// Starts connecting to the next address that doesn't fail right away.
+    fn start(&mut self) -> Option<HttpStream> {
+        while let Some(ip) = self.addrs.pop_front() {
+            let addr = SocketAddr::new(ip, self.port);
+            self.tried.push(addr);
+            match TcpStream::connect(&addr) {
+                Ok(tcp) => {
+                    trace!("connecting to {}", addr);
+                    self.pending += 1;
+                    return Some(HttpStream(tcp));
+                },
+                Err(e) => {
+                    debug!("connect to {} failed: {}", addr, e);
+                    self.errors.push(e);
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether nothing more will come of these attempts.
+    fn done(&self) -> bool {
+        self.pending == 0 && (self.connected || self.addrs.is_empty())
+    }
+
+    fn error(&mut self) -> io::Error {
+        if self.tried.is_empty() {
+            return io::Error::new(io::ErrorKind::Other, "no addresses found for host");
+        }
+        let kind = self.errors.last().map_or(io::ErrorKind::Other, |err| err.kind());
+        io::Error::new(kind, ConnectError::new(
+            ::std::mem::replace(&mut self.tried, Vec::new()),
+            ::std::mem::replace(&mut self.errors, Vec::new()),
+        ))
+    }
+}
+
+/// Orders addresses to alternate between IPv6 and IPv4, starting with the
+/// family of the first, as in RFC 8305.
+fn interleave<I: Iterator<Item=IpAddr>>(addrs: I) -> VecDeque<IpAddr> {
+    let (mut first, mut second): (VecDeque<_>, VecDeque<_>) = (VecDeque::new(), VecDeque::new());
+    let mut first_v6 = None;
+    for addr in addrs {
+        let v6 = match addr {
+            IpAddr::V6(..) => true,
+            IpAddr::V4(..) => false,
+        };
+        if first_v6.is_none() {
+            first_v6 = Some(v6);
+        }
+        if first_v6 == Some(v6) {
+            first.push_back(addr);
+        } else {
+            second.push_back(addr);
+        }
+    }
+    let mut ordered = VecDeque::with_capacity(first.len() + second.len());
+    loop {
+        match (first.pop_front(), second.pop_front()) {
+            (None, None) => return ordered,
+            (a, b) => {
+                ordered.extend(a);
+                ordered.extend(b);
+            }
+        }
+    }
+}
+
 /// A connector for the `http` scheme.
+///
+/// Hosts with several addresses are connected to as in RFC 8305, "Happy
+/// Eyeballs": the addresses are tried in turn, alternating between IPv6
+/// and IPv4, and an address that is slow to connect is raced by the next.
+///
+/// Host names are resolved by a `ThreadResolver`, unless given another
+/// `Resolve`.
 pub struct HttpConnector {
     dns: Option<Dns>,
     threads: usize,
-    resolving: HashMap<String, Vec<(&'static str, String, u16)>>,
+    resolver: Option<Box<Resolve>>,
+    dns_cache: Option<Duration>,
+    resolving: HashMap<String, Vec<((&'static str, String, u16), u16)>>,
+    /// The attempts of the socket `connected` handed out last, until the
+    /// client says which race it is in.
+    joining: Option<Attempts>,
+    /// Attempts by the id of their race.
+    attempts: HashMap<usize, Attempts>,
+    /// Sockets to more addresses, to hand out from `raced`, with the race
+    /// each is for.
+    started: VecDeque<(usize, HttpStream)>,
 }
 
 impl HttpConnector {
-    /// Set the number of resolver threads.
+    /// Set the number of resolver threads, when using the default resolver.
     ///
     /// Default is 4.
     pub fn threads(mut self, threads: usize) -> HttpConnector {
@@ -49,6 +283,28 @@ impl HttpConnector {
         self.threads = threads;
         self
     }
+
+    /// Set the resolver of host names.
+    pub fn resolver<R: Resolve + 'static>(mut self, resolver: R) -> HttpConnector {
+        debug_assert!(self.dns.is_none(), "setting resolver after Dns is created does nothing");
+        self.resolver = Some(Box::new(resolver));
+        self
+    }
+
+    /// Cache resolved addresses for as long as the resolver says they may
+    /// be, or for `ttl` if it doesn't know.
+    ///
+    /// Default is to not cache.
+    pub fn dns_cache(mut self, ttl: Duration) -> HttpConnector {
+        self.dns_cache = Some(ttl);
+        self
+    }
+
+    /// Resolves `host`, to connect to it on `port` for `key`.
+    fn resolve(&mut self, host: &str, port: u16, key: (&'static str, String, u16)) {
+        self.dns.as_mut().expect("dns workers lost").resolve(host);
+        self.resolving.entry(host.to_owned()).or_insert_with(Vec::new).push((key, port));
+    }
 }
 
 impl Default for HttpConnector {
@@ -56,7 +312,12 @@ impl Default for HttpConnector {
         HttpConnector {
             dns: None,
             threads: 4,
+            resolver: None,
+            dns_cache: None,
             resolving: HashMap::new(),
+            joining: None,
+            attempts: HashMap::new(),
+            started: VecDeque::new(),
         }
     }
 }
@@ -65,7 +326,9 @@ impl fmt::Debug for HttpConnector {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("HttpConnector")
             .field("threads", &self.threads)
+            .field("dns_cache", &self.dns_cache)
             .field("resolving", &self.resolving)
+            .field("attempts", &self.attempts)
             .finish()
     }
 }
@@ -94,8 +357,7 @@ impl Connect for HttpConnector {
         debug!("Http::connect({:?})", url);
         if let Some(key) = self.key(url) {
             let host = url.host_str().expect("http scheme must have a host");
-            self.dns.as_ref().expect("dns workers lost").resolve(host);
-            self.resolving.entry(host.to_owned()).or_insert_with(Vec::new).push(key.clone());
+            self.resolve(host, key.2, key.clone());
             Ok(key)
         } else {
             Err(io::Error::new(io::ErrorKind::InvalidInput, "scheme must be http"))
@@ -103,30 +365,109 @@ impl Connect for HttpConnector {
     }
 
     fn connected(&mut self) -> Option<(Self::Key, io::Result<HttpStream>)> {
-        let (host, addrs) = match self.dns.as_ref().expect("dns workers lost").resolved() {
+        let (host, addrs) = match self.dns.as_mut().expect("dns workers lost").resolved() {
             Ok(res) => res,
             Err(_) => return None
         };
-        //TODO: try all addrs
-        let addr = addrs.and_then(|mut addrs| Ok(addrs.next().unwrap()));
-        debug!("Http::resolved <- ({:?}, {:?})", host, addr);
-        if let Entry::Occupied(mut entry) = self.resolving.entry(host) {
+        debug!("Http::resolved <- ({:?}, ok={})", host, addrs.is_ok());
+        let (resolved, port) = if let Entry::Occupied(mut entry) = self.resolving.entry(host) {
             let resolved = entry.get_mut().remove(0);
             if entry.get().is_empty() {
                 entry.remove();
             }
-            let port = resolved.2;
-            Some((resolved, addr.and_then(|addr| TcpStream::connect(&SocketAddr::new(addr, port))
-                                                            .map(HttpStream))
-                ))
+            resolved
         } else {
             trace!("^--  resolved but not in hashmap?");
-            None
+            return None;
+        };
+        let res = match addrs {
+            Ok(addrs) => {
+                let mut attempts = Attempts::new(interleave(addrs), port);
+                match attempts.start() {
+                    Some(stream) => {
+                        self.joining = Some(attempts);
+                        Ok(stream)
+                    },
+                    None => Err(attempts.error())
+                }
+            },
+            Err(e) => Err(e)
+        };
+        Some((resolved, res))
+    }
+
+    fn joined(&mut self, _key: &Self::Key, race: usize) {
+        if let Some(attempts) = self.joining.take() {
+            self.attempts.insert(race, attempts);
+        }
+    }
+
+    fn attempted(&mut self, key: &Self::Key, race: usize, attempt: Attempt) -> Option<io::Error> {
+        let (done, res) = match self.attempts.get_mut(&race) {
+            Some(attempts) => {
+                match attempt {
+                    Attempt::Connected => {
+                        attempts.pending = attempts.pending.saturating_sub(1);
+                        attempts.connected = true;
+                    },
+                    Attempt::Slow => {
+                        if !attempts.connected {
+                            if let Some(stream) = attempts.start() {
+                                self.started.push_back((race, stream));
+                            }
+                        }
+                    },
+                    Attempt::Failed(err) => {
+                        debug!("connect attempt for {:?} failed: {}", key, err);
+                        attempts.pending = attempts.pending.saturating_sub(1);
+                        attempts.errors.push(err);
+                        if !attempts.connected {
+                            if let Some(stream) = attempts.start() {
+                                self.started.push_back((race, stream));
+                            }
+                        }
+                    },
+                    Attempt::Cancelled => {
+                        attempts.pending = attempts.pending.saturating_sub(1);
+                    },
+                    Attempt::Aborted(err) => {
+                        debug!("connect attempt for {:?} aborted: {}", key, err);
+                        attempts.pending = attempts.pending.saturating_sub(1);
+                        attempts.addrs.clear();
+                        attempts.aborted = true;
+                    }
+                }
+                if !attempts.done() {
+                    (false, None)
+                } else if attempts.connected || attempts.aborted {
+                    (true, None)
+                } else {
+                    (true, Some(attempts.error()))
+                }
+            },
+            None => return match attempt {
+                Attempt::Failed(err) => Some(err),
+                _ => None
+            }
+        };
+        if done {
+            self.attempts.remove(&race);
         }
+        res
+    }
+
+    fn raced(&mut self, _key: &Self::Key, race: usize) -> Option<io::Result<HttpStream>> {
+        let pos = match self.started.iter().position(|&(id, _)| id == race) {
+            Some(pos) => pos,
+            None => return None
+        };
+        self.started.remove(pos).map(|(_, stream)| Ok(stream))
     }
 
     fn register(&mut self, reg: Registration) {
-        self.dns = Some(Dns::new(reg.notify, self.threads));
+        let threads = self.threads;
+        let resolver = self.resolver.take().unwrap_or_else(|| Box::new(ThreadResolver::new(threads)));
+        self.dns = Some(Dns::new(resolver, reg.notify, self.dns_cache));
     }
 }
 
@@ -145,6 +486,20 @@ impl<S: SslClient> HttpsConnector<S> {
             ssl: s,
         }
     }
+
+    /// Protects the stream for `https` keys.
+    fn wrap(&self, key: &(&'static str, String, u16), http: HttpStream) -> io::Result<HttpsStream<S::Stream>> {
+        if key.0 == "https" {
+            self.ssl.wrap_client_alpn(http, &key.1, ::http::ALPN_PROTOCOLS)
+                .map(HttpsStream::Https)
+                .map_err(|e| match e {
+                    ::Error::Io(e) => e,
+                    e => io::Error::new(io::ErrorKind::Other, e)
+                })
+        } else {
+            Ok(HttpsStream::Http(http))
+        }
+    }
 }
 
 impl<S: SslClient> Connect for HttpsConnector<S> {
@@ -172,8 +527,124 @@ impl<S: SslClient> Connect for HttpsConnector<S> {
         debug!("Https::connect({:?})", url);
         if let Some(key) = self.key(url) {
             let host = url.host_str().expect("http scheme must have a host");
-            self.http.dns.as_ref().expect("dns workers lost").resolve(host);
-            self.http.resolving.entry(host.to_owned()).or_insert_with(Vec::new).push(key.clone());
+            self.http.resolve(host, key.2, key.clone());
+            Ok(key)
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "scheme must be http or https"))
+        }
+    }
+
+    fn connected(&mut self) -> Option<(Self::Key, io::Result<Self::Output>)> {
+        self.http.connected().map(|(key, res)| {
+            let res = res.and_then(|http| self.wrap(&key, http));
+            (key, res)
+        })
+    }
+
+    fn joined(&mut self, key: &Self::Key, race: usize) {
+        self.http.joined(key, race)
+    }
+
+    fn attempted(&mut self, key: &Self::Key, race: usize, attempt: Attempt) -> Option<io::Error> {
+        self.http.attempted(key, race, attempt)
+    }
+
+    fn raced(&mut self, key: &Self::Key, race: usize) -> Option<io::Result<Self::Output>> {
+        self.http.raced(key, race).map(|res| res.and_then(|http| self.wrap(key, http)))
+    }
+
+    fn register(&mut self, reg: Registration) {
+        self.http.register(reg);
+    }
+}
+
+/// A connector that sends requests through an HTTP proxy.
+///
+/// `http` requests are forwarded by the proxy, while `https` requests are
+/// tunneled through it with `CONNECT`, and then protected using SSL. Hosts
+/// excluded by `Proxy::no_proxy` are connected to directly.
+#[derive(Debug)]
+pub struct ProxyConnector<S: SslClient> {
+    http: HttpConnector,
+    proxy: Proxy,
+    ssl: Arc<S>,
+}
+
+impl<S: SslClient> ProxyConnector<S> {
+    /// Create a new connector using the proxy, and the provided SSL implementation.
+    pub fn new(proxy: Proxy, s: S) -> ProxyConnector<S> {
+        ProxyConnector {
+            http: HttpConnector::default(),
+            proxy: proxy,
+            ssl: Arc::new(s),
+        }
+    }
+
+    fn tunnels(&self, key: &(&'static str, String, u16)) -> bool {
+        key.0 == "https" && self.proxy.intercepts(&key.1)
+    }
+
+    /// Sets up the stream for what the key needs from the proxy.
+    fn wrap(&self, key: &(&'static str, String, u16), http: HttpStream) -> io::Result<ProxyStream<S>> {
+        if key.0 != "https" {
+            Ok(proxy::http(http))
+        } else if self.tunnels(key) {
+            // wrapped with SSL once the proxy opens the tunnel
+            Ok(proxy::tunnel(http, self.ssl.clone(), &self.proxy, &key.1, key.2))
+        } else {
+            self.ssl.wrap_client_alpn(http, &key.1, ::http::ALPN_PROTOCOLS)
+                .map(proxy::https::<S>)
+                .map_err(|e| match e {
+                    ::Error::Io(e) => e,
+                    e => io::Error::new(io::ErrorKind::Other, e)
+                })
+        }
+    }
+}
+
+impl<S: SslClient> Connect for ProxyConnector<S> {
+    type Output = ProxyStream<S>;
+    type Key = (&'static str, String, u16);
+
+    fn dns_workers(&mut self, count: usize) {
+        self.http.dns_workers(count)
+    }
+
+    fn key(&self, url: &Url) -> Option<Self::Key> {
+        let scheme = match url.scheme() {
+            "http" => "http",
+            "https" => "https",
+            _ => return None
+        };
+        let host = url.host_str().expect("http scheme must have host");
+        if scheme == "http" && self.proxy.intercepts(host) {
+            // any connection to the proxy can forward the request
+            Some(("http", self.proxy.host().to_owned(), self.proxy.port()))
+        } else {
+            Some((
+                scheme,
+                host.to_owned(),
+                url.port_or_known_default().expect("http scheme must have a port"),
+            ))
+        }
+    }
+
+    fn forwarded(&self, url: &Url) -> Option<Headers> {
+        match url.host_str() {
+            Some(host) if url.scheme() == "http" && self.proxy.intercepts(host) => Some(self.proxy.headers()),
+            _ => None
+        }
+    }
+
+    fn connect(&mut self, url: &Url) -> io::Result<Self::Key> {
+        debug!("Proxy::connect({:?})", url);
+        if let Some(key) = self.key(url) {
+            let (host, port) = if self.tunnels(&key) {
+                (self.proxy.host().to_owned(), self.proxy.port())
+            } else {
+                (key.1.clone(), key.2)
+            };
+            self.http.resolve(&host, port, key.clone());
             Ok(key)
         } else {
             Err(io::Error::new(io::ErrorKind::InvalidInput, "scheme must be http or https"))
@@ -182,20 +653,166 @@ impl<S: SslClient> Connect for HttpsConnector<S> {
 
     fn connected(&mut self) -> Option<(Self::Key, io::Result<Self::Output>)> {
         self.http.connected().map(|(key, res)| {
-            let res = res.and_then(|http| {
-                if key.0 == "https" {
-                    self.ssl.wrap_client(http, &key.1)
-                        .map(HttpsStream::Https)
-                        .map_err(|e| match e {
-                            ::Error::Io(e) => e,
-                            e => io::Error::new(io::ErrorKind::Other, e)
-                        })
+            let res = res.and_then(|http| self.wrap(&key, http));
+            (key, res)
+        })
+    }
+
+    fn joined(&mut self, key: &Self::Key, race: usize) {
+        self.http.joined(key, race)
+    }
+
+    fn attempted(&mut self, key: &Self::Key, race: usize, attempt: Attempt) -> Option<io::Error> {
+        self.http.attempted(key, race, attempt)
+    }
+
+    fn raced(&mut self, key: &Self::Key, race: usize) -> Option<io::Result<Self::Output>> {
+        self.http.raced(key, race).map(|res| res.and_then(|http| self.wrap(key, http)))
+    }
+
+    fn register(&mut self, reg: Registration) {
+        self.http.register(reg);
+    }
+}
+
+/// A connector that reaches servers through a SOCKS5 proxy.
+///
+/// The handshake with the proxy happens on the event loop, before the
+/// request is written. `https` streams are then protected using SSL.
+#[derive(Debug)]
+pub struct Socks5Connector<S: SslClient> {
+    http: HttpConnector,
+    proxy: SocketAddr,
+    auth: Option<(String, String)>,
+    remote_dns: bool,
+    ssl: Arc<S>,
+    /// Whether the proxy resolves the host of each key.
+    resolving: HashMap<String, Vec<((&'static str, String, u16), bool)>>,
+}
+
+impl<S: SslClient> Socks5Connector<S> {
+    /// Create a new connector to the proxy at `addr`, using the provided
+    /// SSL implementation.
+    pub fn new(addr: SocketAddr, s: S) -> Socks5Connector<S> {
+        Socks5Connector {
+            http: HttpConnector::default(),
+            proxy: addr,
+            auth: None,
+            remote_dns: true,
+            ssl: Arc::new(s),
+            resolving: HashMap::new(),
+        }
+    }
+
+    /// Authenticate to the proxy with a username and password.
+    pub fn auth(mut self, username: String, password: String) -> Socks5Connector<S> {
+        self.auth = Some((username, password));
+        self
+    }
+
+    /// Set whether the proxy resolves host names, instead of the client.
+    ///
+    /// When the client resolves them, each address of a host is asked for
+    /// in turn, until the proxy reaches one.
+    ///
+    /// Default is true.
+    pub fn remote_dns(mut self, val: bool) -> Socks5Connector<S> {
+        self.remote_dns = val;
+        self
+    }
+
+    /// Set the resolver of host names, for when the client resolves them.
+    pub fn resolver<R: Resolve + 'static>(mut self, resolver: R) -> Socks5Connector<S> {
+        self.http = self.http.resolver(resolver);
+        self
+    }
+}
+
+impl<S: SslClient> Connect for Socks5Connector<S> {
+    type Output = ProxyStream<S>;
+    type Key = (&'static str, String, u16);
+
+    fn dns_workers(&mut self, count: usize) {
+        self.http.dns_workers(count)
+    }
+
+    fn key(&self, url: &Url) -> Option<Self::Key> {
+        let scheme = match url.scheme() {
+            "http" => "http",
+            "https" => "https",
+            _ => return None
+        };
+        Some((
+            scheme,
+            url.host_str().expect("http scheme must have host").to_owned(),
+            url.port_or_known_default().expect("http scheme must have a port"),
+        ))
+    }
+
+    fn connect(&mut self, url: &Url) -> io::Result<Self::Key> {
+        debug!("Socks5::connect({:?})", url);
+        if let Some(key) = self.key(url) {
+            let remote = match Target::new(&key.1, key.2) {
+                Target::Domain(..) => self.remote_dns,
+                Target::Addr(..) => true,
+            };
+            // resolving the proxy's own address is instant, and wakes up
+            // the loop the same way
+            let host = if remote {
+                self.proxy.ip().to_string()
+            } else {
+                key.1.clone()
+            };
+            self.http.dns.as_mut().expect("dns workers lost").resolve(host.clone());
+            self.resolving.entry(host).or_insert_with(Vec::new).push((key.clone(), remote));
+            Ok(key)
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "scheme must be http or https"))
+        }
+    }
+
+    fn connected(&mut self) -> Option<(Self::Key, io::Result<Self::Output>)> {
+        let (host, addrs) = match self.http.dns.as_mut().expect("dns workers lost").resolved() {
+            Ok(res) => res,
+            Err(_) => return None
+        };
+        let (key, remote) = if let Entry::Occupied(mut entry) = self.resolving.entry(host) {
+            let resolved = entry.get_mut().remove(0);
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+            resolved
+        } else {
+            trace!("^--  resolved but not in hashmap?");
+            return None;
+        };
+        let ssl = if key.0 == "https" {
+            Some(self.ssl.clone())
+        } else {
+            None
+        };
+        let res = if remote {
+            let target = Target::new(&key.1, key.2);
+            debug!("Socks5::resolved <- ({:?}, {:?})", key, target);
+            TcpStream::connect(&self.proxy).map(|tcp| {
+                proxy::socks5(HttpStream(tcp), ssl, &key.1, target, self.auth.clone())
+            })
+        } else {
+            let port = key.2;
+            let addrs = addrs.and_then(|addrs| {
+                let addrs = addrs.map(|ip| SocketAddr::new(ip, port)).collect::<VecDeque<_>>();
+                if addrs.is_empty() {
+                    Err(io::Error::new(io::ErrorKind::Other, "no addresses found"))
                 } else {
-                    Ok(HttpsStream::Http(http))
+                    Ok(addrs)
                 }
             });
-            (key, res)
-        })
+            debug!("Socks5::resolved <- ({:?}, {:?})", key, addrs);
+            addrs.and_then(|addrs| TcpStream::connect(&self.proxy).map(|tcp| {
+                proxy::socks5_addrs(HttpStream(tcp), ssl, &key.1, self.proxy, addrs, self.auth.clone())
+            }))
+        };
+        Some((key, res))
     }
 
     fn register(&mut self, reg: Registration) {
@@ -203,6 +820,103 @@ impl<S: SslClient> Connect for HttpsConnector<S> {
     }
 }
 
+/// A connector to servers listening on Unix domain sockets, such as the
+/// Docker daemon.
+///
+/// A `unix` url has the path of the socket hex encoded as its host, as
+/// built by `UnixConnector::url`. A connector given a `socket` also sends
+/// every `http` request to that one, whatever its host.
+///
+/// # Example
+///
+/// ```
+/// use hyper::client::UnixConnector;
+///
+/// let url = UnixConnector::url("/var/run/docker.sock", "/containers/json").unwrap();
+/// assert_eq!(url.as_str(), "unix://2f7661722f72756e2f646f636b65722e736f636b/containers/json");
+///
+/// let connector = UnixConnector::default().socket("/var/run/docker.sock");
+/// ```
+#[cfg(unix)]
+#[derive(Default)]
+pub struct UnixConnector {
+    socket: Option<PathBuf>,
+    /// Sockets to hand out from `connected`.
+    connected: VecDeque<(PathBuf, io::Result<UnixStream>)>,
+    wakeup: Option<::rotor::Notifier>,
+}
+
+#[cfg(unix)]
+impl UnixConnector {
+    /// Send `http` requests to the socket at `path`.
+    pub fn socket<P: Into<PathBuf>>(mut self, path: P) -> UnixConnector {
+        self.socket = Some(path.into());
+        self
+    }
+
+    /// Builds a `unix` url for `path` on the server at the socket `socket`.
+    pub fn url<P: AsRef<Path>>(socket: P, path: &str) -> Result<Url, ::url::ParseError> {
+        use std::os::unix::ffi::OsStrExt;
+        use serialize::hex::ToHex;
+        Url::parse(&format!("unix://{}{}", socket.as_ref().as_os_str().as_bytes().to_hex(), path))
+    }
+}
+
+#[cfg(unix)]
+impl fmt::Debug for UnixConnector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UnixConnector")
+            .field("socket", &self.socket)
+            .field("connected", &self.connected.len())
+            .finish()
+    }
+}
+
+#[cfg(unix)]
+impl Connect for UnixConnector {
+    type Output = UnixStream;
+    type Key = PathBuf;
+
+    fn dns_workers(&mut self, _count: usize) {}
+
+    fn key(&self, url: &Url) -> Option<PathBuf> {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt;
+        use serialize::hex::FromHex;
+        match url.scheme() {
+            "unix" => url.host_str()
+                .and_then(|host| host.from_hex().ok())
+                .map(|path| PathBuf::from(OsString::from_vec(path))),
+            "http" => self.socket.clone(),
+            _ => None
+        }
+    }
+
+    fn connect(&mut self, url: &Url) -> io::Result<PathBuf> {
+        debug!("Unix::connect({:?})", url);
+        if let Some(key) = self.key(url) {
+            // there is nothing to resolve, so the socket is ready once the
+            // loop wakes up again
+            let res = UnixStream::connect(&key);
+            self.connected.push_back((key.clone(), res));
+            if let Some(ref wakeup) = self.wakeup {
+                let _ = wakeup.wakeup();
+            }
+            Ok(key)
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "scheme must be unix, or http with a socket"))
+        }
+    }
+
+    fn connected(&mut self) -> Option<(PathBuf, io::Result<UnixStream>)> {
+        self.connected.pop_front()
+    }
+
+    fn register(&mut self, reg: Registration) {
+        self.wakeup = Some(reg.wakeup);
+    }
+}
+
 #[cfg(not(any(feature = "openssl", feature = "security-framework")))]
 #[doc(hidden)]
 pub type DefaultConnector = HttpConnector;
@@ -223,3 +937,106 @@ fn _assert_defaults() {
 
     _assert::<DefaultConnector, DefaultTransport>();
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::collections::VecDeque;
+    use std::net::IpAddr;
+    use super::{Attempt, Attempts, Connect, ConnectError, HttpConnector, interleave};
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_interleave() {
+        let addrs = vec![ip("::1"), ip("::2"), ip("::3"), ip("10.0.0.1"), ip("10.0.0.2")];
+        assert_eq!(interleave(addrs.into_iter()).into_iter().collect::<Vec<_>>(),
+                   vec![ip("::1"), ip("10.0.0.1"), ip("::2"), ip("10.0.0.2"), ip("::3")]);
+
+        let addrs = vec![ip("10.0.0.1"), ip("::1")];
+        assert_eq!(interleave(addrs.into_iter()).into_iter().collect::<Vec<_>>(),
+                   vec![ip("10.0.0.1"), ip("::1")]);
+
+        assert!(interleave(Vec::new().into_iter()).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_key() {
+        use std::path::PathBuf;
+        use super::{Connect, UnixConnector};
+
+        let url = UnixConnector::url("/tmp/hyper.sock", "/foo?bar").unwrap();
+        assert_eq!(url.path(), "/foo");
+        let connector = UnixConnector::default();
+        assert_eq!(connector.key(&url), Some(PathBuf::from("/tmp/hyper.sock")));
+        assert_eq!(connector.key(&"http://example.domain/".parse().unwrap()), None);
+
+        let connector = connector.socket("/tmp/other.sock");
+        assert_eq!(connector.key(&"http://example.domain/".parse().unwrap()), Some(PathBuf::from("/tmp/other.sock")));
+    }
+
+    fn racing(connector: &mut HttpConnector, key: &(&'static str, String, u16), race: usize, pending: usize) {
+        let mut attempts = Attempts::new(VecDeque::from(vec![ip("127.0.0.1")]), key.2);
+        attempts.pending = pending;
+        connector.joining = Some(attempts);
+        connector.joined(key, race);
+    }
+
+    #[test]
+    fn test_attempted_cancelled() {
+        let mut connector = HttpConnector::default();
+        let key = ("http", "example.domain".to_owned(), 80);
+        racing(&mut connector, &key, 0, 2);
+
+        assert!(connector.attempted(&key, 0, Attempt::Connected).is_none());
+        assert!(connector.attempts.contains_key(&0));
+        // the socket that lost the race is closed
+        assert!(connector.attempted(&key, 0, Attempt::Cancelled).is_none());
+        assert!(!connector.attempts.contains_key(&0));
+        assert!(connector.raced(&key, 0).is_none());
+    }
+
+    #[test]
+    fn test_attempted_aborted() {
+        let mut connector = HttpConnector::default();
+        let key = ("http", "example.domain".to_owned(), 80);
+        racing(&mut connector, &key, 0, 1);
+
+        let err = io::Error::new(io::ErrorKind::Other, "no room for another socket");
+        assert!(connector.attempted(&key, 0, Attempt::Aborted(err)).is_none());
+        assert!(!connector.attempts.contains_key(&0));
+        assert!(connector.raced(&key, 0).is_none());
+    }
+
+    #[test]
+    fn test_attempted_races_apart() {
+        let mut connector = HttpConnector::default();
+        let key = ("http", "example.domain".to_owned(), 80);
+        racing(&mut connector, &key, 0, 1);
+        racing(&mut connector, &key, 1, 1);
+
+        // the second race failing doesn't touch the first
+        let err = io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused");
+        assert!(connector.attempted(&key, 1, Attempt::Failed(err)).is_some());
+        assert!(connector.attempts.contains_key(&0));
+        assert!(!connector.attempts.contains_key(&1));
+
+        assert!(connector.attempted(&key, 0, Attempt::Connected).is_none());
+        assert!(connector.attempts.is_empty());
+    }
+
+    #[test]
+    fn test_connect_error() {
+        let err = ConnectError {
+            addrs: vec!["[::1]:80".parse().unwrap(), "127.0.0.1:80".parse().unwrap()],
+            errors: vec![
+                io::Error::new(io::ErrorKind::TimedOut, "timed out"),
+                io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused"),
+            ],
+        };
+        assert_eq!(err.to_string(), "failed to connect to [::1]:80, 127.0.0.1:80: connection refused");
+    }
+}
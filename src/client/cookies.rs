@@ -0,0 +1,439 @@
+//! Keeping the cookies servers set, for a `Client`.
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use time;
+
+use header::{CookiePair, SetCookie};
+use Url;
+
+/// Cookies that servers set, to send back to them in later requests.
+///
+/// Given to `Config::cookie_store`, the `Client` keeps every cookie from
+/// `Set-Cookie` headers, and sends those matching a request's domain, path
+/// and scheme in its `Cookie` header, as in
+/// [RFC 6265](https://tools.ietf.org/html/rfc6265). Clones share the same
+/// cookies, so a store kept outside the `Client` can be saved, and loaded
+/// again for the next one.
+///
+/// # Example
+///
+/// ```
+/// use hyper::Client;
+/// use hyper::client::CookieStore;
+///
+/// let store = CookieStore::new();
+/// let config = Client::<()>::configure()
+///     .cookie_store(store.clone());
+/// # drop(config);
+///
+/// let mut saved = Vec::new();
+/// store.save(&mut saved).unwrap();
+/// let store = CookieStore::load(&saved[..]).unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct CookieStore {
+    cookies: Arc<Mutex<Vec<Stored>>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Stored {
+    name: String,
+    value: String,
+    domain: String,
+    /// Only sent to `domain` itself, not to its subdomains.
+    host_only: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    /// Seconds since the epoch, or `None` until the process exits.
+    expires: Option<i64>,
+}
+
+impl CookieStore {
+    /// Create a store without any cookies.
+    pub fn new() -> CookieStore {
+        CookieStore::default()
+    }
+
+    /// Keeps the cookies set by a response to `url`.
+    ///
+    /// Cookies for a domain that `url` isn't in, or for a public suffix
+    /// like `com` that many unrelated sites share, are ignored. Cookies that
+    /// have expired remove those of the same name, domain and path.
+    pub fn store(&self, url: &Url, set_cookie: &SetCookie) {
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => return
+        };
+        let now = time::get_time().sec;
+        let mut cookies = self.lock();
+        for pair in set_cookie.iter() {
+            let stored = match Stored::new(pair, url, &host, now) {
+                Some(stored) => stored,
+                None => {
+                    debug!("ignoring cookie {:?} set by {:?}", pair.name, host);
+                    continue;
+                }
+            };
+            cookies.retain(|cookie| {
+                cookie.name != stored.name || cookie.domain != stored.domain || cookie.path != stored.path
+            });
+            if !stored.expired(now) {
+                trace!("storing cookie {:?} for {:?}", stored.name, stored.domain);
+                cookies.push(stored);
+            }
+        }
+    }
+
+    /// The cookies to send with a request to `url`, those with longer paths
+    /// first.
+    pub fn matches(&self, url: &Url) -> Vec<CookiePair> {
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => return Vec::new()
+        };
+        let secure = url.scheme() == "https";
+        let now = time::get_time().sec;
+        let mut cookies = self.lock();
+        cookies.retain(|cookie| !cookie.expired(now));
+        let mut matching = cookies.iter().filter(|cookie| {
+            let domain = if cookie.host_only {
+                host == cookie.domain
+            } else {
+                domain_match(&host, &cookie.domain)
+            };
+            domain && path_match(url.path(), &cookie.path) && (secure || !cookie.secure)
+        }).collect::<Vec<_>>();
+        // a stable sort, so cookies of the same path stay in order of creation
+        matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+        matching.into_iter()
+            .map(|cookie| CookiePair::new(cookie.name.clone(), cookie.value.clone()))
+            .collect()
+    }
+
+    /// The number of cookies stored, including any that have expired.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Whether there are no cookies stored.
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Removes all cookies.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Writes the cookies in the Netscape `cookies.txt` format, as used by
+    /// curl and wget.
+    ///
+    /// Session cookies are written with an expiry of `0`.
+    pub fn save<W: Write>(&self, mut dst: W) -> io::Result<()> {
+        let now = time::get_time().sec;
+        try!(dst.write_all(b"# Netscape HTTP Cookie File\n"));
+        for cookie in self.lock().iter().filter(|cookie| !cookie.expired(now)) {
+            try!(write!(dst, "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                        if cookie.http_only { "#HttpOnly_" } else { "" },
+                        if cookie.host_only { "" } else { "." },
+                        cookie.domain,
+                        flag(!cookie.host_only),
+                        cookie.path,
+                        flag(cookie.secure),
+                        cookie.expires.unwrap_or(0),
+                        cookie.name,
+                        cookie.value));
+        }
+        dst.flush()
+    }
+
+    /// Reads cookies in the Netscape `cookies.txt` format, leaving out any
+    /// that have expired.
+    pub fn load<R: BufRead>(src: R) -> io::Result<CookieStore> {
+        let now = time::get_time().sec;
+        let mut cookies = Vec::new();
+        for line in src.lines() {
+            let line = try!(line);
+            let (http_only, line) = if line.starts_with("#HttpOnly_") {
+                (true, &line["#HttpOnly_".len()..])
+            } else {
+                (false, &line[..])
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let cookie = match parse_line(line, http_only) {
+                Some(cookie) => cookie,
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid cookies.txt line"))
+            };
+            if !cookie.expired(now) {
+                cookies.push(cookie);
+            }
+        }
+        Ok(CookieStore {
+            cookies: Arc::new(Mutex::new(cookies)),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<Vec<Stored>> {
+        self.cookies.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl fmt::Debug for CookieStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CookieStore")
+            .field("cookies", &*self.lock())
+            .finish()
+    }
+}
+
+impl Stored {
+    fn new(pair: &CookiePair, url: &Url, host: &str, now: i64) -> Option<Stored> {
+        let (domain, host_only) = match pair.domain {
+            Some(ref domain) if !domain.trim_left_matches('.').is_empty() => {
+                let domain = domain.trim_left_matches('.').to_lowercase();
+                if !domain_match(host, &domain) {
+                    return None;
+                }
+                if is_public_suffix(&domain) {
+                    // a cookie for every site under `com` is only allowed
+                    // from `com` itself, and only sent back there
+                    if domain != host {
+                        return None;
+                    }
+                    (domain, true)
+                } else {
+                    (domain, false)
+                }
+            },
+            _ => (host.to_owned(), true)
+        };
+        let path = match pair.path {
+            Some(ref path) if path.starts_with('/') => path.clone(),
+            _ => default_path(url.path()).to_owned()
+        };
+        // Max-Age wins over Expires
+        let expires = match (pair.max_age, pair.expires) {
+            (Some(max_age), _) => Some(now.saturating_add(max_age as i64)),
+            (None, Some(tm)) => Some(tm.to_timespec().sec),
+            (None, None) => None
+        };
+        Some(Stored {
+            name: pair.name.clone(),
+            value: pair.value.clone(),
+            domain: domain,
+            host_only: host_only,
+            path: path,
+            secure: pair.secure,
+            http_only: pair.httponly,
+            expires: expires,
+        })
+    }
+
+    fn expired(&self, now: i64) -> bool {
+        self.expires.map_or(false, |expires| expires <= now)
+    }
+}
+
+/// Whether `host` is `domain`, or a name in it.
+fn domain_match(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    let is_ip = host.trim_left_matches('[').trim_right_matches(']').parse::<IpAddr>().is_ok();
+    !is_ip && host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.')
+}
+
+/// Registries of more than one label that anyone can register names under.
+/// This is far from the full public suffix list, only the common ones.
+static PUBLIC_SUFFIXES: &'static [&'static str] = &[
+    "ac.uk", "co.uk", "gov.uk", "ltd.uk", "me.uk", "net.uk", "org.uk", "plc.uk",
+    "com.au", "net.au", "org.au", "edu.au", "gov.au",
+    "co.nz", "net.nz", "org.nz",
+    "co.jp", "ne.jp", "or.jp", "ac.jp", "go.jp",
+    "com.br", "net.br", "org.br",
+    "com.cn", "net.cn", "org.cn",
+    "co.in", "net.in", "org.in",
+    "co.kr", "or.kr",
+    "co.za", "org.za",
+    "com.mx", "com.tr", "com.tw", "com.hk", "com.sg",
+];
+
+/// Whether `domain` is one that cookies can't be set for, as it holds the
+/// names of unrelated sites, like `com` or `co.uk`.
+fn is_public_suffix(domain: &str) -> bool {
+    !domain.contains('.') || PUBLIC_SUFFIXES.contains(&domain)
+}
+
+/// Whether a request to `path` is for a cookie with `cookie_path`.
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path ||
+        (path.starts_with(cookie_path) &&
+         (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The path of a cookie set without one, the "directory" of the request.
+fn default_path(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i]
+    }
+}
+
+fn flag(val: bool) -> &'static str {
+    if val { "TRUE" } else { "FALSE" }
+}
+
+fn parse_line(line: &str, http_only: bool) -> Option<Stored> {
+    let fields = line.split('\t').collect::<Vec<_>>();
+    if fields.len() != 7 {
+        return None;
+    }
+    let subdomains = match fields[1] {
+        "TRUE" => true,
+        "FALSE" => false,
+        _ => return None
+    };
+    let secure = match fields[3] {
+        "TRUE" => true,
+        "FALSE" => false,
+        _ => return None
+    };
+    let expires = match fields[4].parse::<i64>() {
+        Ok(0) => None,
+        Ok(expires) => Some(expires),
+        Err(_) => return None
+    };
+    Some(Stored {
+        name: fields[5].to_owned(),
+        value: fields[6].to_owned(),
+        domain: fields[0].trim_left_matches('.').to_lowercase(),
+        host_only: !subdomains,
+        path: fields[2].to_owned(),
+        secure: secure,
+        http_only: http_only,
+        expires: expires,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use header::{CookiePair, SetCookie};
+    use Url;
+    use super::{CookieStore, default_path, path_match};
+
+    fn set_cookie(s: &str) -> SetCookie {
+        SetCookie(vec![s.parse().unwrap()])
+    }
+
+    fn names(store: &CookieStore, url: &str) -> Vec<String> {
+        store.matches(&Url::parse(url).unwrap()).into_iter()
+            .map(|pair: CookiePair| pair.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(default_path("/"), "/");
+        assert_eq!(default_path("/a"), "/");
+        assert_eq!(default_path("/a/b"), "/a");
+        assert!(path_match("/a", "/a"));
+        assert!(path_match("/a/b", "/a"));
+        assert!(path_match("/a/b", "/a/"));
+        assert!(!path_match("/ab", "/a"));
+        assert!(!path_match("/", "/a"));
+    }
+
+    #[test]
+    fn test_domain() {
+        let store = CookieStore::new();
+        let url = Url::parse("http://www.hyper.rs/").unwrap();
+        store.store(&url, &set_cookie("host=1"));
+        store.store(&url, &set_cookie("domain=1; Domain=.hyper.rs"));
+        store.store(&url, &set_cookie("other=1; Domain=example.domain"));
+        store.store(&url, &set_cookie("suffix=1; Domain=per.rs"));
+
+        assert_eq!(names(&store, "http://www.hyper.rs/"), vec!["host", "domain"]);
+        assert_eq!(names(&store, "http://hyper.rs/"), vec!["domain"]);
+        assert_eq!(names(&store, "http://api.hyper.rs/"), vec!["domain"]);
+        assert!(names(&store, "http://example.domain/").is_empty());
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_public_suffix() {
+        let store = CookieStore::new();
+        let url = Url::parse("http://www.hyper.rs/").unwrap();
+        store.store(&url, &set_cookie("tld=1; Domain=rs"));
+        store.store(&url, &set_cookie("dot=1; Domain=.rs"));
+        let url = Url::parse("http://www.example.co.uk/").unwrap();
+        store.store(&url, &set_cookie("registry=1; Domain=co.uk"));
+        store.store(&url, &set_cookie("site=1; Domain=example.co.uk"));
+
+        assert!(names(&store, "http://other.rs/").is_empty());
+        assert!(names(&store, "http://other.co.uk/").is_empty());
+        assert_eq!(names(&store, "http://www.example.co.uk/"), vec!["site"]);
+        assert_eq!(store.len(), 1);
+
+        // only the host itself may use a single label
+        let url = Url::parse("http://localhost/").unwrap();
+        store.store(&url, &set_cookie("local=1; Domain=localhost"));
+        assert_eq!(names(&store, "http://localhost/"), vec!["local"]);
+        assert!(names(&store, "http://www.localhost/").is_empty());
+    }
+
+    #[test]
+    fn test_path_and_secure() {
+        let store = CookieStore::new();
+        let url = Url::parse("https://hyper.rs/docs/guide").unwrap();
+        store.store(&url, &set_cookie("docs=1"));
+        store.store(&url, &set_cookie("root=1; Path=/"));
+        store.store(&url, &set_cookie("secure=1; Path=/; Secure"));
+
+        assert_eq!(names(&store, "https://hyper.rs/docs/api"), vec!["docs", "root", "secure"]);
+        assert_eq!(names(&store, "https://hyper.rs/"), vec!["root", "secure"]);
+        assert_eq!(names(&store, "http://hyper.rs/docs"), vec!["docs", "root"]);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let store = CookieStore::new();
+        let url = Url::parse("http://hyper.rs/").unwrap();
+        store.store(&url, &set_cookie("a=1"));
+        store.store(&url, &set_cookie("a=2"));
+        assert_eq!(store.matches(&url)[0].value, "2");
+
+        store.store(&url, &set_cookie("a=3; Max-Age=0"));
+        assert!(store.is_empty());
+
+        store.store(&url, &set_cookie("b=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert!(store.is_empty());
+        store.store(&url, &set_cookie("b=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Max-Age=60"));
+        assert_eq!(names(&store, "http://hyper.rs/"), vec!["b"]);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let store = CookieStore::new();
+        let url = Url::parse("https://www.hyper.rs/a/b").unwrap();
+        store.store(&url, &set_cookie("session=1"));
+        store.store(&url, &set_cookie("id=2; Domain=hyper.rs; Path=/; Secure; HttpOnly; Max-Age=60"));
+
+        let mut saved = Vec::new();
+        store.save(&mut saved).unwrap();
+        let text = String::from_utf8(saved.clone()).unwrap();
+        assert!(text.starts_with("# Netscape HTTP Cookie File\n"));
+        assert!(text.contains("www.hyper.rs\tFALSE\t/a\tFALSE\t0\tsession\t1\n"), "{:?}", text);
+        assert!(text.contains("#HttpOnly_.hyper.rs\tTRUE\t/\tTRUE\t"), "{:?}", text);
+
+        let loaded = CookieStore::load(&saved[..]).unwrap();
+        assert_eq!(*loaded.lock(), *store.lock());
+
+        assert!(CookieStore::load(&b"hyper.rs\tTRUE\t/\n"[..]).is_err());
+    }
+}
@@ -1,65 +1,304 @@
+//! Resolving host names for an `HttpConnector`.
+use std::collections::HashMap;
+use std::fmt;
 use std::io;
-use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::net::{IpAddr, ToSocketAddrs};
 use std::thread;
+use std::time::{Duration, Instant};
 use std::vec;
 
 use ::spmc;
 
 use http::channel;
 
-pub struct Dns {
-    tx: spmc::Sender<String>,
-    rx: channel::Receiver<Answer>,
+/// Resolves host names to the addresses an `HttpConnector` connects to.
+///
+/// Once the client's event loop is running, a `Resolve` is registered with
+/// `Answers`, which wake up the loop when sent to. A resolver can answer
+/// right away from `resolve`, from threads of its own, like the default
+/// `ThreadResolver`, or from whatever drives an asynchronous lookup.
+pub trait Resolve: Send {
+    /// Called once, with where to send answers.
+    fn register(&mut self, answers: Answers);
+    /// Starts resolving `host`, sending the answer to the registered
+    /// `Answers` when done.
+    fn resolve(&mut self, host: &str);
+}
+
+/// Where a `Resolve` sends the addresses it found.
+#[derive(Clone)]
+pub struct Answers {
+    tx: channel::Sender<Answer>,
 }
 
-pub type Answer = (String, io::Result<IpAddrs>);
+impl Answers {
+    /// Sends the addresses of `host`, or why they couldn't be found.
+    ///
+    /// An error means the client has closed, and no more answers are needed.
+    pub fn send(&self, host: String, addrs: io::Result<Vec<IpAddr>>) -> io::Result<()> {
+        self.answer(Answer {
+            host: host,
+            addrs: addrs,
+            ttl: None,
+        })
+    }
+
+    /// Sends the addresses of `host`, along with how long they may be cached.
+    pub fn send_ttl(&self, host: String, addrs: Vec<IpAddr>, ttl: Duration) -> io::Result<()> {
+        self.answer(Answer {
+            host: host,
+            addrs: Ok(addrs),
+            ttl: Some(ttl),
+        })
+    }
+
+    fn answer(&self, answer: Answer) -> io::Result<()> {
+        self.tx.send(answer).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client closed"))
+    }
+}
+
+impl fmt::Debug for Answers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Answers")
+            .finish()
+    }
+}
+
+pub struct Answer {
+    host: String,
+    addrs: io::Result<Vec<IpAddr>>,
+    ttl: Option<Duration>,
+}
 
 pub struct IpAddrs {
-    iter: vec::IntoIter<SocketAddr>,
+    iter: vec::IntoIter<IpAddr>,
 }
 
 impl Iterator for IpAddrs {
     type Item = IpAddr;
     #[inline]
     fn next(&mut self) -> Option<IpAddr> {
-        self.iter.next().map(|addr| addr.ip())
+        self.iter.next()
     }
 }
 
+/// The resolver of a connector, with the answers it already knows.
+pub struct Dns {
+    resolver: Box<Resolve>,
+    answers: Answers,
+    rx: channel::Receiver<Answer>,
+    cache: Option<Cache>,
+}
+
 impl Dns {
-    pub fn new(notify: (channel::Sender<Answer>, channel::Receiver<Answer>), threads: usize) -> Dns {
-        let (tx, rx) = spmc::channel();
-        for _ in 0..threads {
-            work(rx.clone(), notify.0.clone());
-        }
+    pub fn new(mut resolver: Box<Resolve>, notify: (channel::Sender<Answer>, channel::Receiver<Answer>), cache_ttl: Option<Duration>) -> Dns {
+        let answers = Answers {
+            tx: notify.0,
+        };
+        resolver.register(answers.clone());
         Dns {
-            tx: tx,
+            resolver: resolver,
+            answers: answers,
             rx: notify.1,
+            cache: cache_ttl.map(Cache::new),
         }
     }
 
-    pub fn resolve<T: Into<String>>(&self, hostname: T) {
-        self.tx.send(hostname.into()).expect("DNS workers all died unexpectedly");
+    pub fn resolve<T: Into<String>>(&mut self, hostname: T) {
+        let host = hostname.into();
+        // an address needs no resolving
+        let ip = host.trim_left_matches('[').trim_right_matches(']').parse::<IpAddr>();
+        if let Ok(ip) = ip {
+            let _ = self.answers.send(host, Ok(vec![ip]));
+            return;
+        }
+        let cached = self.cache.as_mut().and_then(|cache| cache.get(&host));
+        if let Some((addrs, ttl)) = cached {
+            trace!("resolve {:?} from cache", host);
+            let _ = self.answers.send_ttl(host, addrs, ttl);
+            return;
+        }
+        self.resolver.resolve(&host);
+    }
+
+    pub fn resolved(&mut self) -> Result<(String, io::Result<IpAddrs>), channel::TryRecvError> {
+        let answer = try!(self.rx.try_recv());
+        if let Some(ref mut cache) = self.cache {
+            if let Ok(ref addrs) = answer.addrs {
+                cache.insert(&answer.host, addrs, answer.ttl);
+            }
+        }
+        Ok((answer.host, answer.addrs.map(|addrs| IpAddrs { iter: addrs.into_iter() })))
+    }
+}
+
+/// Addresses found before, until their TTL runs out.
+struct Cache {
+    /// The TTL of answers from resolvers that don't know it.
+    ttl: Duration,
+    entries: HashMap<String, (Vec<IpAddr>, Instant)>,
+}
+
+impl Cache {
+    fn new(ttl: Duration) -> Cache {
+        Cache {
+            ttl: ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// The addresses of `host`, and how much longer they may be used.
+    fn get(&mut self, host: &str) -> Option<(Vec<IpAddr>, Duration)> {
+        let now = Instant::now();
+        let expired = match self.entries.get(host) {
+            Some(&(ref addrs, expires)) => {
+                if expires > now {
+                    return Some((addrs.clone(), expires - now));
+                }
+                true
+            },
+            None => false
+        };
+        if expired {
+            self.entries.remove(host);
+        }
+        None
     }
 
-    pub fn resolved(&self) -> Result<Answer, channel::TryRecvError> {
-        self.rx.try_recv()
+    fn insert(&mut self, host: &str, addrs: &[IpAddr], ttl: Option<Duration>) {
+        let ttl = ttl.unwrap_or(self.ttl);
+        if ttl == Duration::from_secs(0) || addrs.is_empty() {
+            return;
+        }
+        self.entries.insert(host.to_owned(), (addrs.to_vec(), Instant::now() + ttl));
     }
 }
 
-fn work(rx: spmc::Receiver<String>, notify: channel::Sender<Answer>) {
+/// Resolves host names with the system resolver, on a pool of threads.
+///
+/// This is what an `HttpConnector` uses unless given another `Resolve`.
+#[derive(Debug)]
+pub struct ThreadResolver {
+    threads: usize,
+    tx: Option<spmc::Sender<String>>,
+}
+
+impl ThreadResolver {
+    /// Create a resolver that uses `threads` threads.
+    pub fn new(threads: usize) -> ThreadResolver {
+        ThreadResolver {
+            threads: threads,
+            tx: None,
+        }
+    }
+}
+
+impl Resolve for ThreadResolver {
+    fn register(&mut self, answers: Answers) {
+        let (tx, rx) = spmc::channel();
+        for _ in 0..self.threads {
+            work(rx.clone(), answers.clone());
+        }
+        self.tx = Some(tx);
+    }
+
+    fn resolve(&mut self, host: &str) {
+        self.tx.as_ref().expect("ThreadResolver not registered")
+            .send(host.to_owned()).expect("DNS workers all died unexpectedly");
+    }
+}
+
+/// Resolves host names from a fixed map, like `/etc/hosts`.
+///
+/// Other host names are given to a fallback resolver, if there is one, and
+/// otherwise fail to resolve.
+///
+/// # Example
+///
+/// ```
+/// use hyper::client::{HttpConnector, StaticResolver, ThreadResolver};
+///
+/// let resolver = StaticResolver::new()
+///     .host("example.domain", "127.0.0.1".parse().unwrap())
+///     .fallback(ThreadResolver::new(4));
+/// let connector = HttpConnector::default().resolver(resolver);
+/// ```
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    fallback: Option<Box<Resolve>>,
+    answers: Option<Answers>,
+}
+
+impl StaticResolver {
+    /// Create a resolver without any hosts.
+    pub fn new() -> StaticResolver {
+        StaticResolver {
+            hosts: HashMap::new(),
+            fallback: None,
+            answers: None,
+        }
+    }
+
+    /// Resolve `host` to `ip`, along with any addresses it was given before.
+    pub fn host(mut self, host: &str, ip: IpAddr) -> StaticResolver {
+        self.hosts.entry(host.to_lowercase()).or_insert_with(Vec::new).push(ip);
+        self
+    }
+
+    /// Resolve other host names with `resolver`.
+    pub fn fallback<R: Resolve + 'static>(mut self, resolver: R) -> StaticResolver {
+        self.fallback = Some(Box::new(resolver));
+        self
+    }
+}
+
+impl Default for StaticResolver {
+    fn default() -> StaticResolver {
+        StaticResolver::new()
+    }
+}
+
+impl fmt::Debug for StaticResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StaticResolver")
+            .field("hosts", &self.hosts)
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+impl Resolve for StaticResolver {
+    fn register(&mut self, answers: Answers) {
+        if let Some(ref mut fallback) = self.fallback {
+            fallback.register(answers.clone());
+        }
+        self.answers = Some(answers);
+    }
+
+    fn resolve(&mut self, host: &str) {
+        let answers = self.answers.as_ref().expect("StaticResolver not registered");
+        if let Some(addrs) = self.hosts.get(&host.to_lowercase()) {
+            let _ = answers.send(host.to_owned(), Ok(addrs.clone()));
+        } else if let Some(ref mut fallback) = self.fallback {
+            fallback.resolve(host);
+        } else {
+            debug!("no static address for {:?}", host);
+            let _ = answers.send(host.to_owned(), Err(io::Error::new(io::ErrorKind::NotFound, "unknown host")));
+        }
+    }
+}
+
+fn work(rx: spmc::Receiver<String>, notify: Answers) {
     thread::Builder::new().name(String::from("hyper-dns")).spawn(move || {
         let mut worker = Worker::new(rx, notify);
         let rx = worker.rx.as_ref().expect("Worker lost rx");
         let notify = worker.notify.as_ref().expect("Worker lost notify");
         while let Ok(host) = rx.recv() {
             debug!("resolve {:?}", host);
-            let res = match (&*host, 80).to_socket_addrs().map(|i| IpAddrs{ iter: i }) {
-                Ok(addrs) => (host, Ok(addrs)),
-                Err(e) => (host, Err(e))
-            };
+            let res = (&*host, 80).to_socket_addrs()
+                .map(|addrs| addrs.map(|addr| addr.ip()).collect());
 
-            if let Err(_) = notify.send(res) {
+            if let Err(_) = notify.send(host, res) {
                 break;
             }
         }
@@ -69,12 +308,12 @@ fn work(rx: spmc::Receiver<String>, notify: channel::Sender<Answer>) {
 
 struct Worker {
     rx: Option<spmc::Receiver<String>>,
-    notify: Option<channel::Sender<Answer>>,
+    notify: Option<Answers>,
     shutdown: bool,
 }
 
 impl Worker {
-    fn new(rx: spmc::Receiver<String>, notify: channel::Sender<Answer>) -> Worker {
+    fn new(rx: spmc::Receiver<String>, notify: Answers) -> Worker {
         Worker {
             rx: Some(rx),
             notify: Some(notify),
@@ -94,3 +333,30 @@ impl Drop for Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::Duration;
+    use super::Cache;
+
+    #[test]
+    fn test_cache() {
+        let ip = "127.0.0.1".parse::<IpAddr>().unwrap();
+        let mut cache = Cache::new(Duration::from_secs(60));
+        assert!(cache.get("hyper.rs").is_none());
+
+        cache.insert("hyper.rs", &[ip], None);
+        let (addrs, ttl) = cache.get("hyper.rs").unwrap();
+        assert_eq!(addrs, vec![ip]);
+        assert!(ttl <= Duration::from_secs(60));
+
+        cache.insert("example.domain", &[ip], Some(Duration::from_secs(0)));
+        assert!(cache.get("example.domain").is_none());
+
+        cache.insert("example.domain", &[ip], Some(Duration::from_millis(1)));
+        ::std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.get("example.domain").is_none());
+        assert!(cache.entries.get("example.domain").is_none());
+    }
+}
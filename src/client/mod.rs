@@ -3,30 +3,55 @@
 //! The HTTP `Client` uses asynchronous IO, and utilizes the `Handler` trait
 //! to convey when IO events are available for a given request.
 
+use std::cmp;
 use std::collections::{VecDeque, HashMap};
 use std::fmt;
 use std::io;
 use std::marker::PhantomData;
+use std::mem;
 use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use rotor::{self, Scope, EventSet, PollOpt};
 
-use header::Host;
-use http::{self, Next, RequestHead, ReadyResult};
+use header::{Headers, Host, Cookie, SetCookie, AcceptEncoding, ContentEncoding, ContentLength, Encoding, qitem};
+use http::{self, Next, Next_, RequestHead, ReadyResult};
+use method::Method;
 use net::Transport;
 use uri::RequestUri;
+use version::HttpVersion;
 use {Url};
 
-pub use self::connect::{Connect, DefaultConnector, HttpConnector, HttpsConnector, DefaultTransport};
+pub use self::cookies::CookieStore;
+pub use self::connect::{Connect, Attempt, ConnectError, DefaultConnector, HttpConnector, HttpsConnector, ProxyConnector, Socks5Connector, DefaultTransport};
+#[cfg(unix)]
+pub use self::connect::UnixConnector;
+pub use self::dns::{Resolve, Answers, ThreadResolver, StaticResolver};
+pub use self::proxy::{Proxy, ProxyStream};
+pub use self::redirect::RedirectPolicy;
 pub use self::request::Request;
 pub use self::response::Response;
+pub use self::retry::RetryPolicy;
+pub use self::stats::{Stats, HostStats};
+pub use self::timeout::Timeouts;
+
+use self::redirect::Redirects;
+use self::retry::Tries;
+use self::stats::Counters;
+use self::timeout::{Limits, Cut};
 
 mod connect;
+mod cookies;
 mod dns;
+mod proxy;
+mod redirect;
 mod request;
 mod response;
+mod retry;
+mod socks;
+mod stats;
+mod timeout;
 
 /// A Client to make outgoing HTTP requests.
 pub struct Client<H> {
@@ -83,32 +108,71 @@ impl<H: Send> Client<H> {
         rotor_config.slab_capacity(config.max_sockets);
         rotor_config.mio().notify_capacity(config.max_sockets);
         let keep_alive = config.keep_alive;
+        let keep_alive_timeout = config.keep_alive_timeout;
+        let max_idle = config.max_idle;
+        let max_sockets_per_host = config.max_sockets_per_host;
         let connect_timeout = config.connect_timeout;
+        let connect_attempt_delay = config.connect_attempt_delay;
+        let connect_attempt_timeout = config.connect_attempt_timeout;
+        let h2c = config.h2c_prior_knowledge;
+        let continue_timeout = config.continue_timeout;
+        let redirect_policy = config.redirect_policy;
+        let retry_policy = config.retry_policy;
+        let cookie_store = config.cookie_store;
+        let decompress = config.decompress;
         let mut loop_ = try!(rotor::Loop::new(&rotor_config));
         let mut notifier = None;
+        let mut connector_notifier = None;
         let mut connector = config.connector;
         connector.dns_workers(config.dns_workers);
         {
             let not = &mut notifier;
+            let connector_not = &mut connector_notifier;
             loop_.add_machine_with(move |scope| {
                 let (tx, rx) = http::channel::new(scope.notifier());
                 let (dns_tx, dns_rx) = http::channel::share(&tx);
                 *not = Some(tx);
+                *connector_not = Some(scope.notifier());
                 connector.register(Registration {
                     notify: (dns_tx, dns_rx),
+                    wakeup: scope.notifier(),
                 });
                 rotor::Response::ok(ClientFsm::Connector(connector, rx))
             }).unwrap();
         }
 
         let notifier = notifier.expect("loop.add_machine_with failed");
+        let connector_notifier = connector_notifier.expect("loop.add_machine_with failed");
+        let redirect_tx = notifier.clone();
         let _handle = try!(thread::Builder::new().name("hyper-client".to_owned()).spawn(move || {
             loop_.run(Context {
                 connect_timeout: connect_timeout,
+                connect_attempt_delay: connect_attempt_delay,
+                connect_attempt_timeout: connect_attempt_timeout,
+                connector_notifier: connector_notifier,
+                attempts: VecDeque::new(),
+                races: HashMap::new(),
+                next_race: 0,
+                racing: VecDeque::new(),
                 keep_alive: keep_alive,
+                keep_alive_timeout: keep_alive_timeout,
+                max_idle: max_idle,
+                max_sockets_per_host: max_sockets_per_host,
+                h2c: h2c,
+                continue_timeout: continue_timeout,
+                redirect_policy: redirect_policy,
+                retry_policy: retry_policy,
+                cookie_store: cookie_store,
+                decompress: decompress,
+                redirect_tx: redirect_tx,
                 idle_conns: HashMap::new(),
+                multiplexed: HashMap::new(),
                 queue: HashMap::new(),
+                hosts: HashMap::new(),
+                unblocked: VecDeque::new(),
                 awaiting_slot: VecDeque::new(),
+                delayed: Vec::new(),
+                counters: Counters::default(),
             }).unwrap()
         }));
 
@@ -125,14 +189,38 @@ impl<H: Send> Client<H> {
     /// If the event loop thread has died, or the queue is full, a `ClientError`
     /// will be returned.
     pub fn request(&self, url: Url, handler: H) -> Result<(), ClientError<H>> {
-        self.tx.send(Notify::Connect(url, handler)).map_err(|e| {
+        self.request_with_timeouts(url, handler, Timeouts::default())
+    }
+
+    /// Build a new request using this Client, which has to keep to `timeouts`.
+    ///
+    /// ## Error
+    ///
+    /// If the event loop thread has died, or the queue is full, a `ClientError`
+    /// will be returned.
+    pub fn request_with_timeouts(&self, url: Url, handler: H, timeouts: Timeouts) -> Result<(), ClientError<H>> {
+        self.tx.send(Notify::Connect(url, handler, Limits::start(&timeouts))).map_err(|e| {
             match e.0 {
-                Some(Notify::Connect(url, handler)) => ClientError(Some((url, handler))),
+                Some(Notify::Connect(url, handler, _)) => ClientError(Some((url, handler))),
                 _ => ClientError(None)
             }
         })
     }
 
+    /// Asks the Client loop for a snapshot of its sockets and requests,
+    /// and the counters it keeps.
+    ///
+    /// This blocks until the loop answers, so don't call it from a
+    /// `Handler`.
+    pub fn stats(&self) -> ::Result<Stats> {
+        let (tx, rx) = mpsc::channel();
+        let gone = || io::Error::new(io::ErrorKind::Other, "client loop is gone").into();
+        if self.tx.send(Notify::Stats(tx)).is_err() {
+            return Err(gone());
+        }
+        rx.recv().map_err(|_| gone())
+    }
+
     /// Close the Client loop.
     pub fn close(self) {
         // Most errors mean that the Receivers are already dead, which would
@@ -145,13 +233,21 @@ impl<H: Send> Client<H> {
 #[derive(Debug, Clone)]
 pub struct Config<C> {
     connect_timeout: Duration,
+    connect_attempt_delay: Duration,
+    connect_attempt_timeout: Duration,
     connector: C,
     keep_alive: bool,
     keep_alive_timeout: Option<Duration>,
-    //TODO: make use of max_idle config
     max_idle: usize,
     max_sockets: usize,
+    max_sockets_per_host: Option<usize>,
     dns_workers: usize,
+    h2c_prior_knowledge: bool,
+    continue_timeout: Duration,
+    redirect_policy: RedirectPolicy,
+    retry_policy: RetryPolicy,
+    cookie_store: Option<CookieStore>,
+    decompress: bool,
 }
 
 impl<C> Config<C> where C: Connect + Send + 'static {
@@ -160,12 +256,21 @@ impl<C> Config<C> where C: Connect + Send + 'static {
     pub fn connector<CC: Connect>(self, val: CC) -> Config<CC> {
         Config {
             connect_timeout: self.connect_timeout,
+            connect_attempt_delay: self.connect_attempt_delay,
+            connect_attempt_timeout: self.connect_attempt_timeout,
             connector: val,
             keep_alive: self.keep_alive,
-            keep_alive_timeout: Some(Duration::from_secs(60 * 2)),
+            keep_alive_timeout: self.keep_alive_timeout,
             max_idle: self.max_idle,
             max_sockets: self.max_sockets,
+            max_sockets_per_host: self.max_sockets_per_host,
             dns_workers: self.dns_workers,
+            h2c_prior_knowledge: self.h2c_prior_knowledge,
+            continue_timeout: self.continue_timeout,
+            redirect_policy: self.redirect_policy,
+            retry_policy: self.retry_policy,
+            cookie_store: self.cookie_store,
+            decompress: self.decompress,
         }
     }
 
@@ -189,8 +294,22 @@ impl<C> Config<C> where C: Connect + Send + 'static {
         self
     }
 
+    /// Set the max number of idle sockets kept alive for each host.
+    ///
+    /// Past this, the least recently used idle socket of the host is closed.
+    ///
+    /// Default is 5.
+    #[inline]
+    pub fn max_idle(mut self, val: usize) -> Config<C> {
+        self.max_idle = val;
+        self
+    }
+
     /// Set the max table size allocated for holding on to live sockets.
     ///
+    /// When it is full, the least recently used idle socket of any host is
+    /// closed to make room.
+    ///
     /// Default is 1024.
     #[inline]
     pub fn max_sockets(mut self, val: usize) -> Config<C> {
@@ -198,6 +317,19 @@ impl<C> Config<C> where C: Connect + Send + 'static {
         self
     }
 
+    /// Set the max number of sockets open or connecting to each host.
+    ///
+    /// Requests to a host that has this many wait for one of its sockets to
+    /// become idle or close, instead of connecting another. The wait counts
+    /// towards the `connect_timeout`.
+    ///
+    /// Default is no limit.
+    #[inline]
+    pub fn max_sockets_per_host(mut self, val: usize) -> Config<C> {
+        self.max_sockets_per_host = Some(val);
+        self
+    }
+
     /// Set the timeout for connecting to a URL.
     ///
     /// Default is 10 seconds.
@@ -207,6 +339,26 @@ impl<C> Config<C> where C: Connect + Send + 'static {
         self
     }
 
+    /// Set how long a socket tries to connect before the `Connect` may
+    /// race it with another address of the host.
+    ///
+    /// Default is 250 milliseconds.
+    #[inline]
+    pub fn connect_attempt_delay(mut self, val: Duration) -> Config<C> {
+        self.connect_attempt_delay = val;
+        self
+    }
+
+    /// Set the timeout for a socket connecting to one address, after
+    /// which the `Connect` may try another.
+    ///
+    /// Default is 5 seconds.
+    #[inline]
+    pub fn connect_attempt_timeout(mut self, val: Duration) -> Config<C> {
+        self.connect_attempt_timeout = val;
+        self
+    }
+
     /// Set number of Dns workers to use for this client
     ///
     /// Default is 4
@@ -216,6 +368,70 @@ impl<C> Config<C> where C: Connect + Send + 'static {
         self
     }
 
+    /// Speak HTTP/2 right away to `http` URLs, without trying HTTP/1 first.
+    ///
+    /// Only enable this when every server reached over plain `http` is
+    /// known to support HTTP/2, such as internal services.
+    ///
+    /// Default is false.
+    #[inline]
+    pub fn h2c_prior_knowledge(mut self, val: bool) -> Config<C> {
+        self.h2c_prior_knowledge = val;
+        self
+    }
+
+    /// Set how long a request with `Expect: 100-continue` waits for the
+    /// server to answer before sending the body anyway.
+    ///
+    /// Default is 1 second.
+    #[inline]
+    pub fn continue_timeout(mut self, val: Duration) -> Config<C> {
+        self.continue_timeout = val;
+        self
+    }
+
+    /// Set which redirects the Client follows, instead of handing them to
+    /// the `Handler`.
+    ///
+    /// Default is `RedirectPolicy::none()`.
+    #[inline]
+    pub fn redirect_policy(mut self, val: RedirectPolicy) -> Config<C> {
+        self.redirect_policy = val;
+        self
+    }
+
+    /// Set how often, and after how long, the Client tries requests again
+    /// when connecting fails or the server is unavailable.
+    ///
+    /// Default is `RetryPolicy::none()`.
+    #[inline]
+    pub fn retry_policy(mut self, val: RetryPolicy) -> Config<C> {
+        self.retry_policy = val;
+        self
+    }
+
+    /// Keep the cookies that servers set in `store`, and send them back.
+    ///
+    /// Default is to not handle cookies, leaving them to the `Handler`.
+    #[inline]
+    pub fn cookie_store(mut self, store: CookieStore) -> Config<C> {
+        self.cookie_store = Some(store);
+        self
+    }
+
+    /// Ask for `gzip` or `deflate` compressed responses, and decompress
+    /// them while the `Handler` reads them.
+    ///
+    /// Decompressed responses have no `Content-Encoding` or `Content-Length`.
+    /// A `Handler` setting its own `Accept-Encoding` gets responses as sent.
+    ///
+    /// Default is disabled.
+    #[inline]
+    pub fn decompress(mut self, val: bool) -> Config<C> {
+        self.decompress = val;
+        self
+    }
+
     /// Construct the Client with this configuration.
     #[inline]
     pub fn build<H: Handler<C::Output>>(self) -> ::Result<Client<H>> {
@@ -227,12 +443,21 @@ impl Default for Config<DefaultConnector> {
     fn default() -> Config<DefaultConnector> {
         Config {
             connect_timeout: Duration::from_secs(10),
+            connect_attempt_delay: Duration::from_millis(250),
+            connect_attempt_timeout: Duration::from_secs(5),
             connector: DefaultConnector::default(),
             keep_alive: true,
             keep_alive_timeout: Some(Duration::from_secs(60 * 2)),
             max_idle: 5,
             max_sockets: 1024,
+            max_sockets_per_host: None,
             dns_workers: 4,
+            h2c_prior_knowledge: false,
+            continue_timeout: Duration::from_secs(1),
+            redirect_policy: RedirectPolicy::none(),
+            retry_policy: RetryPolicy::none(),
+            cookie_store: None,
+            decompress: false,
         }
     }
 }
@@ -266,8 +491,17 @@ impl<H> fmt::Display for ClientError<H> {
 /// Each event handler returns it's desired `Next` action.
 pub trait Handler<T: Transport>: Send + 'static {
     /// This event occurs first, triggering when a `Request` head can be written..
+    ///
+    /// When the `RedirectPolicy` follows a redirect, this occurs again for
+    /// the next URL, and the request is written all over. The same goes
+    /// for each time the request is tried again.
     fn on_request(&mut self, request: &mut Request) -> http::Next;
     /// This event occurs each time the `Request` is ready to be written to.
+    ///
+    /// If the request has `Expect: 100-continue`, this only occurs once the
+    /// server has sent `100 Continue`, or didn't answer within the
+    /// `continue_timeout`. If the server answers with a final response
+    /// instead, `on_response` occurs and the body is never written.
     fn on_request_writable(&mut self, request: &mut http::Encoder<T>) -> http::Next;
     /// This event occurs after the first time this handler signals `Next::read()`,
     /// and a Response has been parsed.
@@ -283,6 +517,15 @@ pub trait Handler<T: Transport>: Send + 'static {
         http::Next::remove()
     }
 
+    /// This event occurs once a `101 Switching Protocols` response has been
+    /// read, asking for the `Upgrade` that takes over the transport.
+    ///
+    /// The default returns `None`, which closes the connection.
+    fn on_upgrade(&mut self) -> Option<Box<http::Upgrade<T>>> {
+        debug!("default Handler.on_upgrade");
+        None
+    }
+
     /// This event occurs when this Handler has requested to remove the Transport.
     fn on_remove(self, _transport: T) where Self: Sized {
         debug!("default Handler.on_remove");
@@ -295,59 +538,382 @@ pub trait Handler<T: Transport>: Send + 'static {
 }
 
 struct Message<H: Handler<T>, T: Transport> {
-    handler: H,
-    url: Option<Url>,
+    /// Given back to the client loop once a redirect has been read.
+    handler: Option<H>,
+    url: Url,
+    /// Headers for the proxy forwarding the request, if any.
+    forwarded: Option<Headers>,
+    method: Method,
+    h2c: bool,
+    switched: bool,
+    redirect_policy: RedirectPolicy,
+    redirects: Redirects,
+    /// Where the response being read redirects to.
+    redirect_to: Option<Url>,
+    redirect_tx: http::channel::Sender<Notify<H>>,
+    retry_policy: RetryPolicy,
+    tries: Tries,
+    /// Whether the connection was used for an earlier request.
+    reused: bool,
+    /// Whether a response head was read.
+    responded: bool,
+    /// How long to wait before trying again, once the body of the
+    /// response being read is done.
+    retry_in: Option<Duration>,
+    cookie_store: Option<CookieStore>,
+    /// Whether compressed responses are asked for, and decompressed.
+    decompress: bool,
+    inflate: Option<http::Inflate>,
+    limits: Limits,
     _marker: PhantomData<T>,
 }
 
+impl<H: Handler<T>, T: Transport> Message<H, T> {
+    /// Queues the handler again, for the `Location` of the response.
+    fn redirect(&mut self, url: Url) -> Next {
+        let handler = match self.handler.take() {
+            Some(handler) => handler,
+            None => return Next::remove(),
+        };
+        let mut redirects = mem::replace(&mut self.redirects, Redirects::default());
+        redirects.previous.push(self.url.clone());
+        trace!("following redirect to {}", url);
+        match self.redirect_tx.send(Notify::Redirect(url, handler, redirects, self.limits)) {
+            Ok(()) => Next::end(),
+            Err(e) => match e.0 {
+                Some(Notify::Redirect(_, mut handler, _, _)) => {
+                    handler.on_error(io::Error::new(io::ErrorKind::Other, "client loop is gone").into())
+                },
+                _ => Next::remove()
+            }
+        }
+    }
+
+    /// Queues the handler again, to try the request after `delay`.
+    fn retry(&mut self, delay: Duration, fresh: bool, next: Next) -> Next {
+        let handler = match self.handler.take() {
+            Some(handler) => handler,
+            None => return Next::remove(),
+        };
+        let retry = Retry {
+            redirects: mem::replace(&mut self.redirects, Redirects::default()),
+            limits: self.limits,
+            tries: self.tries,
+            delay: delay,
+            fresh: fresh,
+        };
+        trace!("trying {} again in {:?}", self.url, delay);
+        match self.redirect_tx.send(Notify::Retry(self.url.clone(), handler, retry)) {
+            Ok(()) => next,
+            Err(e) => match e.0 {
+                Some(Notify::Retry(_, mut handler, _)) => {
+                    handler.on_error(io::Error::new(io::ErrorKind::Other, "client loop is gone").into())
+                },
+                _ => Next::remove()
+            }
+        }
+    }
+
+    fn incoming(&mut self, mut head: http::ResponseHead) -> Next {
+        trace!("on_incoming {:?}", head);
+        self.responded = true;
+        self.switched = head.subject.0 == 101;
+        let status = head.subject.0;
+        if let Some(ref store) = self.cookie_store {
+            if let Some(set_cookie) = head.headers.get::<SetCookie>() {
+                store.store(&self.url, set_cookie);
+            }
+        }
+        if let Some(url) = redirect::location(&self.redirect_policy, status, &head.headers, &self.url, &self.redirects.previous) {
+            if redirect::changes_to_get(status, &self.method) {
+                self.redirects.to_get = true;
+            }
+            // read the body to the end, so the connection can be used again
+            self.redirect_to = Some(url);
+            return Next::read();
+        }
+        if let Some(delay) = retry::unavailable(&self.retry_policy, &self.tries, status, &self.method, &head.headers) {
+            debug!("{} is unavailable, trying again in {:?}", self.url, delay);
+            self.tries.retries += 1;
+            self.retry_in = Some(delay);
+            return Next::read();
+        }
+        if self.decompress {
+            let inflate = match head.headers.get::<ContentEncoding>() {
+                Some(&ContentEncoding(ref codings)) if codings.len() == 1 => http::Inflate::new(&codings[0]),
+                _ => None
+            };
+            if inflate.is_some() {
+                // the handler reads what the headers would have described
+                // without the compression
+                head.headers.remove::<ContentEncoding>();
+                head.headers.remove::<ContentLength>();
+            }
+            self.inflate = inflate;
+        }
+        let resp = response::new(head, self.url.clone());
+        match self.handler {
+            Some(ref mut handler) => handler.on_response(resp),
+            None => Next::remove(),
+        }
+    }
+
+    fn decode(&mut self, transport: &mut http::Decoder<T>) -> Next {
+        if self.redirect_to.is_some() || self.retry_in.is_some() {
+            let mut buf = [0; 4096];
+            loop {
+                match transport.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(_) => (),
+                    Err(e) => match e.kind() {
+                        io::ErrorKind::WouldBlock => return Next::read(),
+                        _ => {
+                            self.redirect_to = None;
+                            self.retry_in = None;
+                            return match self.handler {
+                                Some(ref mut handler) => handler.on_error(e.into()),
+                                None => Next::remove(),
+                            };
+                        }
+                    }
+                }
+            }
+            if let Some(delay) = self.retry_in.take() {
+                return self.retry(delay, false, Next::end());
+            }
+            let url = self.redirect_to.take().expect("checked above");
+            return self.redirect(url);
+        }
+        match self.handler {
+            Some(ref mut handler) => match self.inflate {
+                Some(ref mut inflate) => loop {
+                    let total_out = inflate.total_out();
+                    let next = handler.on_response_readable(&mut http::Decoder::inflate(&mut *inflate, &mut *transport));
+                    // compressed data already read from the transport won't
+                    // make it readable again, so keep going while it helps
+                    let again = match http::interest(&next).0 {
+                        Next_::Read | Next_::ReadWrite => inflate.total_out() > total_out && inflate.is_buffered(),
+                        _ => false
+                    };
+                    if !again {
+                        return next;
+                    }
+                },
+                None => handler.on_response_readable(transport),
+            },
+            None => Next::remove(),
+        }
+    }
+}
+
 impl<H: Handler<T>, T: Transport> http::MessageHandler<T> for Message<H, T> {
     type Message = http::ClientMessage;
 
     fn on_outgoing(&mut self, head: &mut RequestHead) -> Next {
-        let url = self.url.take().expect("Message.url is missing");
-        if let Some(host) = url.host_str() {
+        if let Some(host) = self.url.host_str() {
             head.headers.set(Host {
                 hostname: host.to_owned(),
-                port: url.port(),
+                port: self.url.port(),
             });
         }
-        head.subject.1 = RequestUri::AbsolutePath {
-            path: url.path().to_owned(),
-            query: url.query().map(|q| q.to_owned()),
+        match self.forwarded {
+            Some(ref headers) => {
+                // the proxy needs the whole url to know where to send it
+                let mut url = self.url.clone();
+                url.set_fragment(None);
+                head.subject.1 = RequestUri::AbsoluteUri(url);
+                head.headers.extend(headers.iter());
+            },
+            None => {
+                head.subject.1 = RequestUri::AbsolutePath {
+                    path: self.url.path().to_owned(),
+                    query: self.url.query().map(|q| q.to_owned()),
+                };
+            }
+        }
+        if self.h2c && self.url.scheme() == "http" && self.forwarded.is_none() {
+            head.version = HttpVersion::H2c;
+        }
+        let mut next = match self.handler {
+            Some(ref mut handler) => handler.on_request(&mut self::request::new(head)),
+            None => return Next::remove(),
         };
-        let mut req = self::request::new(head);
-        self.handler.on_request(&mut req)
+        if self.redirects.to_get {
+            head.subject.0 = Method::Get;
+            redirect::remove_body_headers(&mut head.headers);
+            // the body isn't sent anymore
+            let (interest, timeout) = http::interest(&next);
+            match interest {
+                Next_::Write | Next_::ReadWrite => {
+                    next = match timeout {
+                        Some(dur) => Next::read().timeout(dur),
+                        None => Next::read(),
+                    };
+                },
+                _ => ()
+            }
+        }
+        let cross_origin = self.redirects.previous.first()
+            .map_or(false, |first| first.origin() != self.url.origin());
+        if cross_origin {
+            redirect::remove_sensitive_headers(&mut head.headers);
+        }
+        if let Some(ref store) = self.cookie_store {
+            // cookies the handler set win over stored ones of the same name
+            let mut cookies = head.headers.get::<Cookie>().map_or(Vec::new(), |cookie| cookie.0.clone());
+            let stored = store.matches(&self.url).into_iter()
+                .filter(|pair| !cookies.iter().any(|cookie| cookie.name == pair.name))
+                .collect::<Vec<_>>();
+            if !stored.is_empty() {
+                cookies.extend(stored);
+                head.headers.set(Cookie(cookies));
+            }
+        }
+        if self.decompress {
+            if head.headers.has::<AcceptEncoding>() {
+                self.decompress = false;
+            } else {
+                head.headers.set(AcceptEncoding(vec![qitem(Encoding::Gzip), qitem(Encoding::Deflate)]));
+            }
+        }
+        self.method = head.subject.0.clone();
+        self.limits.apply(next)
     }
 
     fn on_encode(&mut self, transport: &mut http::Encoder<T>) -> Next {
-        self.handler.on_request_writable(transport)
+        let next = match self.handler {
+            Some(ref mut handler) => handler.on_request_writable(transport),
+            None => Next::remove(),
+        };
+        self.limits.apply(next)
     }
 
     fn on_incoming(&mut self, head: http::ResponseHead, _: &T) -> Next {
-        trace!("on_incoming {:?}", head);
-        let resp = response::new(head);
-        self.handler.on_response(resp)
+        let next = self.incoming(head);
+        self.limits.apply(next)
     }
 
     fn on_decode(&mut self, transport: &mut http::Decoder<T>) -> Next {
-        self.handler.on_response_readable(transport)
+        let next = self.decode(transport);
+        self.limits.apply(next)
     }
 
     fn on_error(&mut self, error: ::Error) -> Next {
-        self.handler.on_error(error)
+        // running out of the request's own timeouts ends it
+        let cut = match error {
+            ::Error::Timeout => self.limits.timed_out(),
+            _ => None
+        };
+        // a pooled connection the server closed before answering is tried
+        // again once, on a new connection
+        if cut.is_none() && self.reused && !self.responded && !self.tries.stale &&
+                retry::is_idempotent(&self.method) && retry::is_closed(&error) {
+            debug!("pooled connection for {} was closed: {}", self.url, error);
+            self.tries.stale = true;
+            return self.retry(Duration::from_secs(0), true, Next::remove());
+        }
+        let error = match cut {
+            Some(cut) => cut.error(),
+            None => error
+        };
+        let next = match self.handler {
+            Some(ref mut handler) => handler.on_error(error),
+            None => Next::remove(),
+        };
+        if cut.is_some() {
+            Next::remove()
+        } else {
+            self.limits.apply(next)
+        }
+    }
+
+    fn on_wakeup(&mut self, next: Next) -> Next {
+        self.limits.apply(next)
+    }
+
+    fn switched_protocols(&self) -> bool {
+        self.switched
+    }
+
+    fn on_upgrade(&mut self) -> Option<Box<http::Upgrade<T>>> {
+        self.handler.as_mut().and_then(|handler| handler.on_upgrade())
     }
 
     fn on_remove(self, transport: T) {
-        self.handler.on_remove(transport);
+        if let Some(handler) = self.handler {
+            handler.on_remove(transport);
+        }
     }
 }
 
 struct Context<K, H, C: Connect> {
     connect_timeout: Duration,
+    connect_attempt_delay: Duration,
+    connect_attempt_timeout: Duration,
+    /// Wakes up the connector, to hear about `attempts`.
+    connector_notifier: rotor::Notifier,
+    /// How connecting went for sockets, and the race each was in.
+    attempts: VecDeque<(K, usize, Attempt)>,
+    /// Sockets connecting for the same request, by race id.
+    races: HashMap<usize, Race>,
+    next_race: usize,
+    /// Sockets the connector started to race slow or failed ones.
+    racing: VecDeque<(C::Key, C::Output, Option<usize>)>,
     keep_alive: bool,
-    idle_conns: HashMap<K, VecDeque<http::Control>>,
+    keep_alive_timeout: Option<Duration>,
+    max_idle: usize,
+    max_sockets_per_host: Option<usize>,
+    h2c: bool,
+    continue_timeout: Duration,
+    redirect_policy: RedirectPolicy,
+    retry_policy: RetryPolicy,
+    redirect_tx: http::channel::Sender<Notify<H>>,
+    cookie_store: Option<CookieStore>,
+    decompress: bool,
+    /// Idle connections, least recently used first.
+    idle_conns: HashMap<K, VecDeque<Idle>>,
+    /// Connections that take many requests at once, like HTTP/2, along with
+    /// their `Conn::id`. These stay here for as long as they take new
+    /// streams, not just while idle.
+    multiplexed: HashMap<K, (usize, http::Control)>,
     queue: HashMap<K, VecDeque<Queued<H>>>,
+    hosts: HashMap<K, Host>,
+    /// Hosts that may connect again for their blocked requests.
+    unblocked: VecDeque<K>,
     awaiting_slot: VecDeque<(C::Key, C::Output)>,
+    /// Requests waiting to be tried again.
+    delayed: Vec<(rotor::Time, Url, H, Retry)>,
+    counters: Counters,
+}
+
+/// A connection waiting in the pool for another request.
+struct Idle {
+    ctrl: http::Control,
+    /// The `Conn::id` of the connection.
+    id: usize,
+    since: rotor::Time,
+}
+
+/// The sockets to one key, and the requests waiting for more.
+#[derive(Debug, Default)]
+struct Host {
+    connecting: usize,
+    open: usize,
+    /// Queued requests that no socket was started or woken up for, as the
+    /// host has `max_sockets_per_host`.
+    blocked: usize,
+    /// When the `Connect` was asked for each socket it has yet to hand out.
+    dials: VecDeque<Instant>,
+}
+
+/// Sockets connecting for the same request. The first to connect is used,
+/// and the others are closed.
+struct Race {
+    /// How many are still connecting.
+    sockets: usize,
+    won: bool,
+    /// Wakes up the machine of each socket, to close the ones that lost.
+    notifiers: Vec<rotor::Notifier>,
 }
 
 /// Macro for advancing state of a ClientFsm::Socket
@@ -357,11 +923,29 @@ struct Context<K, H, C: Connect> {
 macro_rules! conn_response {
     ($scope:expr, $conn:expr, $time:expr) => {{
         match $conn {
-            Some((conn, timeout)) => {
-                //TODO: HTTP2: a connection doesn't need to be idle to be used for a second stream
-                if conn.is_idle() {
-                    $scope.idle_conns.entry(conn.key().clone()).or_insert_with(VecDeque::new)
-                        .push_back(conn.control());
+            Some((mut conn, timeout)) => {
+                if conn.pool_streams() {
+                    // requests already waiting on this key can go out as
+                    // new streams; sockets still connecting for them will
+                    // find the queue empty and close
+                    let ctrl = conn.control();
+                    let waiting = $scope.queue.get(conn.key()).map_or(0, |queue| queue.len());
+                    for _ in 0..waiting {
+                        let _ = ctrl.ready(Next::write());
+                    }
+                    $scope.multiplexed.insert(conn.key().clone(), (conn.id(), ctrl));
+                } else if conn.pool_idle() {
+                    let ctrl = conn.control();
+                    if $scope.unblock(conn.key()) {
+                        // a request was waiting for this host to have a socket
+                        let _ = ctrl.ready(Next::write());
+                    } else {
+                        $scope.pool(conn.key().clone(), Idle {
+                            ctrl: ctrl,
+                            id: conn.id(),
+                            since: $time,
+                        });
+                    }
                 }
                 match timeout {
                     Some(dur) => rotor::Response::ok(ClientFsm::Socket(conn))
@@ -373,7 +957,7 @@ macro_rules! conn_response {
             None => {
                 if let Some((key, socket)) = $scope.awaiting_slot.pop_front() {
                     rotor_try!($scope.register(&socket, EventSet::writable() | EventSet::hup(), PollOpt::level()));
-                    rotor::Response::ok(ClientFsm::Connecting((key, socket)))
+                    ClientFsm::connecting((key, socket, None), Attempting::new($time), $scope)
                 } else {
                     rotor::Response::done()
                 }
@@ -383,6 +967,203 @@ macro_rules! conn_response {
 }
 
 impl<K: http::Key, H, C: Connect> Context<K, H, C> {
+    /// Whether the host already has `max_sockets_per_host`.
+    fn is_full(&self, key: &K) -> bool {
+        match (self.max_sockets_per_host, self.hosts.get(key)) {
+            (Some(max), Some(host)) => host.connecting + host.open >= max,
+            _ => false
+        }
+    }
+
+    fn host(&mut self, key: &K) -> &mut Host {
+        if !self.hosts.contains_key(key) {
+            self.hosts.insert(key.clone(), Host::default());
+        }
+        self.hosts.get_mut(key).expect("inserted above")
+    }
+
+    /// Takes one of the host's blocked requests, for a socket of it that
+    /// is free to take it.
+    fn unblock(&mut self, key: &K) -> bool {
+        let waiting = self.queue.get(key).map_or(0, |queue| queue.len());
+        match self.hosts.get_mut(key) {
+            Some(host) if host.blocked > 0 && waiting > 0 => {
+                host.blocked = cmp::min(host.blocked, waiting) - 1;
+                true
+            },
+            _ => false
+        }
+    }
+
+    /// A socket for the key is done connecting, either way.
+    fn connect_done(&mut self, key: &K, ok: bool) {
+        let blocked = {
+            let host = self.host(key);
+            host.connecting = host.connecting.saturating_sub(1);
+            if ok {
+                host.open += 1;
+            }
+            host.blocked > 0
+        };
+        if blocked && !ok {
+            self.unblocked.push_back(key.clone());
+            let _ = self.connector_notifier.wakeup();
+        }
+    }
+
+    /// Keeps an idle connection, closing the least recently used one of the
+    /// host if it has more than `max_idle`.
+    fn pool(&mut self, key: K, idle: Idle) {
+        let max_idle = self.max_idle;
+        let conns = self.idle_conns.entry(key).or_insert_with(VecDeque::new);
+        conns.push_back(idle);
+        while conns.len() > max_idle {
+            if let Some(evicted) = conns.pop_front() {
+                trace!("closing idle connection over max_idle");
+                if evicted.ctrl.ready(Next::remove()).is_ok() {
+                    self.counters.evictions += 1;
+                }
+            }
+        }
+    }
+
+    /// Closes the idle connection that was used the longest time ago, of
+    /// any host.
+    fn evict_idle(&mut self) -> bool {
+        loop {
+            let oldest = self.idle_conns.iter()
+                .filter_map(|(key, conns)| conns.front().map(|idle| (key, idle.since)))
+                .fold(None, |oldest: Option<(&K, rotor::Time)>, (key, since)| match oldest {
+                    Some((_, time)) if time <= since => oldest,
+                    _ => Some((key, since))
+                })
+                .map(|(key, _)| key.clone());
+            let key = match oldest {
+                Some(key) => key,
+                None => return false
+            };
+            let (evicted, empty) = {
+                let conns = self.idle_conns.get_mut(&key).expect("found above");
+                let idle = conns.pop_front().expect("found above");
+                // an err means the socket is already dead
+                (idle.ctrl.ready(Next::remove()).is_ok(), conns.is_empty())
+            };
+            if empty {
+                self.idle_conns.remove(&key);
+            }
+            if evicted {
+                trace!("evicted idle connection for {:?}", key);
+                self.counters.evictions += 1;
+                return true;
+            }
+        }
+    }
+
+    /// The `Connect` was asked for a socket to the key.
+    fn dialed(&mut self, key: &K) {
+        self.host(key).connecting += 1;
+        self.host(key).dials.push_back(Instant::now());
+    }
+
+    /// The `Connect` handed out a socket, or an error, for the key.
+    fn resolved(&mut self, key: &K) {
+        if let Some(started) = self.hosts.get_mut(key).and_then(|host| host.dials.pop_front()) {
+            self.counters.resolves += 1;
+            self.counters.resolve_time = self.counters.resolve_time + started.elapsed();
+        }
+    }
+
+    /// A socket took `dur` to connect.
+    fn connected_in(&mut self, dur: Duration) {
+        self.counters.connects += 1;
+        self.counters.connect_time = self.counters.connect_time + dur;
+    }
+
+    /// A snapshot of the sockets and requests, and the counters.
+    fn stats(&self) -> Stats {
+        let mut keys = self.hosts.keys().collect::<Vec<_>>();
+        for key in self.queue.keys().chain(self.idle_conns.keys()) {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        let hosts = keys.into_iter().map(|key| {
+            let (open, connecting) = self.hosts.get(key).map_or((0, 0), |host| (host.open, host.connecting));
+            stats::host(format!("{:?}", key), open,
+                        self.idle_conns.get(key).map_or(0, |conns| conns.len()),
+                        connecting,
+                        self.queue.get(key).map_or(0, |queue| queue.len()))
+        }).collect();
+        stats::new(hosts, self.awaiting_slot.len(), self.delayed.len(), self.counters)
+    }
+
+    /// Tells the connector how connecting a socket went.
+    fn attempted(&mut self, key: K, race: usize, attempt: Attempt) {
+        trace!("attempted {:?}: {:?}", key, attempt);
+        self.attempts.push_back((key, race, attempt));
+        let _ = self.connector_notifier.wakeup();
+    }
+
+    /// Adds a socket to a race, or starts a new one. A race that is over
+    /// starts again under the same id, which the connector knows it by.
+    fn join_race(&mut self, race: Option<usize>) -> usize {
+        let id = match race {
+            Some(id) => id,
+            None => {
+                let id = self.next_race;
+                self.next_race = self.next_race.wrapping_add(1);
+                id
+            }
+        };
+        self.races.entry(id).or_insert_with(|| Race {
+            sockets: 0,
+            won: false,
+            notifiers: Vec::new(),
+        }).sockets += 1;
+        id
+    }
+
+    fn watch_race(&mut self, race: usize, notifier: rotor::Notifier) {
+        if let Some(race) = self.races.get_mut(&race) {
+            race.notifiers.push(notifier);
+        }
+    }
+
+    /// Whether another socket of the race already connected.
+    fn is_lost(&self, race: Option<usize>) -> bool {
+        race.and_then(|id| self.races.get(&id)).map_or(false, |race| race.won)
+    }
+
+    /// A socket of the race connected, so the others should close.
+    fn win_race(&mut self, race: Option<usize>) {
+        if let Some(race) = race.and_then(|id| self.races.get_mut(&id)) {
+            race.won = true;
+            for notifier in race.notifiers.drain(..) {
+                let _ = notifier.wakeup();
+            }
+        }
+    }
+
+    /// A socket is done connecting, returning whether others of its race
+    /// still are.
+    fn leave_race(&mut self, race: Option<usize>) -> bool {
+        let id = match race {
+            Some(id) => id,
+            None => return false
+        };
+        let others = match self.races.get_mut(&id) {
+            Some(race) => {
+                race.sockets = race.sockets.saturating_sub(1);
+                race.sockets
+            },
+            None => return false
+        };
+        if others == 0 {
+            self.races.remove(&id);
+        }
+        others > 0
+    }
+
     fn pop_queue(&mut self, key: &K) -> Option<Queued<H>> {
         let mut should_remove = false;
         let queued = {
@@ -400,6 +1181,24 @@ impl<K: http::Key, H, C: Connect> Context<K, H, C> {
 
         queued
     }
+
+    /// Waits to try a request again, though not past its deadline.
+    fn delay_retry(&mut self, now: rotor::Time, url: Url, handler: H, mut retry: Retry) {
+        let wait = retry.limits.remaining().map_or(retry.delay, |left| cmp::min(left, retry.delay));
+        retry.delay = Duration::from_secs(0);
+        self.delayed.push((now + wait, url, handler, retry));
+    }
+
+    /// Takes a delayed request that is due to be tried again.
+    fn take_due(&mut self, now: rotor::Time) -> Option<(Url, H, Retry)> {
+        match self.delayed.iter().position(|&(due, _, _, _)| due <= now) {
+            Some(pos) => {
+                let (_, url, handler, retry) = self.delayed.swap_remove(pos);
+                Some((url, handler, retry))
+            },
+            None => None
+        }
+    }
 }
 
 impl<K, H, T, C> http::MessageHandlerFactory<K, T> for Context<K, H, C>
@@ -415,34 +1214,133 @@ impl<K, H, T, C> http::MessageHandlerFactory<K, T> for Context<K, H, C>
         self.pop_queue(key).map(|queued| {
             let (url, mut handler) = (queued.url, queued.handler);
             handler.on_control(seed.control());
+            self.counters.requests += 1;
+            if seed.is_reused() {
+                self.counters.reused += 1;
+            }
 
             Message {
-                handler: handler,
-                url: Some(url),
+                handler: Some(handler),
+                url: url,
+                forwarded: queued.forwarded,
+                method: Method::Get,
+                h2c: self.h2c,
+                switched: false,
+                redirect_policy: self.redirect_policy.clone(),
+                redirects: queued.redirects,
+                redirect_to: None,
+                redirect_tx: self.redirect_tx.clone(),
+                retry_policy: self.retry_policy,
+                tries: queued.tries,
+                reused: seed.is_reused(),
+                responded: false,
+                retry_in: None,
+                cookie_store: self.cookie_store.clone(),
+                decompress: self.decompress,
+                inflate: None,
+                limits: queued.limits,
                 _marker: PhantomData,
             }
         })
     }
 
     fn keep_alive_interest(&self) -> Next {
-        Next::wait()
+        match self.keep_alive_timeout {
+            Some(dur) => Next::wait().timeout(dur),
+            None => Next::wait()
+        }
+    }
+
+    fn on_close(&mut self, key: &K, id: usize) {
+        let empty = match self.idle_conns.get_mut(key) {
+            Some(conns) => {
+                conns.retain(|idle| idle.id != id);
+                conns.is_empty()
+            },
+            None => false
+        };
+        if empty {
+            self.idle_conns.remove(key);
+        }
+        let (open, blocked) = {
+            let host = self.host(key);
+            host.open = host.open.saturating_sub(1);
+            (host.open + host.connecting, host.blocked)
+        };
+        if blocked > 0 {
+            // another socket may connect in its place
+            self.unblocked.push_back(key.clone());
+            let _ = self.connector_notifier.wakeup();
+        } else if open == 0 {
+            self.hosts.remove(key);
+        }
+    }
+
+    fn on_stop_streams(&mut self, key: &K, id: usize, refused: usize) {
+        let current = self.multiplexed.get(key).map_or(false, |&(conn, _)| conn == id);
+        if current {
+            trace!("{:?} stopped taking streams", key);
+            self.multiplexed.remove(key);
+        }
+        if refused > 0 {
+            // the requests it was woken up for connect on their own
+            self.host(key).blocked += refused;
+            self.unblocked.push_back(key.clone());
+            let _ = self.connector_notifier.wakeup();
+        }
     }
 }
 
 enum Notify<T> {
-    Connect(Url, T),
+    Connect(Url, T, Limits),
+    /// A request following a redirect, from the loop itself.
+    Redirect(Url, T, Redirects, Limits),
+    /// A request tried again, from the loop itself.
+    Retry(Url, T, Retry),
+    Stats(mpsc::Sender<Stats>),
     Shutdown,
 }
 
+/// What a request tried again keeps from its earlier tries.
+struct Retry {
+    redirects: Redirects,
+    limits: Limits,
+    tries: Tries,
+    /// How long to wait before trying.
+    delay: Duration,
+    /// Whether to connect anew, rather than use a pooled connection.
+    fresh: bool,
+}
+
 enum ClientFsm<C, H>
 where C: Connect,
       C::Output: Transport,
       H: Handler<C::Output> {
     Connector(C, http::channel::Receiver<Notify<H>>),
-    Connecting((C::Key, C::Output)),
+    Connecting((C::Key, C::Output, Option<usize>), Attempting),
     Socket(http::Conn<C::Key, C::Output, Message<H, C::Output>>)
 }
 
+/// How long a socket has been connecting.
+#[derive(Debug, Clone, Copy)]
+struct Attempting {
+    started: rotor::Time,
+    /// The same as `started`, to tell how long connecting took.
+    since: Instant,
+    /// Whether the connector was told this socket is slow.
+    raced: bool,
+}
+
+impl Attempting {
+    fn new(now: rotor::Time) -> Attempting {
+        Attempting {
+            started: now,
+            since: Instant::now(),
+            raced: false,
+        }
+    }
+}
+
 unsafe impl<C, H> Send for ClientFsm<C, H>
 where
     C: Connect + Send,
@@ -457,11 +1355,18 @@ where C: Connect,
       C::Output: Transport,
       H: Handler<C::Output> {
     type Context = Context<C::Key, H, C>;
-    type Seed = (C::Key, C::Output);
+    /// The socket, and the race it is in. Sockets without one were handed
+    /// out by the connector a while ago, and it no longer hears about them.
+    type Seed = (C::Key, C::Output, Option<usize>);
 
     fn create(seed: Self::Seed, scope: &mut Scope<Self::Context>) -> rotor::Response<Self, rotor::Void> {
         rotor_try!(scope.register(&seed.1, EventSet::writable() | EventSet::hup(), PollOpt::level()));
-        rotor::Response::ok(ClientFsm::Connecting(seed))
+        if let Some(race) = seed.2 {
+            let notifier = scope.notifier();
+            scope.watch_race(race, notifier);
+        }
+        let now = scope.now();
+        ClientFsm::connecting(seed, Attempting::new(now), scope)
     }
 
     fn ready(self, events: EventSet, scope: &mut Scope<Self::Context>) -> rotor::Response<Self, Self::Seed> {
@@ -478,18 +1383,31 @@ where C: Connect,
                     }
                 }
             },
-            ClientFsm::Connecting(mut seed) => {
-                if events.is_error() || events.is_hup() {
-                    if let Some(err) = seed.1.take_socket_error().err() {
-                        debug!("error while connecting: {:?}", err);
-                        scope.pop_queue(&seed.0).map(move |mut queued| queued.handler.on_error(::Error::Io(err)));
-                    } else {
-                        trace!("connecting is_error, but no socket error");
-                    }
-
-                    rotor::Response::done()
+            ClientFsm::Connecting(mut seed, attempting) => {
+                if scope.is_lost(seed.2) {
+                    ClientFsm::lost(seed, scope)
+                } else if events.is_error() || events.is_hup() {
+                    let err = match seed.1.take_socket_error() {
+                        Err(err) => {
+                            debug!("error while connecting: {:?}", err);
+                            err
+                        },
+                        Ok(()) => {
+                            trace!("connecting is_error, but no socket error");
+                            io::Error::new(io::ErrorKind::ConnectionAborted, "connection closed while connecting")
+                        }
+                    };
+                    ClientFsm::connect_error(seed, err, scope)
                 } else if events.is_writable() {
-                    if scope.queue.contains_key(&seed.0) {
+                    scope.win_race(seed.2);
+                    if let Some(race) = seed.2 {
+                        scope.leave_race(seed.2);
+                        scope.attempted(seed.0.clone(), race, Attempt::Connected);
+                    }
+                    let wanted = scope.queue.contains_key(&seed.0);
+                    scope.connect_done(&seed.0, wanted);
+                    scope.connected_in(attempting.since.elapsed());
+                    if wanted {
                         trace!("connected and writable {:?}", seed.0);
                         rotor::Response::ok(
                             ClientFsm::Socket(
@@ -500,6 +1418,7 @@ where C: Connect,
                                     scope.notifier(),
                                     scope.now()
                                 ).keep_alive(scope.keep_alive)
+                                .continue_timeout(scope.continue_timeout)
                             )
                         )
                     } else {
@@ -508,7 +1427,7 @@ where C: Connect,
                     }
                 } else {
                     // spurious?
-                    rotor::Response::ok(ClientFsm::Connecting(seed))
+                    ClientFsm::connecting(seed, attempting, scope)
                 }
             }
             ClientFsm::Connector(..) => {
@@ -531,43 +1450,24 @@ where C: Connect,
     ) -> rotor::Response<Self, Self::Seed> {
         // see if there's an idle connections that can be terminated. If yes, put this seed on a
         // list waiting for empty slot.
-        if let rotor::SpawnError::NoSlabSpace((key, socket)) = error {
+        if let rotor::SpawnError::NoSlabSpace((key, socket, race)) = error {
+            if let Some(id) = race {
+                // whatever happens to the socket, the connector shouldn't
+                // try other addresses for it
+                let others = scope.leave_race(race);
+                let err = io::Error::new(io::ErrorKind::Other, "no room for another socket");
+                scope.attempted(key.clone(), id, Attempt::Aborted(err));
+                if others {
+                    // the request is still connecting without this one
+                    trace!("dropping raced socket for {:?}, slab full", key);
+                    return self.connect(scope);
+                }
+            }
             if let Some(mut queued) = scope.pop_queue(&key) {
                 trace!("attempting to remove an idle socket");
-                // Remove an idle connection. Any connection. Just make some space
-                // for the new request.
-                let mut remove_keys = Vec::new();
-                let mut found_idle = false;
-
-                // Check all idle connections regardless of origin
-                for (key, idle) in scope.idle_conns.iter_mut() {
-                    // Pop from the front since those are lease recently used
-                    while let Some(ctrl) = idle.pop_front() {
-                        // Signal connection to close. An err here means the
-                        // socket is already dead can should be tossed.
-                        if ctrl.ready(Next::remove()).is_ok() {
-                            found_idle = true;
-                            break;
-                        }
-                    }
-
-                    // This list is empty, mark it for removal
-                    if idle.is_empty() {
-                        remove_keys.push(key.to_owned());
-                    }
-
-                    // if found, stop looking for an idle connection.
-                    if found_idle {
-                        break;
-                    }
-                }
-
-                trace!("idle conns: {:?}", scope.idle_conns);
-
-                // Remove empty idle lists.
-                for key in &remove_keys {
-                    scope.idle_conns.remove(&key);
-                }
+                // Remove the least recently used idle connection, of any
+                // host, to make space for the new request.
+                let found_idle = scope.evict_idle();
 
                 if found_idle {
                     // A socket should be evicted soon; put it on a queue to
@@ -581,8 +1481,11 @@ where C: Connect,
                 } else {
                     // Couldn't evict a socket, just run the error handler.
                     debug!("Error spawning state machine; slab full and no sockets idle");
+                    scope.connect_done(&key, false);
                     let _ = queued.handler.on_error(::Error::Full);
                 }
+            } else {
+                scope.connect_done(&key, false);
             }
         }
 
@@ -599,7 +1502,7 @@ where C: Connect,
                     for (key, mut vec) in &mut scope.queue {
                         while !vec.is_empty() && vec[0].deadline <= now {
                             vec.pop_front()
-                               .map(|mut queued| queued.handler.on_error(::Error::Timeout));
+                               .map(|mut queued| queued.handler.on_error(queued.cut.map_or(::Error::Timeout, Cut::error)));
                         }
                         if vec.is_empty() {
                             empty_keys.push(key.clone());
@@ -609,14 +1512,26 @@ where C: Connect,
                 for key in &empty_keys {
                     scope.queue.remove(key);
                 }
-                match self.deadline(scope) {
-                    Some(deadline) => {
-                        rotor::Response::ok(self).deadline(deadline)
-                    },
-                    None => rotor::Response::ok(self)
-                }
+                // delayed requests that are due get tried again
+                self.connect(scope)
             }
-            ClientFsm::Connecting(..) => unreachable!(),
+            ClientFsm::Connecting(seed, mut attempting) => {
+                let now = scope.now();
+                if scope.is_lost(seed.2) {
+                    ClientFsm::lost(seed, scope)
+                } else if now >= attempting.started + scope.connect_attempt_timeout {
+                    debug!("connecting timed out: {:?}", seed.0);
+                    let err = io::Error::new(io::ErrorKind::TimedOut, "connection attempt timed out");
+                    ClientFsm::connect_error(seed, err, scope)
+                } else {
+                    trace!("connecting is slow: {:?}", seed.0);
+                    if let Some(race) = seed.2 {
+                        scope.attempted(seed.0.clone(), race, Attempt::Slow);
+                    }
+                    attempting.raced = true;
+                    ClientFsm::connecting(seed, attempting, scope)
+                }
+            },
             ClientFsm::Socket(conn) => {
                 let res = conn.timeout(scope);
                 let now = scope.now();
@@ -635,7 +1550,14 @@ where C: Connect,
                 let now = scope.now();
                 conn_response!(scope, res, now)
             },
-            ClientFsm::Connecting(..) => unreachable!("connecting sockets should not be woken up")
+            ClientFsm::Connecting(seed, attempting) => {
+                // another socket of the race may have connected first
+                if scope.is_lost(seed.2) {
+                    ClientFsm::lost(seed, scope)
+                } else {
+                    ClientFsm::connecting(seed, attempting, scope)
+                }
+            }
         }
     }
 }
@@ -648,79 +1570,87 @@ where C: Connect,
     fn connect(self, scope: &mut rotor::Scope<<Self as rotor::Machine>::Context>) -> rotor::Response<Self, <Self as rotor::Machine>::Seed> {
         match self {
             ClientFsm::Connector(mut connector, rx) => {
+                let now = scope.now();
+                while let Some((key, race, attempt)) = scope.attempts.pop_front() {
+                    if let Some(e) = connector.attempted(&key, race, attempt) {
+                        debug!("connecting {:?} failed: {}", key, e);
+                        ClientFsm::connect_failed(scope, now, &key, e);
+                    }
+                    // a socket to another address, to race the one it
+                    // just heard about
+                    match connector.raced(&key, race) {
+                        Some(Ok(socket)) => {
+                            scope.join_race(Some(race));
+                            scope.racing.push_back((key, socket, Some(race)));
+                        },
+                        Some(Err(e)) => {
+                            // the connector still counts it as connecting
+                            scope.join_race(Some(race));
+                            scope.leave_race(Some(race));
+                            scope.attempted(key, race, Attempt::Failed(e));
+                        },
+                        None => ()
+                    }
+                }
+                if let Some(seed) = scope.racing.pop_front() {
+                    trace!("racing another socket for {:?}", seed.0);
+                    return rotor::Response::spawn(ClientFsm::Connector(connector, rx), seed);
+                }
+                // sockets of hosts at their limit went away, so requests
+                // waiting on them can connect
+                while let Some(key) = scope.unblocked.pop_front() {
+                    while !scope.is_full(&key) && scope.unblock(&key) {
+                        let url = match scope.queue.get(&key).and_then(|queue| queue.back()) {
+                            Some(queued) => queued.url.clone(),
+                            None => break
+                        };
+                        match connector.connect(&url) {
+                            Ok(key) => scope.dialed(&key),
+                            Err(e) => {
+                                debug!("connecting {:?} failed: {}", key, e);
+                                scope.pop_queue(&key).map(|mut queued| queued.handler.on_error(e.into()));
+                            }
+                        }
+                    }
+                }
                 if let Some((key, res)) = connector.connected() {
+                    scope.resolved(&key);
                     match res {
                         Ok(socket) => {
                             trace!("connecting {:?}", key);
-                            return rotor::Response::spawn(ClientFsm::Connector(connector, rx), (key, socket));
+                            let race = scope.join_race(None);
+                            connector.joined(&key, race);
+                            return rotor::Response::spawn(ClientFsm::Connector(connector, rx), (key, socket, Some(race)));
                         },
                         Err(e) => {
                             trace!("connect error = {:?}", e);
-                            scope.pop_queue(&key).map(|mut queued| queued.handler.on_error(::Error::Io(e)));
+                            ClientFsm::connect_failed(scope, now, &key, e);
                         }
                     }
                 }
                 loop {
-                    match rx.try_recv() {
-                        Ok(Notify::Connect(url, mut handler)) => {
-                            // check pool for sockets to this domain
-                            if let Some(key) = connector.key(&url) {
-                                let mut remove_idle = false;
-                                let mut woke_up = false;
-                                if let Some(mut idle) = scope.idle_conns.get_mut(&key) {
-                                    // Pop from back since those are most recently used. Connections
-                                    // at the front are allowed to expire.
-                                    while let Some(ctrl) = idle.pop_back() {
-                                        // err means the socket has since died
-                                        if ctrl.ready(Next::write()).is_ok() {
-                                            woke_up = true;
-                                            break;
-                                        }
-                                    }
-                                    remove_idle = idle.is_empty();
-                                }
-                                if remove_idle {
-                                    scope.idle_conns.remove(&key);
-                                }
-
-                                if woke_up {
-                                    trace!("woke up idle conn for '{}'", url);
-                                    let deadline = scope.now() + scope.connect_timeout;
-                                    scope.queue
-                                        .entry(key)
-                                        .or_insert_with(VecDeque::new)
-                                        .push_back(Queued {
-                                            deadline: deadline,
-                                            handler: handler,
-                                            url: url
-                                        });
-                                    continue;
-                                }
-                            } else {
-                                // this connector cannot handle this url anyways
-                                let _ = handler.on_error(io::Error::new(io::ErrorKind::InvalidInput, "invalid url for connector").into());
+                    let notify = match scope.take_due(now) {
+                        Some((url, handler, retry)) => Ok(Notify::Retry(url, handler, retry)),
+                        None => rx.try_recv()
+                    };
+                    let (url, mut handler, redirects, limits, tries, fresh) = match notify {
+                        Ok(Notify::Connect(url, handler, limits)) => {
+                            (url, handler, Redirects::default(), limits, Tries::default(), false)
+                        },
+                        Ok(Notify::Redirect(url, handler, redirects, limits)) => {
+                            (url, handler, redirects, limits, Tries::default(), false)
+                        },
+                        Ok(Notify::Retry(url, handler, retry)) => {
+                            if retry.delay > Duration::from_secs(0) {
+                                scope.delay_retry(now, url, handler, retry);
                                 continue;
                             }
-                            // no exist connection, call connector
-                            match connector.connect(&url) {
-                                Ok(key) => {
-                                    let deadline = scope.now() + scope.connect_timeout;
-                                    scope.queue
-                                        .entry(key)
-                                        .or_insert_with(VecDeque::new)
-                                        .push_back(Queued {
-                                            deadline: deadline,
-                                            handler: handler,
-                                            url: url
-                                        });
-                                }
-                                Err(e) => {
-                                    let _todo = handler.on_error(e.into());
-                                    trace!("Connect error, next={:?}", _todo);
-                                    continue;
-                                }
-                            }
-                        }
+                            (url, handler, retry.redirects, retry.limits, retry.tries, retry.fresh)
+                        },
+                        Ok(Notify::Stats(tx)) => {
+                            let _ = tx.send(scope.stats());
+                            continue;
+                        },
                         Ok(Notify::Shutdown) => {
                             scope.shutdown_loop();
                             return rotor::Response::done()
@@ -742,6 +1672,71 @@ where C: Connect,
                                 None => rotor::Response::ok(fsm)
                             };
                         }
+                    };
+                    // check pool for sockets to this domain
+                    if let Some(key) = connector.key(&url) {
+                        let mut remove_idle = false;
+                        // a multiplexed connection takes this as a new
+                        // stream, once the server allows another one
+                        let mut woke_up = !fresh && scope.multiplexed.get(&key)
+                            .map_or(false, |&(_, ref ctrl)| ctrl.ready(Next::write()).is_ok());
+                        if !woke_up && !fresh {
+                            // err means the socket has since died
+                            scope.multiplexed.remove(&key);
+                            if let Some(mut idle) = scope.idle_conns.get_mut(&key) {
+                                // Pop from back since those are most recently used. Connections
+                                // at the front are allowed to expire.
+                                while let Some(conn) = idle.pop_back() {
+                                    // err means the socket has since died
+                                    if conn.ctrl.ready(Next::write()).is_ok() {
+                                        woke_up = true;
+                                        break;
+                                    }
+                                }
+                                remove_idle = idle.is_empty();
+                            }
+                        }
+                        if remove_idle {
+                            scope.idle_conns.remove(&key);
+                        }
+
+                        let full = !woke_up && scope.is_full(&key);
+                        if woke_up || full {
+                            if woke_up {
+                                trace!("woke up idle conn for '{}'", url);
+                            } else {
+                                trace!("{:?} has max_sockets_per_host, '{}' waits", key, url);
+                                scope.host(&key).blocked += 1;
+                            }
+                            let queued = Queued::new(scope.now(), scope.connect_timeout, handler,
+                                                     connector.forwarded(&url), url, redirects, limits, tries);
+                            scope.queue
+                                .entry(key)
+                                .or_insert_with(VecDeque::new)
+                                .push_back(queued);
+                            continue;
+                        }
+                    } else {
+                        // this connector cannot handle this url anyways
+                        let _ = handler.on_error(io::Error::new(io::ErrorKind::InvalidInput, "invalid url for connector").into());
+                        continue;
+                    }
+                    // no exist connection, call connector
+                    match connector.connect(&url) {
+                        Ok(key) => {
+                            scope.dialed(&key);
+                            let queued = Queued::new(scope.now(), scope.connect_timeout, handler,
+                                                     connector.forwarded(&url), url, redirects, limits, tries);
+                            scope.queue
+                                .entry(key)
+                                .or_insert_with(VecDeque::new)
+                                .push_back(queued);
+                        }
+                        Err(e) => {
+                            let _todo = handler.on_error(e.into());
+                            trace!("Connect error, next={:?}", _todo);
+                            continue;
+                        }
                     }
                 }
             },
@@ -749,6 +1744,65 @@ where C: Connect,
         }
     }
 
+    /// Tries a request that couldn't connect again, if the `RetryPolicy`
+    /// allows it, or tells its handler.
+    fn connect_failed(scope: &mut rotor::Scope<<Self as rotor::Machine>::Context>, now: rotor::Time, key: &C::Key, err: io::Error) {
+        scope.connect_done(key, false);
+        if let Some(mut queued) = scope.pop_queue(key) {
+            match scope.retry_policy.delay(&queued.tries) {
+                Some(delay) => {
+                    debug!("connecting for {} failed, trying again in {:?}: {}", queued.url, delay, err);
+                    let mut tries = queued.tries;
+                    tries.retries += 1;
+                    scope.delay_retry(now, queued.url, queued.handler, Retry {
+                        redirects: queued.redirects,
+                        limits: queued.limits,
+                        tries: tries,
+                        delay: delay,
+                        fresh: true,
+                    });
+                },
+                None => {
+                    let _ = queued.handler.on_error(::Error::Io(err));
+                }
+            }
+        }
+    }
+
+    /// Closes a socket that lost its race to another one.
+    fn lost(seed: <Self as rotor::Machine>::Seed, scope: &mut rotor::Scope<<Self as rotor::Machine>::Context>) -> rotor::Response<Self, <Self as rotor::Machine>::Seed> {
+        trace!("closing socket that lost the race for {:?}", seed.0);
+        scope.leave_race(seed.2);
+        if let Some(race) = seed.2 {
+            scope.attempted(seed.0, race, Attempt::Cancelled);
+        }
+        rotor::Response::done()
+    }
+
+    fn connect_error(seed: <Self as rotor::Machine>::Seed, err: io::Error, scope: &mut rotor::Scope<<Self as rotor::Machine>::Context>) -> rotor::Response<Self, <Self as rotor::Machine>::Seed> {
+        if let Some(race) = seed.2 {
+            // the connector may still have other addresses to try
+            scope.leave_race(seed.2);
+            scope.attempted(seed.0, race, Attempt::Failed(err));
+        } else {
+            let now = scope.now();
+            ClientFsm::connect_failed(scope, now, &seed.0, err);
+        }
+        rotor::Response::done()
+    }
+
+    /// Waits for a socket to connect, until the connector should hear it is
+    /// slow, or until it times out.
+    fn connecting(seed: <Self as rotor::Machine>::Seed, attempting: Attempting, scope: &<Self as rotor::Machine>::Context) -> rotor::Response<Self, <Self as rotor::Machine>::Seed> {
+        let wait = if attempting.raced || scope.connect_attempt_delay > scope.connect_attempt_timeout {
+            scope.connect_attempt_timeout
+        } else {
+            scope.connect_attempt_delay
+        };
+        rotor::Response::ok(ClientFsm::Connecting(seed, attempting))
+            .deadline(attempting.started + wait)
+    }
+
     fn deadline(&self, scope: &mut rotor::Scope<<Self as rotor::Machine>::Context>) -> Option<rotor::Time> {
         match *self {
             ClientFsm::Connector(..) => {
@@ -765,6 +1819,11 @@ where C: Connect,
                         }
                     }
                 }
+                for &(due, _, _, _) in &scope.delayed {
+                    if earliest.map_or(true, |earliest| due < earliest) {
+                        earliest = Some(due);
+                    }
+                }
                 trace!("deadline = {:?}, now = {:?}", earliest, scope.now());
                 earliest
             }
@@ -775,14 +1834,40 @@ where C: Connect,
 
 struct Queued<H> {
     deadline: rotor::Time,
+    /// Set if the `deadline` is the request's own, rather than the
+    /// `connect_timeout`.
+    cut: Option<Cut>,
     handler: H,
+    forwarded: Option<Headers>,
     url: Url,
+    redirects: Redirects,
+    limits: Limits,
+    tries: Tries,
+}
+
+impl<H> Queued<H> {
+    fn new(now: rotor::Time, connect_timeout: Duration, handler: H, forwarded: Option<Headers>,
+           url: Url, redirects: Redirects, limits: Limits, tries: Tries) -> Queued<H> {
+        let (timeout, cut) = limits.shortest(Some(connect_timeout), Next_::Wait);
+        Queued {
+            deadline: now + timeout.unwrap_or(connect_timeout),
+            cut: cut,
+            handler: handler,
+            forwarded: forwarded,
+            url: url,
+            redirects: redirects,
+            limits: limits,
+            tries: tries,
+        }
+    }
 }
 
 #[doc(hidden)]
 #[allow(missing_debug_implementations)]
 pub struct Registration {
     notify: (http::channel::Sender<self::dns::Answer>, http::channel::Receiver<self::dns::Answer>),
+    /// Wakes up the connector, to take sockets from `Connect::connected`.
+    wakeup: rotor::Notifier,
 }
 
 #[cfg(test)]
@@ -0,0 +1,209 @@
+//! Following redirects for a `Client`.
+use std::fmt;
+use std::sync::Arc;
+
+use header::{Headers, Location, ContentLength, ContentType, TransferEncoding};
+use method::Method;
+use Url;
+
+/// Decides which redirect responses a `Client` follows by itself.
+///
+/// A followed redirect is never seen by the `Handler`. Its body is read and
+/// thrown away, and the request is made again to the `Location`, calling
+/// `Handler::on_request` again. `301`, `302` and `303` responses to a `POST`
+/// change it to a `GET` without a body, as do `303` responses to anything
+/// but `HEAD`. `307` and `308` responses keep the method, so the `Handler`
+/// writes its body again.
+///
+/// `Authorization` and `Cookie` headers are removed from requests to
+/// another origin than the one originally asked for.
+///
+/// # Example
+///
+/// ```
+/// use hyper::client::RedirectPolicy;
+///
+/// let policy = RedirectPolicy::limited(5).same_origin();
+/// ```
+#[derive(Clone)]
+pub struct RedirectPolicy {
+    max_redirects: usize,
+    same_origin: bool,
+    filter: Option<Arc<Fn(&Url, &Url) -> bool + Send + Sync>>,
+}
+
+impl RedirectPolicy {
+    /// Don't follow any redirects, handing them to the `Handler` instead.
+    ///
+    /// This is the default.
+    pub fn none() -> RedirectPolicy {
+        RedirectPolicy::limited(0)
+    }
+
+    /// Follow up to `max` redirects in a row. The response after that is
+    /// handed to the `Handler`, even if it is another redirect.
+    pub fn limited(max: usize) -> RedirectPolicy {
+        RedirectPolicy {
+            max_redirects: max,
+            same_origin: false,
+            filter: None,
+        }
+    }
+
+    /// Only follow redirects to the origin of the URL first requested.
+    pub fn same_origin(mut self) -> RedirectPolicy {
+        self.same_origin = true;
+        self
+    }
+
+    /// Only follow redirects that `predicate` allows. It is given the URL
+    /// redirected from, and the URL redirected to.
+    pub fn filter<F>(mut self, predicate: F) -> RedirectPolicy
+    where F: Fn(&Url, &Url) -> bool + Send + Sync + 'static {
+        self.filter = Some(Arc::new(predicate));
+        self
+    }
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> RedirectPolicy {
+        RedirectPolicy::none()
+    }
+}
+
+impl fmt::Debug for RedirectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RedirectPolicy")
+            .field("max_redirects", &self.max_redirects)
+            .field("same_origin", &self.same_origin)
+            .field("filter", &self.filter.is_some())
+            .finish()
+    }
+}
+
+/// The redirects a request has followed so far.
+#[derive(Debug, Clone, Default)]
+pub struct Redirects {
+    /// The URLs redirected from, first requested first.
+    pub previous: Vec<Url>,
+    /// Whether the request has become a `GET` without a body.
+    pub to_get: bool,
+}
+
+/// The URL to follow a response to, if it is a redirect the policy allows.
+pub fn location(policy: &RedirectPolicy, status: u16, headers: &Headers, url: &Url, previous: &[Url]) -> Option<Url> {
+    match status {
+        301 | 302 | 303 | 307 | 308 => (),
+        _ => return None
+    }
+    if previous.len() >= policy.max_redirects {
+        if policy.max_redirects > 0 {
+            debug!("not following more than {} redirects", policy.max_redirects);
+        }
+        return None;
+    }
+    let next = match headers.get::<Location>().and_then(|location| url.join(location).ok()) {
+        Some(next) => next,
+        None => {
+            debug!("redirect without a valid Location: {:?}", headers.get_raw("Location"));
+            return None;
+        }
+    };
+    if next.scheme() != "http" && next.scheme() != "https" {
+        debug!("not following redirect to {}", next);
+        return None;
+    }
+    if policy.same_origin && next.origin() != previous.first().unwrap_or(url).origin() {
+        debug!("not following redirect to another origin: {}", next);
+        return None;
+    }
+    if let Some(ref filter) = policy.filter {
+        if !filter(url, &next) {
+            debug!("redirect filter rejected {}", next);
+            return None;
+        }
+    }
+    Some(next)
+}
+
+/// Whether following a redirect changes the request to a `GET`.
+pub fn changes_to_get(status: u16, method: &Method) -> bool {
+    match status {
+        301 | 302 => *method == Method::Post,
+        303 => *method != Method::Head,
+        _ => false
+    }
+}
+
+/// Changes a request's headers to match it becoming a `GET` without a body.
+pub fn remove_body_headers(headers: &mut Headers) {
+    headers.remove::<ContentLength>();
+    headers.remove::<ContentType>();
+    headers.remove::<TransferEncoding>();
+}
+
+/// Removes credentials that were meant for another origin.
+pub fn remove_sensitive_headers(headers: &mut Headers) {
+    // by name, whatever the scheme
+    headers.remove_raw("Authorization");
+    headers.remove_raw("Cookie");
+}
+
+#[cfg(test)]
+mod tests {
+    use header::{Headers, Location};
+    use method::Method;
+    use Url;
+    use super::{RedirectPolicy, location, changes_to_get};
+
+    fn redirect(loc: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.set(Location(loc.to_owned()));
+        headers
+    }
+
+    #[test]
+    fn test_location() {
+        let url = Url::parse("http://hyper.rs/a/b").unwrap();
+        let policy = RedirectPolicy::limited(2);
+
+        assert_eq!(location(&policy, 302, &redirect("c"), &url, &[]),
+                   Some(Url::parse("http://hyper.rs/a/c").unwrap()));
+        assert_eq!(location(&policy, 308, &redirect("https://example.domain/"), &url, &[]),
+                   Some(Url::parse("https://example.domain/").unwrap()));
+        assert_eq!(location(&policy, 200, &redirect("c"), &url, &[]), None);
+        assert_eq!(location(&policy, 304, &redirect("c"), &url, &[]), None);
+        assert_eq!(location(&policy, 302, &Headers::new(), &url, &[]), None);
+        assert_eq!(location(&policy, 302, &redirect("ftp://hyper.rs/"), &url, &[]), None);
+
+        let previous = [url.clone(), url.clone()];
+        assert_eq!(location(&policy, 302, &redirect("c"), &url, &previous), None);
+        assert_eq!(location(&RedirectPolicy::none(), 302, &redirect("c"), &url, &[]), None);
+    }
+
+    #[test]
+    fn test_location_same_origin() {
+        let first = Url::parse("http://hyper.rs/").unwrap();
+        let url = Url::parse("http://hyper.rs/a").unwrap();
+        let policy = RedirectPolicy::limited(5).same_origin();
+
+        assert!(location(&policy, 301, &redirect("/b"), &url, &[first.clone()]).is_some());
+        assert!(location(&policy, 301, &redirect("https://hyper.rs/b"), &url, &[first.clone()]).is_none());
+        assert!(location(&policy, 301, &redirect("http://hyper.rs:8080/b"), &url, &[]).is_none());
+
+        let policy = RedirectPolicy::limited(5).filter(|_, to| to.path() != "/forbidden");
+        assert!(location(&policy, 307, &redirect("/allowed"), &url, &[]).is_some());
+        assert!(location(&policy, 307, &redirect("/forbidden"), &url, &[]).is_none());
+    }
+
+    #[test]
+    fn test_changes_to_get() {
+        assert!(changes_to_get(301, &Method::Post));
+        assert!(changes_to_get(302, &Method::Post));
+        assert!(!changes_to_get(302, &Method::Put));
+        assert!(changes_to_get(303, &Method::Put));
+        assert!(!changes_to_get(303, &Method::Head));
+        assert!(!changes_to_get(307, &Method::Post));
+        assert!(!changes_to_get(308, &Method::Post));
+    }
+}
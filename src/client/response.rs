@@ -4,8 +4,9 @@ use header;
 use http::{self, RawStatus};
 use status;
 use version;
+use Url;
 
-pub fn new(incoming: http::ResponseHead) -> Response {
+pub fn new(incoming: http::ResponseHead, url: Url) -> Response {
     trace!("Response::new");
     let status = status::StatusCode::from_u16(incoming.subject.0);
     debug!("version={:?}, status={:?}", incoming.version, status);
@@ -16,6 +17,7 @@ pub fn new(incoming: http::ResponseHead) -> Response {
         version: incoming.version,
         headers: incoming.headers,
         status_raw: incoming.subject,
+        url: url,
     }
 
 }
@@ -27,6 +29,7 @@ pub struct Response {
     headers: header::Headers,
     version: version::HttpVersion,
     status_raw: RawStatus,
+    url: Url,
 }
 
 impl Response {
@@ -42,9 +45,10 @@ impl Response {
     #[inline]
     pub fn status_raw(&self) -> &RawStatus { &self.status_raw }
 
-    /// Get the final URL of this response.
+    /// Get the final URL of this response, after any redirects the
+    /// `RedirectPolicy` followed.
     #[inline]
-    //pub fn url(&self) -> &Url { &self.url }
+    pub fn url(&self) -> &Url { &self.url }
 
     /// Get the HTTP version of this response from the server.
     #[inline]
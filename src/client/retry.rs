@@ -0,0 +1,185 @@
+//! Trying requests again for a `Client`.
+use std::cmp;
+use std::io;
+use std::time::Duration;
+
+use header::{Headers, RetryAfter};
+use method::Method;
+use time;
+
+/// Decides how often a `Client` tries a request again by itself, and how
+/// long it waits in between.
+///
+/// A request is tried again when connecting for it fails, and when the
+/// server answers an idempotent request with `503 Service Unavailable`.
+/// The `Handler` sees neither, and `Handler::on_request` occurs again for
+/// the next try. Waits start at the `backoff`, and double each time up to
+/// the `max_backoff`. A `503` response waits as long as its `Retry-After`
+/// asks instead, unless that is longer than the `max_backoff`, in which
+/// case the response is handed to the `Handler`.
+///
+/// Whatever the policy, an idempotent request on a pooled connection that
+/// the server closed before responding is tried once more on a new one.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use hyper::client::RetryPolicy;
+///
+/// let policy = RetryPolicy::limited(3).backoff(Duration::from_millis(500));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Don't try requests again, handing failures to the `Handler` instead.
+    ///
+    /// This is the default.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::limited(0)
+    }
+
+    /// Try a request up to `max` more times.
+    pub fn limited(max: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: max,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets how long to wait before the first retry.
+    ///
+    /// Default is 100 milliseconds.
+    pub fn backoff(mut self, val: Duration) -> RetryPolicy {
+        self.backoff = val;
+        self
+    }
+
+    /// Sets the longest wait before a retry.
+    ///
+    /// Default is 10 seconds.
+    pub fn max_backoff(mut self, val: Duration) -> RetryPolicy {
+        self.max_backoff = val;
+        self
+    }
+
+    /// How long to wait before trying again, if the policy allows it.
+    pub fn delay(&self, tries: &Tries) -> Option<Duration> {
+        if tries.retries >= self.max_retries {
+            return None;
+        }
+        let mut delay = self.backoff;
+        for _ in 0..tries.retries {
+            if delay >= self.max_backoff {
+                break;
+            }
+            delay = delay * 2;
+        }
+        Some(cmp::min(delay, self.max_backoff))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::none()
+    }
+}
+
+/// The tries of a request so far.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tries {
+    /// How many retries of the policy were used.
+    pub retries: u32,
+    /// Whether it was tried again after a pooled connection was closed.
+    pub stale: bool,
+}
+
+/// How long to wait before trying again, if the response is a `503` that
+/// the policy retries.
+pub fn unavailable(policy: &RetryPolicy, tries: &Tries, status: u16, method: &Method, headers: &Headers) -> Option<Duration> {
+    if status != 503 || !is_idempotent(method) {
+        return None;
+    }
+    let delay = match policy.delay(tries) {
+        Some(delay) => delay,
+        None => return None
+    };
+    let delay = match headers.get::<RetryAfter>() {
+        Some(&RetryAfter::Delay(dur)) => dur,
+        Some(&RetryAfter::DateTime(date)) => {
+            let wait = (date.0.to_timespec() - time::now_utc().to_timespec()).num_milliseconds();
+            Duration::from_millis(cmp::max(wait, 0) as u64)
+        },
+        None => delay
+    };
+    if delay > policy.max_backoff {
+        debug!("not waiting {:?} to retry", delay);
+        return None;
+    }
+    Some(delay)
+}
+
+/// Whether a request with `method` can be sent again without changing more
+/// than sending it once would.
+pub fn is_idempotent(method: &Method) -> bool {
+    match *method {
+        Method::Get | Method::Head | Method::Put | Method::Delete |
+        Method::Options | Method::Trace => true,
+        _ => false
+    }
+}
+
+/// Whether an error is the connection having been closed.
+pub fn is_closed(err: &::Error) -> bool {
+    match *err {
+        ::Error::Io(ref e) => match e.kind() {
+            io::ErrorKind::UnexpectedEof |
+            io::ErrorKind::ConnectionReset |
+            io::ErrorKind::ConnectionAborted |
+            io::ErrorKind::BrokenPipe => true,
+            _ => false
+        },
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use header::{Headers, RetryAfter};
+    use method::Method;
+    use super::{RetryPolicy, Tries, unavailable};
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::limited(4).max_backoff(Duration::from_millis(300));
+        let delay = |retries| policy.delay(&Tries { retries: retries, stale: false });
+        assert_eq!(delay(0), Some(Duration::from_millis(100)));
+        assert_eq!(delay(1), Some(Duration::from_millis(200)));
+        assert_eq!(delay(2), Some(Duration::from_millis(300)));
+        assert_eq!(delay(4), None);
+        assert_eq!(RetryPolicy::none().delay(&Tries::default()), None);
+    }
+
+    #[test]
+    fn test_unavailable() {
+        let policy = RetryPolicy::limited(1);
+        let tries = Tries::default();
+        let mut headers = Headers::new();
+        assert_eq!(unavailable(&policy, &tries, 503, &Method::Get, &headers), Some(Duration::from_millis(100)));
+        assert_eq!(unavailable(&policy, &tries, 500, &Method::Get, &headers), None);
+        assert_eq!(unavailable(&policy, &tries, 503, &Method::Post, &headers), None);
+
+        headers.set(RetryAfter::Delay(Duration::from_secs(2)));
+        assert_eq!(unavailable(&policy, &tries, 503, &Method::Get, &headers), Some(Duration::from_secs(2)));
+        headers.set(RetryAfter::Delay(Duration::from_secs(60)));
+        assert_eq!(unavailable(&policy, &tries, 503, &Method::Get, &headers), None);
+    }
+}
@@ -0,0 +1,262 @@
+//! The SOCKS5 handshake, as in [RFC 1928](https://tools.ietf.org/html/rfc1928)
+//! and [RFC 1929](https://tools.ietf.org/html/rfc1929).
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use super::proxy::Step;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHODS: u8 = 0xFF;
+const CONNECT: u8 = 1;
+
+/// Where the proxy is asked to connect to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// A host name, resolved by the proxy.
+    Domain(String, u16),
+    /// An address, resolved by the client.
+    Addr(SocketAddr),
+}
+
+impl Target {
+    /// The target for `host:port`, as an address if `host` is one already.
+    pub fn new(host: &str, port: u16) -> Target {
+        let unbracketed = host.trim_left_matches('[').trim_right_matches(']');
+        match unbracketed.parse::<IpAddr>() {
+            Ok(ip) => Target::Addr(SocketAddr::new(ip, port)),
+            Err(_) => Target::Domain(host.to_owned(), port),
+        }
+    }
+}
+
+/// The client side of the handshake, after sending the greeting.
+#[derive(Debug)]
+pub struct Handshake {
+    target: Target,
+    auth: Option<(String, String)>,
+    state: State,
+    unreachable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Method,
+    Auth,
+    Connect,
+}
+
+/// Starts a handshake, returning the greeting to send the proxy.
+pub fn handshake(target: Target, auth: Option<(String, String)>) -> (Handshake, Vec<u8>) {
+    let greeting = if auth.is_some() {
+        vec![VERSION, 2, NO_AUTH, USERNAME_PASSWORD]
+    } else {
+        vec![VERSION, 1, NO_AUTH]
+    };
+    (Handshake {
+        target: target,
+        auth: auth,
+        state: State::Method,
+        unreachable: false,
+    }, greeting)
+}
+
+impl Handshake {
+    /// Reacts to the proxy's answer so far.
+    pub fn step(&mut self, buf: &[u8]) -> io::Result<Step> {
+        match self.state {
+            State::Method => {
+                if buf.len() < 2 {
+                    return Ok(Step::Read);
+                }
+                try!(exact(buf, 2));
+                if buf[0] != VERSION {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "not a SOCKS5 proxy"));
+                }
+                match buf[1] {
+                    NO_AUTH => {
+                        self.state = State::Connect;
+                        self.connect_request().map(Step::Write)
+                    },
+                    USERNAME_PASSWORD if self.auth.is_some() => {
+                        self.state = State::Auth;
+                        self.auth_request().map(Step::Write)
+                    },
+                    NO_ACCEPTABLE_METHODS => {
+                        Err(io::Error::new(io::ErrorKind::PermissionDenied, "SOCKS5 proxy requires authentication"))
+                    },
+                    method => {
+                        debug!("SOCKS5 proxy chose unoffered method {}", method);
+                        Err(io::Error::new(io::ErrorKind::InvalidData, "SOCKS5 proxy chose an unoffered method"))
+                    }
+                }
+            },
+            State::Auth => {
+                if buf.len() < 2 {
+                    return Ok(Step::Read);
+                }
+                try!(exact(buf, 2));
+                if buf[1] == 0 {
+                    self.state = State::Connect;
+                    self.connect_request().map(Step::Write)
+                } else {
+                    Err(io::Error::new(io::ErrorKind::PermissionDenied, "SOCKS5 authentication failed"))
+                }
+            },
+            State::Connect => {
+                if buf.len() < 5 {
+                    return Ok(Step::Read);
+                }
+                if buf[0] != VERSION {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "not a SOCKS5 proxy"));
+                }
+                if buf[1] != 0 {
+                    debug!("SOCKS5 connect failed: {}", buf[1]);
+                    // network or host unreachable, refused, or TTL expired
+                    self.unreachable = 3 <= buf[1] && buf[1] <= 6;
+                    return Err(reply_error(buf[1]));
+                }
+                // the address the proxy bound, which isn't needed
+                let len = match buf[3] {
+                    1 => 4 + 4 + 2,
+                    3 => 4 + 1 + buf[4] as usize + 2,
+                    4 => 4 + 16 + 2,
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid SOCKS5 address type"))
+                };
+                if buf.len() < len {
+                    return Ok(Step::Read);
+                }
+                try!(exact(buf, len));
+                Ok(Step::Done)
+            }
+        }
+    }
+
+    /// Whether the proxy answered that it couldn't reach the target, so
+    /// another address of the same host may do better.
+    pub fn unreachable(&self) -> bool {
+        self.unreachable
+    }
+
+    fn auth_request(&self) -> io::Result<Vec<u8>> {
+        let &(ref username, ref password) = self.auth.as_ref().expect("auth method needs credentials");
+        if username.len() > 255 || password.len() > 255 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "SOCKS5 username or password too long"));
+        }
+        let mut req = vec![1, username.len() as u8];
+        req.extend_from_slice(username.as_bytes());
+        req.push(password.len() as u8);
+        req.extend_from_slice(password.as_bytes());
+        Ok(req)
+    }
+
+    fn connect_request(&self) -> io::Result<Vec<u8>> {
+        let mut req = vec![VERSION, CONNECT, 0];
+        let port = match self.target {
+            Target::Domain(ref host, port) => {
+                if host.len() > 255 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "host name too long for SOCKS5"));
+                }
+                req.push(3);
+                req.push(host.len() as u8);
+                req.extend_from_slice(host.as_bytes());
+                port
+            },
+            Target::Addr(SocketAddr::V4(ref addr)) => {
+                req.push(1);
+                req.extend_from_slice(&addr.ip().octets());
+                addr.port()
+            },
+            Target::Addr(SocketAddr::V6(ref addr)) => {
+                req.push(4);
+                for segment in &addr.ip().segments() {
+                    req.push((segment >> 8) as u8);
+                    req.push(*segment as u8);
+                }
+                addr.port()
+            }
+        };
+        req.push((port >> 8) as u8);
+        req.push(port as u8);
+        Ok(req)
+    }
+}
+
+/// The proxy can't have sent more than its answer.
+fn exact(buf: &[u8], len: usize) -> io::Result<()> {
+    if buf.len() > len {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "SOCKS5 proxy sent data before the tunnel was used"))
+    } else {
+        Ok(())
+    }
+}
+
+fn reply_error(rep: u8) -> io::Error {
+    let (kind, msg) = match rep {
+        2 => (io::ErrorKind::PermissionDenied, "SOCKS5 connection not allowed by ruleset"),
+        3 => (io::ErrorKind::Other, "SOCKS5 network unreachable"),
+        4 => (io::ErrorKind::Other, "SOCKS5 host unreachable"),
+        5 => (io::ErrorKind::ConnectionRefused, "SOCKS5 connection refused"),
+        6 => (io::ErrorKind::TimedOut, "SOCKS5 TTL expired"),
+        7 => (io::ErrorKind::InvalidInput, "SOCKS5 command not supported"),
+        8 => (io::ErrorKind::InvalidInput, "SOCKS5 address type not supported"),
+        _ => (io::ErrorKind::Other, "SOCKS5 general server failure"),
+    };
+    io::Error::new(kind, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use client::proxy::Step;
+    use super::{Target, handshake};
+
+    #[test]
+    fn test_target() {
+        assert_eq!(Target::new("hyper.rs", 80), Target::Domain("hyper.rs".to_owned(), 80));
+        assert_eq!(Target::new("127.0.0.1", 80), Target::Addr("127.0.0.1:80".parse::<SocketAddr>().unwrap()));
+        assert_eq!(Target::new("[::1]", 443), Target::Addr("[::1]:443".parse::<SocketAddr>().unwrap()));
+    }
+
+    #[test]
+    fn test_handshake_no_auth() {
+        let (mut socks, greeting) = handshake(Target::new("hyper.rs", 80), None);
+        assert_eq!(greeting, vec![5, 1, 0]);
+        assert_eq!(socks.step(&[5]).unwrap(), Step::Read);
+        assert_eq!(socks.step(&[5, 0]).unwrap(),
+                   Step::Write(b"\x05\x01\x00\x03\x08hyper.rs\x00\x50".to_vec()));
+        assert_eq!(socks.step(&[5, 0, 0, 1, 127]).unwrap(), Step::Read);
+        assert_eq!(socks.step(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap(), Step::Done);
+    }
+
+    #[test]
+    fn test_handshake_auth() {
+        let target = Target::new("10.0.0.1", 443);
+        let (mut socks, greeting) = handshake(target, Some(("user".to_owned(), "pass".to_owned())));
+        assert_eq!(greeting, vec![5, 2, 0, 2]);
+        assert_eq!(socks.step(&[5, 2]).unwrap(), Step::Write(b"\x01\x04user\x04pass".to_vec()));
+        assert_eq!(socks.step(&[1, 0]).unwrap(), Step::Write(vec![5, 1, 0, 1, 10, 0, 0, 1, 1, 187]));
+
+        let (mut socks, _) = handshake(Target::new("hyper.rs", 80), Some(("user".to_owned(), "wrong".to_owned())));
+        socks.step(&[5, 2]).unwrap();
+        assert!(socks.step(&[1, 1]).is_err());
+    }
+
+    #[test]
+    fn test_handshake_refused() {
+        let (mut socks, _) = handshake(Target::new("hyper.rs", 80), None);
+        assert!(socks.step(&[5, 0xFF]).is_err());
+
+        let (mut socks, _) = handshake(Target::new("hyper.rs", 80), None);
+        socks.step(&[5, 0]).unwrap();
+        assert!(socks.step(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(socks.unreachable());
+
+        // the same for every address
+        let (mut socks, _) = handshake(Target::new("hyper.rs", 80), None);
+        socks.step(&[5, 0]).unwrap();
+        assert!(socks.step(&[5, 2, 0, 1, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(!socks.unreachable());
+    }
+}
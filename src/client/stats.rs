@@ -0,0 +1,171 @@
+//! Looking into the sockets and requests of a `Client`.
+use std::time::Duration;
+
+/// A snapshot of the sockets and requests of a `Client`, along with
+/// counters kept since it was built.
+///
+/// Get one with `Client::stats`.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    hosts: Vec<HostStats>,
+    awaiting_slot: usize,
+    delayed: usize,
+    counters: Counters,
+}
+
+impl Stats {
+    /// The sockets and queued requests of each `Connect::Key` that has any.
+    pub fn hosts(&self) -> &[HostStats] {
+        &self.hosts
+    }
+
+    /// The requests waiting for a socket, of every host.
+    pub fn queued(&self) -> usize {
+        self.hosts.iter().map(|host| host.queued).fold(0, |sum, queued| sum + queued)
+    }
+
+    /// The sockets that connected, but wait for an idle one to be closed
+    /// to make room in `max_sockets`.
+    pub fn awaiting_slot(&self) -> usize {
+        self.awaiting_slot
+    }
+
+    /// The requests waiting to be tried again by the `RetryPolicy`.
+    pub fn delayed(&self) -> usize {
+        self.delayed
+    }
+
+    /// How many requests were sent, including redirects and retries.
+    pub fn requests(&self) -> u64 {
+        self.counters.requests
+    }
+
+    /// How many requests were sent on a connection used before.
+    pub fn reused(&self) -> u64 {
+        self.counters.reused
+    }
+
+    /// How many idle sockets were closed to keep to `max_idle` or
+    /// `max_sockets`.
+    pub fn evictions(&self) -> u64 {
+        self.counters.evictions
+    }
+
+    /// How many sockets connected.
+    pub fn connects(&self) -> u64 {
+        self.counters.connects
+    }
+
+    /// The time the sockets in `connects` took to connect, all added up.
+    pub fn connect_time(&self) -> Duration {
+        self.counters.connect_time
+    }
+
+    /// How many times the `Connect` resolved a host, and handed out a
+    /// socket or an error for it.
+    pub fn resolves(&self) -> u64 {
+        self.counters.resolves
+    }
+
+    /// The time from asking the `Connect` for a socket until it handed one
+    /// out, for each of the `resolves`, all added up.
+    ///
+    /// For the `HttpConnector`, this is how long DNS lookups took.
+    pub fn resolve_time(&self) -> Duration {
+        self.counters.resolve_time
+    }
+}
+
+/// The sockets and queued requests of one `Connect::Key`.
+#[derive(Debug, Clone)]
+pub struct HostStats {
+    key: String,
+    active: usize,
+    idle: usize,
+    connecting: usize,
+    queued: usize,
+}
+
+impl HostStats {
+    /// The `Connect::Key`, as its `Debug` output.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The open sockets with requests on them.
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// The open sockets waiting in the pool for a request.
+    pub fn idle(&self) -> usize {
+        self.idle
+    }
+
+    /// The sockets still connecting, or resolving the host to connect.
+    pub fn connecting(&self) -> usize {
+        self.connecting
+    }
+
+    /// The requests waiting for a socket.
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+}
+
+/// The counters of a client loop, kept since it started.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counters {
+    pub requests: u64,
+    pub reused: u64,
+    pub evictions: u64,
+    pub connects: u64,
+    pub connect_time: Duration,
+    pub resolves: u64,
+    pub resolve_time: Duration,
+}
+
+/// Builds the `HostStats` of a key.
+pub fn host(key: String, open: usize, idle: usize, connecting: usize, queued: usize) -> HostStats {
+    HostStats {
+        key: key,
+        // multiplexed connections count as open, and are never idle
+        active: open.saturating_sub(idle),
+        idle: idle,
+        connecting: connecting,
+        queued: queued,
+    }
+}
+
+/// Builds a snapshot.
+pub fn new(hosts: Vec<HostStats>, awaiting_slot: usize, delayed: usize, counters: Counters) -> Stats {
+    Stats {
+        hosts: hosts,
+        awaiting_slot: awaiting_slot,
+        delayed: delayed,
+        counters: counters,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{Counters, host, new};
+
+    #[test]
+    fn test_stats() {
+        let mut counters = Counters::default();
+        counters.requests = 3;
+        counters.connect_time = Duration::from_millis(20);
+        let stats = new(vec![
+            host("a".to_owned(), 3, 1, 0, 0),
+            host("b".to_owned(), 1, 0, 1, 4),
+        ], 0, 1, counters);
+        assert_eq!(stats.hosts()[0].active(), 2);
+        assert_eq!(stats.hosts()[1].connecting(), 1);
+        assert_eq!(stats.queued(), 4);
+        assert_eq!(stats.delayed(), 1);
+        assert_eq!(stats.requests(), 3);
+        assert_eq!(stats.connect_time(), Duration::from_millis(20));
+    }
+}
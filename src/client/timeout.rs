@@ -0,0 +1,191 @@
+//! Timeouts of a single request, kept whatever its `Handler` returns.
+use std::time::{Duration, Instant};
+
+use http::{self, Next, Next_};
+
+/// Timeouts for a request, given to `Client::request_with_timeouts`.
+///
+/// Unlike a `Next::timeout`, these apply to every step of the request, and
+/// a handler can't forget or override them. When one runs out, the handler's
+/// `on_error` gets `Error::Deadline`, `Error::ReadTimeout` or
+/// `Error::WriteTimeout`, and the request is ended.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use hyper::client::Timeouts;
+///
+/// let timeouts = Timeouts::new()
+///     .deadline(Duration::from_secs(30))
+///     .read(Duration::from_secs(5));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timeouts {
+    deadline: Option<Duration>,
+    read: Option<Duration>,
+    write: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Timeouts that never run out.
+    pub fn new() -> Timeouts {
+        Timeouts::default()
+    }
+
+    /// Sets how long the whole request may take.
+    ///
+    /// This starts when the request is given to the `Client`, and includes
+    /// waiting for a connection, following redirects, and reading the
+    /// response to the end.
+    pub fn deadline(mut self, val: Duration) -> Timeouts {
+        self.deadline = Some(val);
+        self
+    }
+
+    /// Sets how long the request may wait to read anything.
+    ///
+    /// This starts over each time the handler is told the response is
+    /// readable.
+    pub fn read(mut self, val: Duration) -> Timeouts {
+        self.read = Some(val);
+        self
+    }
+
+    /// Sets how long the request may wait to write anything.
+    ///
+    /// This starts over each time the handler is told the request is
+    /// writable.
+    pub fn write(mut self, val: Duration) -> Timeouts {
+        self.write = Some(val);
+        self
+    }
+}
+
+/// The `Timeouts` of a request that has started.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    deadline: Option<Instant>,
+    read: Option<Duration>,
+    write: Option<Duration>,
+    /// Which limit the last `Next` was cut short to.
+    cut: Option<Cut>,
+}
+
+/// A limit that is shorter than the timeout a handler asked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cut {
+    Deadline,
+    Read,
+    Write,
+}
+
+impl Cut {
+    /// The error for running out of this limit.
+    pub fn error(self) -> ::Error {
+        match self {
+            Cut::Deadline => ::Error::Deadline,
+            Cut::Read => ::Error::ReadTimeout,
+            Cut::Write => ::Error::WriteTimeout,
+        }
+    }
+}
+
+impl Limits {
+    /// Starts the clock of the request's deadline.
+    pub fn start(timeouts: &Timeouts) -> Limits {
+        Limits {
+            deadline: timeouts.deadline.map(|dur| Instant::now() + dur),
+            read: timeouts.read,
+            write: timeouts.write,
+            cut: None,
+        }
+    }
+
+    /// How long until the deadline, or `None` if there isn't one.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| {
+            let now = Instant::now();
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    /// The shortest of `timeout` and the limits for `interest`, and which
+    /// limit that is, if it isn't `timeout`.
+    pub fn shortest(&self, timeout: Option<Duration>, interest: Next_) -> (Option<Duration>, Option<Cut>) {
+        let (read, write) = match interest {
+            Next_::Read => (self.read, None),
+            Next_::Write => (None, self.write),
+            Next_::ReadWrite => (self.read, self.write),
+            Next_::Wait => (None, None),
+            Next_::End | Next_::Remove => return (timeout, None),
+        };
+        let mut shortest = (timeout, None);
+        for &(limit, cut) in &[(read, Cut::Read), (write, Cut::Write), (self.remaining(), Cut::Deadline)] {
+            if let Some(limit) = limit {
+                if shortest.0.map_or(true, |dur| limit < dur) {
+                    shortest = (Some(limit), Some(cut));
+                }
+            }
+        }
+        shortest
+    }
+
+    /// Cuts the timeout of `next` short to the limits of the request.
+    pub fn apply(&mut self, next: Next) -> Next {
+        let (interest, timeout) = http::interest(&next);
+        let (timeout, cut) = self.shortest(timeout, interest);
+        self.cut = cut;
+        match (cut, timeout) {
+            (Some(_), Some(dur)) => next.timeout(dur),
+            _ => next
+        }
+    }
+
+    /// The limit that the last `Next` timed out on, if it wasn't the
+    /// handler's own timeout.
+    pub fn timed_out(&self) -> Option<Cut> {
+        self.cut
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use http::{self, Next, Next_};
+    use super::{Timeouts, Limits, Cut};
+
+    #[test]
+    fn test_limits_shortest() {
+        let limits = Limits::start(&Timeouts::new()
+            .read(Duration::from_secs(5))
+            .write(Duration::from_secs(3)));
+        let secs = |n| Some(Duration::from_secs(n));
+        assert_eq!(limits.shortest(None, Next_::Read), (secs(5), Some(Cut::Read)));
+        assert_eq!(limits.shortest(secs(1), Next_::Read), (secs(1), None));
+        assert_eq!(limits.shortest(None, Next_::ReadWrite), (secs(3), Some(Cut::Write)));
+        assert_eq!(limits.shortest(None, Next_::Wait), (None, None));
+        assert_eq!(limits.shortest(None, Next_::End), (None, None));
+
+        let limits = Limits::start(&Timeouts::new().deadline(Duration::from_secs(2)));
+        let (timeout, cut) = limits.shortest(secs(10), Next_::Wait);
+        assert!(timeout.unwrap() <= Duration::from_secs(2));
+        assert_eq!(cut, Some(Cut::Deadline));
+    }
+
+    #[test]
+    fn test_limits_apply() {
+        let mut limits = Limits::start(&Timeouts::new().read(Duration::from_secs(5)));
+        let next = limits.apply(Next::read());
+        assert_eq!(http::interest(&next).1, Some(Duration::from_secs(5)));
+        assert_eq!(limits.timed_out(), Some(Cut::Read));
+
+        let next = limits.apply(Next::write().timeout(Duration::from_secs(1)));
+        assert_eq!(http::interest(&next).1, Some(Duration::from_secs(1)));
+        assert_eq!(limits.timed_out(), None);
+    }
+}
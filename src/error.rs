@@ -41,7 +41,7 @@ pub enum Error {
     Version,
     /// An invalid `Header`.
     Header,
-    /// A message head is too large to be reasonable.
+    /// A message head, or the body it declares, is too large to be reasonable.
     TooLarge,
     /// A message reached EOF, but is not complete.
     Incomplete,
@@ -49,6 +49,12 @@ pub enum Error {
     Status,
     /// A timeout occurred waiting for an IO event.
     Timeout,
+    /// A client request wasn't done by its deadline.
+    Deadline,
+    /// A client request couldn't read anything within its read timeout.
+    ReadTimeout,
+    /// A client request couldn't write anything within its write timeout.
+    WriteTimeout,
     /// Event loop is full and cannot process request
     Full,
     /// An `io::Error` that occurred while trying to read or write to a network stream.
@@ -89,10 +95,13 @@ impl StdError for Error {
             Method => "Invalid Method specified",
             Version => "Invalid HTTP version specified",
             Header => "Invalid Header provided",
-            TooLarge => "Message head is too large",
+            TooLarge => "Message is too large",
             Status => "Invalid Status provided",
             Incomplete => "Message is incomplete",
             Timeout => "Timeout",
+            Error::Deadline => "Request deadline elapsed",
+            Error::ReadTimeout => "Read timeout",
+            Error::WriteTimeout => "Write timeout",
             Error::Full => "Event loop is full",
             Uri(ref e) => e.description(),
             Io(ref e) => e.description(),
@@ -42,18 +42,26 @@ pub use self::if_modified_since::IfModifiedSince;
 pub use self::if_none_match::IfNoneMatch;
 pub use self::if_unmodified_since::IfUnmodifiedSince;
 pub use self::if_range::IfRange;
+pub use self::last_event_id::LastEventID;
 pub use self::last_modified::LastModified;
 pub use self::location::Location;
 pub use self::origin::Origin;
 pub use self::pragma::Pragma;
 pub use self::prefer::{Prefer, Preference};
 pub use self::preference_applied::PreferenceApplied;
+pub use self::proxy_authorization::ProxyAuthorization;
 pub use self::range::{Range, ByteRangeSpec};
 pub use self::referer::Referer;
 pub use self::referrer_policy::ReferrerPolicy;
+pub use self::retry_after::RetryAfter;
+pub use self::sec_websocket_accept::SecWebSocketAccept;
+pub use self::sec_websocket_key::SecWebSocketKey;
+pub use self::sec_websocket_protocol::SecWebSocketProtocol;
+pub use self::sec_websocket_version::SecWebSocketVersion;
 pub use self::server::Server;
 pub use self::set_cookie::SetCookie;
 pub use self::strict_transport_security::StrictTransportSecurity;
+pub use self::te::Te;
 pub use self::transfer_encoding::TransferEncoding;
 pub use self::upgrade::{Upgrade, Protocol, ProtocolName};
 pub use self::user_agent::UserAgent;
@@ -405,18 +413,26 @@ mod if_modified_since;
 mod if_none_match;
 mod if_range;
 mod if_unmodified_since;
+mod last_event_id;
 mod last_modified;
 mod location;
 mod origin;
 mod pragma;
 mod prefer;
 mod preference_applied;
+mod proxy_authorization;
 mod range;
 mod referer;
 mod referrer_policy;
+mod retry_after;
+mod sec_websocket_accept;
+mod sec_websocket_key;
+mod sec_websocket_protocol;
+mod sec_websocket_version;
 mod server;
 mod set_cookie;
 mod strict_transport_security;
+mod te;
 mod transfer_encoding;
 mod upgrade;
 mod user_agent;
@@ -0,0 +1,77 @@
+use std::fmt;
+use std::time::Duration;
+
+use header::{Header, HttpDate, Raw, parsing};
+
+/// `Retry-After` header, defined in [RFC7231](http://tools.ietf.org/html/rfc7231#section-7.1.3)
+///
+/// The `Retry-After` header field indicates how long the user agent ought
+/// to wait before making a follow-up request. When sent with a `503
+/// (Service Unavailable)` response, it indicates how long the service is
+/// expected to be unavailable to the client.
+///
+/// # ABNF
+/// ```plain
+/// Retry-After = HTTP-date / delay-seconds
+/// delay-seconds = 1*DIGIT
+/// ```
+///
+/// # Example values
+/// * `Fri, 31 Dec 1999 23:59:59 GMT`
+/// * `120`
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use hyper::header::{Headers, RetryAfter};
+///
+/// let mut headers = Headers::new();
+/// headers.set(RetryAfter::Delay(Duration::from_secs(120)));
+/// ```
+#[derive(Clone, PartialEq, Debug)]
+pub enum RetryAfter {
+    /// How long to wait, in whole seconds.
+    Delay(Duration),
+    /// When to try again.
+    DateTime(HttpDate),
+}
+
+impl Header for RetryAfter {
+    fn header_name() -> &'static str {
+        static NAME: &'static str = "Retry-After";
+        NAME
+    }
+
+    fn parse_header(raw: &Raw) -> ::Result<RetryAfter> {
+        let value: String = try!(parsing::from_one_raw_str(raw));
+        let value = value.trim();
+        match value.parse::<u64>() {
+            Ok(secs) => Ok(RetryAfter::Delay(Duration::from_secs(secs))),
+            Err(_) => value.parse().map(RetryAfter::DateTime),
+        }
+    }
+
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RetryAfter::Delay(ref dur) => fmt::Display::fmt(&dur.as_secs(), f),
+            RetryAfter::DateTime(ref date) => fmt::Display::fmt(date, f),
+        }
+    }
+}
+
+#[test]
+fn test_parse_delay() {
+    let a: RetryAfter = Header::parse_header(&"120".into()).unwrap();
+    assert_eq!(a, RetryAfter::Delay(Duration::from_secs(120)));
+    let e: ::Result<RetryAfter> = Header::parse_header(&"-1".into());
+    assert!(e.is_err());
+}
+
+#[test]
+fn test_parse_date() {
+    let a: RetryAfter = Header::parse_header(&"Fri, 31 Dec 1999 23:59:59 GMT".into()).unwrap();
+    match a {
+        RetryAfter::DateTime(date) => assert_eq!(date.0.tm_year, 99),
+        other => panic!("expected a date, actual: {:?}", other),
+    }
+}
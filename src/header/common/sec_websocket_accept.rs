@@ -0,0 +1,28 @@
+header! {
+    /// `Sec-WebSocket-Accept` header, defined in
+    /// [RFC6455](https://tools.ietf.org/html/rfc6455#section-11.3.3)
+    ///
+    /// The `Sec-WebSocket-Accept` header is sent by a server accepting a
+    /// WebSocket. It is derived from the `Sec-WebSocket-Key` of the request.
+    ///
+    /// # ABNF
+    /// ```plain
+    /// Sec-WebSocket-Accept = base64-value-non-empty
+    /// ```
+    ///
+    /// # Example values
+    /// * `s3pPLMBiTxaQ9kYGzzhZRbK+xOo=`
+    ///
+    /// # Examples
+    /// ```
+    /// use hyper::header::{Headers, SecWebSocketAccept};
+    ///
+    /// let mut headers = Headers::new();
+    /// headers.set(SecWebSocketAccept("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_owned()));
+    /// ```
+    (SecWebSocketAccept, "Sec-WebSocket-Accept") => [String]
+
+    test_sec_websocket_accept {
+        test_header!(test1, vec![b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo="]);
+    }
+}
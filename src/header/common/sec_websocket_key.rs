@@ -0,0 +1,29 @@
+header! {
+    /// `Sec-WebSocket-Key` header, defined in
+    /// [RFC6455](https://tools.ietf.org/html/rfc6455#section-11.3.1)
+    ///
+    /// The `Sec-WebSocket-Key` header is sent by a client opening a
+    /// WebSocket. It holds a base64-encoded random 16 byte nonce, which the
+    /// server proves to have read with `Sec-WebSocket-Accept`.
+    ///
+    /// # ABNF
+    /// ```plain
+    /// Sec-WebSocket-Key = base64-value-non-empty
+    /// ```
+    ///
+    /// # Example values
+    /// * `dGhlIHNhbXBsZSBub25jZQ==`
+    ///
+    /// # Examples
+    /// ```
+    /// use hyper::header::{Headers, SecWebSocketKey};
+    ///
+    /// let mut headers = Headers::new();
+    /// headers.set(SecWebSocketKey("dGhlIHNhbXBsZSBub25jZQ==".to_owned()));
+    /// ```
+    (SecWebSocketKey, "Sec-WebSocket-Key") => [String]
+
+    test_sec_websocket_key {
+        test_header!(test1, vec![b"dGhlIHNhbXBsZSBub25jZQ=="]);
+    }
+}
@@ -0,0 +1,30 @@
+header! {
+    /// `Sec-WebSocket-Protocol` header, defined in
+    /// [RFC6455](https://tools.ietf.org/html/rfc6455#section-11.3.4)
+    ///
+    /// The `Sec-WebSocket-Protocol` header lists the subprotocols a client
+    /// opening a WebSocket can speak, in order of preference. The server
+    /// answers with the one it chose.
+    ///
+    /// # ABNF
+    /// ```plain
+    /// Sec-WebSocket-Protocol = 1#token
+    /// ```
+    ///
+    /// # Example values
+    /// * `chat, superchat`
+    ///
+    /// # Examples
+    /// ```
+    /// use hyper::header::{Headers, SecWebSocketProtocol};
+    ///
+    /// let mut headers = Headers::new();
+    /// headers.set(SecWebSocketProtocol(vec!["chat".to_owned()]));
+    /// ```
+    (SecWebSocketProtocol, "Sec-WebSocket-Protocol") => (String)+
+
+    test_sec_websocket_protocol {
+        test_header!(test1, vec![b"chat, superchat"],
+            Some(SecWebSocketProtocol(vec!["chat".to_owned(), "superchat".to_owned()])));
+    }
+}
@@ -0,0 +1,31 @@
+header! {
+    /// `Sec-WebSocket-Version` header, defined in
+    /// [RFC6455](https://tools.ietf.org/html/rfc6455#section-11.3.5)
+    ///
+    /// The `Sec-WebSocket-Version` header is sent by a client opening a
+    /// WebSocket, with the version of the protocol it wants to speak. The
+    /// only version defined by RFC6455 is `13`.
+    ///
+    /// # ABNF
+    /// ```plain
+    /// Sec-WebSocket-Version = version
+    /// version = DIGIT | (NZDIGIT DIGIT) |
+    ///           ("1" DIGIT DIGIT) | ("2" DIGIT DIGIT)
+    /// ```
+    ///
+    /// # Example values
+    /// * `13`
+    ///
+    /// # Examples
+    /// ```
+    /// use hyper::header::{Headers, SecWebSocketVersion};
+    ///
+    /// let mut headers = Headers::new();
+    /// headers.set(SecWebSocketVersion(13));
+    /// ```
+    (SecWebSocketVersion, "Sec-WebSocket-Version") => [u8]
+
+    test_sec_websocket_version {
+        test_header!(test1, vec![b"13"], Some(SecWebSocketVersion(13)));
+    }
+}
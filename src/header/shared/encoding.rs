@@ -1,10 +1,10 @@
 use std::fmt;
 use std::str;
 
-pub use self::Encoding::{Chunked, Gzip, Deflate, Compress, Identity, EncodingExt};
+pub use self::Encoding::{Chunked, Gzip, Deflate, Compress, Identity, Trailers, EncodingExt};
 
-/// A value to represent an encoding used in `Transfer-Encoding`
-/// or `Accept-Encoding` header.
+/// A value to represent an encoding used in `Transfer-Encoding`,
+/// `Accept-Encoding` or `TE` header.
 #[derive(Clone, PartialEq, Debug)]
 pub enum Encoding {
     /// The `chunked` encoding.
@@ -17,6 +17,8 @@ pub enum Encoding {
     Compress,
     /// The `identity` encoding.
     Identity,
+    /// The `trailers` keyword of the `TE` header.
+    Trailers,
     /// Some other encoding that is less common, can be any String.
     EncodingExt(String)
 }
@@ -29,6 +31,7 @@ impl fmt::Display for Encoding {
             Deflate => "deflate",
             Compress => "compress",
             Identity => "identity",
+            Trailers => "trailers",
             EncodingExt(ref s) => s.as_ref()
         })
     }
@@ -43,6 +46,7 @@ impl str::FromStr for Encoding {
             "gzip" => Ok(Gzip),
             "compress" => Ok(Compress),
             "identity" => Ok(Identity),
+            "trailers" => Ok(Trailers),
             _ => Ok(EncodingExt(s.to_owned()))
         }
     }
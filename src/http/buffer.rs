@@ -1,5 +1,5 @@
 use std::cmp;
-use std::io::{self, Read};
+use std::io::{self, BufRead, Read};
 use std::ptr;
 
 
@@ -114,6 +114,19 @@ impl<'a, R: io::Read> Read for BufReader<'a, R> {
     }
 }
 
+impl<'a, R: io::Read> BufRead for BufReader<'a, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.buf.is_empty() {
+            try!(self.buf.read_from(&mut *self.reader));
+        }
+        Ok(self.buf.bytes())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.buf.consume(amt)
+    }
+}
+
 #[inline]
 unsafe fn grow_zerofill(buf: &mut Vec<u8>, additional: usize) {
     let len = buf.len();
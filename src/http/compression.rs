@@ -0,0 +1,567 @@
+//! Compressed message bodies, as in `Content-Encoding: gzip` or `deflate`.
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use flate2::{Compress, Compression, Crc, Decompress, Flush, Status};
+
+use header::{AcceptEncoding, Encoding, Quality};
+
+/// How much compressed data is read from the transport at once.
+const READ_SIZE: usize = 8192;
+/// How much compressed data is kept when the transport can't take it.
+const MAX_BUFFER: usize = 8192 * 4;
+
+const GZIP_FHCRC: u8 = 0x02;
+const GZIP_FEXTRA: u8 = 0x04;
+const GZIP_FNAME: u8 = 0x08;
+const GZIP_FCOMMENT: u8 = 0x10;
+
+/// Decompresses a body as it is read, without needing all of it.
+pub struct Inflate {
+    gzip: bool,
+    state: InflateState,
+    inflater: Option<Decompress>,
+    /// Compressed bytes read, but not yet inflated.
+    input: Vec<u8>,
+    eof: bool,
+    crc: Crc,
+    len: u32,
+    total_out: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InflateState {
+    Header,
+    Body,
+    Trailer,
+    Done,
+}
+
+impl Inflate {
+    /// Decompresses the `gzip` or `deflate` content coding.
+    pub fn new(encoding: &Encoding) -> Option<Inflate> {
+        let gzip = match *encoding {
+            Encoding::Gzip => true,
+            Encoding::Deflate => false,
+            _ => return None
+        };
+        Some(Inflate {
+            gzip: gzip,
+            state: InflateState::Header,
+            inflater: None,
+            input: Vec::new(),
+            eof: false,
+            crc: Crc::new(),
+            len: 0,
+            total_out: 0,
+        })
+    }
+
+    /// Reads compressed data from `src`, into `buf` as decompressed.
+    ///
+    /// Errors from `src`, like `WouldBlock`, are returned as they are.
+    pub fn read<R: Read + ?Sized>(&mut self, src: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some(n) = try!(self.step(buf)) {
+                return Ok(n);
+            }
+            if self.eof {
+                if self.state == InflateState::Header && self.input.is_empty() {
+                    // a coding was declared, but the body is empty
+                    self.state = InflateState::Done;
+                    return Ok(0);
+                }
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "compressed body ended early"));
+            }
+            let start = self.input.len();
+            self.input.resize(start + READ_SIZE, 0);
+            let res = src.read(&mut self.input[start..]);
+            self.input.truncate(start + *res.as_ref().unwrap_or(&0));
+            if try!(res) == 0 {
+                self.eof = true;
+            }
+        }
+    }
+
+    /// Inflates what has been read so far, or `None` if more is needed.
+    fn step(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        loop {
+            match self.state {
+                InflateState::Header => {
+                    if self.gzip {
+                        match try!(gzip_header(&self.input)) {
+                            Some(len) => self.consume(len),
+                            None => return Ok(None)
+                        }
+                        self.inflater = Some(Decompress::new(false));
+                    } else {
+                        if self.input.len() < 2 {
+                            return Ok(None);
+                        }
+                        // `deflate` should be zlib, but some servers send
+                        // raw deflate data instead
+                        let zlib = is_zlib(self.input[0], self.input[1]);
+                        self.inflater = Some(Decompress::new(zlib));
+                    }
+                    self.state = InflateState::Body;
+                },
+                InflateState::Body => {
+                    let (consumed, produced, status) = {
+                        let inflater = self.inflater.as_mut().expect("inflater is set in Body");
+                        let (total_in, total_out) = (inflater.total_in(), inflater.total_out());
+                        let status = try!(inflater.decompress(&self.input, buf, Flush::None)
+                            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+                        ((inflater.total_in() - total_in) as usize,
+                         (inflater.total_out() - total_out) as usize,
+                         status)
+                    };
+                    self.consume(consumed);
+                    self.total_out += produced as u64;
+                    if self.gzip {
+                        self.crc.update(&buf[..produced]);
+                        self.len = self.len.wrapping_add(produced as u32);
+                    }
+                    if status == Status::StreamEnd {
+                        self.state = if self.gzip {
+                            InflateState::Trailer
+                        } else {
+                            InflateState::Done
+                        };
+                    }
+                    if produced > 0 {
+                        return Ok(Some(produced));
+                    }
+                    if consumed == 0 && status != Status::StreamEnd {
+                        return Ok(None);
+                    }
+                },
+                InflateState::Trailer => {
+                    if self.input.len() < 8 {
+                        return Ok(None);
+                    }
+                    let crc = le_u32(&self.input[..4]);
+                    let len = le_u32(&self.input[4..8]);
+                    if crc != self.crc.sum() || len != self.len {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "gzip checksum mismatch"));
+                    }
+                    self.consume(8);
+                    self.state = InflateState::Done;
+                },
+                InflateState::Done => return Ok(Some(0))
+            }
+        }
+    }
+
+    /// How many bytes have been decompressed so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Whether compressed data has been read from the transport, but not
+    /// decompressed yet.
+    ///
+    /// The transport won't be readable again for it.
+    pub fn is_buffered(&self) -> bool {
+        !self.input.is_empty()
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.input.drain(..len);
+    }
+}
+
+impl fmt::Debug for Inflate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Inflate")
+            .field("gzip", &self.gzip)
+            .field("state", &self.state)
+            .field("buffered", &self.input.len())
+            .finish()
+    }
+}
+
+/// Compresses a body as it is written.
+pub struct Deflate {
+    gzip: bool,
+    compress: Compress,
+    /// Compressed bytes not yet written to the transport.
+    output: Vec<u8>,
+    crc: Crc,
+    len: u32,
+    finishing: bool,
+    closed: bool,
+}
+
+impl Deflate {
+    /// Compresses with the `gzip` or `deflate` content coding.
+    pub fn new(encoding: &Encoding) -> Option<Deflate> {
+        let (gzip, output) = match *encoding {
+            Encoding::Gzip => (true, GZIP_HEADER.to_vec()),
+            Encoding::Deflate => (false, Vec::new()),
+            _ => return None
+        };
+        Some(Deflate {
+            gzip: gzip,
+            // `deflate` is zlib, while gzip has its own header
+            compress: Compress::new(Compression::Default, !gzip),
+            output: output,
+            crc: Crc::new(),
+            len: 0,
+            finishing: false,
+            closed: false,
+        })
+    }
+
+    /// Compresses `data`, writing as much as `dst` takes.
+    ///
+    /// Compressed data is buffered while `dst` would block, up to a limit.
+    pub fn write<W: Write + ?Sized>(&mut self, dst: &mut W, data: &[u8]) -> io::Result<usize> {
+        if self.finishing {
+            return Ok(0);
+        }
+        try!(self.write_buffered(dst));
+        if self.output.len() >= MAX_BUFFER {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+        }
+        self.compress(data, Flush::None);
+        if self.gzip {
+            self.crc.update(data);
+            self.len = self.len.wrapping_add(data.len() as u32);
+        }
+        try!(self.write_buffered(dst));
+        Ok(data.len())
+    }
+
+    /// Writes out everything compressed so far.
+    pub fn flush<W: Write + ?Sized>(&mut self, dst: &mut W) -> io::Result<()> {
+        if !self.finishing {
+            self.compress(&[], Flush::Sync);
+        }
+        try!(self.write_all(dst));
+        dst.flush()
+    }
+
+    /// Ends the compressed data, returning whether it has all been written.
+    pub fn finish<W: Write + ?Sized>(&mut self, dst: &mut W) -> io::Result<bool> {
+        if !self.finishing {
+            self.compress(&[], Flush::Finish);
+            if self.gzip {
+                let (crc, len) = (self.crc.sum(), self.len);
+                self.output.extend_from_slice(&le_bytes(crc));
+                self.output.extend_from_slice(&le_bytes(len));
+            }
+            self.finishing = true;
+        }
+        match self.write_all(dst) {
+            Ok(()) => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Whether the compressed data has been ended, but not all written yet.
+    pub fn is_finishing(&self) -> bool {
+        self.finishing && !self.closed
+    }
+
+    /// Whether the compressed data has all been written, and the body closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Notes that the body underneath has been closed.
+    pub fn set_closed(&mut self) {
+        self.closed = true;
+    }
+
+    fn compress(&mut self, mut data: &[u8], flush: Flush) {
+        let mut chunk = [0; 4096];
+        loop {
+            let (total_in, total_out) = (self.compress.total_in(), self.compress.total_out());
+            let status = self.compress.compress(data, &mut chunk, flush);
+            let consumed = (self.compress.total_in() - total_in) as usize;
+            let produced = (self.compress.total_out() - total_out) as usize;
+            data = &data[consumed..];
+            self.output.extend_from_slice(&chunk[..produced]);
+            let done = match flush {
+                Flush::Finish => status == Status::StreamEnd,
+                _ => data.is_empty() && produced < chunk.len(),
+            };
+            if done || (consumed == 0 && produced == 0) {
+                return;
+            }
+        }
+    }
+
+    fn write_all<W: Write + ?Sized>(&mut self, dst: &mut W) -> io::Result<()> {
+        while !self.output.is_empty() {
+            let n = try!(dst.write(&self.output));
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "body closed"));
+            }
+            self.output.drain(..n);
+        }
+        Ok(())
+    }
+
+    /// Writes what `dst` takes without blocking.
+    fn write_buffered<W: Write + ?Sized>(&mut self, dst: &mut W) -> io::Result<()> {
+        match self.write_all(dst) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            res => res
+        }
+    }
+}
+
+impl fmt::Debug for Deflate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Deflate")
+            .field("gzip", &self.gzip)
+            .field("buffered", &self.output.len())
+            .field("finishing", &self.finishing)
+            .field("closed", &self.closed)
+            .finish()
+    }
+}
+
+/// The coding to compress with, of those a request accepts.
+///
+/// `gzip` is preferred over `deflate` when both are accepted as much.
+pub fn negotiate(accept: &AcceptEncoding) -> Option<Encoding> {
+    let quality = |encoding: &Encoding| {
+        let mut any = None;
+        for item in accept.iter() {
+            if item.item == *encoding {
+                return item.quality;
+            }
+            if item.item == Encoding::EncodingExt("*".to_owned()) {
+                any = Some(item.quality);
+            }
+        }
+        any.unwrap_or(Quality(0))
+    };
+    let (gzip, deflate) = (quality(&Encoding::Gzip), quality(&Encoding::Deflate));
+    if gzip == Quality(0) && deflate == Quality(0) {
+        None
+    } else if gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else {
+        Some(Encoding::Deflate)
+    }
+}
+
+/// A gzip header without a name or time, from an unknown OS.
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+
+fn le_bytes(n: u32) -> [u8; 4] {
+    [n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]
+}
+
+/// The length of the gzip header at the start of `buf`, if it is all there.
+fn gzip_header(buf: &[u8]) -> io::Result<Option<usize>> {
+    if buf.len() < 10 {
+        return Ok(None);
+    }
+    if buf[0] != 0x1f || buf[1] != 0x8b {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid gzip header"));
+    }
+    if buf[2] != 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported gzip compression method"));
+    }
+    let flags = buf[3];
+    let mut len = 10;
+    if flags & GZIP_FEXTRA != 0 {
+        if buf.len() < len + 2 {
+            return Ok(None);
+        }
+        len += 2 + (buf[len] as usize | (buf[len + 1] as usize) << 8);
+    }
+    for &flag in &[GZIP_FNAME, GZIP_FCOMMENT] {
+        if flags & flag != 0 {
+            // zero terminated
+            if buf.len() <= len {
+                return Ok(None);
+            }
+            match buf[len..].iter().position(|&b| b == 0) {
+                Some(pos) => len += pos + 1,
+                None => return Ok(None)
+            }
+        }
+    }
+    if flags & GZIP_FHCRC != 0 {
+        len += 2;
+    }
+    if buf.len() < len {
+        Ok(None)
+    } else {
+        Ok(Some(len))
+    }
+}
+
+/// Whether a deflate stream starts with a zlib header.
+fn is_zlib(cmf: u8, flg: u8) -> bool {
+    cmf & 0x0F == 8 && ((cmf as u16) << 8 | flg as u16) % 31 == 0
+}
+
+fn le_u32(buf: &[u8]) -> u32 {
+    buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+
+    use flate2::Compression;
+    use flate2::read::GzDecoder;
+    use flate2::write::{GzEncoder, ZlibEncoder, DeflateEncoder};
+
+    use header::{AcceptEncoding, Encoding, QualityItem, qitem, q};
+    use super::{Inflate, Deflate, gzip_header, negotiate};
+
+    /// Hands out `chunk` bytes at a time, blocking in between.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        chunk: usize,
+        block: bool,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.block = !self.block;
+            if self.block {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+            }
+            let n = ::std::cmp::min(::std::cmp::min(self.chunk, buf.len()), self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    fn inflate(encoding: Encoding, data: Vec<u8>, chunk: usize) -> io::Result<Vec<u8>> {
+        let mut inflate = Inflate::new(&encoding).unwrap();
+        let mut src = Trickle { data: data, pos: 0, chunk: chunk, block: false };
+        let mut out = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            match inflate.read(&mut src, &mut buf) {
+                Ok(0) => return Ok(out),
+                Ok(n) => out.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn body() -> Vec<u8> {
+        (0..10000).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_inflate_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(&body()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        for &chunk in &[1, 5, 4096] {
+            assert_eq!(inflate(Encoding::Gzip, gzipped.clone(), chunk).unwrap(), body());
+        }
+
+        let mut corrupt = gzipped.clone();
+        let len = corrupt.len();
+        corrupt[len - 5] ^= 1;
+        assert!(inflate(Encoding::Gzip, corrupt, 4096).is_err());
+        assert!(inflate(Encoding::Gzip, gzipped[..gzipped.len() - 1].to_vec(), 4096).is_err());
+        assert_eq!(inflate(Encoding::Gzip, Vec::new(), 4096).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_inflate_deflate() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(&body()).unwrap();
+        assert_eq!(inflate(Encoding::Deflate, encoder.finish().unwrap(), 3).unwrap(), body());
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(&body()).unwrap();
+        assert_eq!(inflate(Encoding::Deflate, encoder.finish().unwrap(), 3).unwrap(), body());
+    }
+
+    #[test]
+    fn test_gzip_header() {
+        let header = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03";
+        assert_eq!(gzip_header(&header[..9]).unwrap(), None);
+        assert_eq!(gzip_header(header).unwrap(), Some(10));
+
+        let named = b"\x1f\x8b\x08\x08\x00\x00\x00\x00\x00\x03body.txt\x00";
+        assert_eq!(gzip_header(&named[..15]).unwrap(), None);
+        assert_eq!(gzip_header(named).unwrap(), Some(19));
+
+        assert!(gzip_header(b"\x1f\x8c\x08\x00\x00\x00\x00\x00\x00\x03").is_err());
+    }
+
+    /// Takes `chunk` bytes at a time, blocking in between.
+    struct Sink {
+        data: Vec<u8>,
+        chunk: usize,
+        block: bool,
+    }
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.block = !self.block;
+            if self.block {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+            }
+            let n = ::std::cmp::min(self.chunk, buf.len());
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn deflate(encoding: Encoding, chunk: usize) -> Vec<u8> {
+        let mut deflate = Deflate::new(&encoding).unwrap();
+        let mut dst = Sink { data: Vec::new(), chunk: chunk, block: false };
+        let body = body();
+        let mut written = 0;
+        while written < body.len() {
+            match deflate.write(&mut dst, &body[written..::std::cmp::min(written + 100, body.len())]) {
+                Ok(n) => written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                Err(e) => panic!("deflate error: {:?}", e),
+            }
+        }
+        while !deflate.finish(&mut dst).unwrap() {}
+        dst.data
+    }
+
+    #[test]
+    fn test_deflate() {
+        for &chunk in &[1, 4096] {
+            let gzipped = deflate(Encoding::Gzip, chunk);
+            let mut decoded = Vec::new();
+            GzDecoder::new(&gzipped[..]).unwrap().read_to_end(&mut decoded).unwrap();
+            assert_eq!(decoded, body());
+            assert_eq!(inflate(Encoding::Gzip, gzipped, 4096).unwrap(), body());
+        }
+        assert_eq!(inflate(Encoding::Deflate, deflate(Encoding::Deflate, 7), 4096).unwrap(), body());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let accept = |items| negotiate(&AcceptEncoding(items));
+        assert_eq!(accept(vec![qitem(Encoding::Gzip), qitem(Encoding::Deflate)]), Some(Encoding::Gzip));
+        assert_eq!(accept(vec![qitem(Encoding::Deflate)]), Some(Encoding::Deflate));
+        assert_eq!(accept(vec![QualityItem::new(Encoding::Gzip, q(0.5)), qitem(Encoding::Deflate)]), Some(Encoding::Deflate));
+        assert_eq!(accept(vec![QualityItem::new(Encoding::Gzip, q(0.0)), qitem(Encoding::EncodingExt("*".to_owned()))]),
+                   Some(Encoding::Deflate));
+        assert_eq!(accept(vec![qitem(Encoding::Identity)]), None);
+        assert_eq!(accept(vec![]), None);
+    }
+}
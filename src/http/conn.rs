@@ -1,14 +1,17 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt;
 use std::hash::Hash;
 use std::io;
 use std::marker::PhantomData;
 use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use std::time::Duration;
 
 use rotor::{self, EventSet, PollOpt, Scope, Time};
 
-use http::{self, h1, Http1Message, Encoder, Decoder, Next, Next_, Reg, Control};
+use header::Headers;
+use http::{self, h1, h2, Http1Message, Http2Message, Encoder, Decoder, Next, Next_, Reg, Control};
 use http::channel;
 use http::internal::WriteBuf;
 use http::buffer::Buffer;
@@ -17,6 +20,9 @@ use version::HttpVersion;
 
 const MAX_BUFFER_SIZE: usize = 8192 + 4096 * 100;
 
+/// Tells connections apart, for as long as the process runs.
+static NEXT_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
 /// This handles a connection, which will have been established over a
 /// Transport (like a socket), and will likely include multiple
 /// `Message`s over HTTP.
@@ -32,8 +38,15 @@ pub struct Conn<K: Key, T: Transport, H: MessageHandler<T>>(Box<ConnInner<K, T,
 /// semantics but avoiding many costly memcpy calls.
 struct ConnInner<K: Key, T: Transport, H: MessageHandler<T>> {
     buf: Buffer,
-    ctrl: (channel::Sender<Next>, channel::Receiver<Next>),
+    ctrl: (channel::Sender<(h2::StreamId, Next)>, channel::Receiver<(h2::StreamId, Next)>),
     keep_alive_enabled: bool,
+    /// Whether a request with `Upgrade: h2c`, or the HTTP/2 connection
+    /// preface, switches to HTTP/2.
+    h2c_upgrade: bool,
+    /// How long a client waits for `100 Continue` before sending the body
+    /// anyway.
+    continue_timeout: Duration,
+    id: usize,
     key: K,
     state: State<H, T>,
     transport: T,
@@ -41,6 +54,10 @@ struct ConnInner<K: Key, T: Transport, H: MessageHandler<T>> {
     ///
     /// This flag is used to prevent busy looping
     read_would_block: bool,
+    /// Whether a message has been handled on this connection before.
+    reused: bool,
+    /// Whether the connection was offered as idle since its last message.
+    idle_pooled: bool,
 }
 
 impl<K: Key, T: Transport, H: MessageHandler<T>> fmt::Debug for ConnInner<K, T, H> {
@@ -73,7 +90,7 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
             State::Http1(Http1 { reading: Reading::Closed, writing: Writing::Closed, .. }) => {
                 Reg::Remove
             }
-            State::Http1(Http1 { ref reading, ref writing, .. }) => {
+            State::Http1(Http1 { ref reading, ref writing, ref expect_continue, .. }) => {
                 let read = match *reading {
                     Reading::Parse |
                     Reading::Body(..) => Reg::Read,
@@ -92,6 +109,11 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                     Writing::KeepAlive => Reg::Wait,
                     Writing::Closed => Reg::Wait,
                 };
+                // the client waits for a `100 Continue` before sending the body
+                let write = match (expect_continue, reading) {
+                    (&Continue::Send(..), &Reading::Body(..)) => Reg::Write,
+                    _ => write,
+                };
 
                 match (read, write) {
                     (Reg::Read, Reg::Write) => Reg::ReadWrite,
@@ -101,6 +123,8 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                     _ => unreachable!("bad read/write reg combo")
                 }
             }
+            State::Http2(ref http2) => http2.interest(),
+            State::Upgraded(ref upgraded) => upgraded.interest.register(),
         }
     }
 
@@ -120,6 +144,18 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
         }
     }
 
+    /// Creates the handler for the next message, or stream, of this
+    /// connection.
+    fn create<F>(&mut self, scope: &mut Scope<F>, id: h2::StreamId) -> Option<H>
+    where F: MessageHandlerFactory<K, T, Output=H> {
+        let handler = scope.create(Seed(&self.key, &self.ctrl.0, id, self.reused));
+        if handler.is_some() {
+            self.reused = true;
+            self.idle_pooled = false;
+        }
+        handler
+    }
+
     fn parse(&mut self) -> ::Result<http::MessageHead<<<H as MessageHandler<T>>::Message as Http1Message>::Incoming>> {
         match self.buf.read_from(&mut self.transport) {
             Ok(0) => {
@@ -140,7 +176,6 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
             },
             None => {
                 if self.buf.len() >= MAX_BUFFER_SIZE {
-                    //TODO: Handler.on_too_large_error()
                     debug!("MAX_BUFFER_SIZE reached, closing");
                     Err(::Error::TooLarge)
                 } else {
@@ -150,10 +185,44 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
         }
     }
 
+    /// Writes the response the factory has for a message head that couldn't
+    /// be parsed, right before the connection is closed.
+    ///
+    /// `partial` is whether the head was still being read, so the buffer
+    /// holds the start of it rather than the body.
+    fn write_error_response<F: MessageHandlerFactory<K, T, Output=H>>(&mut self, scope: &mut Scope<F>, err: &::Error, partial: bool) {
+        let response = {
+            let buf = if partial { self.buf.bytes() } else { &[] };
+            scope.on_parse_error(err, buf)
+        };
+        let (head, body) = match response {
+            Some(response) => response,
+            None => return,
+        };
+        let mut buf = Vec::new();
+        H::Message::encode(head, &mut buf);
+        buf.extend_from_slice(&body);
+        // nothing else is queued for the transport, so the response should
+        // fit in a single write; it is only a courtesy before closing
+        match self.transport.write(&buf) {
+            Ok(n) if n == buf.len() => trace!("wrote error response"),
+            Ok(n) => debug!("wrote {} of {} bytes of error response", n, buf.len()),
+            Err(e) => debug!("error writing error response: {:?}", e),
+        }
+        let _ = self.transport.flush();
+    }
+
     fn read<F: MessageHandlerFactory<K, T, Output=H>>(&mut self, scope: &mut Scope<F>, state: State<H, T>) -> State<H, T> {
          match state {
             State::Init { interest: Next_::Read, .. } => {
-                let head = match self.parse() {
+                let alpn_h2 = self.transport.alpn_protocol() == Some("h2");
+                if alpn_h2 {
+                    trace!("h2 negotiated with ALPN");
+                    let idle_timeout = scope.keep_alive_interest().timeout;
+                    let http2 = Http2::new(h2::Connection::server(), HttpVersion::H2, idle_timeout, scope.now());
+                    return self.read(scope, State::Http2(http2));
+                }
+                let mut head = match self.parse() {
                     Ok(head) => head,
                     Err(::Error::Io(e)) => match e.kind() {
                         io::ErrorKind::WouldBlock |
@@ -167,29 +236,74 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                         }
                     },
                     Err(e) => {
-                        //TODO: send proper error codes depending on error
+                        let is_preface = self.h2c_upgrade && {
+                            let bytes = self.buf.bytes();
+                            h2::PREFACE.starts_with(bytes) || bytes.starts_with(h2::PREFACE)
+                        };
+                        if is_preface {
+                            trace!("HTTP/2 connection preface, switching to h2c");
+                            let idle_timeout = scope.keep_alive_interest().timeout;
+                            let http2 = Http2::new(h2::Connection::server(), HttpVersion::H2c, idle_timeout, scope.now());
+                            return self.read(scope, State::Http2(http2));
+                        }
                         trace!("parse eror: {:?}", e);
+                        self.write_error_response(scope, &e, true);
                         return State::Closed;
                     }
                 };
-                let mut handler = match scope.create(Seed(&self.key, &self.ctrl.0)) {
+                let mut handler = match self.create(scope, 1) {
                     Some(handler) => handler,
                     None => unreachable!()
                 };
                 match H::Message::decoder(&head) {
                     Ok(decoder) => {
                         trace!("decoder = {:?}", decoder);
+                        let now = scope.now();
+                        // only requests without a body are upgraded, so the
+                        // body never has to be read as HTTP/1 first
+                        let upgrade = if self.h2c_upgrade && decoder.is_eof() {
+                            h2::upgrade_settings(&head.headers)
+                        } else {
+                            None
+                        };
+                        if let Some(settings) = upgrade {
+                            match h2::Connection::server_upgrade(&settings) {
+                                Ok(conn) => {
+                                    trace!("upgrading to h2c");
+                                    head.version = HttpVersion::H2c;
+                                    let trailers = head.accepts_trailers();
+                                    let next = handler.on_incoming(head, &self.transport);
+                                    trace!("handler.on_incoming() -> {:?}", next);
+                                    let idle_timeout = scope.keep_alive_interest().timeout;
+                                    let mut http2 = Http2::new(conn, HttpVersion::H2c, idle_timeout, now);
+                                    let mut stream = Http2Stream::new(handler, next, now);
+                                    stream.head_received = true;
+                                    stream.trailers_allowed = trailers;
+                                    http2.streams.insert(1, stream);
+                                    return self.read(scope, State::Http2(http2));
+                                },
+                                Err(reason) => debug!("bad HTTP2-Settings, staying with HTTP/1: {}", reason),
+                            }
+                        }
+
                         let keep_alive = self.keep_alive_enabled && head.should_keep_alive();
+                        let expect_continue = if head.expects_continue() && !decoder.is_eof() {
+                            Continue::Send(0)
+                        } else {
+                            Continue::No
+                        };
+                        let trailers = head.accepts_trailers();
                         let next = handler.on_incoming(head, &self.transport);
                         trace!("handler.on_incoming() -> {:?}", next);
 
-                        let now = scope.now();
                         match next.interest {
                             Next_::Read => self.read(scope, State::Http1(Http1 {
                                 handler: handler,
                                 reading: Reading::Body(decoder),
                                 writing: Writing::Init,
                                 keep_alive: keep_alive,
+                                expect_continue: expect_continue,
+                                trailers: trailers,
                                 timeout: next.timeout,
                                 timeout_start: Some(now),
                                 _marker: PhantomData,
@@ -207,6 +321,8 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                                 },
                                 writing: Writing::Head,
                                 keep_alive: keep_alive,
+                                expect_continue: expect_continue,
+                                trailers: trailers,
                                 timeout: next.timeout,
                                 timeout_start: Some(now),
                                 _marker: PhantomData,
@@ -216,6 +332,8 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                                 reading: Reading::Body(decoder),
                                 writing: Writing::Head,
                                 keep_alive: keep_alive,
+                                expect_continue: expect_continue,
+                                trailers: trailers,
                                 timeout: next.timeout,
                                 timeout_start: Some(now),
                                 _marker: PhantomData,
@@ -225,6 +343,8 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                                 reading: Reading::Wait(decoder),
                                 writing: Writing::Init,
                                 keep_alive: keep_alive,
+                                expect_continue: expect_continue,
+                                trailers: trailers,
                                 timeout: next.timeout,
                                 timeout_start: Some(now),
                                 _marker: PhantomData,
@@ -235,9 +355,7 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                     },
                     Err(e) => {
                         debug!("error creating decoder: {:?}", e);
-                        //TODO: update state from returned Next
-                        //this would allow a Server to respond with a proper
-                        //4xx code
+                        self.write_error_response(scope, &e, false);
                         let _ = handler.on_error(e);
                         State::Closed
                     }
@@ -275,6 +393,17 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                 let next = match http1.reading {
                     Reading::Init => None,
                     Reading::Parse => match self.parse() {
+                        Ok(ref head) if H::Message::is_informational(head) => {
+                            trace!("skipping interim response");
+                            // the next parse finds the real response
+                            match mem::replace(&mut http1.expect_continue, Continue::No) {
+                                Continue::Await(next) => Some(next),
+                                other => {
+                                    http1.expect_continue = other;
+                                    None
+                                }
+                            }
+                        },
                         Ok(head) => match H::Message::decoder(&head) {
                             Ok(decoder) => {
                                 trace!("decoder = {:?}", decoder);
@@ -283,14 +412,21 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                                 if http1.keep_alive {
                                     http1.keep_alive = head.should_keep_alive();
                                 }
+                                if let Continue::Await(..) = http1.expect_continue {
+                                    // the server answered without the body,
+                                    // which can't be sent on this connection now
+                                    http1.expect_continue = Continue::No;
+                                    http1.keep_alive = false;
+                                }
                                 let next = http1.handler.on_incoming(head, &self.transport);
                                 http1.reading = Reading::Wait(decoder);
                                 trace!("handler.on_incoming() -> {:?}", next);
                                 Some(next)
                             },
                             Err(e) => {
+                                // a response can't be answered, only dropped
                                 debug!("error creating decoder: {:?}", e);
-                                //TODO: respond with 400
+                                let _ = http1.handler.on_error(e);
                                 return State::Closed;
                             }
                         },
@@ -302,6 +438,7 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                             },
                             _ => {
                                 debug!("io error trying to parse {:?}", e);
+                                let _ = http1.handler.on_error(e.into());
                                 return State::Closed;
                             }
                         },
@@ -312,12 +449,9 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                         }
                     },
                     Reading::Body(ref mut decoder) => {
-                        let wrapped = if !self.buf.is_empty() {
-                            super::Trans::Buf(self.buf.wrap(&mut self.transport))
-                        } else {
-                            super::Trans::Port(&mut self.transport)
-                        };
-
+                        // read through the buffer, so a chunked trailer can be parsed
+                        // out of it without taking bytes of the next message
+                        let wrapped = self.buf.wrap(&mut self.transport);
                         Some(http1.handler.on_decode(&mut Decoder::h1(decoder, wrapped)))
                     },
                     _ => {
@@ -342,6 +476,17 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                     s
                 }
             },
+            State::Http2(http2) => self.read_h2(scope, http2),
+            State::Upgraded(mut upgraded) => {
+                if !upgraded.started {
+                    return State::Upgraded(upgraded);
+                }
+                let next = upgraded.handler.on_readable(&mut self.transport);
+                trace!("upgrade.on_readable() -> {:?}", next);
+                let mut s = State::Upgraded(upgraded);
+                s.update(next, &**scope, Some(scope.now()));
+                s
+            },
             State::Closed => {
                 trace!("on_readable State::Closed");
                 State::Closed
@@ -350,12 +495,30 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
     }
 
     fn write<F: MessageHandlerFactory<K, T, Output=H>>(&mut self, scope: &mut Scope<F>, mut state: State<H, T>) -> State<H, T> {
+        // a `100 Continue` owed to the client goes out before anything else
+        let continued = match state {
+            State::Http1(ref mut http1) => http1.write_continue(&mut self.transport),
+            _ => Ok(true),
+        };
+        match continued {
+            Ok(true) => (),
+            Ok(false) => return state,
+            Err(e) => {
+                let next = match state {
+                    State::Http1(ref mut http1) => http1.handler.on_error(e.into()),
+                    _ => unreachable!(),
+                };
+                state.update(next, &**scope, Some(scope.now()));
+                return state;
+            }
+        }
+
         let next = match state {
             State::Init { interest: Next_::Write, .. } => {
                 // this is a Client request, which writes first, so pay
                 // attention to the version written here, which will adjust
                 // our internal state to Http1 or Http2
-                let mut handler = match scope.create(Seed(&self.key, &self.ctrl.0)) {
+                let mut handler = match self.create(scope, 1) {
                     Some(handler) => handler,
                     None => {
                         trace!("could not create handler {:?}", self.key);
@@ -364,11 +527,39 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                 };
                 let mut head = http::MessageHead::default();
                 let mut interest = handler.on_outgoing(&mut head);
+                if self.transport.alpn_protocol() == Some("h2") {
+                    // the server agreed to h2, whatever was asked for
+                    head.version = HttpVersion::H2;
+                }
+                if head.version == HttpVersion::H2 || head.version == HttpVersion::H2c {
+                    let now = scope.now();
+                    let mut http2 = Http2::new(h2::Connection::client(), head.version, None, now);
+                    let id = http2.conn.open();
+                    let fields = H::Message::encode_h2(head, http2.scheme());
+                    http2.conn.send_headers(id, &fields, ends_stream(&interest));
+                    let mut stream = Http2Stream::new(handler, interest, now);
+                    stream.head_sent = true;
+                    http2.streams.insert(id, stream);
+                    return self.drive_h2(scope, http2);
+                }
                 if head.version == HttpVersion::Http11 {
                     let mut buf = Vec::new();
                     let keep_alive = self.keep_alive_enabled && head.should_keep_alive();
+                    let expects_continue = head.expects_continue();
+                    let mut expect_continue = Continue::No;
                     let mut encoder = H::Message::encode(head, &mut buf);
                     let writing = match interest.interest {
+                        // send the head alone, and hold the body back until
+                        // the server agrees to it, or doesn't answer in time
+                        _ if expects_continue => {
+                            expect_continue = Continue::Await(interest.clone());
+                            interest = Next::read().timeout(self.continue_timeout);
+                            Writing::Chunk(Chunk {
+                                buf: Cow::Owned(buf),
+                                pos: 0,
+                                next: (encoder, interest.clone())
+                            })
+                        },
                         // user wants to write some data right away
                         // try to write the headers and the first chunk
                         // together, so they are in the same packet
@@ -392,6 +583,8 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                         writing: writing,
                         handler: handler,
                         keep_alive: keep_alive,
+                        expect_continue: expect_continue,
+                        trailers: true,
                         timeout: interest.timeout,
                         timeout_start: Some(scope.now()),
                         _marker: PhantomData,
@@ -403,7 +596,7 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                 trace!("Conn.on_writable State::{:?}", state);
                 None
             }
-            State::Http1(Http1 { ref mut handler, ref mut writing, ref mut keep_alive, .. }) => {
+            State::Http1(Http1 { ref mut handler, ref mut writing, ref mut keep_alive, ref trailers, .. }) => {
                 match *writing {
                     Writing::Init => {
                         trace!("Conn.on_writable Http1::Writing::Init");
@@ -420,6 +613,9 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                         }
                         let mut buf = Vec::new();
                         let mut encoder = <<H as MessageHandler<T>>::Message as Http1Message>::encode(head, &mut buf);
+                        if !*trailers {
+                            encoder.disallow_trailers();
+                        }
                         *writing = match interest.interest {
                             // user wants to write some data right away
                             // try to write the headers and the first chunk
@@ -480,6 +676,14 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
                     }
                 }
             },
+            State::Http2(http2) => return self.drive_h2(scope, http2),
+            State::Upgraded(ref mut upgraded) => {
+                if upgraded.started {
+                    Some(upgraded.handler.on_writable(&mut self.transport))
+                } else {
+                    None
+                }
+            },
             State::Closed => {
                 trace!("on_writable State::Closed");
                 None
@@ -498,6 +702,9 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
 
         let state_machine_ok = match self.state {
             State::Init { .. } => !was_init && !self.buf.is_empty(),
+            // frames left in the buffer are incomplete, and each read
+            // already drives every stream as far as it can go
+            State::Http2(..) => false,
             _ => !self.buf.is_empty()
         };
 
@@ -510,6 +717,11 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
         let next = match self.state {
             State::Init { .. } => Next::remove(),
             State::Http1(ref mut http1) => http1.handler.on_error(err),
+            State::Http2(ref mut http2) => {
+                http2.abort(io::ErrorKind::ConnectionAborted, &err.to_string());
+                Next::remove()
+            }
+            State::Upgraded(ref mut upgraded) => upgraded.handler.on_error(err),
             State::Closed => Next::remove(),
         };
         self.state.update(next, factory, None);
@@ -538,9 +750,307 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> ConnInner<K, T, H> {
         match self.state {
             State::Init { .. } | State::Closed => (),
             State::Http1(http1) => http1.handler.on_remove(self.transport),
+            State::Http2(http2) => http2.on_remove(self.transport),
+            State::Upgraded(upgraded) => upgraded.handler.on_remove(self.transport),
+        }
+    }
+
+    /// Gives a protocol that just took over the connection the bytes that
+    /// were read past the end of the HTTP message.
+    fn start_upgrade(&mut self, now: Time) {
+        let next = match self.state {
+            State::Upgraded(ref mut upgraded) => {
+                if upgraded.started {
+                    return;
+                }
+                upgraded.started = true;
+                let buffered = self.buf.bytes().to_vec();
+                self.buf.reset();
+                upgraded.handler.on_start(buffered)
+            },
+            _ => return,
+        };
+        trace!("upgrade.on_start() -> {:?}", next);
+        if let State::Upgraded(ref mut upgraded) = self.state {
+            upgraded.update(next, Some(now));
+        }
+    }
+
+    fn read_h2<F>(&mut self, scope: &mut Scope<F>, mut http2: Http2<H, T>) -> State<H, T>
+    where F: MessageHandlerFactory<K, T, Output=H> {
+        match self.buf.read_from(&mut self.transport) {
+            Ok(0) => {
+                trace!("h2 read eof");
+                http2.abort(io::ErrorKind::UnexpectedEof, "connection closed");
+                return State::Closed;
+            },
+            Ok(_) => {},
+            Err(e) => match e.kind() {
+                io::ErrorKind::WouldBlock |
+                io::ErrorKind::Interrupted => self.read_would_block = true,
+                _ => {
+                    debug!("io error reading h2 {:?}", e);
+                    http2.abort(e.kind(), &e.to_string());
+                    return State::Closed;
+                }
+            }
+        }
+
+        let mut events = Vec::new();
+        let result = http2.conn.recv(self.buf.bytes(), &mut events);
+        if let Ok(len) = result {
+            trace!("h2 read {} bytes out of {}", len, self.buf.len());
+            self.buf.consume(len);
+        }
+        for event in events {
+            self.on_h2_event(scope, &mut http2, event);
+        }
+        if let Err(reason) = result {
+            debug!("h2 connection error: {}", reason);
+            // try to tell the peer why
+            let _ = http2.conn.flush(&mut self.transport);
+            http2.abort(io::ErrorKind::InvalidData, &format!("HTTP/2 connection error: {}", reason));
+            return State::Closed;
+        }
+        self.drive_h2(scope, http2)
+    }
+
+    fn on_h2_event<F>(&mut self, scope: &mut Scope<F>, http2: &mut Http2<H, T>, event: h2::Event)
+    where F: MessageHandlerFactory<K, T, Output=H> {
+        trace!("h2 event {:?}", event);
+        let now = scope.now();
+        match event {
+            h2::Event::Headers { id, fields, .. } => {
+                if http2.streams.get(&id).map_or(false, |stream| stream.head_received) {
+                    // the decoder hands these out once the body is read
+                    match h2::decode_trailers(&fields) {
+                        Ok(trailers) => {
+                            if let Some(stream) = http2.streams.get_mut(&id) {
+                                stream.recv_trailers = Some(trailers);
+                            }
+                        },
+                        Err(e) => {
+                            debug!("malformed h2 trailers on stream {}: {:?}", id, e);
+                            if let Some(mut stream) = http2.streams.remove(&id) {
+                                let _ = stream.handler.on_error(e);
+                            }
+                            http2.conn.reset(id, h2::Reason::ProtocolError);
+                        }
+                    }
+                    return;
+                }
+                let head = H::Message::decode_h2(fields, http2.version);
+                if let Some(mut stream) = http2.streams.remove(&id) {
+                    stream.head_received = true;
+                    match head {
+                        Ok(head) => {
+                            let next = stream.handler.on_incoming(head, &self.transport);
+                            trace!("handler.on_incoming() -> {:?}", next);
+                            stream.update(next, now);
+                            http2.streams.insert(id, stream);
+                        },
+                        Err(e) => {
+                            debug!("malformed h2 head on stream {}: {:?}", id, e);
+                            let _ = stream.handler.on_error(e);
+                            http2.conn.reset(id, h2::Reason::ProtocolError);
+                        }
+                    }
+                    return;
+                }
+
+                if http2.conn.role() == h2::Role::Client {
+                    // we've already given up on this stream
+                    http2.conn.reset(id, h2::Reason::Cancel);
+                    return;
+                }
+
+                // a new stream from a client
+                let mut handler = match self.create(scope, id) {
+                    Some(handler) => handler,
+                    None => {
+                        http2.conn.reset(id, h2::Reason::RefusedStream);
+                        return;
+                    }
+                };
+                match head {
+                    Ok(head) => {
+                        let trailers = head.accepts_trailers();
+                        let next = handler.on_incoming(head, &self.transport);
+                        trace!("handler.on_incoming() -> {:?}", next);
+                        let mut stream = Http2Stream::new(handler, next, now);
+                        stream.head_received = true;
+                        stream.trailers_allowed = trailers;
+                        http2.streams.insert(id, stream);
+                    },
+                    Err(e) => {
+                        debug!("malformed h2 request on stream {}: {:?}", id, e);
+                        match scope.on_parse_error(&e, &[]) {
+                            Some((head, body)) => {
+                                let fields = H::Message::encode_h2(head, http2.scheme());
+                                http2.conn.send_headers(id, &fields, body.is_empty());
+                                http2.conn.send_data(id, &body, true);
+                                // the rest of the request isn't wanted
+                                http2.conn.reset(id, h2::Reason::NoError);
+                            },
+                            None => http2.conn.reset(id, h2::Reason::ProtocolError),
+                        }
+                        let _ = handler.on_error(e);
+                    }
+                }
+            },
+            h2::Event::Reset(id, reason) => {
+                if let Some(mut stream) = http2.streams.remove(&id) {
+                    let err = io::Error::new(io::ErrorKind::ConnectionReset, format!("stream reset: {}", reason));
+                    let _ = stream.handler.on_error(err.into());
+                }
+            },
+            h2::Event::GoAway(last_id, reason) => {
+                // streams we opened after last_id were never processed
+                if http2.conn.role() == h2::Role::Client {
+                    let refused = http2.streams.keys()
+                        .filter(|&&id| id > last_id)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    for id in refused {
+                        if let Some(mut stream) = http2.streams.remove(&id) {
+                            http2.conn.remove(id);
+                            let err = io::Error::new(io::ErrorKind::ConnectionAborted, format!("stream refused: {}", reason));
+                            let _ = stream.handler.on_error(err.into());
+                        }
+                    }
+                }
+            },
+            // buffered data and opened windows are picked up by drive_h2
+            h2::Event::Data(..) |
+            h2::Event::Window(..) => (),
         }
     }
 
+    /// Runs each stream's handler for as long as it can make progress, then
+    /// writes out whatever frames that produced.
+    fn drive_h2<F>(&mut self, scope: &mut Scope<F>, mut http2: Http2<H, T>) -> State<H, T>
+    where F: MessageHandlerFactory<K, T, Output=H> {
+        let now = scope.now();
+        if http2.conn.is_closing() && (http2.pooled || http2.pending_opens > 0) {
+            // new streams go to other connections from now on, including
+            // the ones the pool already asked this one for
+            http2.pooled = false;
+            let refused = mem::replace(&mut http2.pending_opens, 0);
+            scope.on_stop_streams(&self.key, self.id, refused);
+        }
+        while http2.pending_opens > 0 && http2.conn.can_open() {
+            http2.pending_opens -= 1;
+            let id = http2.conn.next_stream_id();
+            match self.create(scope, id) {
+                Some(handler) => {
+                    let id = http2.conn.open();
+                    http2.streams.insert(id, Http2Stream::new(handler, Next::write(), now));
+                },
+                None => http2.pending_opens = 0,
+            }
+        }
+
+        let ids = http2.streams.keys().cloned().collect::<Vec<_>>();
+        for id in ids {
+            if let Some(stream) = http2.streams.remove(&id) {
+                if let Some(stream) = self.drive_h2_stream(&mut http2, id, stream, now) {
+                    http2.streams.insert(id, stream);
+                }
+            }
+        }
+        if http2.streams.is_empty() {
+            if http2.idle_start.is_none() {
+                http2.idle_start = Some(now);
+            }
+        } else {
+            http2.idle_start = None;
+        }
+
+        match http2.conn.flush(&mut self.transport) {
+            Ok(()) => State::Http2(http2),
+            Err(e) => match e.kind() {
+                io::ErrorKind::WouldBlock |
+                io::ErrorKind::Interrupted => State::Http2(http2),
+                _ => {
+                    debug!("io error writing h2 {:?}", e);
+                    http2.abort(e.kind(), &e.to_string());
+                    State::Closed
+                }
+            }
+        }
+    }
+
+    fn drive_h2_stream(&mut self, http2: &mut Http2<H, T>, id: h2::StreamId, mut stream: Http2Stream<H>, now: Time) -> Option<Http2Stream<H>> {
+        // a handler that keeps finding more to do shouldn't starve the
+        // other streams
+        for _ in 0..4 {
+            let mut progress = false;
+            if stream.wants_read() && stream.head_received && http2.conn.is_readable(id) {
+                let next = stream.handler.on_decode(&mut Decoder::h2(&mut http2.conn, id, &self.transport, &stream.recv_trailers));
+                trace!("handler.on_decode() -> {:?}", next);
+                stream.update(next, now);
+                progress = true;
+            }
+            if stream.wants_write() {
+                if !stream.head_sent {
+                    let mut head = http::MessageHead::default();
+                    head.version = http2.version;
+                    let next = stream.handler.on_outgoing(&mut head);
+                    trace!("handler.on_outgoing() -> {:?}", next);
+                    let fields = H::Message::encode_h2(head, http2.scheme());
+                    http2.conn.send_headers(id, &fields, ends_stream(&next));
+                    stream.head_sent = true;
+                    stream.update(next, now);
+                    progress = true;
+                } else if http2.conn.send_capacity(id) > 0 && http2.conn.buffered() < h2::MAX_SEND_BUFFER {
+                    let next = {
+                        let trailers = if stream.trailers_allowed {
+                            Some(&mut stream.send_trailers)
+                        } else {
+                            None
+                        };
+                        stream.handler.on_encode(&mut Encoder::h2(&mut http2.conn, id, &mut self.transport, trailers))
+                    };
+                    trace!("handler.on_encode() -> {:?}", next);
+                    if ends_stream(&next) {
+                        h2::end_stream(&mut http2.conn, id, stream.send_trailers.take());
+                    }
+                    stream.update(next, now);
+                    progress = true;
+                }
+            }
+
+            match stream.interest {
+                Next_::End => {
+                    if !stream.head_sent {
+                        http2.conn.reset(id, h2::Reason::InternalError);
+                        return None;
+                    }
+                    h2::end_stream(&mut http2.conn, id, stream.send_trailers.take());
+                    if http2.conn.is_recv_closed(id) {
+                        http2.conn.remove(id);
+                    } else {
+                        // we don't care about the rest of the message
+                        let reason = match http2.conn.role() {
+                            h2::Role::Server => h2::Reason::NoError,
+                            h2::Role::Client => h2::Reason::Cancel,
+                        };
+                        http2.conn.reset(id, reason);
+                    }
+                    return None;
+                },
+                Next_::Remove => {
+                    http2.conn.reset(id, h2::Reason::Cancel);
+                    return None;
+                },
+                _ => (),
+            }
+            if !progress {
+                break;
+            }
+        }
+        Some(stream)
+    }
 }
 
 pub enum ReadyResult<C> {
@@ -560,6 +1070,9 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> Conn<K, T, H> {
             buf: Buffer::new(),
             ctrl: channel::new(notify),
             keep_alive_enabled: true,
+            h2c_upgrade: false,
+            continue_timeout: Duration::from_secs(1),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
             key: key,
             state: State::Init {
                 interest: next.interest,
@@ -568,6 +1081,8 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> Conn<K, T, H> {
             },
             transport: transport,
             read_would_block: false,
+            reused: false,
+            idle_pooled: false,
         }))
     }
 
@@ -576,6 +1091,16 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> Conn<K, T, H> {
         self
     }
 
+    pub fn h2c_upgrade(mut self, val: bool) -> Conn<K, T, H> {
+        self.0.h2c_upgrade = val;
+        self
+    }
+
+    pub fn continue_timeout(mut self, val: Duration) -> Conn<K, T, H> {
+        self.0.continue_timeout = val;
+        self
+    }
+
     pub fn ready<F>(
         mut self,
         events: EventSet,
@@ -598,7 +1123,7 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> Conn<K, T, H> {
         if events.is_hup() {
             trace!("Conn::ready got hangup");
             let _ = scope.deregister(&self.0.transport);
-            self.on_remove();
+            self.on_remove(scope);
             return ReadyResult::Done(None);
         }
 
@@ -642,6 +1167,8 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> Conn<K, T, H> {
             self.0.on_writable(scope);
         }
 
+        self.0.start_upgrade(scope.now());
+
         let mut events = match self.0.register() {
             Reg::Read => EventSet::readable(),
             Reg::Write => EventSet::writable(),
@@ -650,7 +1177,7 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> Conn<K, T, H> {
             Reg::Remove => {
                 trace!("removing transport");
                 let _ = scope.deregister(&self.0.transport);
-                self.on_remove();
+                self.on_remove(scope);
                 return ReadyResult::Done(None);
             },
         };
@@ -670,6 +1197,7 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> Conn<K, T, H> {
             Err(e) => {
                 trace!("error reregistering: {:?}", e);
                 self.0.on_error(e.into(), &**scope);
+                scope.on_close(&self.0.key, self.0.id);
                 ReadyResult::Done(None)
             }
         }
@@ -677,8 +1205,25 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> Conn<K, T, H> {
 
     pub fn wakeup<F>(mut self, scope: &mut Scope<F>) -> Option<(Self, Option<Duration>)>
     where F: MessageHandlerFactory<K, T, Output=H> {
-        while let Ok(next) = self.0.ctrl.1.try_recv() {
-            trace!("woke up with {:?}", next);
+        while let Ok((id, next)) = self.0.ctrl.1.try_recv() {
+            trace!("woke up with {:?} for stream {}", next, id);
+            if let State::Http2(ref mut http2) = self.0.state {
+                http2.wakeup(id, next, scope.now());
+                continue;
+            }
+            if let State::Upgraded(ref mut upgraded) = self.0.state {
+                let next = if upgraded.started {
+                    upgraded.handler.on_wakeup(next)
+                } else {
+                    next
+                };
+                upgraded.update(next, Some(scope.now()));
+                continue;
+            }
+            let next = match self.0.state {
+                State::Http1(ref mut http1) => http1.handler.on_wakeup(next),
+                _ => next
+            };
             let timeout_start = self.0.state.timeout_start();
             self.0.state.update(next, &**scope, timeout_start);
         }
@@ -694,21 +1239,39 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> Conn<K, T, H> {
 
     pub fn timeout<F>(mut self, scope: &mut Scope<F>) -> Option<(Self, Option<Duration>)>
     where F: MessageHandlerFactory<K, T, Output=H> {
+        let now = scope.now();
+        let is_h2 = match self.0.state {
+            State::Http2(..) => true,
+            _ => false,
+        };
         // Run error handler if timeout has elapsed
-        if self.0.state.timeout_elapsed(scope.now()) {
-            self.0.on_error(::Error::Timeout, &**scope);
+        if self.0.state.timeout_elapsed(now) {
+            if let State::Http2(ref mut http2) = self.0.state {
+                http2.on_timeout(now);
+            }
+            if !is_h2 && !self.0.state.continue_timeout(&**scope, now) {
+                self.0.on_error(::Error::Timeout, &**scope);
+            }
         }
 
+        // h2 streams that timed out still need to be driven to an end
+        let events = if is_h2 {
+            EventSet::writable()
+        } else {
+            EventSet::none()
+        };
         let mut conn = Some(self);
         loop {
-            match conn.take().unwrap().ready(EventSet::none(), scope) {
+            match conn.take().unwrap().ready(events, scope) {
                 ReadyResult::Done(val) => return val,
                 ReadyResult::Continue(c) => conn = Some(c),
             }
         }
     }
 
-    fn on_remove(self) {
+    fn on_remove<F>(self, scope: &mut Scope<F>)
+    where F: MessageHandlerFactory<K, T, Output=H> {
+        scope.on_close(&self.0.key, self.0.id);
         self.0.on_remove()
     }
 
@@ -716,10 +1279,39 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> Conn<K, T, H> {
         &self.0.key
     }
 
+    /// A number no other connection has.
+    pub fn id(&self) -> usize {
+        self.0.id
+    }
+
     pub fn control(&self) -> Control {
         Control {
             tx: self.0.ctrl.0.clone(),
+            stream: 0,
+        }
+    }
+
+    /// Whether no message is in progress, such as between the messages of
+    /// a kept alive connection.
+    pub fn is_between_messages(&self) -> bool {
+        match self.0.state {
+            State::Init { .. } => true,
+            _ => false
+        }
+    }
+
+    /// Stops a multiplexed connection from taking new streams, such as with
+    /// a GOAWAY for HTTP/2, and lets the open ones finish.
+    ///
+    /// Returns whether it wasn't already closing.
+    pub fn close_streams(&mut self) -> bool {
+        if let State::Http2(ref mut http2) = self.0.state {
+            if !http2.conn.is_closing() {
+                http2.conn.close();
+                return true;
+            }
         }
+        false
     }
 
     pub fn is_idle(&self) -> bool {
@@ -729,6 +1321,37 @@ impl<K: Key, T: Transport, H: MessageHandler<T>> Conn<K, T, H> {
             false
         }
     }
+
+    /// Whether this is a client connection that can open more streams while
+    /// others are still active, and that hasn't been offered for that yet.
+    ///
+    /// Returns `true` at most once per connection, so the same connection is
+    /// not pooled several times over.
+    pub fn pool_streams(&mut self) -> bool {
+        if !self.0.keep_alive_enabled {
+            return false;
+        }
+        if let State::Http2(ref mut http2) = self.0.state {
+            if !http2.pooled && http2.conn.role() == h2::Role::Client && !http2.conn.is_closing() {
+                http2.pooled = true;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether this connection is idle, and hasn't been offered as idle
+    /// since its last message.
+    ///
+    /// Returns `true` at most once between messages, so the same connection
+    /// is not pooled several times over.
+    pub fn pool_idle(&mut self) -> bool {
+        if !self.is_idle() || self.0.idle_pooled {
+            return false;
+        }
+        self.0.idle_pooled = true;
+        true
+    }
 }
 
 enum State<H: MessageHandler<T>, T: Transport> {
@@ -746,7 +1369,10 @@ enum State<H: MessageHandler<T>, T: Transport> {
     /// when we've identified a certain message, we must always parse frame
     /// head to determine if the incoming frame is part of a current message,
     /// or a new one. This also means we could have multiple messages at once.
-    //Http2 {},
+    Http2(Http2<H, T>),
+    /// After a `101 Switching Protocols`, the connection belongs to the
+    /// `Upgrade` returned by the handler.
+    Upgraded(Upgraded<T>),
     Closed,
 }
 
@@ -777,6 +1403,8 @@ impl<H: MessageHandler<T>, T: Transport> State<H, T> {
         match *self {
             State::Init { timeout, .. } => timeout,
             State::Http1(ref http1) => http1.timeout,
+            State::Http2(ref http2) => http2.timeout(),
+            State::Upgraded(ref upgraded) => upgraded.timeout,
             State::Closed => None,
         }
     }
@@ -785,6 +1413,8 @@ impl<H: MessageHandler<T>, T: Transport> State<H, T> {
         match *self {
             State::Init { timeout_start, .. } => timeout_start,
             State::Http1(ref http1) => http1.timeout_start,
+            State::Http2(..) => None,
+            State::Upgraded(ref upgraded) => upgraded.timeout_start,
             State::Closed => None,
         }
     }
@@ -799,6 +1429,8 @@ impl<H: MessageHandler<T>, T: Transport> State<H, T> {
                 }
             },
             State::Http1(ref http1) => http1.timeout_elapsed(now),
+            State::Http2(ref http2) => http2.timeout_elapsed(now),
+            State::Upgraded(ref upgraded) => upgraded.timeout_elapsed(now),
             State::Closed => false,
         }
     }
@@ -815,12 +1447,38 @@ impl<H: MessageHandler<T>, T: Transport> fmt::Debug for State<H, T> {
             State::Http1(ref h1) => f.debug_tuple("Http1")
                 .field(h1)
                 .finish(),
+            State::Http2(ref h2) => f.debug_tuple("Http2")
+                .field(h2)
+                .finish(),
+            State::Upgraded(ref upgraded) => f.debug_tuple("Upgraded")
+                .field(upgraded)
+                .finish(),
             State::Closed => f.write_str("Closed")
         }
     }
 }
 
 impl<H: MessageHandler<T>, T: Transport> State<H, T> {
+    /// Stops waiting for a `100 Continue` that didn't come in time, and
+    /// sends the body anyway. Returns whether it was waiting for one.
+    fn continue_timeout<F, K>(&mut self, factory: &F, now: Time) -> bool
+    where F: MessageHandlerFactory<K, T>,
+          K: Key {
+        let next = match *self {
+            State::Http1(ref mut http1) => match mem::replace(&mut http1.expect_continue, Continue::No) {
+                Continue::Await(next) => next,
+                other => {
+                    http1.expect_continue = other;
+                    return false;
+                }
+            },
+            _ => return false,
+        };
+        debug!("no 100 Continue in time, sending the body");
+        self.update(next, factory, Some(now));
+        true
+    }
+
     fn update<F, K>(&mut self, next: Next, factory: &F, timeout_start: Option<Time>)
             where F: MessageHandlerFactory<K, T>,
                   K: Key
@@ -829,6 +1487,12 @@ impl<H: MessageHandler<T>, T: Transport> State<H, T> {
             let state = mem::replace(self, State::Closed);
             trace!("State::update state={:?}, interest={:?}", state, next.interest);
             match (state, next.interest) {
+                // the transport is handed back in on_remove, so an upgraded
+                // connection is never dropped here
+                (State::Upgraded(mut upgraded), _) => {
+                    upgraded.update(next, timeout_start);
+                    mem::replace(self, State::Upgraded(upgraded));
+                }
                 (_, Next_::Remove) |
                 (State::Closed, _) => return, // Keep State::Closed.
                 (State::Init { .. }, e) => {
@@ -843,6 +1507,19 @@ impl<H: MessageHandler<T>, T: Transport> State<H, T> {
                     match next_ {
                         Next_::Remove => unreachable!(), // Covered in (_, Next_::Remove) case above.
                         Next_::End => {
+                            let written = match http1.writing {
+                                Writing::Init |
+                                Writing::Head => false,
+                                Writing::Chunk(ref chunk) => chunk.is_written(),
+                                _ => true,
+                            };
+                            if written && http1.handler.switched_protocols() {
+                                // whatever comes next isn't HTTP anymore
+                                if let Some(handler) = http1.handler.on_upgrade() {
+                                    mem::replace(self, State::Upgraded(Upgraded::new(handler)));
+                                }
+                                return;
+                            }
                             let reading = match http1.reading {
                                 Reading::Body(ref decoder) |
                                 Reading::Wait(ref decoder) if decoder.is_eof() => {
@@ -1016,6 +1693,10 @@ impl<H: MessageHandler<T>, T: Transport> State<H, T> {
                     http1.timeout = timeout;
                     mem::replace(self, State::Http1(http1));
                 }
+                (State::Http2(http2), _) => {
+                    // streams are updated one by one, in Http2::wakeup
+                    mem::replace(self, State::Http2(http2));
+                }
             };
         }
 }
@@ -1027,6 +1708,9 @@ struct Http1<H, T> {
     reading: Reading,
     writing: Writing,
     keep_alive: bool,
+    expect_continue: Continue,
+    /// Whether the peer accepts trailers after a chunked body.
+    trailers: bool,
     timeout: Option<Duration>,
     timeout_start: Option<Time>,
     _marker: PhantomData<T>,
@@ -1040,6 +1724,46 @@ impl<H, T> Http1<H, T> {
             false
         }
     }
+
+    /// Writes the `100 Continue` owed to a client, once the handler wants
+    /// to read the body. Returns whether the response can be written now.
+    fn write_continue<W: io::Write>(&mut self, dst: &mut W) -> io::Result<bool> {
+        let pos = match self.expect_continue {
+            Continue::Send(pos) => pos,
+            _ => return Ok(true),
+        };
+        let wants_body = match self.reading {
+            Reading::Body(..) => true,
+            _ => false,
+        };
+        if pos == 0 && !wants_body {
+            if let Writing::Init = self.writing {
+                // the handler hasn't decided yet
+            } else {
+                // the response doesn't need the body, so the client never
+                // sends it, and the connection closes after
+                self.expect_continue = Continue::No;
+            }
+            return Ok(true);
+        }
+        match dst.write(&CONTINUE[pos..]) {
+            Ok(n) => {
+                if pos + n == CONTINUE.len() {
+                    trace!("wrote 100 Continue");
+                    self.expect_continue = Continue::No;
+                    Ok(true)
+                } else {
+                    self.expect_continue = Continue::Send(pos + n);
+                    Ok(false)
+                }
+            },
+            Err(e) => match e.kind() {
+                io::ErrorKind::WouldBlock |
+                io::ErrorKind::Interrupted => Ok(false),
+                _ => Err(e),
+            }
+        }
+    }
 }
 
 impl<H, T> fmt::Debug for Http1<H, T> {
@@ -1048,11 +1772,268 @@ impl<H, T> fmt::Debug for Http1<H, T> {
             .field("reading", &self.reading)
             .field("writing", &self.writing)
             .field("keep_alive", &self.keep_alive)
+            .field("expect_continue", &self.expect_continue)
+            .field("trailers", &self.trailers)
             .field("timeout", &self.timeout)
             .finish()
     }
 }
 
+struct Http2<H, T> {
+    conn: h2::Connection,
+    streams: HashMap<h2::StreamId, Http2Stream<H>>,
+    version: HttpVersion,
+    /// New streams the client pool has asked for.
+    pending_opens: usize,
+    /// Whether the client pool already knows it can open streams here.
+    pooled: bool,
+    idle_timeout: Option<Duration>,
+    idle_start: Option<Time>,
+    _marker: PhantomData<T>,
+}
+
+impl<H: MessageHandler<T>, T: Transport> Http2<H, T> {
+    fn new(conn: h2::Connection, version: HttpVersion, idle_timeout: Option<Duration>, now: Time) -> Http2<H, T> {
+        Http2 {
+            conn: conn,
+            streams: HashMap::new(),
+            version: version,
+            pending_opens: 0,
+            pooled: false,
+            idle_timeout: idle_timeout,
+            idle_start: Some(now),
+            _marker: PhantomData,
+        }
+    }
+
+    fn scheme(&self) -> &'static str {
+        match self.version {
+            HttpVersion::H2 => "https",
+            _ => "http",
+        }
+    }
+
+    fn interest(&self) -> Reg {
+        if self.conn.is_closing() && self.streams.is_empty() {
+            return if self.conn.buffered() > 0 {
+                Reg::Write
+            } else {
+                Reg::Remove
+            };
+        }
+        let write = self.conn.buffered() > 0 ||
+            (self.pending_opens > 0 && self.conn.can_open()) ||
+            self.streams.iter().any(|(&id, stream)| {
+                stream.wants_write() && (!stream.head_sent || self.conn.send_capacity(id) > 0)
+            });
+        // always reading, there could be a frame for any stream
+        if write {
+            Reg::ReadWrite
+        } else {
+            Reg::Read
+        }
+    }
+
+    fn wakeup(&mut self, id: h2::StreamId, next: Next, now: Time) {
+        if id == 0 {
+            match next.interest {
+                Next_::Write |
+                Next_::ReadWrite => self.pending_opens += 1,
+                Next_::End |
+                Next_::Remove => self.conn.close(),
+                Next_::Read |
+                Next_::Wait => (),
+            }
+        } else if let Some(stream) = self.streams.get_mut(&id) {
+            let next = stream.handler.on_wakeup(next);
+            stream.update(next, now);
+        }
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        if self.streams.is_empty() {
+            self.idle_timeout
+        } else {
+            self.streams.values().filter_map(|stream| stream.timeout).min()
+        }
+    }
+
+    fn idle_elapsed(&self, now: Time) -> bool {
+        if let (Some(timeout), Some(start)) = (self.idle_timeout, self.idle_start) {
+            self.streams.is_empty() && timeout_elapsed(timeout, start, now)
+        } else {
+            false
+        }
+    }
+
+    fn timeout_elapsed(&self, now: Time) -> bool {
+        self.idle_elapsed(now) || self.streams.values().any(|stream| stream.timeout_elapsed(now))
+    }
+
+    fn on_timeout(&mut self, now: Time) {
+        if self.idle_elapsed(now) {
+            trace!("h2 connection idle, closing");
+            self.conn.close();
+        }
+        for stream in self.streams.values_mut() {
+            if stream.timeout_elapsed(now) {
+                let next = stream.handler.on_error(::Error::Timeout);
+                stream.update(next, now);
+            }
+        }
+    }
+
+    /// Tells every stream that the connection is gone.
+    fn abort(&mut self, kind: io::ErrorKind, msg: &str) {
+        for (_, mut stream) in self.streams.drain() {
+            let _ = stream.handler.on_error(io::Error::new(kind, msg).into());
+        }
+    }
+
+    fn on_remove(mut self, transport: T) {
+        // the transport can only go to one handler, so the others are told
+        // the connection is gone instead
+        let first = self.streams.keys().min().cloned();
+        let first = first.and_then(|id| self.streams.remove(&id));
+        self.abort(io::ErrorKind::ConnectionAborted, "connection closed");
+        if let Some(stream) = first {
+            stream.handler.on_remove(transport);
+        }
+    }
+}
+
+impl<H, T> fmt::Debug for Http2<H, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Http2")
+            .field("version", &self.version)
+            .field("streams", &self.streams)
+            .field("conn", &self.conn)
+            .finish()
+    }
+}
+
+struct Http2Stream<H> {
+    handler: H,
+    interest: Next_,
+    head_received: bool,
+    head_sent: bool,
+    /// Whether the peer accepts trailers, which a client must have asked
+    /// for with `TE: trailers`.
+    trailers_allowed: bool,
+    recv_trailers: Option<Headers>,
+    send_trailers: Option<Headers>,
+    timeout: Option<Duration>,
+    timeout_start: Option<Time>,
+}
+
+impl<H> Http2Stream<H> {
+    fn new(handler: H, next: Next, now: Time) -> Http2Stream<H> {
+        Http2Stream {
+            handler: handler,
+            interest: next.interest,
+            head_received: false,
+            head_sent: false,
+            trailers_allowed: true,
+            recv_trailers: None,
+            send_trailers: None,
+            timeout: next.timeout,
+            timeout_start: Some(now),
+        }
+    }
+
+    fn update(&mut self, next: Next, now: Time) {
+        self.interest = next.interest;
+        self.timeout = next.timeout;
+        self.timeout_start = Some(now);
+    }
+
+    fn wants_read(&self) -> bool {
+        match self.interest {
+            Next_::Read | Next_::ReadWrite => true,
+            _ => false,
+        }
+    }
+
+    fn wants_write(&self) -> bool {
+        match self.interest {
+            Next_::Write | Next_::ReadWrite => true,
+            _ => false,
+        }
+    }
+
+    fn timeout_elapsed(&self, now: Time) -> bool {
+        if let (Some(timeout), Some(start)) = (self.timeout, self.timeout_start) {
+            timeout_elapsed(timeout, start, now)
+        } else {
+            false
+        }
+    }
+}
+
+impl<H> fmt::Debug for Http2Stream<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Http2Stream")
+            .field("interest", &self.interest)
+            .field("head_received", &self.head_received)
+            .field("head_sent", &self.head_sent)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+struct Upgraded<T: Transport> {
+    handler: Box<http::Upgrade<T>>,
+    /// Whether `on_start` was called yet.
+    started: bool,
+    interest: Next_,
+    timeout: Option<Duration>,
+    timeout_start: Option<Time>,
+}
+
+impl<T: Transport> Upgraded<T> {
+    fn new(handler: Box<http::Upgrade<T>>) -> Upgraded<T> {
+        Upgraded {
+            handler: handler,
+            started: false,
+            interest: Next_::Wait,
+            timeout: None,
+            timeout_start: None,
+        }
+    }
+
+    fn update(&mut self, next: Next, now: Option<Time>) {
+        self.interest = next.interest;
+        self.timeout = next.timeout;
+        self.timeout_start = now;
+    }
+
+    fn timeout_elapsed(&self, now: Time) -> bool {
+        if let (Some(timeout), Some(start)) = (self.timeout, self.timeout_start) {
+            timeout_elapsed(timeout, start, now)
+        } else {
+            false
+        }
+    }
+}
+
+impl<T: Transport> fmt::Debug for Upgraded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Upgraded")
+            .field("started", &self.started)
+            .field("interest", &self.interest)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// Whether a handler is done sending once it returns this.
+fn ends_stream(next: &Next) -> bool {
+    match next.interest {
+        Next_::Read | Next_::End => true,
+        _ => false,
+    }
+}
+
 #[derive(Debug)]
 enum Reading {
     Init,
@@ -1074,6 +2055,20 @@ enum Writing {
     Closed
 }
 
+/// Where a message with `Expect: 100-continue` is at.
+#[derive(Debug)]
+enum Continue {
+    No,
+    /// A server owes the client a `100 Continue` before reading the body,
+    /// and has written this much of it.
+    Send(usize),
+    /// A client waits for a `100 Continue` before writing the body, and
+    /// then carries on with this `Next`.
+    Await(Next),
+}
+
+const CONTINUE: &'static [u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
 #[derive(Debug)]
 struct Chunk {
     buf: Cow<'static, [u8]>,
@@ -1088,28 +2083,42 @@ impl Chunk {
 }
 
 pub trait MessageHandler<T: Transport> {
-    type Message: Http1Message;
+    type Message: Http1Message + Http2Message;
     fn on_incoming(&mut self, head: http::MessageHead<<Self::Message as Http1Message>::Incoming>, transport: &T) -> Next;
     fn on_outgoing(&mut self, head: &mut http::MessageHead<<Self::Message as Http1Message>::Outgoing>) -> Next;
     fn on_decode(&mut self, &mut http::Decoder<T>) -> Next;
     fn on_encode(&mut self, &mut http::Encoder<T>) -> Next;
     fn on_error(&mut self, err: ::Error) -> Next;
+    /// Whether the message was a `101 Switching Protocols` response.
+    fn switched_protocols(&self) -> bool;
+    /// Sees the `Next` a `Control` woke the message up with, before the
+    /// connection does.
+    fn on_wakeup(&mut self, next: Next) -> Next {
+        next
+    }
+    fn on_upgrade(&mut self) -> Option<Box<http::Upgrade<T>>>;
 
     fn on_remove(self, T) where Self: Sized;
 }
 
-pub struct Seed<'a, K: Key + 'a>(&'a K, &'a channel::Sender<Next>);
+pub struct Seed<'a, K: Key + 'a>(&'a K, &'a channel::Sender<(h2::StreamId, Next)>, h2::StreamId, bool);
 
 impl<'a, K: Key + 'a> Seed<'a, K> {
     pub fn control(&self) -> Control {
         Control {
             tx: self.1.clone(),
+            stream: self.2,
         }
     }
 
     pub fn key(&self) -> &K {
         self.0
     }
+
+    /// Whether the connection handled another message before this one.
+    pub fn is_reused(&self) -> bool {
+        self.3
+    }
 }
 
 
@@ -1119,6 +2128,21 @@ pub trait MessageHandlerFactory<K: Key, T: Transport> {
     fn create(&mut self, seed: Seed<K>) -> Option<Self::Output>;
 
     fn keep_alive_interest(&self) -> Next;
+
+    /// A connection for `key` closed, whose `Conn::id` was `id`.
+    fn on_close(&mut self, _key: &K, _id: usize) {}
+
+    /// A multiplexed connection for `key` stopped taking new streams, such
+    /// as after a GOAWAY. `refused` is how many the pool asked it for that
+    /// it won't open.
+    fn on_stop_streams(&mut self, _key: &K, _id: usize, _refused: usize) {}
+
+    /// The response to send for an incoming message that couldn't be
+    /// parsed, along with the bytes of its head read so far. They are empty
+    /// if the whole head was read, but its body can't be.
+    fn on_parse_error(&mut self, _err: &::Error, _buf: &[u8]) -> Option<(http::MessageHead<<<Self::Output as MessageHandler<T>>::Message as Http1Message>::Outgoing>, Vec<u8>)> {
+        None
+    }
 }
 
 pub trait Key: Eq + Hash + Clone + fmt::Debug {}
@@ -1,8 +1,20 @@
 use std::{cmp, usize};
-use std::io::{self, Read};
+use std::io::{self, BufRead, Read};
+
+use httparse;
+
+use header::Headers;
+
+use super::FORBIDDEN_TRAILERS;
 
 use self::Kind::{Length, Chunked, Eof};
 
+/// The most trailer bytes that are read after a chunked body.
+const MAX_TRAILER_SIZE: usize = 8192;
+
+/// The most trailer fields that are parsed.
+const MAX_TRAILERS: usize = 100;
+
 /// Decoders to handle different Transfer-Encodings.
 ///
 /// If a message body does not include a Transfer-Encoding, it *should*
@@ -10,19 +22,36 @@ use self::Kind::{Length, Chunked, Eof};
 #[derive(Debug, Clone)]
 pub struct Decoder {
     kind: Kind,
+    /// The trailer section of a chunked body, while it is read.
+    trailer: Vec<u8>,
+    trailers: Option<Headers>,
 }
 
 impl Decoder {
     pub fn length(x: u64) -> Decoder {
-        Decoder { kind: Kind::Length(x) }
+        Decoder::new(Kind::Length(x))
     }
 
     pub fn chunked() -> Decoder {
-        Decoder { kind: Kind::Chunked(ChunkedState::Size, 0) }
+        Decoder::new(Kind::Chunked(ChunkedState::Size, 0))
     }
 
     pub fn eof() -> Decoder {
-        Decoder { kind: Kind::Eof(false) }
+        Decoder::new(Kind::Eof(false))
+    }
+
+    fn new(kind: Kind) -> Decoder {
+        Decoder {
+            kind: kind,
+            trailer: Vec::new(),
+            trailers: None,
+        }
+    }
+
+    /// The trailer fields that came after a chunked body, once all of it
+    /// has been read.
+    pub fn trailers(&self) -> Option<&Headers> {
+        self.trailers.as_ref()
     }
 }
 
@@ -58,6 +87,7 @@ enum ChunkedState {
     Body,
     BodyCr,
     BodyLf,
+    Trailer,
     End,
 }
 
@@ -74,7 +104,7 @@ impl Decoder {
 }
 
 impl Decoder {
-    pub fn decode<R: Read>(&mut self, body: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    pub fn decode<R: BufRead>(&mut self, body: &mut R, buf: &mut [u8]) -> io::Result<usize> {
         match self.kind {
             Length(ref mut remaining) => {
                 trace!("Sized read, remaining={:?}", remaining);
@@ -98,7 +128,11 @@ impl Decoder {
                 loop {
                     let mut read = 0;
                     // advances the chunked state
-                    *state = try!(state.step(body, size, buf, &mut read));
+                    *state = if *state == ChunkedState::Trailer {
+                        try!(read_trailer(body, &mut self.trailer, &mut self.trailers))
+                    } else {
+                        try!(state.step(body, size, buf, &mut read))
+                    };
                     if *state == ChunkedState::End {
                         trace!("end of chunked");
                         return Ok(0);
@@ -148,6 +182,8 @@ impl ChunkedState {
             Body => try!(ChunkedState::read_body(body, size, buf, read)),
             BodyCr => try!(ChunkedState::read_body_cr(body)),
             BodyLf => try!(ChunkedState::read_body_lf(body)),
+            // needs somewhere to keep the trailer, see `read_trailer`
+            Trailer => ChunkedState::Trailer,
             End => ChunkedState::End,
         })
     }
@@ -201,7 +237,7 @@ impl ChunkedState {
         trace!("Chunk size is {:?}", size);
         match byte!(rdr) {
             b'\n' if *size > 0 => Ok(ChunkedState::Body),
-            b'\n' if *size == 0 => Ok(ChunkedState::End),
+            b'\n' if *size == 0 => Ok(ChunkedState::Trailer),
             _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid chunk size LF")),
         }
     }
@@ -252,6 +288,60 @@ impl ChunkedState {
     }
 }
 
+/// Reads the trailer section after the last chunk, up to the empty line
+/// that ends the body.
+///
+/// The trailer is parsed out of what `rdr` has buffered, and only the bytes
+/// up to the end of it are consumed, so the start of a following message
+/// stays in the buffer. Bytes already consumed are kept in `trailer` in
+/// case the read would block halfway.
+fn read_trailer<R: BufRead>(rdr: &mut R, trailer: &mut Vec<u8>, trailers: &mut Option<Headers>) -> io::Result<ChunkedState> {
+    loop {
+        let start = trailer.len();
+        let read = {
+            let bytes = try!(rdr.fill_buf());
+            trailer.extend_from_slice(bytes);
+            bytes.len()
+        };
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      "Unexpected eof during chunked trailer"));
+        }
+
+        let parsed = {
+            let mut raw = [httparse::EMPTY_HEADER; MAX_TRAILERS];
+            match httparse::parse_headers(&trailer[..], &mut raw) {
+                Ok(httparse::Status::Complete((len, raw))) => match Headers::from_raw(raw) {
+                    Ok(headers) => Some((len, headers)),
+                    Err(_) => return Err(invalid_trailer()),
+                },
+                Ok(httparse::Status::Partial) => None,
+                Err(_) => return Err(invalid_trailer()),
+            }
+        };
+        match parsed {
+            Some((len, _)) if len > MAX_TRAILER_SIZE => break,
+            Some((len, mut headers)) => {
+                rdr.consume(len - start);
+                for name in FORBIDDEN_TRAILERS {
+                    headers.remove_raw(name);
+                }
+                trace!("chunked trailers: {:?}", headers);
+                *trailers = Some(headers);
+                trailer.clear();
+                return Ok(ChunkedState::End);
+            },
+            None if trailer.len() > MAX_TRAILER_SIZE => break,
+            None => rdr.consume(read),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "Chunked trailer too large"))
+}
+
+fn invalid_trailer() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "Invalid chunked trailer")
+}
+
 #[cfg(test)]
 mod tests {
     use std::error::Error;
@@ -276,7 +366,7 @@ mod tests {
                 let desc = format!("read_size failed for {:?}", s);
                 state = result.expect(desc.as_str());
                 trace!("State {:?}", state);
-                if state == ChunkedState::Body || state == ChunkedState::End {
+                if state == ChunkedState::Body || state == ChunkedState::Trailer {
                     break;
                 }
             }
@@ -300,7 +390,7 @@ mod tests {
                     }
                 };
                 trace!("State {:?}", state);
-                if state == ChunkedState::Body || state == ChunkedState::End {
+                if state == ChunkedState::Body || state == ChunkedState::Trailer {
                     panic!(format!("Was Ok. Expected Err for {:?}", s));
                 }
             }
@@ -375,7 +465,7 @@ mod tests {
 
     #[test]
     fn test_read_chunked_after_eof() {
-        let content = b"10\r\n1234567890abcdef\r\n0\r\n";
+        let content = b"10\r\n1234567890abcdef\r\n0\r\n\r\n";
         let mut mock_buf = io::Cursor::new(content);
         let mut buf = [0u8; 50];
         let mut decoder = Decoder::chunked();
@@ -393,6 +483,45 @@ mod tests {
         // ensure read after eof also returns eof
         let count = decoder.decode(&mut mock_buf, &mut buf).expect("decode");
         assert_eq!(0, count);
+        assert_eq!(decoder.trailers().map(|t| t.len()), Some(0));
+    }
+
+    #[test]
+    fn test_read_chunked_trailers() {
+        use header::ContentLength;
+
+        let content = b"3\r\nfoo\r\n0\r\nContent-Length: 3\r\nGrpc-Status: 0\r\n\
+                        Authorization: secret\r\nHost: example.com\r\n\r\nnext";
+        for block_at in 0..content.len() {
+            let mut ins = Async::new(io::Cursor::new(&content[..]), block_at);
+            let mut decoder = Decoder::chunked();
+            let mut buf = [0u8; 10];
+            loop {
+                match decoder.decode(&mut ins, &mut buf) {
+                    Ok(0) => break,
+                    Ok(_) => (),
+                    Err(e) => {
+                        assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+                        assert!(decoder.trailers().is_none());
+                        ins.block_in(content.len());
+                    }
+                }
+            }
+            let trailers = decoder.trailers().expect("trailers");
+            // forbidden in a trailer, so dropped
+            assert_eq!(trailers.get::<ContentLength>(), None);
+            assert!(trailers.get_raw("Authorization").is_none());
+            assert!(trailers.get_raw("Host").is_none());
+            assert_eq!(trailers.get_raw("Grpc-Status").and_then(|raw| raw.one()), Some(&b"0"[..]));
+            // nothing past the body was read
+            let mut rest = Vec::new();
+            io::Read::read_to_end(&mut ins, &mut rest).unwrap();
+            assert_eq!(rest, b"next");
+        }
+
+        let mut bad = &b"0\r\nnot a header\r\n\r\n"[..];
+        let e = Decoder::chunked().decode(&mut bad, &mut [0u8; 10]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
     }
 
     // perform an async read using a custom buffer size and causing a blocking
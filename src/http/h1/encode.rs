@@ -2,14 +2,20 @@ use std::borrow::Cow;
 use std::cmp;
 use std::io::{self, Write};
 
+use header::Headers;
 use http::internal::{AtomicWrite, WriteBuf};
 
+use super::FORBIDDEN_TRAILERS;
+
 /// Encoders to handle different Transfer-Encodings.
 #[derive(Debug, Clone)]
 pub struct Encoder {
     kind: Kind,
     prefix: Prefix,
     is_closed: bool,
+    trailers: Option<Headers>,
+    /// Whether the peer asked for trailers with `TE: trailers`.
+    trailers_allowed: bool,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -28,6 +34,8 @@ impl Encoder {
             kind: Kind::Chunked(Chunked::Init),
             prefix: Prefix(None),
             is_closed: false,
+            trailers: None,
+            trailers_allowed: true,
         }
     }
 
@@ -36,6 +44,8 @@ impl Encoder {
             kind: Kind::Length(len),
             prefix: Prefix(None),
             is_closed: false,
+            trailers: None,
+            trailers_allowed: true,
         }
     }
 
@@ -63,13 +73,32 @@ impl Encoder {
         self.is_closed = true;
     }
 
+    /// Sets trailer fields to write after the last chunk.
+    ///
+    /// They are dropped if the body isn't chunked, or the peer didn't ask
+    /// for them. Fields that aren't allowed in a trailer are left out.
+    pub fn set_trailers(&mut self, mut trailers: Headers) {
+        for name in FORBIDDEN_TRAILERS {
+            trailers.remove_raw(name);
+        }
+        match self.kind {
+            Kind::Chunked(..) if self.trailers_allowed => self.trailers = Some(trailers),
+            _ => debug!("dropping trailers that can't be sent: {:?}", trailers),
+        }
+    }
+
+    /// Drops any trailers, because a server wasn't asked for them.
+    pub fn disallow_trailers(&mut self) {
+        self.trailers_allowed = false;
+    }
+
     pub fn finish(self) -> Option<WriteBuf<Cow<'static, [u8]>>> {
         let trailer = self.trailer();
         let buf = self.prefix.0;
 
         match (buf, trailer) {
             (Some(mut buf), Some(trailer)) => {
-                buf.bytes.extend_from_slice(trailer);
+                buf.bytes.extend_from_slice(&trailer);
                 Some(WriteBuf {
                     bytes: Cow::Owned(buf.bytes),
                     pos: buf.pos,
@@ -81,7 +110,7 @@ impl Encoder {
             }),
             (None, Some(trailer)) => {
                 Some(WriteBuf {
-                    bytes: Cow::Borrowed(trailer),
+                    bytes: trailer,
                     pos: 0,
                 })
             },
@@ -89,11 +118,12 @@ impl Encoder {
         }
     }
 
-    fn trailer(&self) -> Option<&'static [u8]> {
+    fn trailer(&self) -> Option<Cow<'static, [u8]>> {
         match self.kind {
-            Kind::Chunked(Chunked::Init) => {
-                Some(b"0\r\n\r\n")
-            }
+            Kind::Chunked(Chunked::Init) => match self.trailers {
+                Some(ref trailers) => Some(Cow::Owned(format!("0\r\n{}\r\n", trailers).into_bytes())),
+                None => Some(Cow::Borrowed(b"0\r\n\r\n")),
+            },
             _ => None
         }
     }
@@ -371,6 +401,37 @@ mod tests {
         assert_eq!(&dst[..], &b"7\r\nfoo bar\r\nD\r\nbaz quux herp\r\n0\r\n\r\n"[..]);
     }
 
+    #[test]
+    fn test_chunked_trailers() {
+        use header::{Headers, ContentType, ContentLength};
+
+        let mut dst = Buf::new();
+        let mut encoder = Encoder::chunked();
+        encoder.encode(&mut dst, b"foo bar").unwrap();
+        let mut trailers = Headers::new();
+        trailers.set_raw("Grpc-Status", "0");
+        encoder.set_trailers(trailers.clone());
+        let end = encoder.finish().unwrap();
+        assert_eq!(&end.bytes[..], &b"0\r\nGrpc-Status: 0\r\n\r\n"[..]);
+
+        // fields that frame the message aren't sent as trailers
+        let mut encoder = Encoder::chunked();
+        let mut forbidden = trailers.clone();
+        forbidden.set(ContentType::plaintext());
+        forbidden.set(ContentLength(3));
+        encoder.set_trailers(forbidden);
+        assert_eq!(&encoder.finish().unwrap().bytes[..], &b"0\r\nGrpc-Status: 0\r\n\r\n"[..]);
+
+        let mut encoder = Encoder::chunked();
+        encoder.disallow_trailers();
+        encoder.set_trailers(trailers.clone());
+        assert_eq!(&encoder.finish().unwrap().bytes[..], &b"0\r\n\r\n"[..]);
+
+        let mut encoder = Encoder::length(0);
+        encoder.set_trailers(trailers);
+        assert!(encoder.finish().is_none());
+    }
+
     #[test]
     fn test_sized_encode() {
         let mut dst = Buf::new();
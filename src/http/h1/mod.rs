@@ -25,6 +25,20 @@ mod decode;
 mod encode;
 mod parse;
 
+/// Fields that a sender must not put in a trailer, since they frame or
+/// route the message, modify the request, or carry credentials
+/// ([RFC 7230 Section 4.1.2](https://tools.ietf.org/html/rfc7230#section-4.1.2)).
+/// They are dropped if a peer sends them anyway, and aren't sent either.
+const FORBIDDEN_TRAILERS: &'static [&'static str] = &[
+    "Transfer-Encoding", "Content-Length", "Trailer",
+    "Host",
+    "Cache-Control", "Expect", "Max-Forwards", "Pragma", "Range", "TE",
+    "If-Match", "If-None-Match", "If-Modified-Since", "If-Unmodified-Since", "If-Range",
+    "Authorization", "Proxy-Authorization", "WWW-Authenticate", "Proxy-Authenticate",
+    "Cookie", "Set-Cookie",
+    "Content-Encoding", "Content-Type", "Content-Range",
+];
+
 /*
 fn should_have_response_body(method: &Method, status: u16) -> bool {
     trace!("should_have_response_body({:?}, {})", method, status);
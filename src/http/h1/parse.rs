@@ -54,6 +54,17 @@ impl Http1Message for ServerMessage {
         } else if head.headers.has::<header::TransferEncoding>() {
             //TODO: check for Transfer-Encoding: chunked
             Ok(Decoder::chunked())
+        } else if let Some(raw) = head.headers.get_raw("Content-Length") {
+            trace!("illegal Content-Length: {:?}", raw);
+            // only digits, but more than a u64 holds
+            let too_large = raw.one().map_or(false, |len| {
+                !len.is_empty() && len.iter().all(|b| b'0' <= *b && *b <= b'9')
+            });
+            if too_large {
+                Err(::Error::TooLarge)
+            } else {
+                Err(::Error::Header)
+            }
         } else {
             Ok(Decoder::length(0))
         }
@@ -70,7 +81,10 @@ impl Http1Message for ServerMessage {
 
         let mut is_chunked = true;
         let mut body = Encoder::chunked();
-        if let Some(cl) = head.headers.get::<header::ContentLength>() {
+        if no_body(head.subject.to_u16()) {
+            body = Encoder::length(0);
+            is_chunked = false;
+        } else if let Some(cl) = head.headers.get::<header::ContentLength>() {
             body = Encoder::length(**cl);
             is_chunked = false
         }
@@ -103,6 +117,10 @@ impl Http1Message for ServerMessage {
         }
         body
     }
+
+    fn is_informational(_head: &MessageHead<Self::Incoming>) -> bool {
+        false
+    }
 }
 
 impl Http1Message for ClientMessage {
@@ -137,14 +155,16 @@ impl Http1Message for ClientMessage {
         // 1. HEAD reponses, and Status 1xx, 204, and 304 cannot have a body.
         // 2. Status 2xx to a CONNECT cannot have a body.
         //
-        // First two steps taken care of before this method.
+        // HEAD responses are taken care of before this method.
         //
         // 3. Transfer-Encoding: chunked has a chunked body.
         // 4. If multiple differing Content-Length headers or invalid, close connection.
         // 5. Content-Length header has a sized body.
         // 6. Not Client.
         // 7. Read till EOF.
-        if let Some(&header::TransferEncoding(ref codings)) = inc.headers.get() {
+        if no_body(inc.subject.0) {
+            Ok(Decoder::length(0))
+        } else if let Some(&header::TransferEncoding(ref codings)) = inc.headers.get() {
             if codings.last() == Some(&header::Encoding::Chunked) {
                 Ok(Decoder::chunked())
             } else {
@@ -203,6 +223,11 @@ impl Http1Message for ClientMessage {
 
         body
     }
+
+    fn is_informational(head: &MessageHead<Self::Incoming>) -> bool {
+        // 101 is the last response before switching protocols
+        head.subject.0 >= 100 && head.subject.0 < 200 && head.subject.0 != 101
+    }
 }
 
 struct FastWrite<'a>(&'a mut Vec<u8>);
@@ -230,9 +255,14 @@ fn extend(dst: &mut Vec<u8>, data: &[u8]) {
     }
 }
 
+/// Status 1xx, 204 and 304 responses cannot have a body.
+fn no_body(code: u16) -> bool {
+    (code >= 100 && code < 200) || code == 204 || code == 304
+}
+
 #[cfg(test)]
 mod tests {
-    use http;
+    use http::{self, Http1Message};
     use super::{parse};
 
     #[test]
@@ -252,6 +282,40 @@ mod tests {
         assert_eq!(res.subject.1, "Howdy");
     }
 
+    #[test]
+    fn test_switching_protocols_has_no_body() {
+        let raw = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n";
+        let (res, _) = parse::<http::ClientMessage, _>(raw).unwrap().unwrap();
+        assert!(http::ClientMessage::decoder(&res).unwrap().is_eof());
+
+        let mut head = http::MessageHead::default();
+        head.subject = ::StatusCode::SwitchingProtocols;
+        let mut dst = Vec::new();
+        assert!(http::ServerMessage::encode(head, &mut dst).is_eof());
+        assert!(!String::from_utf8(dst).unwrap().contains("Transfer-Encoding"));
+    }
+
+    #[test]
+    fn test_expect_continue() {
+        let raw = b"PUT /upload HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n";
+        let (req, _) = parse::<http::ServerMessage, _>(raw).unwrap().unwrap();
+        assert!(req.expects_continue());
+
+        let raw = b"PUT /upload HTTP/1.0\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n";
+        let (req, _) = parse::<http::ServerMessage, _>(raw).unwrap().unwrap();
+        assert!(!req.expects_continue());
+
+        let raw = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n\r\n";
+        let (res, len) = parse::<http::ClientMessage, _>(raw).unwrap().unwrap();
+        assert!(http::ClientMessage::is_informational(&res));
+        let (res, _) = parse::<http::ClientMessage, _>(&raw[len..]).unwrap().unwrap();
+        assert!(!http::ClientMessage::is_informational(&res));
+
+        let raw = b"HTTP/1.1 101 Switching Protocols\r\n\r\n";
+        let (res, _) = parse::<http::ClientMessage, _>(raw).unwrap().unwrap();
+        assert!(!http::ClientMessage::is_informational(&res));
+    }
+
     #[cfg(feature = "nightly")]
     use test::Bencher;
 
@@ -0,0 +1,946 @@
+//! The HTTP/2 connection state, independent of any IO.
+//!
+//! A `Connection` is fed bytes read from the transport with `recv`, which
+//! reports what happened as `Event`s, and queues every frame it wants to
+//! send into an internal buffer, which is drained with `flush`.
+use std::cmp;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use super::StreamId;
+use super::frame::{self, Frame, Reason};
+use super::hpack::{self, HeaderField};
+
+/// The client connection preface.
+pub const PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The initial flow control window of connections and streams.
+const DEFAULT_WINDOW: u32 = 65_535;
+/// The largest a flow control window may grow.
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+/// The largest stream id either side may use.
+const MAX_STREAM_ID: StreamId = (1 << 31) - 1;
+/// How many concurrent streams a server allows its client to open.
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+const UPGRADE_RESPONSE: &'static [u8] = b"HTTP/1.1 101 Switching Protocols\r\n\
+    Connection: Upgrade\r\n\
+    Upgrade: h2c\r\n\r\n";
+
+/// An upper bound on the size of a header block, across CONTINUATIONs.
+const MAX_HEADER_BLOCK: usize = 1 << 16;
+
+/// Which end of the connection we are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// Something that happened while receiving frames.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// A complete header block for a stream. On a server, this is the start
+    /// of a new stream, or trailers. On a client, this is a response head,
+    /// or trailers. Informational responses are not reported.
+    Headers {
+        id: StreamId,
+        fields: Vec<HeaderField>,
+        end_stream: bool,
+    },
+    /// Data has been buffered for a stream, or its receiving side ended.
+    Data(StreamId),
+    /// The stream has been reset, and is gone.
+    Reset(StreamId, Reason),
+    /// A send window opened. Stream 0 means the connection window, which
+    /// may affect every stream.
+    Window(StreamId),
+    /// The peer will not process any streams above this id.
+    GoAway(StreamId, Reason),
+}
+
+#[derive(Debug)]
+struct Stream {
+    recv_buf: Vec<u8>,
+    recv_pos: usize,
+    recv_closed: bool,
+    recv_window: i64,
+    recv_unacked: u32,
+    send_closed: bool,
+    send_window: i64,
+}
+
+impl Stream {
+    fn new(send_window: u32) -> Stream {
+        Stream {
+            recv_buf: Vec::new(),
+            recv_pos: 0,
+            recv_closed: false,
+            recv_window: DEFAULT_WINDOW as i64,
+            recv_unacked: 0,
+            send_closed: false,
+            send_window: send_window as i64,
+        }
+    }
+
+    fn unread(&self) -> usize {
+        self.recv_buf.len() - self.recv_pos
+    }
+}
+
+#[derive(Debug)]
+struct PartialHeaders {
+    id: StreamId,
+    block: Vec<u8>,
+    end_stream: bool,
+}
+
+/// The state of an HTTP/2 connection.
+#[derive(Debug)]
+pub struct Connection {
+    role: Role,
+    awaiting_preface: bool,
+    settings_received: bool,
+
+    remote_initial_window: u32,
+    remote_max_frame_size: u32,
+    remote_max_concurrent: Option<u32>,
+
+    encoder: hpack::Encoder,
+    decoder: hpack::Decoder,
+    partial: Option<PartialHeaders>,
+
+    streams: HashMap<StreamId, Stream>,
+    next_id: StreamId,
+    last_remote_id: StreamId,
+
+    send_window: i64,
+    recv_window: i64,
+    recv_unacked: u32,
+
+    goaway_received: Option<StreamId>,
+    goaway_sent: bool,
+
+    out: Vec<u8>,
+    out_pos: usize,
+}
+
+impl Connection {
+    fn new(role: Role) -> Connection {
+        Connection {
+            role: role,
+            awaiting_preface: false,
+            settings_received: false,
+
+            remote_initial_window: DEFAULT_WINDOW,
+            remote_max_frame_size: frame::DEFAULT_MAX_FRAME_SIZE,
+            remote_max_concurrent: None,
+
+            encoder: hpack::Encoder::new(),
+            decoder: hpack::Decoder::new(),
+            partial: None,
+
+            streams: HashMap::new(),
+            next_id: match role {
+                Role::Client => 1,
+                Role::Server => 2,
+            },
+            last_remote_id: 0,
+
+            send_window: DEFAULT_WINDOW as i64,
+            recv_window: DEFAULT_WINDOW as i64,
+            recv_unacked: 0,
+
+            goaway_received: None,
+            goaway_sent: false,
+
+            out: Vec::new(),
+            out_pos: 0,
+        }
+    }
+
+    /// Starts a client connection, queueing the connection preface.
+    pub fn client() -> Connection {
+        let mut conn = Connection::new(Role::Client);
+        conn.out.extend_from_slice(PREFACE);
+        frame::write_settings(&mut conn.out, &[(frame::SETTINGS_ENABLE_PUSH, 0)]);
+        conn
+    }
+
+    /// Starts a server connection, which expects to read the client
+    /// preface first.
+    pub fn server() -> Connection {
+        let mut conn = Connection::new(Role::Server);
+        conn.awaiting_preface = true;
+        frame::write_settings(&mut conn.out, &[
+            (frame::SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS)
+        ]);
+        conn
+    }
+
+    /// Starts a server connection upgraded from HTTP/1.1 by a request with
+    /// `Upgrade: h2c`, queueing the `101 Switching Protocols` response.
+    ///
+    /// `settings` is the decoded `HTTP2-Settings` header. The upgraded
+    /// request becomes stream 1, which the client has already finished.
+    pub fn server_upgrade(settings: &[u8]) -> Result<Connection, Reason> {
+        let params = try!(frame::parse_settings(settings));
+        let mut conn = Connection::new(Role::Server);
+        // the 101 response acknowledges these, no SETTINGS ack is sent
+        try!(conn.apply_settings(params));
+        conn.out.extend_from_slice(UPGRADE_RESPONSE);
+        conn.awaiting_preface = true;
+        frame::write_settings(&mut conn.out, &[
+            (frame::SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS)
+        ]);
+        let mut stream = Stream::new(conn.remote_initial_window);
+        stream.recv_closed = true;
+        conn.streams.insert(1, stream);
+        conn.last_remote_id = 1;
+        Ok(conn)
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Parses and handles as many complete frames as are in `buf`.
+    ///
+    /// Returns how many bytes were used. A returned error is a connection
+    /// error, for which a GOAWAY has already been queued.
+    pub fn recv(&mut self, buf: &[u8], events: &mut Vec<Event>) -> Result<usize, Reason> {
+        let mut pos = 0;
+        if self.awaiting_preface {
+            if buf.len() < PREFACE.len() {
+                if PREFACE.starts_with(buf) {
+                    return Ok(0);
+                }
+                return Err(self.go_away(Reason::ProtocolError));
+            }
+            if &buf[..PREFACE.len()] != PREFACE {
+                return Err(self.go_away(Reason::ProtocolError));
+            }
+            self.awaiting_preface = false;
+            pos = PREFACE.len();
+        }
+        loop {
+            match frame::parse(&buf[pos..], frame::DEFAULT_MAX_FRAME_SIZE) {
+                Ok(Some((frame, n))) => {
+                    pos += n;
+                    if let Err(reason) = self.recv_frame(frame, events) {
+                        debug!("h2 connection error: {:?}", reason);
+                        return Err(self.go_away(reason));
+                    }
+                },
+                Ok(None) => return Ok(pos),
+                Err(reason) => return Err(self.go_away(reason)),
+            }
+        }
+    }
+
+    fn recv_frame(&mut self, frame: Frame, events: &mut Vec<Event>) -> Result<(), Reason> {
+        if !self.settings_received {
+            match frame {
+                Frame::Settings { ack: false, .. } => (),
+                _ => return Err(Reason::ProtocolError),
+            }
+        }
+        if let Some(ref partial) = self.partial {
+            match frame {
+                Frame::Continuation { id, .. } if id == partial.id => (),
+                _ => return Err(Reason::ProtocolError),
+            }
+        }
+
+        match frame {
+            Frame::Data { id, data, flow_len, end_stream } => {
+                self.recv_data(id, data, flow_len, end_stream, events)
+            },
+            Frame::Headers { id, block, end_stream, end_headers } => {
+                self.partial = Some(PartialHeaders {
+                    id: id,
+                    block: block.to_vec(),
+                    end_stream: end_stream,
+                });
+                if end_headers {
+                    self.recv_headers(events)
+                } else {
+                    Ok(())
+                }
+            },
+            Frame::Continuation { block, end_headers, .. } => {
+                match self.partial {
+                    Some(ref mut partial) => {
+                        partial.block.extend_from_slice(block);
+                        if partial.block.len() > MAX_HEADER_BLOCK {
+                            return Err(Reason::EnhanceYourCalm);
+                        }
+                    },
+                    None => return Err(Reason::ProtocolError),
+                }
+                if end_headers {
+                    self.recv_headers(events)
+                } else {
+                    Ok(())
+                }
+            },
+            Frame::Priority { id, dependency } => {
+                if id == dependency {
+                    self.stream_error(id, Reason::ProtocolError, events);
+                }
+                Ok(())
+            },
+            Frame::Reset { id, reason } => {
+                if self.is_idle(id) {
+                    return Err(Reason::ProtocolError);
+                }
+                if let Some(stream) = self.streams.remove(&id) {
+                    self.release_connection(stream.unread() as u32);
+                    events.push(Event::Reset(id, reason));
+                }
+                Ok(())
+            },
+            Frame::Settings { ack: true, .. } => Ok(()),
+            Frame::Settings { ack: false, params } => self.recv_settings(params, events),
+            // we never enable server push, and clients cannot push at all
+            Frame::PushPromise { .. } => Err(Reason::ProtocolError),
+            Frame::Ping { ack, payload } => {
+                if !ack {
+                    frame::write_ping(&mut self.out, payload, true);
+                }
+                Ok(())
+            },
+            Frame::GoAway { last_id, reason } => {
+                debug!("h2 received GOAWAY last_id={}, reason={:?}", last_id, reason);
+                self.goaway_received = Some(last_id);
+                events.push(Event::GoAway(last_id, reason));
+                Ok(())
+            },
+            Frame::WindowUpdate { id, increment } => self.recv_window_update(id, increment, events),
+            Frame::Unknown => Ok(()),
+        }
+    }
+
+    fn recv_headers(&mut self, events: &mut Vec<Event>) -> Result<(), Reason> {
+        let partial = self.partial.take().expect("recv_headers without partial headers");
+        // the block must always be decoded, even if the stream is ignored,
+        // to keep the decoder table in sync
+        let fields = try!(self.decoder.decode(&partial.block).map_err(|e| {
+            debug!("hpack error: {}", e);
+            Reason::CompressionError
+        }));
+        let id = partial.id;
+        let end_stream = partial.end_stream;
+
+        let known = match self.streams.get_mut(&id) {
+            Some(stream) => {
+                if stream.recv_closed {
+                    None
+                } else {
+                    if is_informational(self.role, &fields) {
+                        return if end_stream {
+                            Err(Reason::ProtocolError)
+                        } else {
+                            Ok(())
+                        };
+                    }
+                    stream.recv_closed = end_stream;
+                    Some(fields)
+                }
+            },
+            None => {
+                return self.recv_headers_unknown(id, fields, end_stream, events);
+            }
+        };
+        match known {
+            Some(fields) => {
+                events.push(Event::Headers {
+                    id: id,
+                    fields: fields,
+                    end_stream: end_stream,
+                });
+            },
+            None => self.stream_error(id, Reason::StreamClosed, events),
+        }
+        Ok(())
+    }
+
+    fn recv_headers_unknown(&mut self, id: StreamId, fields: Vec<HeaderField>, end_stream: bool, events: &mut Vec<Event>) -> Result<(), Reason> {
+        if self.is_local(id) || self.role == Role::Client {
+            if self.is_idle(id) {
+                return Err(Reason::ProtocolError);
+            }
+            frame::write_reset(&mut self.out, id, Reason::StreamClosed);
+            return Ok(());
+        }
+        if id <= self.last_remote_id {
+            frame::write_reset(&mut self.out, id, Reason::StreamClosed);
+            return Ok(());
+        }
+        self.last_remote_id = id;
+        if self.goaway_sent {
+            trace!("h2 ignoring stream {} after GOAWAY", id);
+            return Ok(());
+        }
+        if self.streams.len() >= MAX_CONCURRENT_STREAMS as usize {
+            debug!("h2 refusing stream {}, max concurrent streams reached", id);
+            frame::write_reset(&mut self.out, id, Reason::RefusedStream);
+            return Ok(());
+        }
+        let mut stream = Stream::new(self.remote_initial_window);
+        stream.recv_closed = end_stream;
+        self.streams.insert(id, stream);
+        events.push(Event::Headers {
+            id: id,
+            fields: fields,
+            end_stream: end_stream,
+        });
+        Ok(())
+    }
+
+    fn recv_data(&mut self, id: StreamId, data: &[u8], flow_len: u32, end_stream: bool, events: &mut Vec<Event>) -> Result<(), Reason> {
+        if flow_len as i64 > self.recv_window {
+            return Err(Reason::FlowControlError);
+        }
+        self.recv_window -= flow_len as i64;
+
+        let result = match self.streams.get_mut(&id) {
+            Some(stream) => {
+                if stream.recv_closed {
+                    Err(Reason::StreamClosed)
+                } else if flow_len as i64 > stream.recv_window {
+                    Err(Reason::FlowControlError)
+                } else {
+                    stream.recv_window -= flow_len as i64;
+                    stream.recv_buf.extend_from_slice(data);
+                    stream.recv_closed = end_stream;
+                    Ok(())
+                }
+            },
+            None => Err(Reason::StreamClosed),
+        };
+
+        match result {
+            Ok(()) => {
+                // padding never reaches the user, so give it back right away
+                self.release(id, flow_len - data.len() as u32);
+                events.push(Event::Data(id));
+                Ok(())
+            },
+            Err(reason) => {
+                if self.is_idle(id) {
+                    return Err(Reason::ProtocolError);
+                }
+                self.release_connection(flow_len);
+                self.stream_error(id, reason, events);
+                Ok(())
+            }
+        }
+    }
+
+    fn recv_settings(&mut self, params: Vec<(u16, u32)>, events: &mut Vec<Event>) -> Result<(), Reason> {
+        let window_opened = try!(self.apply_settings(params));
+        self.settings_received = true;
+        frame::write_settings_ack(&mut self.out);
+        if window_opened {
+            events.push(Event::Window(0));
+        }
+        Ok(())
+    }
+
+    /// Returns whether stream send windows grew.
+    fn apply_settings(&mut self, params: Vec<(u16, u32)>) -> Result<bool, Reason> {
+        let mut window_opened = false;
+        for (key, value) in params {
+            match key {
+                frame::SETTINGS_ENABLE_PUSH => {
+                    if value > 1 {
+                        return Err(Reason::ProtocolError);
+                    }
+                },
+                frame::SETTINGS_MAX_CONCURRENT_STREAMS => {
+                    self.remote_max_concurrent = Some(value);
+                },
+                frame::SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > MAX_WINDOW {
+                        return Err(Reason::FlowControlError);
+                    }
+                    let delta = value as i64 - self.remote_initial_window as i64;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > MAX_WINDOW {
+                            return Err(Reason::FlowControlError);
+                        }
+                    }
+                    self.remote_initial_window = value;
+                    window_opened = window_opened || delta > 0;
+                },
+                frame::SETTINGS_MAX_FRAME_SIZE => {
+                    if value < frame::DEFAULT_MAX_FRAME_SIZE || value > frame::MAX_MAX_FRAME_SIZE {
+                        return Err(Reason::ProtocolError);
+                    }
+                    self.remote_max_frame_size = value;
+                },
+                // our encoder never uses the dynamic table, and the header
+                // list size is only advisory
+                _ => (),
+            }
+        }
+        Ok(window_opened)
+    }
+
+    fn recv_window_update(&mut self, id: StreamId, increment: u32, events: &mut Vec<Event>) -> Result<(), Reason> {
+        if id == 0 {
+            if increment == 0 {
+                return Err(Reason::ProtocolError);
+            }
+            self.send_window += increment as i64;
+            if self.send_window > MAX_WINDOW {
+                return Err(Reason::FlowControlError);
+            }
+            events.push(Event::Window(0));
+            return Ok(());
+        }
+
+        if self.is_idle(id) {
+            return Err(Reason::ProtocolError);
+        }
+        let result = match self.streams.get_mut(&id) {
+            Some(stream) => {
+                stream.send_window += increment as i64;
+                if increment == 0 {
+                    Err(Reason::ProtocolError)
+                } else if stream.send_window > MAX_WINDOW {
+                    Err(Reason::FlowControlError)
+                } else {
+                    Ok(())
+                }
+            },
+            // a stream we've already closed
+            None => return Ok(()),
+        };
+        match result {
+            Ok(()) => events.push(Event::Window(id)),
+            Err(reason) => self.stream_error(id, reason, events),
+        }
+        Ok(())
+    }
+
+    /// Whether `id` belongs to a stream that has never been used.
+    fn is_idle(&self, id: StreamId) -> bool {
+        if self.is_local(id) {
+            id >= self.next_id
+        } else {
+            id > self.last_remote_id
+        }
+    }
+
+    /// Whether `id` is of a stream we would initiate.
+    fn is_local(&self, id: StreamId) -> bool {
+        match self.role {
+            Role::Client => id % 2 == 1,
+            Role::Server => id % 2 == 0,
+        }
+    }
+
+    fn stream_error(&mut self, id: StreamId, reason: Reason, events: &mut Vec<Event>) {
+        debug!("h2 stream error on {}: {:?}", id, reason);
+        frame::write_reset(&mut self.out, id, reason);
+        if let Some(stream) = self.streams.remove(&id) {
+            self.release_connection(stream.unread() as u32);
+            events.push(Event::Reset(id, reason));
+        }
+    }
+
+    /// Gives back `n` bytes of receive window, once they have been consumed.
+    fn release(&mut self, id: StreamId, n: u32) {
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.recv_unacked += n;
+            if stream.recv_unacked >= DEFAULT_WINDOW / 2 && !stream.recv_closed {
+                frame::write_window_update(&mut self.out, id, stream.recv_unacked);
+                stream.recv_window += stream.recv_unacked as i64;
+                stream.recv_unacked = 0;
+            }
+        }
+        self.release_connection(n);
+    }
+
+    fn release_connection(&mut self, n: u32) {
+        self.recv_unacked += n;
+        if self.recv_unacked >= DEFAULT_WINDOW / 2 {
+            frame::write_window_update(&mut self.out, 0, self.recv_unacked);
+            self.recv_window += self.recv_unacked as i64;
+            self.recv_unacked = 0;
+        }
+    }
+
+    fn go_away(&mut self, reason: Reason) -> Reason {
+        if !self.goaway_sent {
+            frame::write_goaway(&mut self.out, self.last_remote_id, reason);
+            self.goaway_sent = true;
+        }
+        reason
+    }
+
+    /// Starts a graceful shutdown, letting current streams finish.
+    pub fn close(&mut self) {
+        self.go_away(Reason::NoError);
+    }
+
+    /// Whether either side has sent a GOAWAY.
+    pub fn is_closing(&self) -> bool {
+        self.goaway_sent || self.goaway_received.is_some()
+    }
+
+    /// Whether a new stream can be opened right now.
+    pub fn can_open(&self) -> bool {
+        self.role == Role::Client &&
+            !self.is_closing() &&
+            self.next_id <= MAX_STREAM_ID &&
+            self.remote_max_concurrent.map_or(true, |max| self.streams.len() < max as usize)
+    }
+
+    /// The id the next opened stream will get.
+    pub fn next_stream_id(&self) -> StreamId {
+        self.next_id
+    }
+
+    /// Opens a new client stream.
+    pub fn open(&mut self) -> StreamId {
+        debug_assert!(self.can_open());
+        let id = self.next_id;
+        self.next_id += 2;
+        self.streams.insert(id, Stream::new(self.remote_initial_window));
+        id
+    }
+
+    /// How many streams are currently open.
+    #[cfg(test)]
+    pub fn active_streams(&self) -> usize {
+        self.streams.len()
+    }
+
+    #[cfg(test)]
+    pub fn has_stream(&self, id: StreamId) -> bool {
+        self.streams.contains_key(&id)
+    }
+
+    pub fn is_recv_closed(&self, id: StreamId) -> bool {
+        self.streams.get(&id).map_or(true, |stream| stream.recv_closed)
+    }
+
+    pub fn is_send_closed(&self, id: StreamId) -> bool {
+        self.streams.get(&id).map_or(true, |stream| stream.send_closed)
+    }
+
+    /// Whether reading the stream would not block.
+    pub fn is_readable(&self, id: StreamId) -> bool {
+        self.streams.get(&id).map_or(false, |stream| stream.unread() > 0 || stream.recv_closed)
+    }
+
+    /// How many bytes of DATA could be sent on a stream right now.
+    pub fn send_capacity(&self, id: StreamId) -> usize {
+        match self.streams.get(&id) {
+            Some(stream) if !stream.send_closed => {
+                cmp::max(0, cmp::min(stream.send_window, self.send_window)) as usize
+            },
+            _ => 0
+        }
+    }
+
+    /// Queues a header block on a stream.
+    pub fn send_headers(&mut self, id: StreamId, fields: &[HeaderField], end_stream: bool) {
+        let mut block = Vec::new();
+        self.encoder.encode(fields.iter().map(|&(ref name, ref value)| (&name[..], &value[..])), &mut block);
+        frame::write_headers(&mut self.out, id, &block, end_stream, self.remote_max_frame_size as usize);
+        if end_stream {
+            if let Some(stream) = self.streams.get_mut(&id) {
+                stream.send_closed = true;
+            }
+        }
+    }
+
+    /// Queues as much of `data` as flow control allows on a stream.
+    ///
+    /// The stream is only ended if all of `data` could be sent.
+    pub fn send_data(&mut self, id: StreamId, data: &[u8], end_stream: bool) -> usize {
+        let max_frame_size = self.remote_max_frame_size as usize;
+        let stream = match self.streams.get_mut(&id) {
+            Some(stream) => stream,
+            None => return 0,
+        };
+        if stream.send_closed {
+            return 0;
+        }
+        let window = cmp::max(0, cmp::min(stream.send_window, self.send_window)) as usize;
+        let n = cmp::min(window, data.len());
+        let end_stream = end_stream && n == data.len();
+        if n == 0 {
+            if end_stream {
+                frame::write_data(&mut self.out, id, b"", true);
+                stream.send_closed = true;
+            }
+            return 0;
+        }
+        let mut chunks = data[..n].chunks(max_frame_size).peekable();
+        while let Some(chunk) = chunks.next() {
+            let last = chunks.peek().is_none();
+            frame::write_data(&mut self.out, id, chunk, end_stream && last);
+        }
+        stream.send_window -= n as i64;
+        self.send_window -= n as i64;
+        stream.send_closed = end_stream;
+        n
+    }
+
+    /// Reads buffered DATA from a stream.
+    ///
+    /// Returns `None` if no data is available yet, and `Some(0)` once the
+    /// stream has ended.
+    pub fn read_data(&mut self, id: StreamId, buf: &mut [u8]) -> Option<usize> {
+        let n = match self.streams.get_mut(&id) {
+            Some(stream) => {
+                let available = stream.unread();
+                if available == 0 {
+                    return if stream.recv_closed {
+                        Some(0)
+                    } else {
+                        None
+                    };
+                }
+                let n = cmp::min(available, buf.len());
+                buf[..n].copy_from_slice(&stream.recv_buf[stream.recv_pos..stream.recv_pos + n]);
+                stream.recv_pos += n;
+                if stream.recv_pos == stream.recv_buf.len() {
+                    stream.recv_buf.clear();
+                    stream.recv_pos = 0;
+                }
+                n
+            },
+            None => return Some(0),
+        };
+        self.release(id, n as u32);
+        Some(n)
+    }
+
+    /// Resets a stream, forgetting about it.
+    pub fn reset(&mut self, id: StreamId, reason: Reason) {
+        debug!("h2 reset stream {}: {:?}", id, reason);
+        frame::write_reset(&mut self.out, id, reason);
+        self.remove(id);
+    }
+
+    /// Forgets a stream, which should have been closed on both sides.
+    pub fn remove(&mut self, id: StreamId) {
+        if let Some(stream) = self.streams.remove(&id) {
+            self.release_connection(stream.unread() as u32);
+        }
+    }
+
+    /// How many bytes are queued to be written.
+    pub fn buffered(&self) -> usize {
+        self.out.len() - self.out_pos
+    }
+
+    /// Writes queued frames to the transport.
+    pub fn flush<W: Write>(&mut self, dst: &mut W) -> io::Result<()> {
+        while self.out_pos < self.out.len() {
+            match try!(dst.write(&self.out[self.out_pos..])) {
+                0 => return Err(io::Error::new(io::ErrorKind::WriteZero, "write zero")),
+                n => self.out_pos += n,
+            }
+        }
+        self.out.clear();
+        self.out_pos = 0;
+        Ok(())
+    }
+}
+
+fn is_informational(role: Role, fields: &[HeaderField]) -> bool {
+    role == Role::Client && fields.iter().any(|&(ref name, ref value)| {
+        name == b":status" && value.first() == Some(&b'1')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Connection, Event};
+    use super::super::frame::{self, Reason};
+
+    fn fields(list: &[(&str, &str)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        list.iter().map(|&(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec())).collect()
+    }
+
+    fn transfer(from: &mut Connection, to: &mut Connection) -> Vec<Event> {
+        let mut buf = Vec::new();
+        from.flush(&mut buf).unwrap();
+        let mut events = Vec::new();
+        assert_eq!(to.recv(&buf, &mut events), Ok(buf.len()));
+        events
+    }
+
+    fn handshake() -> (Connection, Connection) {
+        let mut client = Connection::client();
+        let mut server = Connection::server();
+        assert_eq!(transfer(&mut client, &mut server), vec![]);
+        assert_eq!(transfer(&mut server, &mut client), vec![]);
+        assert_eq!(transfer(&mut client, &mut server), vec![]);
+        (client, server)
+    }
+
+    #[test]
+    fn test_request_response() {
+        let (mut client, mut server) = handshake();
+
+        assert!(client.can_open());
+        let id = client.open();
+        assert_eq!(id, 1);
+        let req = fields(&[(":method", "POST"), (":scheme", "http"), (":path", "/"), (":authority", "hyper.rs")]);
+        client.send_headers(id, &req, false);
+        assert_eq!(client.send_data(id, b"hello", true), 5);
+        assert!(client.is_send_closed(id));
+
+        assert_eq!(transfer(&mut client, &mut server), vec![
+            Event::Headers { id: 1, fields: req, end_stream: false },
+            Event::Data(1),
+        ]);
+        let mut buf = [0; 16];
+        assert_eq!(server.read_data(1, &mut buf), Some(5));
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(server.read_data(1, &mut buf), Some(0));
+
+        let res = fields(&[(":status", "200")]);
+        server.send_headers(1, &res, true);
+        server.remove(1);
+        assert_eq!(transfer(&mut server, &mut client), vec![
+            Event::Headers { id: 1, fields: res, end_stream: true },
+        ]);
+        assert!(client.is_recv_closed(1));
+    }
+
+    #[test]
+    fn test_preface_in_pieces() {
+        let mut server = Connection::server();
+        let mut events = Vec::new();
+        assert_eq!(server.recv(b"PRI * HTTP", &mut events), Ok(0));
+        assert_eq!(server.recv(b"GET / HTTP/1.1\r\n", &mut events), Err(Reason::ProtocolError));
+    }
+
+    #[test]
+    fn test_server_upgrade() {
+        let settings = [0, 4, 0, 0, 0, 10]; // SETTINGS_INITIAL_WINDOW_SIZE = 10
+        let mut server = Connection::server_upgrade(&settings).unwrap();
+        let mut buf = Vec::new();
+        server.flush(&mut buf).unwrap();
+        let head = b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+        assert_eq!(&buf[..head.len()], &head[..]);
+        assert_eq!(frame::parse(&buf[head.len()..], frame::DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap().1,
+                   buf.len() - head.len());
+
+        // the upgraded request is stream 1, and can only be responded to
+        assert!(server.is_recv_closed(1));
+        assert_eq!(server.send_capacity(1), 10);
+
+        let mut client = Connection::client();
+        assert_eq!(transfer(&mut client, &mut server), vec![]);
+
+        assert_eq!(Connection::server_upgrade(&[0; 5]).err(), Some(Reason::FrameSizeError));
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let (mut client, mut server) = handshake();
+        let id = client.open();
+        client.send_headers(id, &fields(&[(":method", "POST")]), false);
+
+        let big = vec![0; 70_000];
+        assert_eq!(client.send_data(id, &big, true), 65_535);
+        assert!(!client.is_send_closed(id));
+        assert_eq!(client.send_capacity(id), 0);
+        transfer(&mut client, &mut server);
+
+        // reading it all gives the window back
+        let mut buf = vec![0; 70_000];
+        assert_eq!(server.read_data(id, &mut buf), Some(65_535));
+        let events = transfer(&mut server, &mut client);
+        assert_eq!(events, vec![Event::Window(id), Event::Window(0)]);
+        assert_eq!(client.send_data(id, &big[65_535..], true), 70_000 - 65_535);
+        assert!(client.is_send_closed(id));
+    }
+
+    #[test]
+    fn test_flow_control_violation() {
+        let (mut client, mut server) = handshake();
+        let id = client.open();
+        client.send_headers(id, &fields(&[(":method", "POST")]), false);
+        transfer(&mut client, &mut server);
+
+        let mut buf = Vec::new();
+        for _ in 0..5 {
+            frame::write_data(&mut buf, id, &[0; 16_000], false);
+        }
+        let mut events = Vec::new();
+        assert_eq!(server.recv(&buf, &mut events), Err(Reason::FlowControlError));
+    }
+
+    #[test]
+    fn test_settings_ack_required_first() {
+        let mut server = Connection::server();
+        let mut buf = super::PREFACE.to_vec();
+        frame::write_ping(&mut buf, [0; 8], false);
+        let mut events = Vec::new();
+        assert_eq!(server.recv(&buf, &mut events), Err(Reason::ProtocolError));
+    }
+
+    #[test]
+    fn test_reset_stream() {
+        let (mut client, mut server) = handshake();
+        let id = client.open();
+        client.send_headers(id, &fields(&[(":method", "GET")]), true);
+        transfer(&mut client, &mut server);
+
+        server.reset(id, Reason::RefusedStream);
+        assert_eq!(transfer(&mut server, &mut client), vec![Event::Reset(id, Reason::RefusedStream)]);
+        assert!(!client.has_stream(id));
+    }
+
+    #[test]
+    fn test_max_concurrent_streams() {
+        let (mut client, mut server) = handshake();
+        assert_eq!(client.remote_max_concurrent, Some(super::MAX_CONCURRENT_STREAMS));
+        for _ in 0..super::MAX_CONCURRENT_STREAMS {
+            let id = client.open();
+            client.send_headers(id, &fields(&[(":method", "GET")]), true);
+        }
+        assert!(!client.can_open());
+        transfer(&mut client, &mut server);
+        assert_eq!(server.active_streams(), super::MAX_CONCURRENT_STREAMS as usize);
+    }
+
+    #[test]
+    fn test_ping_pong() {
+        let (mut client, mut server) = handshake();
+        let mut buf = Vec::new();
+        frame::write_ping(&mut buf, [7; 8], false);
+        let mut events = Vec::new();
+        server.recv(&buf, &mut events).unwrap();
+        let mut out = Vec::new();
+        server.flush(&mut out).unwrap();
+        let mut expected = Vec::new();
+        frame::write_ping(&mut expected, [7; 8], true);
+        assert_eq!(out, expected);
+        assert_eq!(transfer(&mut server, &mut client), vec![]);
+    }
+
+    #[test]
+    fn test_informational_response_skipped() {
+        let (mut client, mut server) = handshake();
+        let id = client.open();
+        client.send_headers(id, &fields(&[(":method", "GET")]), true);
+        transfer(&mut client, &mut server);
+        server.send_headers(id, &fields(&[(":status", "100")]), false);
+        server.send_headers(id, &fields(&[(":status", "204")]), true);
+        assert_eq!(transfer(&mut server, &mut client), vec![
+            Event::Headers { id: id, fields: fields(&[(":status", "204")]), end_stream: true },
+        ]);
+    }
+}
@@ -0,0 +1,509 @@
+//! HTTP/2 frame parsing and serialization.
+use std::fmt;
+
+use super::StreamId;
+
+/// The size of every frame header.
+pub const HEAD_LEN: usize = 9;
+
+/// The initial, and smallest allowed, SETTINGS_MAX_FRAME_SIZE.
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+/// The largest allowed SETTINGS_MAX_FRAME_SIZE.
+pub const MAX_MAX_FRAME_SIZE: u32 = 16_777_215;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+#[allow(dead_code)]
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// HTTP/2 error codes, used in RST_STREAM and GOAWAY frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    NoError,
+    ProtocolError,
+    InternalError,
+    FlowControlError,
+    SettingsTimeout,
+    StreamClosed,
+    FrameSizeError,
+    RefusedStream,
+    Cancel,
+    CompressionError,
+    ConnectError,
+    EnhanceYourCalm,
+    InadequateSecurity,
+    Http11Required,
+    Unknown(u32),
+}
+
+impl Reason {
+    pub fn code(&self) -> u32 {
+        match *self {
+            Reason::NoError => 0x0,
+            Reason::ProtocolError => 0x1,
+            Reason::InternalError => 0x2,
+            Reason::FlowControlError => 0x3,
+            Reason::SettingsTimeout => 0x4,
+            Reason::StreamClosed => 0x5,
+            Reason::FrameSizeError => 0x6,
+            Reason::RefusedStream => 0x7,
+            Reason::Cancel => 0x8,
+            Reason::CompressionError => 0x9,
+            Reason::ConnectError => 0xa,
+            Reason::EnhanceYourCalm => 0xb,
+            Reason::InadequateSecurity => 0xc,
+            Reason::Http11Required => 0xd,
+            Reason::Unknown(code) => code,
+        }
+    }
+}
+
+impl From<u32> for Reason {
+    fn from(code: u32) -> Reason {
+        match code {
+            0x0 => Reason::NoError,
+            0x1 => Reason::ProtocolError,
+            0x2 => Reason::InternalError,
+            0x3 => Reason::FlowControlError,
+            0x4 => Reason::SettingsTimeout,
+            0x5 => Reason::StreamClosed,
+            0x6 => Reason::FrameSizeError,
+            0x7 => Reason::RefusedStream,
+            0x8 => Reason::Cancel,
+            0x9 => Reason::CompressionError,
+            0xa => Reason::ConnectError,
+            0xb => Reason::EnhanceYourCalm,
+            0xc => Reason::InadequateSecurity,
+            0xd => Reason::Http11Required,
+            other => Reason::Unknown(other),
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Reason::Unknown(code) => write!(f, "unknown error code {:#x}", code),
+            ref known => fmt::Debug::fmt(known, f),
+        }
+    }
+}
+
+/// A parsed frame, borrowing its payload from the read buffer.
+#[derive(Debug, PartialEq)]
+pub enum Frame<'a> {
+    Data {
+        id: StreamId,
+        data: &'a [u8],
+        /// The whole payload length, including padding, which counts
+        /// against flow control.
+        flow_len: u32,
+        end_stream: bool,
+    },
+    Headers {
+        id: StreamId,
+        block: &'a [u8],
+        end_stream: bool,
+        end_headers: bool,
+    },
+    Priority {
+        id: StreamId,
+        dependency: StreamId,
+    },
+    Reset {
+        id: StreamId,
+        reason: Reason,
+    },
+    Settings {
+        ack: bool,
+        params: Vec<(u16, u32)>,
+    },
+    PushPromise {
+        id: StreamId,
+        promised: StreamId,
+    },
+    Ping {
+        ack: bool,
+        payload: [u8; 8],
+    },
+    GoAway {
+        last_id: StreamId,
+        reason: Reason,
+    },
+    WindowUpdate {
+        id: StreamId,
+        increment: u32,
+    },
+    Continuation {
+        id: StreamId,
+        block: &'a [u8],
+        end_headers: bool,
+    },
+    /// Frames of unknown types must be ignored.
+    Unknown,
+}
+
+/// Parses the parameters in the payload of a SETTINGS frame.
+pub fn parse_settings(payload: &[u8]) -> Result<Vec<(u16, u32)>, Reason> {
+    if payload.len() % 6 != 0 {
+        return Err(Reason::FrameSizeError);
+    }
+    Ok(payload.chunks(6).map(|param| {
+        (((param[0] as u16) << 8) | param[1] as u16, read_u32(&param[2..]))
+    }).collect())
+}
+
+/// Parses a single frame from the start of `buf`.
+///
+/// Returns `Ok(None)` if `buf` does not yet hold a complete frame, and
+/// otherwise the frame and the number of bytes it used. Errors are
+/// connection errors.
+pub fn parse(buf: &[u8], max_frame_size: u32) -> Result<Option<(Frame, usize)>, Reason> {
+    if buf.len() < HEAD_LEN {
+        return Ok(None);
+    }
+    let len = read_u24(buf);
+    let kind = buf[3];
+    let flags = buf[4];
+    let id = read_u31(&buf[5..]);
+
+    if len > max_frame_size {
+        debug!("frame too large: {} > {}", len, max_frame_size);
+        return Err(Reason::FrameSizeError);
+    }
+    let total = HEAD_LEN + len as usize;
+    if buf.len() < total {
+        return Ok(None);
+    }
+    let payload = &buf[HEAD_LEN..total];
+
+    let frame = match kind {
+        DATA => {
+            if id == 0 {
+                return Err(Reason::ProtocolError);
+            }
+            Frame::Data {
+                id: id,
+                data: try!(strip_padding(flags, payload)),
+                flow_len: len,
+                end_stream: flags & END_STREAM == END_STREAM,
+            }
+        },
+        HEADERS => {
+            if id == 0 {
+                return Err(Reason::ProtocolError);
+            }
+            let mut block = try!(strip_padding(flags, payload));
+            if flags & PRIORITY_FLAG == PRIORITY_FLAG {
+                if block.len() < 5 {
+                    return Err(Reason::FrameSizeError);
+                }
+                block = &block[5..];
+            }
+            Frame::Headers {
+                id: id,
+                block: block,
+                end_stream: flags & END_STREAM == END_STREAM,
+                end_headers: flags & END_HEADERS == END_HEADERS,
+            }
+        },
+        PRIORITY => {
+            if id == 0 {
+                return Err(Reason::ProtocolError);
+            }
+            if len != 5 {
+                return Err(Reason::FrameSizeError);
+            }
+            Frame::Priority {
+                id: id,
+                dependency: read_u31(payload),
+            }
+        },
+        RST_STREAM => {
+            if id == 0 {
+                return Err(Reason::ProtocolError);
+            }
+            if len != 4 {
+                return Err(Reason::FrameSizeError);
+            }
+            Frame::Reset {
+                id: id,
+                reason: Reason::from(read_u32(payload)),
+            }
+        },
+        SETTINGS => {
+            if id != 0 {
+                return Err(Reason::ProtocolError);
+            }
+            let ack = flags & ACK == ACK;
+            if ack && len != 0 {
+                return Err(Reason::FrameSizeError);
+            }
+            Frame::Settings {
+                ack: ack,
+                params: try!(parse_settings(payload)),
+            }
+        },
+        PUSH_PROMISE => {
+            if id == 0 {
+                return Err(Reason::ProtocolError);
+            }
+            let block = try!(strip_padding(flags, payload));
+            if block.len() < 4 {
+                return Err(Reason::FrameSizeError);
+            }
+            Frame::PushPromise {
+                id: id,
+                promised: read_u31(block),
+            }
+        },
+        PING => {
+            if id != 0 {
+                return Err(Reason::ProtocolError);
+            }
+            if len != 8 {
+                return Err(Reason::FrameSizeError);
+            }
+            let mut data = [0; 8];
+            data.copy_from_slice(payload);
+            Frame::Ping {
+                ack: flags & ACK == ACK,
+                payload: data,
+            }
+        },
+        GOAWAY => {
+            if id != 0 {
+                return Err(Reason::ProtocolError);
+            }
+            if len < 8 {
+                return Err(Reason::FrameSizeError);
+            }
+            Frame::GoAway {
+                last_id: read_u31(payload),
+                reason: Reason::from(read_u32(&payload[4..])),
+            }
+        },
+        WINDOW_UPDATE => {
+            if len != 4 {
+                return Err(Reason::FrameSizeError);
+            }
+            Frame::WindowUpdate {
+                id: id,
+                increment: read_u31(payload),
+            }
+        },
+        CONTINUATION => {
+            if id == 0 {
+                return Err(Reason::ProtocolError);
+            }
+            Frame::Continuation {
+                id: id,
+                block: payload,
+                end_headers: flags & END_HEADERS == END_HEADERS,
+            }
+        },
+        _ => Frame::Unknown,
+    };
+    trace!("parsed frame {:?}", frame);
+    Ok(Some((frame, total)))
+}
+
+fn strip_padding(flags: u8, payload: &[u8]) -> Result<&[u8], Reason> {
+    if flags & PADDED == PADDED {
+        if payload.is_empty() {
+            return Err(Reason::FrameSizeError);
+        }
+        let pad = payload[0] as usize;
+        if pad >= payload.len() {
+            return Err(Reason::ProtocolError);
+        }
+        Ok(&payload[1..payload.len() - pad])
+    } else {
+        Ok(payload)
+    }
+}
+
+fn read_u24(buf: &[u8]) -> u32 {
+    ((buf[0] as u32) << 16) | ((buf[1] as u32) << 8) | buf[2] as u32
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    ((buf[0] as u32) << 24) | ((buf[1] as u32) << 16) | ((buf[2] as u32) << 8) | buf[3] as u32
+}
+
+fn read_u31(buf: &[u8]) -> u32 {
+    read_u32(buf) & 0x7fff_ffff
+}
+
+fn write_u32(dst: &mut Vec<u8>, val: u32) {
+    dst.extend_from_slice(&[(val >> 24) as u8, (val >> 16) as u8, (val >> 8) as u8, val as u8]);
+}
+
+fn write_head(dst: &mut Vec<u8>, len: usize, kind: u8, flags: u8, id: StreamId) {
+    debug_assert!(len <= MAX_MAX_FRAME_SIZE as usize);
+    dst.extend_from_slice(&[(len >> 16) as u8, (len >> 8) as u8, len as u8, kind, flags]);
+    write_u32(dst, id & 0x7fff_ffff);
+}
+
+pub fn write_data(dst: &mut Vec<u8>, id: StreamId, data: &[u8], end_stream: bool) {
+    write_head(dst, data.len(), DATA, if end_stream { END_STREAM } else { 0 }, id);
+    dst.extend_from_slice(data);
+}
+
+/// Writes a header block as a HEADERS frame, followed by as many
+/// CONTINUATION frames as are needed to respect `max_frame_size`.
+pub fn write_headers(dst: &mut Vec<u8>, id: StreamId, block: &[u8], end_stream: bool, max_frame_size: usize) {
+    let mut chunks = block.chunks(max_frame_size).peekable();
+    let first = chunks.next().unwrap_or(b"");
+    let mut flags = if end_stream { END_STREAM } else { 0 };
+    if chunks.peek().is_none() {
+        flags |= END_HEADERS;
+    }
+    write_head(dst, first.len(), HEADERS, flags, id);
+    dst.extend_from_slice(first);
+    while let Some(chunk) = chunks.next() {
+        let flags = if chunks.peek().is_none() { END_HEADERS } else { 0 };
+        write_head(dst, chunk.len(), CONTINUATION, flags, id);
+        dst.extend_from_slice(chunk);
+    }
+}
+
+pub fn write_reset(dst: &mut Vec<u8>, id: StreamId, reason: Reason) {
+    write_head(dst, 4, RST_STREAM, 0, id);
+    write_u32(dst, reason.code());
+}
+
+pub fn write_settings(dst: &mut Vec<u8>, params: &[(u16, u32)]) {
+    write_head(dst, params.len() * 6, SETTINGS, 0, 0);
+    for &(id, value) in params {
+        dst.extend_from_slice(&[(id >> 8) as u8, id as u8]);
+        write_u32(dst, value);
+    }
+}
+
+pub fn write_settings_ack(dst: &mut Vec<u8>) {
+    write_head(dst, 0, SETTINGS, ACK, 0);
+}
+
+pub fn write_ping(dst: &mut Vec<u8>, payload: [u8; 8], ack: bool) {
+    write_head(dst, 8, PING, if ack { ACK } else { 0 }, 0);
+    dst.extend_from_slice(&payload);
+}
+
+pub fn write_goaway(dst: &mut Vec<u8>, last_id: StreamId, reason: Reason) {
+    write_head(dst, 8, GOAWAY, 0, 0);
+    write_u32(dst, last_id);
+    write_u32(dst, reason.code());
+}
+
+pub fn write_window_update(dst: &mut Vec<u8>, id: StreamId, increment: u32) {
+    write_head(dst, 4, WINDOW_UPDATE, 0, id);
+    write_u32(dst, increment);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_incomplete() {
+        let mut buf = Vec::new();
+        write_data(&mut buf, 1, b"hello", true);
+        assert_eq!(parse(&buf[..4], DEFAULT_MAX_FRAME_SIZE), Ok(None));
+        assert_eq!(parse(&buf[..buf.len() - 1], DEFAULT_MAX_FRAME_SIZE), Ok(None));
+        assert_eq!(parse(&buf, DEFAULT_MAX_FRAME_SIZE), Ok(Some((Frame::Data {
+            id: 1,
+            data: b"hello",
+            flow_len: 5,
+            end_stream: true,
+        }, buf.len()))));
+    }
+
+    #[test]
+    fn test_parse_padded_data() {
+        let buf = b"\x00\x00\x08\x00\x09\x00\x00\x00\x03\x02abcde\x00\x00";
+        assert_eq!(parse(buf, DEFAULT_MAX_FRAME_SIZE), Ok(Some((Frame::Data {
+            id: 3,
+            data: b"abcde",
+            flow_len: 8,
+            end_stream: true,
+        }, buf.len()))));
+
+        let bad = b"\x00\x00\x02\x00\x08\x00\x00\x00\x03\x05a";
+        assert_eq!(parse(bad, DEFAULT_MAX_FRAME_SIZE), Err(Reason::ProtocolError));
+    }
+
+    #[test]
+    fn test_parse_too_large() {
+        let mut buf = Vec::new();
+        write_data(&mut buf, 1, &[0; 20], false);
+        assert_eq!(parse(&buf, 16), Err(Reason::FrameSizeError));
+    }
+
+    #[test]
+    fn test_headers_continuation() {
+        let mut buf = Vec::new();
+        write_headers(&mut buf, 5, b"abcdefghij", true, 4);
+        let (frame, n) = parse(&buf, DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap();
+        assert_eq!(frame, Frame::Headers { id: 5, block: b"abcd", end_stream: true, end_headers: false });
+        let (frame, m) = parse(&buf[n..], DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap();
+        assert_eq!(frame, Frame::Continuation { id: 5, block: b"efgh", end_headers: false });
+        let (frame, _) = parse(&buf[n + m..], DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap();
+        assert_eq!(frame, Frame::Continuation { id: 5, block: b"ij", end_headers: true });
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let mut buf = Vec::new();
+        write_settings(&mut buf, &[(SETTINGS_ENABLE_PUSH, 0), (SETTINGS_INITIAL_WINDOW_SIZE, 1 << 20)]);
+        assert_eq!(parse(&buf, DEFAULT_MAX_FRAME_SIZE), Ok(Some((Frame::Settings {
+            ack: false,
+            params: vec![(SETTINGS_ENABLE_PUSH, 0), (SETTINGS_INITIAL_WINDOW_SIZE, 1 << 20)],
+        }, buf.len()))));
+
+        buf.clear();
+        write_settings_ack(&mut buf);
+        assert_eq!(parse(&buf, DEFAULT_MAX_FRAME_SIZE), Ok(Some((Frame::Settings {
+            ack: true,
+            params: vec![],
+        }, buf.len()))));
+    }
+
+    #[test]
+    fn test_control_frames_on_streams() {
+        let mut buf = Vec::new();
+        write_ping(&mut buf, [1; 8], false);
+        buf[8] = 1;
+        assert_eq!(parse(&buf, DEFAULT_MAX_FRAME_SIZE), Err(Reason::ProtocolError));
+
+        buf.clear();
+        write_reset(&mut buf, 0, Reason::Cancel);
+        assert_eq!(parse(&buf, DEFAULT_MAX_FRAME_SIZE), Err(Reason::ProtocolError));
+    }
+
+    #[test]
+    fn test_unknown_frame_ignored() {
+        let buf = b"\x00\x00\x01\xfa\x00\x00\x00\x00\x00x";
+        assert_eq!(parse(buf, DEFAULT_MAX_FRAME_SIZE), Ok(Some((Frame::Unknown, buf.len()))));
+    }
+}
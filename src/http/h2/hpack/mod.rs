@@ -0,0 +1,330 @@
+//! HPACK, the header compression format for HTTP/2.
+//!
+//! The `Decoder` implements all of RFC 7541. The `Encoder` is deliberately
+//! simple: it announces a dynamic table size of zero and then only ever
+//! references the static table, which keeps it stateless while still
+//! compressing the most common fields.
+use std::fmt;
+
+use self::table::Table;
+
+mod huffman;
+mod table;
+
+/// A decoded header field, as a name and value pair.
+pub type HeaderField = (Vec<u8>, Vec<u8>);
+
+/// The default size of the dynamic table, before any SETTINGS.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Errors that can occur decoding a header block.
+///
+/// All of these are connection errors of type `COMPRESSION_ERROR`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecoderError {
+    /// The block ended in the middle of a representation.
+    UnexpectedEof,
+    /// An integer did not fit in the allowed size.
+    IntegerOverflow,
+    /// A representation referred to an index that does not exist.
+    InvalidIndex,
+    /// A Huffman encoded string was malformed.
+    InvalidHuffman,
+    /// A table size update was larger than allowed, or not at the start of
+    /// a block.
+    InvalidTableSize,
+}
+
+impl fmt::Display for DecoderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            DecoderError::UnexpectedEof => "unexpected end of header block",
+            DecoderError::IntegerOverflow => "integer overflow",
+            DecoderError::InvalidIndex => "invalid table index",
+            DecoderError::InvalidHuffman => "invalid huffman code",
+            DecoderError::InvalidTableSize => "invalid table size update",
+        })
+    }
+}
+
+/// Decodes header blocks, maintaining the dynamic table between them.
+#[derive(Debug)]
+pub struct Decoder {
+    table: Table,
+    /// The largest table size the peer may ask for, as we advertised in
+    /// SETTINGS_HEADER_TABLE_SIZE.
+    max_size: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            table: Table::new(DEFAULT_TABLE_SIZE),
+            max_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    /// Decodes a complete header block.
+    pub fn decode(&mut self, mut buf: &[u8]) -> Result<Vec<HeaderField>, DecoderError> {
+        let mut fields = Vec::new();
+        let mut allow_size_update = true;
+        while !buf.is_empty() {
+            let first = buf[0];
+            if first & 0x80 == 0x80 {
+                // Indexed Header Field
+                let (index, n) = try!(decode_int(buf, 7));
+                buf = &buf[n..];
+                let (name, value) = match self.table.get(index) {
+                    Some((name, value)) => (name.to_vec(), value.to_vec()),
+                    None => return Err(DecoderError::InvalidIndex),
+                };
+                fields.push((name, value));
+            } else if first & 0xc0 == 0x40 {
+                // Literal Header Field with Incremental Indexing
+                let (field, n) = try!(self.decode_literal(buf, 6));
+                buf = &buf[n..];
+                self.table.insert(field.0.clone(), field.1.clone());
+                fields.push(field);
+            } else if first & 0xe0 == 0x20 {
+                // Dynamic Table Size Update
+                if !allow_size_update {
+                    return Err(DecoderError::InvalidTableSize);
+                }
+                let (size, n) = try!(decode_int(buf, 5));
+                buf = &buf[n..];
+                if size > self.max_size {
+                    return Err(DecoderError::InvalidTableSize);
+                }
+                self.table.set_max_size(size);
+                continue;
+            } else {
+                // Literal Header Field without Indexing, or Never Indexed
+                let (field, n) = try!(self.decode_literal(buf, 4));
+                buf = &buf[n..];
+                fields.push(field);
+            }
+            allow_size_update = false;
+        }
+        Ok(fields)
+    }
+
+    fn decode_literal(&self, buf: &[u8], prefix: u8) -> Result<(HeaderField, usize), DecoderError> {
+        let (index, mut pos) = try!(decode_int(buf, prefix));
+        let name = if index == 0 {
+            let (name, n) = try!(decode_string(&buf[pos..]));
+            pos += n;
+            name
+        } else {
+            match self.table.get(index) {
+                Some((name, _)) => name.to_vec(),
+                None => return Err(DecoderError::InvalidIndex),
+            }
+        };
+        let (value, n) = try!(decode_string(&buf[pos..]));
+        pos += n;
+        Ok(((name, value), pos))
+    }
+}
+
+/// Encodes header blocks.
+#[derive(Debug)]
+pub struct Encoder {
+    announced: bool,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            announced: false,
+        }
+    }
+
+    /// Encodes a complete header block into `dst`.
+    ///
+    /// Field names must already be lowercase.
+    pub fn encode<'a, I>(&mut self, fields: I, dst: &mut Vec<u8>)
+    where I: IntoIterator<Item=(&'a [u8], &'a [u8])> {
+        if !self.announced {
+            // we never index anything, so tell the peer our table is empty
+            encode_int(0, 5, 0x20, dst);
+            self.announced = true;
+        }
+        for (name, value) in fields {
+            match table::find_static(name, value) {
+                Some((index, true)) => encode_int(index, 7, 0x80, dst),
+                Some((index, false)) => {
+                    encode_int(index, 4, 0x00, dst);
+                    encode_string(value, dst);
+                }
+                None => {
+                    dst.push(0x00);
+                    encode_string(name, dst);
+                    encode_string(value, dst);
+                }
+            }
+        }
+    }
+}
+
+fn decode_int(buf: &[u8], prefix: u8) -> Result<(usize, usize), DecoderError> {
+    if buf.is_empty() {
+        return Err(DecoderError::UnexpectedEof);
+    }
+    let mask = ((1u16 << prefix) - 1) as u8;
+    let mut value = (buf[0] & mask) as usize;
+    if value < mask as usize {
+        return Ok((value, 1));
+    }
+    let mut shift = 0;
+    for (i, &byte) in buf[1..].iter().enumerate() {
+        // anything beyond 28 bits of continuation is unreasonable
+        if shift > 21 {
+            return Err(DecoderError::IntegerOverflow);
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 2));
+        }
+    }
+    Err(DecoderError::UnexpectedEof)
+}
+
+fn encode_int(mut value: usize, prefix: u8, flags: u8, dst: &mut Vec<u8>) {
+    let mask = ((1u16 << prefix) - 1) as u8;
+    if value < mask as usize {
+        dst.push(flags | value as u8);
+        return;
+    }
+    dst.push(flags | mask);
+    value -= mask as usize;
+    while value >= 128 {
+        dst.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    dst.push(value as u8);
+}
+
+fn decode_string(buf: &[u8]) -> Result<(Vec<u8>, usize), DecoderError> {
+    if buf.is_empty() {
+        return Err(DecoderError::UnexpectedEof);
+    }
+    let huffman = buf[0] & 0x80 == 0x80;
+    let (len, n) = try!(decode_int(buf, 7));
+    if buf.len() - n < len {
+        return Err(DecoderError::UnexpectedEof);
+    }
+    let raw = &buf[n..n + len];
+    let value = if huffman {
+        let mut value = Vec::with_capacity(len * 8 / 5);
+        try!(huffman::decode(raw, &mut value).map_err(|_| DecoderError::InvalidHuffman));
+        value
+    } else {
+        raw.to_vec()
+    };
+    Ok((value, n + len))
+}
+
+fn encode_string(value: &[u8], dst: &mut Vec<u8>) {
+    let len = huffman::encoded_len(value);
+    if len < value.len() {
+        encode_int(len, 7, 0x80, dst);
+        huffman::encode(value, dst);
+    } else {
+        encode_int(value.len(), 7, 0x00, dst);
+        dst.extend_from_slice(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoder, DecoderError, Encoder, decode_int, encode_int};
+
+    fn fields(list: &[(&str, &str)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        list.iter().map(|&(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec())).collect()
+    }
+
+    #[test]
+    fn test_integer_examples() {
+        // RFC 7541, Appendix C.1
+        let mut dst = Vec::new();
+        encode_int(10, 5, 0, &mut dst);
+        assert_eq!(dst, b"\x0a");
+        dst.clear();
+        encode_int(1337, 5, 0, &mut dst);
+        assert_eq!(dst, b"\x1f\x9a\x0a");
+        assert_eq!(decode_int(&dst, 5), Ok((1337, 3)));
+        dst.clear();
+        encode_int(42, 8, 0, &mut dst);
+        assert_eq!(dst, b"\x2a");
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        assert_eq!(decode_int(b"\x1f\xff\xff\xff\xff\xff\x01", 5), Err(DecoderError::IntegerOverflow));
+        assert_eq!(decode_int(b"\x1f\xff", 5), Err(DecoderError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_decode_requests_with_huffman() {
+        // RFC 7541, Appendix C.4
+        let mut decoder = Decoder::new();
+        assert_eq!(
+            decoder.decode(b"\x82\x86\x84\x41\x8c\xf1\xe3\xc2\xe5\xf2\x3a\x6b\xa0\xab\x90\xf4\xff").unwrap(),
+            fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/"),
+                     (":authority", "www.example.com")]));
+        assert_eq!(
+            decoder.decode(b"\x82\x86\x84\xbe\x58\x86\xa8\xeb\x10\x64\x9c\xbf").unwrap(),
+            fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/"),
+                     (":authority", "www.example.com"), ("cache-control", "no-cache")]));
+        assert_eq!(
+            decoder.decode(b"\x82\x87\x85\xbf\x40\x88\x25\xa8\x49\xe9\x5b\xa9\x7d\x7f\
+                             \x89\x25\xa8\x49\xe9\x5b\xb8\xe8\xb4\xbf").unwrap(),
+            fields(&[(":method", "GET"), (":scheme", "https"), (":path", "/index.html"),
+                     (":authority", "www.example.com"), ("custom-key", "custom-value")]));
+    }
+
+    #[test]
+    fn test_decode_responses_with_eviction() {
+        // RFC 7541, Appendix C.5, with a 256 byte table
+        let mut decoder = Decoder::new();
+        decoder.decode(b"\x3f\xe1\x01").unwrap();
+        assert_eq!(
+            decoder.decode(b"\x48\x03\x33\x30\x32\x58\x07\x70\x72\x69\x76\x61\x74\x65\
+                             \x61\x1d\x4d\x6f\x6e\x2c\x20\x32\x31\x20\x4f\x63\x74\x20\
+                             \x32\x30\x31\x33\x20\x32\x30\x3a\x31\x33\x3a\x32\x31\x20\
+                             \x47\x4d\x54\x6e\x17\x68\x74\x74\x70\x73\x3a\x2f\x2f\x77\
+                             \x77\x77\x2e\x65\x78\x61\x6d\x70\x6c\x65\x2e\x63\x6f\x6d").unwrap(),
+            fields(&[(":status", "302"), ("cache-control", "private"),
+                     ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                     ("location", "https://www.example.com")]));
+        assert_eq!(
+            decoder.decode(b"\x48\x03\x33\x30\x37\xc1\xc0\xbf").unwrap(),
+            fields(&[(":status", "307"), ("cache-control", "private"),
+                     ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                     ("location", "https://www.example.com")]));
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.decode(b"\xff\x00"), Err(DecoderError::InvalidIndex));
+        assert_eq!(decoder.decode(b"\x3f\xe2\x1f"), Err(DecoderError::InvalidTableSize));
+        assert_eq!(decoder.decode(b"\x82\x20"), Err(DecoderError::InvalidTableSize));
+        assert_eq!(decoder.decode(b"\x40\x05abc"), Err(DecoderError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let list = fields(&[(":method", "GET"), (":path", "/index.html"), (":scheme", "https"),
+                            (":authority", "hyper.rs"), ("accept", "*/*"),
+                            ("x-custom", "some value that is long enough to compress")]);
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+        for _ in 0..2 {
+            let mut block = Vec::new();
+            encoder.encode(list.iter().map(|&(ref n, ref v)| (&n[..], &v[..])), &mut block);
+            assert_eq!(decoder.decode(&block).unwrap(), list);
+        }
+    }
+}
@@ -4,7 +4,7 @@ use std::fmt;
 use std::io::{self, Read, Write};
 use std::time::Duration;
 
-use header::Connection;
+use header::{Connection, Expect, Te};
 use header::ConnectionOption::{KeepAlive, Close};
 use header::Headers;
 use method::Method;
@@ -17,13 +17,20 @@ use version::HttpVersion::{Http10, Http11};
 #[cfg(feature = "serde-serialization")]
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+pub use self::compression::{Inflate, Deflate, negotiate};
 pub use self::conn::{Conn, MessageHandler, MessageHandlerFactory, Seed, Key, ReadyResult};
+pub use self::upgrade::Upgrade;
 
 mod buffer;
 pub mod channel;
+mod compression;
 mod conn;
 mod h1;
-//mod h2;
+mod h2;
+mod upgrade;
+
+/// Protocols offered with ALPN on TLS connections, most preferred first.
+pub const ALPN_PROTOCOLS: &'static [&'static str] = &["h2", "http/1.1"];
 
 /// Wraps a `Transport` to provide HTTP decoding when reading.
 #[derive(Debug)]
@@ -35,43 +42,87 @@ pub struct Encoder<'a, T: Transport + 'a>(EncoderImpl<'a, T>);
 
 #[derive(Debug)]
 enum DecoderImpl<'a, T: Read + 'a> {
-    H1(&'a mut h1::Decoder, Trans<'a, T>),
+    H1(&'a mut h1::Decoder, buffer::BufReader<'a, T>),
+    H2(&'a mut h2::Connection, h2::StreamId, &'a T, &'a Option<Headers>),
+    Inflate(&'a mut Inflate, Body<'a, T>),
 }
 
-#[derive(Debug)]
-enum Trans<'a, T: Read + 'a> {
-    Port(&'a mut T),
-    Buf(self::buffer::BufReader<'a, T>)
+/// Another `Decoder`, read from by one that decompresses.
+struct Body<'a, T: 'a>(&'a mut (Source<T> + 'a));
+
+trait Source<T>: Read {
+    fn transport(&self) -> &T;
+    fn trailers(&self) -> Option<&Headers>;
 }
 
-impl<'a, T: Read + 'a> Trans<'a, T> {
-    fn get_ref(&self) -> &T {
-        match *self {
-            Trans::Port(ref t) => &*t,
-            Trans::Buf(ref buf) => buf.get_ref()
-        }
+impl<'a, T: Read> Source<T> for Decoder<'a, T> {
+    fn transport(&self) -> &T {
+        self.get_ref()
+    }
+
+    fn trailers(&self) -> Option<&Headers> {
+        self.trailers()
     }
 }
 
-impl<'a, T: Read + 'a> Read for Trans<'a, T> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match *self {
-            Trans::Port(ref mut t) => t.read(buf),
-            Trans::Buf(ref mut b) => b.read(buf)
-        }
+impl<'a, T: 'a> fmt::Debug for Body<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Body")
     }
 }
 
 #[derive(Debug)]
 enum EncoderImpl<'a, T: Transport + 'a> {
     H1(&'a mut h1::Encoder, &'a mut T),
+    /// The trailers slot is `None` if the peer doesn't accept trailers.
+    H2(&'a mut h2::Connection, h2::StreamId, &'a mut T, Option<&'a mut Option<Headers>>),
+    Deflate(&'a mut Deflate, Sink<'a, T>),
+}
+
+/// Another `Encoder`, written to by one that compresses.
+struct Sink<'a, T: 'a>(&'a mut (Drain<T> + 'a));
+
+trait Drain<T>: Write {
+    fn close(&mut self);
+    fn set_trailers(&mut self, trailers: Headers);
+    fn transport(&self) -> &T;
+}
+
+impl<'a, T: Transport> Drain<T> for Encoder<'a, T> {
+    fn close(&mut self) {
+        self.close()
+    }
+
+    fn set_trailers(&mut self, trailers: Headers) {
+        self.set_trailers(trailers)
+    }
+
+    fn transport(&self) -> &T {
+        self.get_ref()
+    }
+}
+
+impl<'a, T: 'a> fmt::Debug for Sink<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Sink")
+    }
 }
 
 impl<'a, T: Read> Decoder<'a, T> {
-    fn h1(decoder: &'a mut h1::Decoder, transport: Trans<'a, T>) -> Decoder<'a, T> {
+    fn h1(decoder: &'a mut h1::Decoder, transport: buffer::BufReader<'a, T>) -> Decoder<'a, T> {
         Decoder(DecoderImpl::H1(decoder, transport))
     }
 
+    fn h2(conn: &'a mut h2::Connection, id: h2::StreamId, transport: &'a T, trailers: &'a Option<Headers>) -> Decoder<'a, T> {
+        Decoder(DecoderImpl::H2(conn, id, transport, trailers))
+    }
+
+    /// Decompresses what `decoder` reads.
+    #[doc(hidden)]
+    pub fn inflate<'b>(inflate: &'a mut Inflate, decoder: &'a mut Decoder<'b, T>) -> Decoder<'a, T> where 'b: 'a {
+        Decoder(DecoderImpl::Inflate(inflate, Body(decoder)))
+    }
+
     /// Read from the `Transport`.
     #[inline]
     pub fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -79,6 +130,15 @@ impl<'a, T: Read> Decoder<'a, T> {
             DecoderImpl::H1(ref mut decoder, ref mut transport) => {
                 decoder.decode(transport, buf)
             }
+            DecoderImpl::H2(ref mut conn, id, _, _) => {
+                match conn.read_data(id, buf) {
+                    Some(n) => Ok(n),
+                    None => Err(io::Error::new(io::ErrorKind::WouldBlock, "would block")),
+                }
+            }
+            DecoderImpl::Inflate(ref mut inflate, ref mut body) => {
+                inflate.read(&mut *body.0, buf)
+            }
         }
     }
 
@@ -101,7 +161,21 @@ impl<'a, T: Read> Decoder<'a, T> {
     /// Get a reference to the transport.
     pub fn get_ref(&self) -> &T {
         match self.0 {
-            DecoderImpl::H1(_, ref transport) => transport.get_ref()
+            DecoderImpl::H1(_, ref transport) => transport.get_ref(),
+            DecoderImpl::H2(_, _, transport, _) => transport,
+            DecoderImpl::Inflate(_, ref body) => body.0.transport(),
+        }
+    }
+
+    /// The trailer fields that followed the body.
+    ///
+    /// This is `None` until the body has been read to the end, and stays
+    /// `None` if the message had no trailers.
+    pub fn trailers(&self) -> Option<&Headers> {
+        match self.0 {
+            DecoderImpl::H1(ref decoder, _) => decoder.trailers(),
+            DecoderImpl::H2(_, _, _, trailers) => trailers.as_ref(),
+            DecoderImpl::Inflate(_, ref body) => body.0.trailers(),
         }
     }
 }
@@ -111,6 +185,16 @@ impl<'a, T: Transport> Encoder<'a, T> {
         Encoder(EncoderImpl::H1(encoder, transport))
     }
 
+    fn h2(conn: &'a mut h2::Connection, id: h2::StreamId, transport: &'a mut T, trailers: Option<&'a mut Option<Headers>>) -> Encoder<'a, T> {
+        Encoder(EncoderImpl::H2(conn, id, transport, trailers))
+    }
+
+    /// Compresses what is written to `encoder`.
+    #[doc(hidden)]
+    pub fn deflate<'b>(deflate: &'a mut Deflate, encoder: &'a mut Encoder<'b, T>) -> Encoder<'a, T> where 'b: 'a {
+        Encoder(EncoderImpl::Deflate(deflate, Sink(encoder)))
+    }
+
     /// Write to the `Transport`.
     #[inline]
     pub fn write(&mut self, data: &[u8]) -> io::Result<usize> {
@@ -125,6 +209,22 @@ impl<'a, T: Transport> Encoder<'a, T> {
                     encoder.encode(*transport, data)
                 }
             }
+            EncoderImpl::H2(ref mut conn, id, ref mut transport, _) => {
+                if conn.is_send_closed(id) {
+                    return Ok(0);
+                }
+                // don't buffer without bound if the transport is slow
+                if conn.buffered() >= h2::MAX_SEND_BUFFER {
+                    try!(conn.flush(*transport));
+                }
+                match conn.send_data(id, data, false) {
+                    0 => Err(io::Error::new(io::ErrorKind::WouldBlock, "flow control window is empty")),
+                    n => Ok(n),
+                }
+            }
+            EncoderImpl::Deflate(ref mut deflate, ref mut sink) => {
+                deflate.write(&mut *sink.0, data)
+            }
         }
     }
 
@@ -152,14 +252,49 @@ impl<'a, T: Transport> Encoder<'a, T> {
     /// the `Encoder` should write the end chunk, or `0\r\n\r\n`.
     pub fn close(&mut self) {
         match self.0 {
-            EncoderImpl::H1(ref mut encoder, _) => encoder.close()
+            EncoderImpl::H1(ref mut encoder, _) => encoder.close(),
+            EncoderImpl::H2(ref mut conn, id, _, ref mut trailers) => {
+                h2::end_stream(conn, id, trailers.as_mut().and_then(|slot| slot.take()));
+            }
+            EncoderImpl::Deflate(ref mut deflate, ref mut sink) => {
+                if deflate.is_closed() {
+                    return;
+                }
+                // the compressed tail may not all fit, and is written by
+                // closing again once writable
+                match deflate.finish(&mut *sink.0) {
+                    Ok(true) => {
+                        sink.0.close();
+                        deflate.set_closed();
+                    }
+                    Ok(false) => trace!("deflate close would block"),
+                    Err(e) => debug!("deflate close error: {}", e),
+                }
+            }
+        }
+    }
+
+    /// Sets trailer fields to send after the body, such as a checksum of it.
+    ///
+    /// Trailers are sent when the message ends, so this must be called
+    /// before `close()`. With HTTP/1 they need a chunked body. A server only
+    /// sends them if the request included `TE: trailers`, and drops them
+    /// otherwise.
+    pub fn set_trailers(&mut self, trailers: Headers) {
+        match self.0 {
+            EncoderImpl::H1(ref mut encoder, _) => encoder.set_trailers(trailers),
+            EncoderImpl::H2(_, _, _, Some(ref mut slot)) => **slot = Some(trailers),
+            EncoderImpl::H2(_, _, _, None) => debug!("dropping trailers that can't be sent: {:?}", trailers),
+            EncoderImpl::Deflate(_, ref mut sink) => sink.0.set_trailers(trailers),
         }
     }
 
     /// Get a reference to the transport.
     pub fn get_ref(&self) -> &T {
         match self.0 {
-            EncoderImpl::H1(_, ref transport) => &*transport
+            EncoderImpl::H1(_, ref transport) => &*transport,
+            EncoderImpl::H2(_, _, ref transport, _) => &*transport,
+            EncoderImpl::Deflate(_, ref sink) => sink.0.transport(),
         }
     }
 }
@@ -183,6 +318,13 @@ impl<'a, T: Transport> Write for Encoder<'a, T> {
             EncoderImpl::H1(_, ref mut transport) => {
                 transport.flush()
             }
+            EncoderImpl::H2(ref mut conn, _, ref mut transport, _) => {
+                try!(conn.flush(*transport));
+                transport.flush()
+            }
+            EncoderImpl::Deflate(ref mut deflate, ref mut sink) => {
+                deflate.flush(&mut *sink.0)
+            }
         }
     }
 }
@@ -250,6 +392,18 @@ impl<S> MessageHead<S> {
     pub fn should_keep_alive(&self) -> bool {
         should_keep_alive(self.version, &self.headers)
     }
+
+    /// Whether the body waits for a `100 Continue`, as asked for with
+    /// `Expect: 100-continue`. HTTP/1.0 doesn't know about it.
+    pub fn expects_continue(&self) -> bool {
+        self.version == Http11 && self.headers.get::<Expect>() == Some(&Expect::Continue)
+    }
+
+    /// Whether trailer fields may be sent back, as asked for with
+    /// `TE: trailers`.
+    pub fn accepts_trailers(&self) -> bool {
+        self.headers.get::<Te>().map_or(false, |te| te.trailers())
+    }
 }
 
 /// The raw status code and reason-phrase.
@@ -326,6 +480,14 @@ pub trait Http1Message {
     fn parse(bytes: &[u8]) -> ParseResult<Self::Incoming>;
     fn decoder(head: &MessageHead<Self::Incoming>) -> ::Result<h1::Decoder>;
     fn encode(head: MessageHead<Self::Outgoing>, dst: &mut Vec<u8>) -> h1::Encoder;
+    /// Whether this is an interim `1xx` response, which comes before the
+    /// real one.
+    fn is_informational(head: &MessageHead<Self::Incoming>) -> bool;
+}
+
+pub trait Http2Message: Http1Message {
+    fn decode_h2(fields: Vec<h2::HeaderField>, version: HttpVersion) -> ::Result<MessageHead<Self::Incoming>>;
+    fn encode_h2(head: MessageHead<Self::Outgoing>, scheme: &str) -> Vec<h2::HeaderField>;
 }
 
 /// Used to signal desired events when working with asynchronous IO.
@@ -348,7 +510,7 @@ impl fmt::Debug for Next {
 
 // Internal enum for `Next`
 #[derive(Debug, Clone, Copy)]
-enum Next_ {
+pub enum Next_ {
     Read,
     Write,
     ReadWrite,
@@ -371,14 +533,17 @@ enum Reg {
 /// A notifier to wakeup a socket after having used `Next::wait()`
 #[derive(Debug, Clone)]
 pub struct Control {
-    tx: self::channel::Sender<Next>,
+    tx: self::channel::Sender<(h2::StreamId, Next)>,
+    /// The HTTP/2 stream this controls. Stream 0 is the whole connection,
+    /// and HTTP/1 ignores it.
+    stream: h2::StreamId,
 }
 
 impl Control {
     /// Wakeup a waiting socket to listen for a certain event.
     pub fn ready(&self, next: Next) -> Result<(), ControlError> {
         //TODO: assert!( next.interest != Next_::Wait ) ?
-        self.tx.send(next).map_err(|_| ControlError(()))
+        self.tx.send((self.stream, next)).map_err(|_| ControlError(()))
     }
 }
 
@@ -450,6 +615,15 @@ impl Next {
     }
 }
 
+/// The interest and timeout of a `Next`.
+///
+/// Protocols driven through an `Upgrade` use this to read the `Next` their
+/// own handler returned, so they can add writing out their buffered frames
+/// to the handler's interest while keeping its timeout.
+pub fn interest(next: &Next) -> (Next_, Option<Duration>) {
+    (next.interest, next.timeout)
+}
+
 impl Next_ {
     fn register(&self) -> Reg {
         match *self {
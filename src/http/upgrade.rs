@@ -0,0 +1,43 @@
+use net::Transport;
+
+use super::Next;
+
+/// A trait to react to events on a connection that switched protocols.
+///
+/// After a `101 Switching Protocols` response, a `Handler` can return an
+/// `Upgrade` to take over the transport. The connection stays in the event
+/// loop, and each event handler returns its desired `Next` action, just as
+/// with HTTP messages. A `Control` received for the HTTP message keeps
+/// working for the new protocol.
+pub trait Upgrade<T: Transport> {
+    /// This event occurs first, with any bytes that were already read from
+    /// the transport past the end of the HTTP message.
+    fn on_start(&mut self, buffered: Vec<u8>) -> Next;
+
+    /// This event occurs each time the transport is ready to be read from.
+    fn on_readable(&mut self, transport: &mut T) -> Next;
+
+    /// This event occurs each time the transport is ready to be written to.
+    fn on_writable(&mut self, transport: &mut T) -> Next;
+
+    /// This event occurs when a `Control` wakes up the connection with `next`.
+    ///
+    /// The default acts on `next` as is.
+    fn on_wakeup(&mut self, next: Next) -> Next {
+        next
+    }
+
+    /// This event occurs whenever an `Error` occurs outside of the other events.
+    ///
+    /// This could IO errors while waiting for events, or a timeout, etc.
+    fn on_error(&mut self, err: ::Error) -> Next {
+        debug!("default Upgrade.on_error({:?})", err);
+        Next::remove()
+    }
+
+    /// This event occurs when this `Upgrade` has signaled `Next::end()` or
+    /// `Next::remove()`, and hands back the transport.
+    fn on_remove(self: Box<Self>, _transport: T) {
+        debug!("default Upgrade.on_remove");
+    }
+}
@@ -15,6 +15,7 @@
 //! [typed Headers system](header/index.html).
 //!
 //! If just getting started, consider looking over the **[Guide](../guide/)**.
+extern crate rand;
 extern crate rustc_serialize as serialize;
 extern crate time;
 #[macro_use] extern crate url;
@@ -27,6 +28,7 @@ extern crate security_framework;
 #[cfg(feature = "serde-serialization")]
 extern crate serde;
 extern crate cookie;
+extern crate flate2;
 extern crate unicase;
 extern crate httparse;
 extern crate rotor;
@@ -50,7 +52,7 @@ pub use url::Url;
 pub use client::Client;
 pub use error::{Result, Error};
 pub use header::Headers;
-pub use http::{Next, Encoder, Decoder, Control, ControlError};
+pub use http::{Next, Encoder, Decoder, Control, ControlError, Upgrade};
 pub use method::Method::{self, Get, Head, Post, Delete};
 pub use net::{HttpStream, Transport};
 pub use status::StatusCode::{self, Ok, BadRequest, NotFound};
@@ -76,9 +78,11 @@ pub mod header;
 mod http;
 pub mod net;
 pub mod server;
+pub mod sse;
 pub mod status;
 pub mod uri;
 pub mod version;
+pub mod websocket;
 
 /// Re-exporting the mime crate, for convenience.
 pub mod mime {
@@ -1,5 +1,5 @@
 use std::cmp;
-use std::io::{self, Read, Write};
+use std::io::{self, BufRead, Read, Write};
 
 #[derive(Debug)]
 pub struct Buf {
@@ -89,6 +89,23 @@ impl<T: Read> Read for Async<T> {
     }
 }
 
+impl<T: BufRead> BufRead for Async<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.bytes_until_block == 0 {
+            Err(io::Error::new(io::ErrorKind::WouldBlock, "mock block"))
+        } else {
+            let n = self.bytes_until_block;
+            let buf = try!(self.inner.fill_buf());
+            Ok(&buf[..cmp::min(n, buf.len())])
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.bytes_until_block -= amt;
+        self.inner.consume(amt)
+    }
+}
+
 impl<T: Write> Write for Async<T> {
     fn write(&mut self, data: &[u8]) -> io::Result<usize> {
         if self.bytes_until_block == 0 {
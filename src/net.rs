@@ -2,6 +2,8 @@
 use std::io::{self, Read, Write};
 use std::net::{SocketAddr};
 use std::option;
+#[cfg(unix)]
+use std::path::Path;
 
 use rotor::mio::tcp::{TcpStream, TcpListener};
 use rotor::mio::{Selector, Token, Evented, EventSet, PollOpt, TryAccept};
@@ -26,6 +28,14 @@ pub trait Transport: Read + Write + Evented + ::vecio::Writev {
     fn blocked(&self) -> Option<Blocked> {
         None
     }
+
+    /// Returns the application protocol chosen with ALPN, if any.
+    ///
+    /// Transports that don't use TLS, or that don't support ALPN, never
+    /// negotiate one.
+    fn alpn_protocol(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// A trait representing a socket transport that can be used in a Client or Server.
@@ -42,6 +52,14 @@ pub trait Transport: Read + Write + Evented {
     fn blocked(&self) -> Option<Blocked> {
         None
     }
+
+    /// Returns the application protocol chosen with ALPN, if any.
+    ///
+    /// Transports that don't use TLS, or that don't support ALPN, never
+    /// negotiate one.
+    fn alpn_protocol(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// Declares when a transport is blocked from any further action, until the
@@ -63,6 +81,14 @@ pub trait Accept: Evented {
     fn accept(&self) -> io::Result<Option<Self::Output>>;
     /// Return the local `SocketAddr` of this listener.
     fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Duplicate the listening socket, so another loop can accept from it,
+    /// such as with `Server::handle_threads`.
+    ///
+    /// The default returns an error.
+    fn try_clone(&self) -> io::Result<Self> where Self: Sized {
+        Err(io::Error::new(io::ErrorKind::Other, "listener cannot be cloned"))
+    }
 }
 
 /// An alias to `mio::tcp::TcpStream`.
@@ -150,6 +176,11 @@ impl Accept for HttpListener {
     fn local_addr(&self) -> io::Result<SocketAddr> {
         self.0.local_addr()
     }
+
+    #[inline]
+    fn try_clone(&self) -> io::Result<HttpListener> {
+        HttpListener::try_clone(self)
+    }
 }
 
 impl Evented for HttpListener {
@@ -178,6 +209,147 @@ impl IntoIterator for HttpListener {
     }
 }
 
+/// An alias to `mio::unix::UnixStream`.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct UnixStream(pub ::rotor::mio::unix::UnixStream);
+
+#[cfg(unix)]
+impl UnixStream {
+    /// Connect to the Unix domain socket at `path`.
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<UnixStream> {
+        ::rotor::mio::unix::UnixStream::connect(path.as_ref())
+            .map(UnixStream)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn take_socket_error(&mut self) -> io::Result<()> {
+        // a Unix socket connects right away, or fails in `connect`
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Read for UnixStream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[cfg(unix)]
+impl Write for UnixStream {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(unix)]
+impl Evented for UnixStream {
+    #[inline]
+    fn register(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+        self.0.register(selector, token, interest, opts)
+    }
+
+    #[inline]
+    fn reregister(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+        self.0.reregister(selector, token, interest, opts)
+    }
+
+    #[inline]
+    fn deregister(&self, selector: &mut Selector) -> io::Result<()> {
+        self.0.deregister(selector)
+    }
+}
+
+#[cfg(unix)]
+impl ::vecio::Writev for UnixStream {
+    #[inline]
+    fn writev(&mut self, bufs: &[&[u8]]) -> io::Result<usize> {
+        use ::vecio::Rawv;
+        self.0.writev(bufs)
+    }
+}
+
+/// An alias to `mio::unix::UnixListener`.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct UnixListener(pub ::rotor::mio::unix::UnixListener);
+
+#[cfg(unix)]
+impl UnixListener {
+    /// Bind to the Unix domain socket at `path`.
+    ///
+    /// The socket file must not exist yet.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixListener> {
+        ::rotor::mio::unix::UnixListener::bind(path.as_ref())
+            .map(UnixListener)
+    }
+
+    /// Try to duplicate the underlying listening socket.
+    pub fn try_clone(&self) -> io::Result<UnixListener> {
+        self.0.try_clone().map(UnixListener)
+    }
+}
+
+#[cfg(unix)]
+impl Accept for UnixListener {
+    type Output = UnixStream;
+
+    #[inline]
+    fn accept(&self) -> io::Result<Option<UnixStream>> {
+        TryAccept::accept(&self.0).map(|ok| ok.map(UnixStream))
+    }
+
+    /// A Unix domain socket has no `SocketAddr`, so this is always an
+    /// `InvalidInput` error.
+    #[inline]
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "unix socket has no SocketAddr"))
+    }
+
+    #[inline]
+    fn try_clone(&self) -> io::Result<UnixListener> {
+        UnixListener::try_clone(self)
+    }
+}
+
+#[cfg(unix)]
+impl Evented for UnixListener {
+    #[inline]
+    fn register(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+        self.0.register(selector, token, interest, opts)
+    }
+
+    #[inline]
+    fn reregister(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+        self.0.reregister(selector, token, interest, opts)
+    }
+
+    #[inline]
+    fn deregister(&self, selector: &mut Selector) -> io::Result<()> {
+        self.0.deregister(selector)
+    }
+}
+
+#[cfg(unix)]
+impl IntoIterator for UnixListener {
+    type Item = Self;
+    type IntoIter = option::IntoIter<Self>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Some(self).into_iter()
+    }
+}
+
 /// Deprecated
 ///
 /// Use `SslClient` and `SslServer` instead.
@@ -188,6 +360,22 @@ pub trait Ssl {
     fn wrap_client(&self, stream: HttpStream, host: &str) -> ::Result<Self::Stream>;
     /// Wrap a server stream with SSL.
     fn wrap_server(&self, stream: HttpStream) -> ::Result<Self::Stream>;
+
+    /// Wrap a client stream with SSL, offering `protocols` with ALPN.
+    ///
+    /// The default ignores the protocols, so HTTP/1.1 is always used.
+    fn wrap_client_alpn(&self, stream: HttpStream, host: &str, protocols: &[&str]) -> ::Result<Self::Stream> {
+        let _ = protocols;
+        self.wrap_client(stream, host)
+    }
+
+    /// Wrap a server stream with SSL, choosing one of `protocols` with ALPN.
+    ///
+    /// The default ignores the protocols, so HTTP/1.1 is always used.
+    fn wrap_server_alpn(&self, stream: HttpStream, protocols: &[&str]) -> ::Result<Self::Stream> {
+        let _ = protocols;
+        self.wrap_server(stream)
+    }
 }
 
 /// An abstraction to allow any SSL implementation to be used with client-side `HttpsStream`s.
@@ -196,6 +384,14 @@ pub trait SslClient {
     type Stream: Transport;
     /// Wrap a client stream with SSL.
     fn wrap_client(&self, stream: HttpStream, host: &str) -> ::Result<Self::Stream>;
+
+    /// Wrap a client stream with SSL, offering `protocols` with ALPN.
+    ///
+    /// The default ignores the protocols, so HTTP/1.1 is always used.
+    fn wrap_client_alpn(&self, stream: HttpStream, host: &str, protocols: &[&str]) -> ::Result<Self::Stream> {
+        let _ = protocols;
+        self.wrap_client(stream, host)
+    }
 }
 
 /// An abstraction to allow any SSL implementation to be used with server-side `HttpsStream`s.
@@ -204,6 +400,15 @@ pub trait SslServer {
     type Stream: Transport;
     /// Wrap a server stream with SSL.
     fn wrap_server(&self, stream: HttpStream) -> ::Result<Self::Stream>;
+
+    /// Wrap a server stream with SSL, choosing one of `protocols`, in order
+    /// of preference, if the client offers it with ALPN.
+    ///
+    /// The default ignores the protocols, so HTTP/1.1 is always used.
+    fn wrap_server_alpn(&self, stream: HttpStream, protocols: &[&str]) -> ::Result<Self::Stream> {
+        let _ = protocols;
+        self.wrap_server(stream)
+    }
 }
 
 impl<S: Ssl> SslClient for S {
@@ -212,6 +417,10 @@ impl<S: Ssl> SslClient for S {
     fn wrap_client(&self, stream: HttpStream, host: &str) -> ::Result<Self::Stream> {
         Ssl::wrap_client(self, stream, host)
     }
+
+    fn wrap_client_alpn(&self, stream: HttpStream, host: &str, protocols: &[&str]) -> ::Result<Self::Stream> {
+        Ssl::wrap_client_alpn(self, stream, host, protocols)
+    }
 }
 
 impl<S: Ssl> SslServer for S {
@@ -220,6 +429,10 @@ impl<S: Ssl> SslServer for S {
     fn wrap_server(&self, stream: HttpStream) -> ::Result<Self::Stream> {
         Ssl::wrap_server(self, stream)
     }
+
+    fn wrap_server_alpn(&self, stream: HttpStream, protocols: &[&str]) -> ::Result<Self::Stream> {
+        Ssl::wrap_server_alpn(self, stream, protocols)
+    }
 }
 
 /// A stream over the HTTP protocol, possibly protected by TLS.
@@ -332,6 +545,14 @@ impl<S: Transport> Transport for HttpsStream<S> {
             HttpsStream::Https(ref s) => s.blocked(),
         }
     }
+
+    #[inline]
+    fn alpn_protocol(&self) -> Option<&str> {
+        match *self {
+            HttpsStream::Http(ref s) => s.alpn_protocol(),
+            HttpsStream::Https(ref s) => s.alpn_protocol(),
+        }
+    }
 }
 
 /// An `HttpListener` over SSL.
@@ -366,7 +587,7 @@ impl<S: SslServer> Accept for HttpsListener<S> {
     #[inline]
     fn accept(&self) -> io::Result<Option<S::Stream>> {
         self.listener.accept().and_then(|s| match s {
-            Some((s, _)) => self.ssl.wrap_server(HttpStream(s)).map(Some).map_err(|e| {
+            Some((s, _)) => self.ssl.wrap_server_alpn(HttpStream(s), ::http::ALPN_PROTOCOLS).map(Some).map_err(|e| {
                 match e {
                     ::Error::Io(e) => e,
                     _ => io::Error::new(io::ErrorKind::Other, e),
@@ -434,6 +655,8 @@ pub type DefaultTransport = <DefaultConnector as Connect>::Output;
 mod openssl {
     use std::io::{self, Write};
     use std::path::Path;
+    #[cfg(feature = "alpn")]
+    use std::str;
 
     use rotor::mio::{Selector, Token, Evented, EventSet, PollOpt};
 
@@ -458,7 +681,11 @@ mod openssl {
     /// ```
     ///
     /// For complete control, create a `SslContext` with the options you desire
-    /// and then create `Openssl { context: ctx }
+    /// and then create `Openssl::new(ctx)`, or `Openssl { context: ctx }` to
+    /// leave the context exactly as it is.
+    ///
+    /// With the `alpn` feature, contexts built here also offer HTTP/2 with
+    /// ALPN.
     #[derive(Debug, Clone)]
     pub struct Openssl {
         /// The `SslContext` from openssl crate.
@@ -466,6 +693,8 @@ mod openssl {
     }
 
     /// A client-specific implementation of OpenSSL.
+    ///
+    /// With the `alpn` feature, it also offers HTTP/2 with ALPN.
     #[derive(Debug, Clone)]
     pub struct OpensslClient(SslContext);
 
@@ -483,7 +712,8 @@ mod openssl {
 
     impl OpensslClient {
         /// Creates a new OpensslClient with a custom SslContext
-        pub fn new(ctx: SslContext) -> OpensslClient {
+        pub fn new(mut ctx: SslContext) -> OpensslClient {
+            set_alpn(&mut ctx);
             OpensslClient(ctx)
         }
     }
@@ -502,7 +732,6 @@ mod openssl {
                 .map_err(From::from)
         }
 
-
         #[cfg(windows)]
         fn wrap_client(&self, stream: HttpStream, host: &str) -> ::Result<Self::Stream> {
             let mut ssl = try!(Ssl::new(&self.0));
@@ -515,17 +744,21 @@ mod openssl {
 
     impl Default for Openssl {
         fn default() -> Openssl {
-            Openssl {
-                context: SslContext::new(SslMethod::Sslv23).unwrap_or_else(|e| {
-                    // if we cannot create a SslContext, that's because of a
-                    // serious problem. just crash.
-                    panic!("{}", e)
-                })
-            }
+            Openssl::new(SslContext::new(SslMethod::Sslv23).unwrap_or_else(|e| {
+                // if we cannot create a SslContext, that's because of a
+                // serious problem. just crash.
+                panic!("{}", e)
+            }))
         }
     }
 
     impl Openssl {
+        /// Creates an `Openssl` with a custom `SslContext`.
+        pub fn new(mut context: SslContext) -> Openssl {
+            set_alpn(&mut context);
+            Openssl { context: context }
+        }
+
         /// Ease creating an `Openssl` with a certificate and key.
         pub fn with_cert_and_key<C, K>(cert: C, key: K) -> Result<Openssl, SslError>
         where C: AsRef<Path>, K: AsRef<Path> {
@@ -533,7 +766,7 @@ mod openssl {
             try!(ctx.set_cipher_list("ALL!EXPORT!EXPORT40!EXPORT56!aNULL!LOW!RC4@STRENGTH"));
             try!(ctx.set_certificate_file(cert.as_ref(), X509FileType::PEM));
             try!(ctx.set_private_key_file(key.as_ref(), X509FileType::PEM));
-            Ok(Openssl { context: ctx })
+            Ok(Openssl::new(ctx))
         }
     }
 
@@ -549,16 +782,34 @@ mod openssl {
         }
 
         fn wrap_server(&self, stream: HttpStream) -> ::Result<Self::Stream> {
-            match SslStream::accept(&self.context, stream) {
-                Ok(ssl_stream) => Ok(openssl_stream(ssl_stream)),
-                Err(SslIoError(e)) => {
-                    Err(io::Error::new(io::ErrorKind::ConnectionAborted, e).into())
-                },
-                Err(e) => Err(e.into())
-            }
+            accept(&self.context, stream)
         }
     }
 
+    fn accept(context: &SslContext, stream: HttpStream) -> ::Result<OpensslStream<HttpStream>> {
+        match SslStream::accept(context, stream) {
+            Ok(ssl_stream) => Ok(openssl_stream(ssl_stream)),
+            Err(SslIoError(e)) => {
+                Err(io::Error::new(io::ErrorKind::ConnectionAborted, e).into())
+            },
+            Err(e) => Err(e.into())
+        }
+    }
+
+    /// Offers the protocols hyper speaks with ALPN, on a context being built.
+    ///
+    /// openssl only configures ALPN on a context, and contexts are shared
+    /// when cloned, so this can't wait until a stream is wrapped. The
+    /// `wrap_*_alpn` defaults then use the context as it is.
+    #[cfg(feature = "alpn")]
+    fn set_alpn(context: &mut SslContext) {
+        let protocols = ::http::ALPN_PROTOCOLS.iter().map(|p| p.as_bytes()).collect::<Vec<_>>();
+        context.set_alpn_protocols(&protocols);
+    }
+
+    #[cfg(not(feature = "alpn"))]
+    fn set_alpn(_context: &mut SslContext) {}
+
     /// A transport protected by OpenSSL.
     #[derive(Debug)]
     pub struct OpensslStream<T> {
@@ -636,6 +887,71 @@ mod openssl {
         fn take_socket_error(&mut self) -> io::Result<()> {
             self.stream.get_mut().take_socket_error()
         }
+
+        #[cfg(feature = "alpn")]
+        fn alpn_protocol(&self) -> Option<&str> {
+            self.stream.ssl().get_selected_alpn_protocol().and_then(|p| str::from_utf8(p).ok())
+        }
+    }
+
+    #[cfg(all(test, feature = "alpn"))]
+    mod tests {
+        use std::net::{TcpListener, TcpStream};
+        use std::str;
+        use std::thread;
+
+        use openssl::crypto::hash::Type;
+        use openssl::ssl::{SslContext, SslStream, SslMethod};
+        use openssl::x509::X509Generator;
+
+        use super::Openssl;
+
+        fn server() -> Openssl {
+            let (cert, key) = X509Generator::new()
+                .set_bitlength(2048)
+                .set_valid_period(1)
+                .add_name("CN".to_owned(), "localhost".to_owned())
+                .set_sign_hash(Type::SHA256)
+                .generate()
+                .unwrap();
+            let mut ctx = SslContext::new(SslMethod::Sslv23).unwrap();
+            ctx.set_certificate(&cert).unwrap();
+            ctx.set_private_key(&key).unwrap();
+            Openssl::new(ctx)
+        }
+
+        /// The protocol the server picks for a client offering `protocols`.
+        fn negotiate(protocols: &[&[u8]]) -> Option<String> {
+            let server = server();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let accepting = thread::spawn(move || {
+                let sock = listener.accept().unwrap().0;
+                let stream = SslStream::accept(&server.context, sock).unwrap();
+                stream.ssl().get_selected_alpn_protocol().map(|p| str::from_utf8(p).unwrap().to_owned())
+            });
+
+            let mut ctx = SslContext::new(SslMethod::Sslv23).unwrap();
+            if !protocols.is_empty() {
+                ctx.set_alpn_protocols(protocols);
+            }
+            let client = SslStream::connect(&ctx, TcpStream::connect(addr).unwrap()).unwrap();
+            let selected = client.ssl().get_selected_alpn_protocol().map(|p| str::from_utf8(p).unwrap().to_owned());
+            assert_eq!(accepting.join().unwrap(), selected);
+            selected
+        }
+
+        #[test]
+        fn test_alpn_h2() {
+            assert_eq!(negotiate(&[b"h2", b"http/1.1"]), Some("h2".to_owned()));
+        }
+
+        #[test]
+        fn test_alpn_http1_fallback() {
+            assert_eq!(negotiate(&[b"http/1.1"]), Some("http/1.1".to_owned()));
+            // no ALPN at all means HTTP/1.1 as well
+            assert_eq!(negotiate(&[]), None);
+        }
     }
 }
 
@@ -0,0 +1,197 @@
+//! Compressing responses with a coding the request accepts.
+use header::{AcceptEncoding, ContentEncoding, ContentLength, ContentType, Encoding, Vary};
+use http::{self, Deflate, Encoder, Decoder, Next, Next_};
+use method::Method;
+use net::Transport;
+use status::StatusCode;
+use unicase::UniCase;
+use version::HttpVersion;
+
+use super::{Handler, Request, Response};
+
+/// Wraps a `Handler`, compressing its responses with `gzip` or `deflate`.
+///
+/// The coding is picked from the request's `Accept-Encoding`, and the
+/// response gets `Content-Encoding` and `Vary: Accept-Encoding` headers.
+/// As the compressed length isn't known beforehand, a `Content-Length` is
+/// removed, and the response is sent chunked.
+///
+/// Responses are left alone if they already have a `Content-Encoding`, have
+/// no body, are shorter than `min_size`, or have a `Content-Type` that is
+/// compressed already, such as images.
+///
+/// # Example
+///
+/// ```no_run
+/// # use hyper::{Next, Encoder, Decoder};
+/// # use hyper::net::HttpStream;
+/// # use hyper::server::{Handler, Request, Response};
+/// # struct Hello;
+/// # impl Handler<HttpStream> for Hello {
+/// #     fn on_request(&mut self, _: Request<HttpStream>) -> Next { Next::write() }
+/// #     fn on_request_readable(&mut self, _: &mut Decoder<HttpStream>) -> Next { Next::write() }
+/// #     fn on_response(&mut self, _: &mut Response) -> Next { Next::write() }
+/// #     fn on_response_writable(&mut self, _: &mut Encoder<HttpStream>) -> Next { Next::end() }
+/// # }
+/// use hyper::server::{Compress, Server};
+///
+/// let server = Server::http(&"127.0.0.1:0".parse().unwrap()).unwrap();
+/// let (_listening, _server) = server.handle(|_| Compress::new(Hello).min_size(256)).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Compress<H> {
+    handler: H,
+    min_size: u64,
+    coding: Option<Encoding>,
+    deflate: Option<Deflate>,
+    /// What the handler wants once the compressed body is all written.
+    next: Option<Next>,
+}
+
+impl<H> Compress<H> {
+    /// Compress the responses of `handler`.
+    pub fn new(handler: H) -> Compress<H> {
+        Compress {
+            handler: handler,
+            min_size: 1024,
+            coding: None,
+            deflate: None,
+            next: None,
+        }
+    }
+
+    /// Sets the `Content-Length` below which responses aren't compressed.
+    ///
+    /// Responses without a `Content-Length` are always compressed.
+    ///
+    /// Default is 1024 bytes.
+    pub fn min_size(mut self, bytes: u64) -> Compress<H> {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Whether a response with these headers should be compressed.
+    fn compresses(&self, response: &Response) -> bool {
+        if partial_or_empty(response.status()) || response.headers().has::<ContentEncoding>() {
+            return false;
+        }
+        if let Some(&ContentLength(len)) = response.headers().get::<ContentLength>() {
+            if len < self.min_size {
+                return false;
+            }
+        }
+        match response.headers().get::<ContentType>() {
+            Some(&ContentType(ref mime)) => !is_compressed(&mime.0.to_string(), &mime.1.to_string()),
+            None => true
+        }
+    }
+}
+
+impl<H: Handler<T>, T: Transport> Handler<T> for Compress<H> {
+    fn on_request(&mut self, request: Request<T>) -> Next {
+        // HTTP/1.0 has no chunked responses, and HEAD no body
+        if *request.version() != HttpVersion::Http10 && *request.method() != Method::Head {
+            self.coding = request.headers().get::<AcceptEncoding>().and_then(http::negotiate);
+        }
+        self.handler.on_request(request)
+    }
+
+    fn on_request_readable(&mut self, request: &mut Decoder<T>) -> Next {
+        self.handler.on_request_readable(request)
+    }
+
+    fn on_response(&mut self, response: &mut Response) -> Next {
+        let next = self.handler.on_response(response);
+        let coding = match self.coding.take() {
+            Some(coding) => coding,
+            None => return next
+        };
+        if !self.compresses(response) {
+            return next;
+        }
+        debug!("compressing response with {}", coding);
+        self.deflate = Deflate::new(&coding);
+        let headers = response.headers_mut();
+        headers.set(ContentEncoding(vec![coding]));
+        headers.remove::<ContentLength>();
+        let accept_encoding = UniCase("Accept-Encoding".to_owned());
+        let vary = match headers.get::<Vary>() {
+            Some(&Vary::Any) => Vary::Any,
+            Some(&Vary::Items(ref items)) => {
+                let mut items = items.clone();
+                if !items.contains(&accept_encoding) {
+                    items.push(accept_encoding);
+                }
+                Vary::Items(items)
+            }
+            None => Vary::Items(vec![accept_encoding])
+        };
+        headers.set(vary);
+        next
+    }
+
+    fn on_response_writable(&mut self, response: &mut Encoder<T>) -> Next {
+        let deflate = match self.deflate {
+            Some(ref mut deflate) => deflate,
+            None => return self.handler.on_response_writable(response)
+        };
+        let next = match self.next.take() {
+            Some(next) => next,
+            None => self.handler.on_response_writable(&mut Encoder::deflate(deflate, response))
+        };
+        let (interest, timeout) = http::interest(&next);
+        let end = match interest {
+            Next_::End => !deflate.is_closed(),
+            _ => false
+        };
+        if end || deflate.is_finishing() {
+            Encoder::deflate(deflate, response).close();
+        }
+        if deflate.is_finishing() {
+            trace!("compressed body not all written, waiting to {:?}", next);
+            self.next = Some(next);
+            return match timeout {
+                Some(dur) => Next::write().timeout(dur),
+                None => Next::write()
+            };
+        }
+        next
+    }
+
+    fn on_error(&mut self, err: ::Error) -> Next {
+        self.handler.on_error(err)
+    }
+
+    fn on_upgrade(&mut self) -> Option<Box<http::Upgrade<T>>> {
+        self.handler.on_upgrade()
+    }
+
+    fn on_remove(self, transport: T) {
+        self.handler.on_remove(transport)
+    }
+}
+
+/// Statuses whose responses have no body, or only part of one.
+fn partial_or_empty(status: &StatusCode) -> bool {
+    match *status {
+        StatusCode::NoContent |
+        StatusCode::NotModified |
+        StatusCode::PartialContent => true,
+        _ => status.is_informational()
+    }
+}
+
+/// Media types whose content is compressed already.
+fn is_compressed(top: &str, sub: &str) -> bool {
+    match top {
+        "image" => sub != "svg+xml",
+        "audio" | "video" => true,
+        "application" => match sub {
+            "zip" | "gzip" | "x-gzip" | "x-bzip2" | "x-xz" | "x-7z-compressed" |
+            "x-rar-compressed" | "pdf" | "octet-stream" => true,
+            _ => false
+        },
+        "font" => sub == "woff" || sub == "woff2",
+        _ => false
+    }
+}
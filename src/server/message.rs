@@ -1,10 +1,12 @@
 use std::marker::PhantomData;
+use std::sync::Arc;
 
-
+use header::Connection;
 use http::{self, Next};
 use net::Transport;
 
 use super::{Handler, request, response};
+use super::shutdown::Signal;
 
 /// A `MessageHandler` for a Server.
 ///
@@ -13,13 +15,17 @@ use super::{Handler, request, response};
 /// would expect in a Server Handler.
 pub struct Message<H: Handler<T>, T: Transport> {
     handler: H,
+    switched: bool,
+    signal: Arc<Signal>,
     _marker: PhantomData<T>
 }
 
 impl<H: Handler<T>, T: Transport> Message<H, T> {
-    pub fn new(handler: H) -> Message<H, T> {
+    pub fn new(handler: H, signal: Arc<Signal>) -> Message<H, T> {
         Message {
             handler: handler,
+            switched: false,
+            signal: signal,
             _marker: PhantomData,
         }
     }
@@ -39,8 +45,16 @@ impl<H: Handler<T>, T: Transport> http::MessageHandler<T> for Message<H, T> {
     }
 
     fn on_outgoing(&mut self, head: &mut http::MessageHead<::status::StatusCode>) -> Next {
-        let mut res = response::new(head);
-        self.handler.on_response(&mut res)
+        let next = {
+            let mut res = response::new(head);
+            self.handler.on_response(&mut res)
+        };
+        self.switched = head.subject == ::status::StatusCode::SwitchingProtocols;
+        if self.signal.is_draining() && !self.switched {
+            // the server is shutting down, so this is the last response
+            head.headers.set(Connection::close());
+        }
+        next
     }
 
     fn on_encode(&mut self, transport: &mut http::Encoder<T>) -> Next {
@@ -51,6 +65,14 @@ impl<H: Handler<T>, T: Transport> http::MessageHandler<T> for Message<H, T> {
         self.handler.on_error(error)
     }
 
+    fn switched_protocols(&self) -> bool {
+        self.switched
+    }
+
+    fn on_upgrade(&mut self) -> Option<Box<http::Upgrade<T>>> {
+        self.handler.on_upgrade()
+    }
+
     fn on_remove(self, transport: T) {
         self.handler.on_remove(transport);
     }
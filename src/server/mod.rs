@@ -2,27 +2,41 @@
 //!
 //! A `Server` is created to listen on a port, parse HTTP requests, and hand
 //! them off to a `Handler`.
+use std::collections::HashMap;
 use std::fmt;
+use std::io;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::{Arc, mpsc};
+use std::thread;
 use std::time::Duration;
 
 use rotor::mio::{EventSet, PollOpt};
 use rotor::{self, Scope};
 
+pub use self::compress::Compress;
 pub use self::request::Request;
 pub use self::response::Response;
+pub use self::shutdown::Draining;
 
+use header::{Connection, ContentLength};
 use http::{self, Next, ReadyResult};
+use status::StatusCode;
+
+use self::shutdown::Signal;
 
 pub use net::{Accept, HttpListener, HttpsListener};
+#[cfg(unix)]
+pub use net::UnixListener;
 use net::{SslServer, Transport};
 
 
+mod compress;
 mod request;
 mod response;
 mod message;
+mod shutdown;
 
 /// A configured `Server` ready to run.
 pub struct ServerLoop<A, H> where A: Accept, H: HandlerFactory<A::Output> {
@@ -43,6 +57,7 @@ pub struct Server<A> {
     keep_alive: bool,
     idle_timeout: Option<Duration>,
     max_sockets: usize,
+    h2c: bool,
 }
 
 impl<A: Accept> Server<A> {
@@ -60,6 +75,7 @@ impl<A: Accept> Server<A> {
             keep_alive: true,
             idle_timeout: Some(Duration::from_secs(10)),
             max_sockets: 4096,
+            h2c: false,
         }
     }
 
@@ -86,6 +102,19 @@ impl<A: Accept> Server<A> {
         self.max_sockets = val;
         self
     }
+
+    /// Enables or disables HTTP/2 over plain text, both upgrading when a
+    /// request asks for it with `Upgrade: h2c`, and accepting clients that
+    /// start with the HTTP/2 connection preface.
+    ///
+    /// Requests with a body are always answered with HTTP/1. When disabled,
+    /// the connection preface is answered as a bad HTTP/1 request.
+    ///
+    /// Default is false.
+    pub fn h2c(mut self, val: bool) -> Server<A> {
+        self.h2c = val;
+        self
+    }
 }
 
 impl Server<HttpListener> { //<H: HandlerFactory<<HttpListener as Accept>::Output>> Server<HttpListener, H> {
@@ -99,6 +128,19 @@ impl Server<HttpListener> { //<H: HandlerFactory<<HttpListener as Accept>::Outpu
     }
 }
 
+#[cfg(unix)]
+impl Server<UnixListener> {
+    /// Creates a new HTTP server config listening on the Unix domain socket
+    /// at `path`.
+    ///
+    /// The socket file must not exist yet. Since it has no `SocketAddr`, the
+    /// `Listening` of this server has no `addrs`.
+    pub fn unix<P: AsRef<Path>>(path: P) -> ::Result<Server<UnixListener>> {
+        UnixListener::bind(path)
+            .map(Server::new)
+            .map_err(From::from)
+    }
+}
 
 impl<S: SslServer> Server<HttpsListener<S>> {
     /// Creates a new server config that will handle `HttpStream`s over SSL.
@@ -116,54 +158,184 @@ impl<A: Accept> Server<A> {
     /// Binds to a socket and starts handling connections.
     pub fn handle<H>(self, factory: H) -> ::Result<(Listening, ServerLoop<A, H>)>
     where H: HandlerFactory<A::Output> {
-        let shutdown = Arc::new(AtomicBool::new(false));
-        
-        let mut config = rotor::Config::new();
-        config.slab_capacity(self.max_sockets);
-        config.mio().notify_capacity(self.max_sockets);
-        let keep_alive = self.keep_alive;
-        let idle_timeout = self.idle_timeout;
-        let mut loop_ = rotor::Loop::new(&config).unwrap();
-
-        let mut addrs = Vec::with_capacity(1 + self.other_listeners.len());
-
-        // Add the lead listener. This one handles shutdown messages.
+        let settings = self.settings();
+        let listeners = Some(self.lead_listener).into_iter().chain(self.other_listeners).collect();
+        let (addrs, handle, server) = try!(new_loop(listeners, factory, settings));
+        let listening = Listening {
+            addrs: addrs,
+            loops: vec![handle],
+        };
+        Ok((listening, server))
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            keep_alive: self.keep_alive,
+            idle_timeout: self.idle_timeout,
+            max_sockets: self.max_sockets,
+            h2c: self.h2c,
+        }
+    }
+}
+
+impl<A: Accept + Send + 'static> Server<A> {
+    /// Starts handling connections with `threads` loops, so the server can
+    /// use more than one core.
+    ///
+    /// Each loop accepts from its own clone of every listener, made with
+    /// `Accept::try_clone`, and gets its own `HandlerFactory` from
+    /// `new_factory`. All but one of the loops run on threads of their own.
+    /// The returned `ServerLoop` is the last one, to run like the one from
+    /// `handle`. The `Listening` closes or shuts down all of them.
+    ///
+    /// Panics if `threads` is 0.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use hyper::{Next, Encoder, Decoder};
+    /// # use hyper::net::HttpStream;
+    /// # use hyper::server::{Handler, Request, Response};
+    /// # struct Hello;
+    /// # impl Handler<HttpStream> for Hello {
+    /// #     fn on_request(&mut self, _: Request<HttpStream>) -> Next { Next::write() }
+    /// #     fn on_request_readable(&mut self, _: &mut Decoder<HttpStream>) -> Next { Next::write() }
+    /// #     fn on_response(&mut self, _: &mut Response) -> Next { Next::write() }
+    /// #     fn on_response_writable(&mut self, _: &mut Encoder<HttpStream>) -> Next { Next::end() }
+    /// # }
+    /// use hyper::server::Server;
+    ///
+    /// let server = Server::http(&"127.0.0.1:3000".parse().unwrap()).unwrap();
+    /// let (listening, server) = server.handle_threads(4, || |_| Hello).unwrap();
+    /// println!("Listening on http://{}", listening);
+    /// server.run();
+    /// ```
+    pub fn handle_threads<F, H>(self, threads: usize, mut new_factory: F) -> ::Result<(Listening, ServerLoop<A, H>)>
+    where F: FnMut() -> H, H: HandlerFactory<A::Output> + Send + 'static {
+        assert!(threads > 0, "Server::handle_threads requires at least 1 thread");
+        let settings = self.settings();
+        let listeners: Vec<A> = Some(self.lead_listener).into_iter().chain(self.other_listeners).collect();
+
+        let mut loops = Vec::with_capacity(threads);
+        for i in 1..threads {
+            match spawn_loop(i, &listeners, new_factory(), settings) {
+                Ok(handle) => loops.push(handle),
+                Err(e) => {
+                    // stop the loops that did start
+                    for handle in &loops {
+                        handle.close();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        match new_loop(listeners, new_factory(), settings) {
+            Ok((addrs, handle, server)) => {
+                loops.push(handle);
+                let listening = Listening {
+                    addrs: addrs,
+                    loops: loops,
+                };
+                Ok((listening, server))
+            },
+            Err(e) => {
+                for handle in &loops {
+                    handle.close();
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The settings of a `Server`, shared by each of its loops.
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    keep_alive: bool,
+    idle_timeout: Option<Duration>,
+    max_sockets: usize,
+    h2c: bool,
+}
+
+/// Builds a loop accepting from `listeners`.
+fn new_loop<A, H>(listeners: Vec<A>, factory: H, settings: Settings) -> ::Result<(Vec<SocketAddr>, LoopHandle, ServerLoop<A, H>)>
+where A: Accept, H: HandlerFactory<A::Output> {
+    let signal = Arc::new(Signal::new());
+
+    let mut config = rotor::Config::new();
+    config.slab_capacity(settings.max_sockets);
+    config.mio().notify_capacity(settings.max_sockets);
+    let mut loop_ = rotor::Loop::new(&config).unwrap();
+
+    let mut addrs = Vec::with_capacity(listeners.len());
+
+    // The lead listener comes first. It handles shutdown messages, and
+    // every listener stops accepting when the server is drained.
+    let mut notifiers = Vec::with_capacity(listeners.len());
+    for listener in listeners {
+        match listener.local_addr() {
+            Ok(addr) => addrs.push(addr),
+            // such as a Unix domain socket
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => (),
+            Err(e) => return Err(e.into())
+        }
         let mut notifier = None;
         {
             let notifier = &mut notifier;
-            let listener = self.lead_listener;
-            addrs.push(try!(listener.local_addr()));
-            let shutdown_rx = shutdown.clone();
+            let signal = signal.clone();
             loop_.add_machine_with(move |scope| {
                 *notifier = Some(scope.notifier());
                 rotor_try!(scope.register(&listener, EventSet::readable(), PollOpt::level()));
-                rotor::Response::ok(ServerFsm::Listener(listener, shutdown_rx))
+                rotor::Response::ok(ServerFsm::Listener(listener, signal))
             }).unwrap();
         }
-        let notifier = notifier.expect("loop.add_machine failed");
+        notifiers.push(notifier.expect("loop.add_machine failed"));
+    }
 
-        // Add the other listeners.
-        for listener in self.other_listeners {
-            addrs.push(try!(listener.local_addr()));
-            let shutdown_rx = shutdown.clone();
-            loop_.add_machine_with(move |scope| {
-                rotor_try!(scope.register(&listener, EventSet::readable(), PollOpt::level()));
-                rotor::Response::ok(ServerFsm::Listener(listener, shutdown_rx))
-            }).unwrap();
-        }
+    let handle = LoopHandle {
+        signal: signal.clone(),
+        notifiers: notifiers,
+    };
+    let server = ServerLoop {
+        inner: Some((loop_, Context {
+            factory: factory,
+            idle_timeout: settings.idle_timeout,
+            keep_alive: settings.keep_alive,
+            h2c: settings.h2c,
+            signal: signal,
+            conns: HashMap::new(),
+            drained: None,
+        }))
+    };
+    Ok((addrs, handle, server))
+}
 
-        let listening = Listening {
-            addrs: addrs,
-            shutdown: (shutdown, notifier),
-        };
-        let server = ServerLoop {
-            inner: Some((loop_, Context {
-                factory: factory,
-                idle_timeout: idle_timeout,
-                keep_alive: keep_alive,
-            }))
-        };
-        Ok((listening, server))
+/// Starts a loop on a thread of its own, accepting from clones of
+/// `listeners`.
+fn spawn_loop<A, H>(i: usize, listeners: &[A], factory: H, settings: Settings) -> ::Result<LoopHandle>
+where A: Accept + Send + 'static, H: HandlerFactory<A::Output> + Send + 'static {
+    let mut clones = Vec::with_capacity(listeners.len());
+    for listener in listeners {
+        clones.push(try!(listener.try_clone()));
+    }
+
+    // the loop is built on its thread, and sends back how to stop it
+    let (tx, rx) = mpsc::channel();
+    try!(thread::Builder::new().name(format!("hyper-server-{}", i)).spawn(move || {
+        match new_loop(clones, factory, settings) {
+            Ok((_, handle, server)) => {
+                let _ = tx.send(Ok(handle));
+                server.run();
+            },
+            Err(e) => {
+                let _ = tx.send(Err(e));
+            }
+        }
+    }));
+    match rx.recv() {
+        Ok(res) => res,
+        Err(_) => Err(::Error::Io(io::Error::new(io::ErrorKind::Other, "server thread panicked")))
     }
 }
 
@@ -189,13 +361,27 @@ struct Context<F> {
     factory: F,
     idle_timeout: Option<Duration>,
     keep_alive: bool,
+    h2c: bool,
+    signal: Arc<Signal>,
+    /// Wakes up each open connection, by its `Conn::id`.
+    conns: HashMap<usize, rotor::Notifier>,
+    /// Set once the loop is draining. Dropping it along with the loop tells
+    /// the `Draining` handle that the loop stopped.
+    drained: Option<mpsc::Sender<()>>,
+}
+
+impl<F> Context<F> {
+    /// Whether the loop is draining, and has no connections left.
+    fn is_drained(&self) -> bool {
+        self.drained.is_some() && self.conns.is_empty()
+    }
 }
 
 impl<F: HandlerFactory<T>, T: Transport> http::MessageHandlerFactory<(), T> for Context<F> {
     type Output = message::Message<F::Output, T>;
 
     fn create(&mut self, seed: http::Seed<()>) -> Option<Self::Output> {
-        Some(message::Message::new(self.factory.create(seed.control())))
+        Some(message::Message::new(self.factory.create(seed.control()), self.signal.clone()))
     }
 
     fn keep_alive_interest(&self) -> Next {
@@ -205,14 +391,42 @@ impl<F: HandlerFactory<T>, T: Transport> http::MessageHandlerFactory<(), T> for
             Next::read()
         }
     }
+
+    fn on_parse_error(&mut self, err: &::Error, buf: &[u8]) -> Option<(http::MessageHead<StatusCode>, Vec<u8>)> {
+        let mut head = http::MessageHead::default();
+        head.subject = error_status(err, buf);
+        let body = self.factory.on_bad_request(err, &mut response::new(&mut head));
+        head.headers.set(ContentLength(body.len() as u64));
+        head.headers.set(Connection::close());
+        Some((head, body))
+    }
+
+    fn on_close(&mut self, _key: &(), id: usize) {
+        self.conns.remove(&id);
+    }
+}
+
+/// The status to answer a request that couldn't be parsed with.
+fn error_status(err: &::Error, buf: &[u8]) -> StatusCode {
+    match *err {
+        // the head was read, but the body it declares is too large
+        ::Error::TooLarge if buf.is_empty() => StatusCode::PayloadTooLarge,
+        // not even the request line fit in the buffer
+        ::Error::TooLarge if !buf.contains(&b'\n') => StatusCode::UriTooLong,
+        ::Error::TooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+        ::Error::Version => StatusCode::HttpVersionNotSupported,
+        _ => StatusCode::BadRequest,
+    }
 }
 
 enum ServerFsm<A, H>
 where A: Accept,
       A::Output: Transport,
       H: HandlerFactory<A::Output> {
-    Listener(A, Arc<AtomicBool>),
-    Conn(http::Conn<(), A::Output, message::Message<H::Output, A::Output>>)
+    Listener(A, Arc<Signal>),
+    Conn(http::Conn<(), A::Output, message::Message<H::Output, A::Output>>),
+    /// Waits for connections to finish, until the deadline.
+    Draining(rotor::Time),
 }
 
 impl<A, H> rotor::Machine for ServerFsm<A, H>
@@ -224,12 +438,12 @@ where A: Accept,
 
     fn create(seed: Self::Seed, scope: &mut Scope<Self::Context>) -> rotor::Response<Self, rotor::Void> {
         rotor_try!(scope.register(&seed, EventSet::readable(), PollOpt::level()));
-        rotor::Response::ok(
-            ServerFsm::Conn(
-                http::Conn::new((), seed, Next::read(), scope.notifier(), scope.now())
-                    .keep_alive(scope.keep_alive)
-            )
-        )
+        let conn = http::Conn::new((), seed, Next::read(), scope.notifier(), scope.now())
+            .keep_alive(scope.keep_alive)
+            .h2c_upgrade(scope.h2c);
+        let notifier = scope.notifier();
+        scope.conns.insert(conn.id(), notifier);
+        rotor::Response::ok(ServerFsm::Conn(conn))
     }
 
     fn ready(self, events: EventSet, scope: &mut Scope<Self::Context>) -> rotor::Response<Self, Self::Seed> {
@@ -252,19 +466,11 @@ where A: Accept,
                 loop {
                     match conn.take().unwrap().ready(events, scope) {
                         ReadyResult::Continue(c) => conn = Some(c),
-                        ReadyResult::Done(res) => {
-                            return match res {
-                                Some((conn, None)) => rotor::Response::ok(ServerFsm::Conn(conn)),
-                                Some((conn, Some(dur))) => {
-                                    rotor::Response::ok(ServerFsm::Conn(conn))
-                                        .deadline(scope.now() + dur)
-                                }
-                                None => rotor::Response::done()
-                            };
-                        }
+                        ReadyResult::Done(res) => return ServerFsm::conn_response(res, scope),
                     }
                 }
-            }
+            },
+            ServerFsm::Draining(deadline) => rotor::Response::ok(ServerFsm::Draining(deadline)).deadline(deadline),
         }
     }
 
@@ -283,6 +489,7 @@ where A: Accept,
                     }
                 }
             },
+            ServerFsm::Draining(deadline) => rotor::Response::ok(ServerFsm::Draining(deadline)).deadline(deadline),
             sock => rotor::Response::ok(sock)
         }
 
@@ -292,52 +499,139 @@ where A: Accept,
         match self {
             ServerFsm::Listener(..) => unreachable!("Listener cannot timeout"),
             ServerFsm::Conn(conn) => {
-                match conn.timeout(scope) {
-                    Some((conn, None)) => rotor::Response::ok(ServerFsm::Conn(conn)),
-                    Some((conn, Some(dur))) => {
-                        rotor::Response::ok(ServerFsm::Conn(conn))
-                            .deadline(scope.now() + dur)
-                    }
-                    None => rotor::Response::done()
-                }
+                let res = conn.timeout(scope);
+                ServerFsm::conn_response(res, scope)
+            },
+            ServerFsm::Draining(..) => {
+                debug!("drain deadline passed with {} connections open", scope.conns.len());
+                scope.shutdown_loop();
+                rotor::Response::done()
             }
         }
     }
 
     fn wakeup(self, scope: &mut Scope<Self::Context>) -> rotor::Response<Self, Self::Seed> {
         match self {
-            ServerFsm::Listener(lst, shutdown) => {
-                if shutdown.load(Ordering::Acquire) {
+            ServerFsm::Listener(lst, signal) => {
+                if signal.is_closed() {
                     let _ = scope.deregister(&lst);
                     scope.shutdown_loop();
                     rotor::Response::done()
+                } else if signal.is_draining() {
+                    // stop accepting, and let one listener wait for the
+                    // connections to finish
+                    let _ = scope.deregister(&lst);
+                    match signal.take_drain() {
+                        Some((grace, tx)) => {
+                            debug!("draining {} connections", scope.conns.len());
+                            scope.drained = Some(tx);
+                            if scope.is_drained() {
+                                scope.shutdown_loop();
+                                return rotor::Response::done();
+                            }
+                            // idle connections close once they notice
+                            for notifier in scope.conns.values() {
+                                let _ = notifier.wakeup();
+                            }
+                            let deadline = scope.now() + grace;
+                            rotor::Response::ok(ServerFsm::Draining(deadline)).deadline(deadline)
+                        },
+                        None => rotor::Response::done()
+                    }
                 } else {
-                    rotor::Response::ok(ServerFsm::Listener(lst, shutdown))
+                    rotor::Response::ok(ServerFsm::Listener(lst, signal))
                 }
             },
-            ServerFsm::Conn(conn) => match conn.wakeup(scope) {
-                Some((conn, None)) => rotor::Response::ok(ServerFsm::Conn(conn)),
-                Some((conn, Some(dur))) => {
-                    rotor::Response::ok(ServerFsm::Conn(conn))
-                        .deadline(scope.now() + dur)
+            ServerFsm::Conn(conn) => {
+                let res = conn.wakeup(scope);
+                ServerFsm::conn_response(res, scope)
+            },
+            ServerFsm::Draining(deadline) => rotor::Response::ok(ServerFsm::Draining(deadline)).deadline(deadline),
+        }
+    }
+}
+
+impl<A, H> ServerFsm<A, H>
+where A: Accept,
+      A::Output: Transport,
+      H: HandlerFactory<A::Output> {
+    fn conn_response(
+        res: Option<(http::Conn<(), A::Output, message::Message<H::Output, A::Output>>, Option<Duration>)>,
+        scope: &mut Scope<Context<H>>
+    ) -> rotor::Response<Self, A::Output> {
+        let (mut conn, timeout) = match res {
+            Some(res) => res,
+            None => return ServerFsm::stop_if_drained(scope)
+        };
+        if scope.drained.is_some() && conn.is_between_messages() {
+            // a draining server closes connections between messages
+            trace!("closing idle connection while draining");
+            scope.conns.remove(&conn.id());
+            return ServerFsm::stop_if_drained(scope);
+        }
+        if scope.drained.is_some() && conn.close_streams() {
+            // h2 connections get a GOAWAY, and close once their streams
+            // finish, right away if there are none
+            trace!("closing multiplexed connection while draining");
+            let mut conn = Some(conn);
+            loop {
+                match conn.take().unwrap().ready(EventSet::writable(), scope) {
+                    ReadyResult::Continue(c) => conn = Some(c),
+                    ReadyResult::Done(res) => return ServerFsm::conn_response(res, scope),
                 }
-                None => rotor::Response::done()
             }
         }
+        match timeout {
+            Some(dur) => rotor::Response::ok(ServerFsm::Conn(conn)).deadline(scope.now() + dur),
+            None => rotor::Response::ok(ServerFsm::Conn(conn))
+        }
+    }
+
+    /// Ends a connection, stopping the loop if it was the last one of a
+    /// draining server.
+    fn stop_if_drained(scope: &mut Scope<Context<H>>) -> rotor::Response<Self, A::Output> {
+        if scope.is_drained() {
+            debug!("server drained");
+            scope.shutdown_loop();
+        }
+        rotor::Response::done()
+    }
+}
+
+/// Stops one loop of a server.
+struct LoopHandle {
+    signal: Arc<Signal>,
+    /// The notifier of the lead listener comes first.
+    notifiers: Vec<rotor::Notifier>,
+}
+
+impl LoopHandle {
+    fn close(&self) {
+        self.signal.close();
+        let _ = self.notifiers[0].wakeup();
+    }
+
+    fn drain(&self, grace: Duration, tx: mpsc::Sender<()>) {
+        self.signal.drain(grace, tx);
+        for notifier in &self.notifiers {
+            let _ = notifier.wakeup();
+        }
     }
 }
 
 /// A handle of the running server.
 pub struct Listening {
     addrs: Vec<SocketAddr>,
-    shutdown: (Arc<AtomicBool>, rotor::Notifier),
+    /// Each loop of the server, from `handle_threads`, or just the one.
+    loops: Vec<LoopHandle>,
 }
 
 impl fmt::Debug for Listening {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Listening")
             .field("addrs", &self.addrs)
-            .field("closed", &self.shutdown.0.load(Ordering::Relaxed))
+            .field("loops", &self.loops.len())
+            .field("closed", &self.loops[0].signal.is_closed())
             .finish()
     }
 }
@@ -361,10 +655,31 @@ impl Listening {
     }
 
     /// Stop the server from listening to its socket address.
+    ///
+    /// This also closes every connection right away, even in the middle of
+    /// a response. Use `shutdown` to let them finish.
     pub fn close(self) {
         debug!("closing server {}", self);
-        self.shutdown.0.store(true, Ordering::Release);
-        self.shutdown.1.wakeup().unwrap();
+        for handle in &self.loops {
+            handle.close();
+        }
+    }
+
+    /// Stop the server from listening, and stop it once the messages
+    /// in progress are done, or `grace` has passed.
+    ///
+    /// Responses sent from then on have `Connection: close`, and kept alive
+    /// connections close once they are idle. Connections still busy after
+    /// `grace`, such as upgraded ones, are closed along with the loop.
+    ///
+    /// The returned `Draining` tells when every loop has stopped.
+    pub fn shutdown(self, grace: Duration) -> Draining {
+        debug!("shutting down server {}", self);
+        let (tx, rx) = mpsc::channel();
+        for handle in &self.loops {
+            handle.drain(grace, tx.clone());
+        }
+        shutdown::draining(rx)
     }
 }
 
@@ -373,6 +688,11 @@ impl Listening {
 /// Each event handler returns its desired `Next` action.
 pub trait Handler<T: Transport> {
     /// This event occurs first, triggering when a `Request` has been parsed.
+    ///
+    /// If the request has `Expect: 100-continue`, the client waits for a
+    /// `100 Continue`, which is sent once this handler signals
+    /// `Next::read()`. Signaling `Next::write()` instead answers without the
+    /// body, such as to reject it with a `401` or `417`.
     fn on_request(&mut self, request: Request<T>) -> Next;
     /// This event occurs each time the `Request` is ready to be read from.
     fn on_request_readable(&mut self, request: &mut http::Decoder<T>) -> Next;
@@ -389,6 +709,15 @@ pub trait Handler<T: Transport> {
         http::Next::remove()
     }
 
+    /// This event occurs once a `101 Switching Protocols` response has been
+    /// written, asking for the `Upgrade` that takes over the transport.
+    ///
+    /// The default returns `None`, which closes the connection.
+    fn on_upgrade(&mut self) -> Option<Box<http::Upgrade<T>>> {
+        debug!("default Handler.on_upgrade");
+        None
+    }
+
     /// This event occurs when this Handler has requested to remove the Transport.
     fn on_remove(self, _transport: T) where Self: Sized {
         debug!("default Handler.on_remove");
@@ -402,6 +731,27 @@ pub trait HandlerFactory<T: Transport> {
     type Output: Handler<T>;
     /// Creates the associated `Handler`.
     fn create(&mut self, ctrl: http::Control) -> Self::Output;
+
+    /// This event occurs when a request couldn't be parsed. The connection
+    /// is closed after the response.
+    ///
+    /// If the head was parsed but the body couldn't be framed from it, the
+    /// `Handler` for the request was already created. It never sees the
+    /// request, only an `on_error` with `err` after this.
+    ///
+    /// The `Response` starts out with a fitting status, such as
+    /// `400 Bad Request`, `413 Payload Too Large`, `414 URI Too Long`,
+    /// `431 Request Header Fields Too Large` or `505 HTTP Version Not
+    /// Supported`. The returned bytes are sent as its body.
+    ///
+    /// `413 Payload Too Large` is only sent for a `Content-Length` too big
+    /// to be parsed. Limiting the size of bodies is up to the `Handler`.
+    ///
+    /// The default sends an empty body.
+    fn on_bad_request(&mut self, err: &::Error, response: &mut Response) -> Vec<u8> {
+        debug!("default HandlerFactory.on_bad_request({:?}) -> {}", err, response.status());
+        Vec::new()
+    }
 }
 
 impl<F, H, T> HandlerFactory<T> for F
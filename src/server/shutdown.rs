@@ -0,0 +1,82 @@
+//! Stopping a server, right away or once its messages are done.
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Shared by a `Listening` with the loop it stops.
+#[derive(Debug)]
+pub struct Signal {
+    closed: AtomicBool,
+    draining: AtomicBool,
+    /// How long to wait for messages to finish, and who to tell once the
+    /// loop is done. Taken by the listener that starts draining.
+    drain: Mutex<Option<(Duration, mpsc::Sender<()>)>>,
+}
+
+impl Signal {
+    pub fn new() -> Signal {
+        Signal {
+            closed: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            drain: Mutex::new(None),
+        }
+    }
+
+    /// Stops the loop right away.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+    }
+
+    /// Stops accepting, and stops the loop once its messages are done, or
+    /// `grace` has passed.
+    pub fn drain(&self, grace: Duration, done: mpsc::Sender<()>) {
+        *self.drain.lock().unwrap() = Some((grace, done));
+        self.draining.store(true, Ordering::Release);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+
+    pub fn take_drain(&self) -> Option<(Duration, mpsc::Sender<()>)> {
+        self.drain.lock().unwrap().take()
+    }
+}
+
+/// A server shutting down gracefully, from `Listening::shutdown`.
+pub struct Draining {
+    rx: mpsc::Receiver<()>,
+}
+
+impl Draining {
+    /// Blocks until every loop of the server has stopped.
+    pub fn wait(self) {
+        let _ = self.rx.recv();
+    }
+
+    /// Whether every loop of the server has stopped.
+    pub fn is_drained(&self) -> bool {
+        match self.rx.try_recv() {
+            Err(mpsc::TryRecvError::Empty) => false,
+            _ => true
+        }
+    }
+}
+
+impl fmt::Debug for Draining {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Draining")
+    }
+}
+
+pub fn draining(rx: mpsc::Receiver<()>) -> Draining {
+    Draining {
+        rx: rx,
+    }
+}
@@ -0,0 +1,492 @@
+//! Server-Sent Events, the `text/event-stream` format of the
+//! [HTML spec](https://html.spec.whatwg.org/multipage/server-sent-events.html).
+//!
+//! - On a `Server`, a `server::Handler` sets up the response with `response`,
+//!   queues `Event`s on an `EventWriter`, and writes them out in
+//!   `on_response_writable`. Events usually come from elsewhere, so the
+//!   handler waits with `Next::wait()` and is woken by a `Control`. A
+//!   `KeepAlive` also wakes it up every so often, so a comment can be sent
+//!   to keep an idle stream open.
+//! - On a `Client`, a `client::Handler` asks for a stream with `request`, and
+//!   reads the body in `on_response_readable` with an `EventReader`. After a
+//!   dropped connection, the `last_event_id` of the reader is sent in the
+//!   next request, so the server can pick up where it left off.
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use client;
+use header::{Accept, CacheControl, CacheDirective, ContentType, LastEventID, qitem};
+use http::{self, Next};
+use mime::{Mime, TopLevel, SubLevel};
+use net::Transport;
+use server;
+
+/// An event, as sent in a `text/event-stream`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Event {
+    /// The type of the event. `None` is a `message` event.
+    pub event: Option<String>,
+    /// The id of the event, which the client sends back in `Last-Event-ID`
+    /// when it reconnects.
+    pub id: Option<String>,
+    /// The data of the event. It can span several lines.
+    pub data: String,
+    /// How long the client should wait before reconnecting.
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    /// Creates a `message` event carrying `data`.
+    pub fn new<S: Into<String>>(data: S) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// Sets the type of the event.
+    pub fn event<S: Into<String>>(mut self, event: S) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets the id of the event.
+    pub fn id<S: Into<String>>(mut self, id: S) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the reconnection time of the event.
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+fn event_stream() -> Mime {
+    Mime(TopLevel::Text, SubLevel::Ext("event-stream".to_owned()), vec![])
+}
+
+/// Sets up `res` to send a stream of events.
+pub fn response(res: &mut server::Response) {
+    res.headers_mut().set(ContentType(event_stream()));
+    res.headers_mut().set(CacheControl(vec![CacheDirective::NoCache]));
+}
+
+/// Sets up `req` to ask for a stream of events, continuing after
+/// `last_event_id` if it is set.
+pub fn request(req: &mut client::Request, last_event_id: Option<&str>) {
+    req.headers_mut().set(Accept(vec![qitem(event_stream())]));
+    req.headers_mut().set(CacheControl(vec![CacheDirective::NoCache]));
+    if let Some(id) = last_event_id {
+        req.headers_mut().set(LastEventID(id.to_owned()));
+    }
+}
+
+/// Queues events to write to an `http::Encoder`.
+#[derive(Debug)]
+pub struct EventWriter {
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl EventWriter {
+    /// Creates an `EventWriter` with nothing queued.
+    pub fn new() -> EventWriter {
+        EventWriter {
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Queues an event.
+    ///
+    /// Line breaks in the data are kept, but in the type or id they would
+    /// end the field early, so anything after one is dropped.
+    pub fn send(&mut self, event: &Event) {
+        if let Some(ref name) = event.event {
+            self.field("event", first_line(name));
+        }
+        if let Some(ref id) = event.id {
+            self.field("id", first_line(id));
+        }
+        if let Some(retry) = event.retry {
+            let millis = retry.as_secs() * 1000 + (retry.subsec_nanos() / 1_000_000) as u64;
+            self.field("retry", &millis.to_string());
+        }
+        for line in lines(&event.data) {
+            self.field("data", line);
+        }
+        self.buf.push(b'\n');
+    }
+
+    /// Queues a comment, which clients ignore.
+    pub fn comment(&mut self, text: &str) {
+        for line in lines(text) {
+            self.buf.push(b':');
+            self.buf.extend_from_slice(line.as_bytes());
+            self.buf.push(b'\n');
+        }
+    }
+
+    /// Whether everything queued has been written.
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    /// Writes what is queued to `encoder`.
+    ///
+    /// If nothing is queued, an empty comment is written instead, so waking
+    /// up the response with nothing to send keeps the stream open.
+    ///
+    /// Returns `Next::write()` if the encoder couldn't take everything, and
+    /// `Next::wait()` once it has. Any error other than `WouldBlock` is
+    /// returned as is.
+    pub fn write<T: Transport>(&mut self, encoder: &mut http::Encoder<T>) -> io::Result<Next> {
+        if self.is_empty() {
+            self.comment("");
+        }
+        while !self.is_empty() {
+            match encoder.try_write(&self.buf[self.pos..]) {
+                Ok(Some(0)) => return Err(io::Error::new(io::ErrorKind::WriteZero, "event stream closed")),
+                Ok(Some(n)) => self.pos += n,
+                Ok(None) => return Ok(Next::write()),
+                Err(e) => return Err(e),
+            }
+        }
+        self.buf.clear();
+        self.pos = 0;
+        Ok(Next::wait())
+    }
+
+    fn field(&mut self, name: &str, value: &str) {
+        self.buf.extend_from_slice(name.as_bytes());
+        self.buf.extend_from_slice(b": ");
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(b'\n');
+    }
+}
+
+/// Splits on any of the line breaks the format allows.
+fn lines(s: &str) -> Vec<&str> {
+    s.split("\r\n").flat_map(|s| s.split(|c| c == '\r' || c == '\n')).collect()
+}
+
+fn first_line(s: &str) -> &str {
+    lines(s)[0]
+}
+
+/// Wakes up a waiting response every `interval`, so an `EventWriter` can
+/// keep the stream open while there are no events to send.
+///
+/// A thread wakes the response through its `Control`, as if an event had
+/// come, and the handler writes out its `EventWriter` in
+/// `on_response_writable` as usual. With nothing queued, that sends an empty
+/// comment. Since ticks don't go through the timeout of the event loop, an
+/// `Error::Timeout` in `on_error` still means the handler's own timeout
+/// passed.
+///
+/// The thread stops once the `KeepAlive` is dropped, or the connection is
+/// gone.
+#[derive(Debug)]
+pub struct KeepAlive {
+    stopped: Arc<AtomicBool>,
+}
+
+impl KeepAlive {
+    /// Starts waking up the response of `ctrl` every `interval`.
+    pub fn new(ctrl: http::Control, interval: Duration) -> KeepAlive {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = stopped.clone();
+        thread::Builder::new().name("hyper-sse-keep-alive".to_owned()).spawn(move || {
+            loop {
+                thread::sleep(interval);
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                if ctrl.ready(Next::write()).is_err() {
+                    trace!("event stream gone, no more keep-alives");
+                    break;
+                }
+            }
+        }).expect("spawn keep-alive thread");
+        KeepAlive {
+            stopped: stopped,
+        }
+    }
+}
+
+impl Drop for KeepAlive {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+/// How large an event, or a line of one, can get by default.
+const MAX_EVENT_SIZE: usize = 1024 * 1024;
+
+/// Parses events out of a `text/event-stream` read from an `http::Decoder`.
+#[derive(Debug)]
+pub struct EventReader {
+    buf: Vec<u8>,
+    max_size: usize,
+    started: bool,
+    event: Option<String>,
+    data: String,
+    last_event_id: Option<String>,
+    retry: Option<Duration>,
+    /// Whether the last id or retry was set by the event being parsed.
+    id_changed: bool,
+    retry_changed: bool,
+}
+
+impl EventReader {
+    /// Creates an `EventReader` for a new stream.
+    pub fn new() -> EventReader {
+        EventReader {
+            buf: Vec::new(),
+            max_size: MAX_EVENT_SIZE,
+            started: false,
+            event: None,
+            data: String::new(),
+            last_event_id: None,
+            retry: None,
+            id_changed: false,
+            retry_changed: false,
+        }
+    }
+
+    /// Sets how large an event, or a line of one, can get before `read`
+    /// gives up on the stream.
+    ///
+    /// Default is 1MB.
+    pub fn max_size(mut self, max: usize) -> EventReader {
+        self.max_size = max;
+        self
+    }
+
+    /// Reads what is available from `decoder`, and returns the events that
+    /// were completed.
+    ///
+    /// `WouldBlock` is returned once nothing is left to read, and an
+    /// `UnexpectedEof` error once the stream has ended. An event larger
+    /// than `max_size` is an `InvalidData` error.
+    pub fn read<T: Transport>(&mut self, decoder: &mut http::Decoder<T>) -> io::Result<Vec<Event>> {
+        let mut events = Vec::new();
+        let mut chunk = [0; 4096];
+        loop {
+            match decoder.read(&mut chunk) {
+                Ok(0) => {
+                    if events.is_empty() {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "event stream ended"));
+                    }
+                    return Ok(events);
+                },
+                Ok(n) => try!(self.feed(&chunk[..n], &mut events)),
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::WouldBlock && !events.is_empty() {
+                        return Ok(events);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// The id of the last event, to send in `Last-Event-ID` when reconnecting.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_ref().map(|id| &id[..])
+    }
+
+    /// How long the server asked to wait before reconnecting.
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    fn feed(&mut self, bytes: &[u8], events: &mut Vec<Event>) -> io::Result<()> {
+        self.buf.extend_from_slice(bytes);
+        if !self.started {
+            // a byte order mark can only come first
+            if self.buf.len() < 3 && b"\xEF\xBB\xBF".starts_with(&self.buf) {
+                return Ok(());
+            }
+            if self.buf.starts_with(b"\xEF\xBB\xBF") {
+                self.buf.drain(..3);
+            }
+            self.started = true;
+        }
+
+        let mut start = 0;
+        let mut i = 0;
+        while i < self.buf.len() {
+            let end = match self.buf[i] {
+                b'\n' => i + 1,
+                b'\r' => {
+                    if i + 1 == self.buf.len() {
+                        // the next byte could be the `\n` of a `\r\n`
+                        break;
+                    }
+                    if self.buf[i + 1] == b'\n' { i + 2 } else { i + 1 }
+                },
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let line = String::from_utf8_lossy(&self.buf[start..i]).into_owned();
+            if let Some(event) = self.line(&line) {
+                events.push(event);
+            }
+            start = end;
+            i = end;
+        }
+        self.buf.drain(..start);
+        // what's left is an unfinished line of the event being parsed
+        if self.buf.len() + self.data.len() > self.max_size {
+            debug!("event larger than {} bytes", self.max_size);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "event too large"));
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, line: &str) -> Option<Event> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (name, value) = match line.find(':') {
+            Some(colon) => {
+                let value = &line[colon + 1..];
+                (&line[..colon], if value.starts_with(' ') { &value[1..] } else { value })
+            },
+            None => (line, ""),
+        };
+        match name {
+            "event" => self.event = Some(value.to_owned()),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            },
+            "id" => {
+                if !value.contains('\0') {
+                    self.last_event_id = Some(value.to_owned());
+                    self.id_changed = true;
+                }
+            },
+            "retry" => {
+                if !value.is_empty() && value.bytes().all(|b| b >= b'0' && b <= b'9') {
+                    if let Ok(millis) = value.parse::<u64>() {
+                        self.retry = Some(Duration::from_millis(millis));
+                        self.retry_changed = true;
+                    }
+                }
+            },
+            _ => trace!("ignoring event field {:?}", name),
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<Event> {
+        let event = self.event.take();
+        let id_changed = self.id_changed;
+        let retry_changed = self.retry_changed;
+        self.id_changed = false;
+        self.retry_changed = false;
+        if self.data.is_empty() {
+            return None;
+        }
+        let mut data = ::std::mem::replace(&mut self.data, String::new());
+        data.pop();
+        Some(Event {
+            event: event,
+            id: if id_changed { self.last_event_id.clone() } else { None },
+            data: data,
+            retry: if retry_changed { self.retry } else { None },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{Event, EventWriter, EventReader};
+
+    fn parse(chunks: &[&[u8]]) -> (EventReader, Vec<Event>) {
+        let mut reader = EventReader::new();
+        let mut events = Vec::new();
+        for chunk in chunks {
+            reader.feed(chunk, &mut events).unwrap();
+        }
+        (reader, events)
+    }
+
+    #[test]
+    fn test_send() {
+        let mut writer = EventWriter::new();
+        writer.send(&Event::new("hello"));
+        writer.send(&Event::new("one\ntwo\r\nthree")
+                        .event("update")
+                        .id("7\nevil: true")
+                        .retry(Duration::from_millis(1500)));
+        writer.comment("ping");
+        assert_eq!(::std::str::from_utf8(&writer.buf).unwrap(),
+                   "data: hello\n\n\
+                    event: update\nid: 7\nretry: 1500\ndata: one\ndata: two\ndata: three\n\n\
+                    :ping\n");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let event = Event::new("a\n\nb").event("x").id("1");
+        let mut writer = EventWriter::new();
+        writer.send(&event);
+        let (reader, events) = parse(&[&writer.buf]);
+        assert_eq!(events, vec![event]);
+        assert_eq!(reader.last_event_id(), Some("1"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let (reader, events) = parse(&[b"\xEF\xBB\xBF: comment\r\ndata:no space\r\n\r\n",
+                                       b"event: ping\rdata\r\rid: 3\nretry: 2000\n\n",
+                                       b"id: 4\n\n",
+                                       b"data: partial\r", b"\n", b"data: more\n"]);
+        assert_eq!(events, vec![
+            Event::new("no space"),
+            Event::new("").event("ping"),
+        ]);
+        // an event without data still sets the last id and retry
+        assert_eq!(reader.last_event_id(), Some("4"));
+        assert_eq!(reader.retry(), Some(Duration::from_millis(2000)));
+        assert_eq!(reader.data, "partial\nmore\n");
+    }
+
+    #[test]
+    fn test_parse_too_large() {
+        let mut reader = EventReader::new().max_size(16);
+        let mut events = Vec::new();
+        reader.feed(b"data: 0123456789\n\n", &mut events).unwrap();
+        assert_eq!(events, vec![Event::new("0123456789")]);
+
+        // a line that never ends
+        assert!(reader.feed(b"data: 0123456789", &mut events).is_ok());
+        let err = reader.feed(b"abcdef", &mut events).unwrap_err();
+        assert_eq!(err.kind(), ::std::io::ErrorKind::InvalidData);
+
+        // or an event of many lines
+        let mut reader = EventReader::new().max_size(16);
+        reader.feed(b"data: 01234567\n", &mut events).unwrap();
+        assert!(reader.feed(b"data: 89abcdef\n", &mut events).is_err());
+    }
+
+    #[test]
+    fn test_parse_split_bom() {
+        let (_, events) = parse(&[b"\xEF", b"\xBB", b"\xBFdata: x\n\n"]);
+        assert_eq!(events, vec![Event::new("x")]);
+    }
+}
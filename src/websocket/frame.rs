@@ -0,0 +1,252 @@
+//! WebSocket framing, as in [RFC6455 section 5](https://tools.ietf.org/html/rfc6455#section-5).
+use std::fmt;
+
+/// The kind of a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(b: u8) -> Option<Opcode> {
+        match b {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn to_u8(&self) -> u8 {
+        match *self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    /// Control frames can't be fragmented, and can come in between the
+    /// fragments of a message.
+    pub fn is_control(&self) -> bool {
+        match *self {
+            Opcode::Close | Opcode::Ping | Opcode::Pong => true,
+            _ => false,
+        }
+    }
+}
+
+/// A single frame, with its payload already unmasked.
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+/// Why a frame couldn't be parsed.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The frame breaks the protocol.
+    Protocol(&'static str),
+    /// The frame is bigger than allowed.
+    TooBig,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Protocol(msg) => f.write_str(msg),
+            Error::TooBig => f.write_str("frame too big"),
+        }
+    }
+}
+
+/// The biggest payload a control frame can have.
+const MAX_CONTROL_PAYLOAD: u64 = 125;
+
+/// Parses a frame from the start of `buf`, along with how many bytes it used.
+///
+/// Returns `None` until `buf` holds the whole frame. Frames sent by a client
+/// must be masked, and frames sent by a server must not, so `masked` is
+/// whether the peer is a client.
+pub fn parse(buf: &[u8], masked: bool, max_payload: usize) -> Result<Option<(Frame, usize)>, Error> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buf[0] & 0x80 != 0;
+    if buf[0] & 0x70 != 0 {
+        return Err(Error::Protocol("reserved bits set"));
+    }
+    let opcode = match Opcode::from_u8(buf[0] & 0x0F) {
+        Some(opcode) => opcode,
+        None => return Err(Error::Protocol("unknown opcode")),
+    };
+    if (buf[1] & 0x80 != 0) != masked {
+        return Err(Error::Protocol(if masked { "frame not masked" } else { "frame masked" }));
+    }
+
+    let mut pos = 2;
+    let len = match buf[1] & 0x7F {
+        126 => {
+            if buf.len() < pos + 2 {
+                return Ok(None);
+            }
+            pos += 2;
+            (buf[2] as u64) << 8 | buf[3] as u64
+        },
+        127 => {
+            if buf.len() < pos + 8 {
+                return Ok(None);
+            }
+            pos += 8;
+            buf[2..10].iter().fold(0, |len, &b| len << 8 | b as u64)
+        },
+        len => len as u64,
+    };
+    if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD) {
+        return Err(Error::Protocol("bad control frame"));
+    }
+    if len > max_payload as u64 {
+        return Err(Error::TooBig);
+    }
+    let len = len as usize;
+
+    let mask = if masked {
+        if buf.len() < pos + 4 {
+            return Ok(None);
+        }
+        pos += 4;
+        Some([buf[pos - 4], buf[pos - 3], buf[pos - 2], buf[pos - 1]])
+    } else {
+        None
+    };
+
+    if buf.len() < pos + len {
+        return Ok(None);
+    }
+    let mut payload = buf[pos..pos + len].to_vec();
+    if let Some(mask) = mask {
+        apply_mask(&mut payload, mask);
+    }
+    Ok(Some((Frame {
+        fin: fin,
+        opcode: opcode,
+        payload: payload,
+    }, pos + len)))
+}
+
+/// Writes a frame to `dst`, masking the payload with `mask` if there is one.
+pub fn encode(opcode: Opcode, fin: bool, payload: &[u8], mask: Option<[u8; 4]>, dst: &mut Vec<u8>) {
+    let fin = if fin { 0x80 } else { 0 };
+    dst.push(fin | opcode.to_u8());
+
+    let masked = if mask.is_some() { 0x80 } else { 0 };
+    let len = payload.len();
+    if len < 126 {
+        dst.push(masked | len as u8);
+    } else if len <= 0xFFFF {
+        dst.push(masked | 126);
+        dst.push((len >> 8) as u8);
+        dst.push(len as u8);
+    } else {
+        dst.push(masked | 127);
+        let len = len as u64;
+        for i in 0..8 {
+            dst.push((len >> (56 - i * 8)) as u8);
+        }
+    }
+
+    match mask {
+        Some(mask) => {
+            dst.extend_from_slice(&mask);
+            let start = dst.len();
+            dst.extend_from_slice(payload);
+            apply_mask(&mut dst[start..], mask);
+        },
+        None => dst.extend_from_slice(payload),
+    }
+}
+
+/// Masking and unmasking are the same operation.
+fn apply_mask(bytes: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, encode, Frame, Opcode, Error};
+
+    #[test]
+    fn test_parse_rfc_examples() {
+        // A single-frame unmasked text message
+        let frame = parse(&[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f], false, 1024).unwrap();
+        assert_eq!(frame, Some((Frame {
+            fin: true,
+            opcode: Opcode::Text,
+            payload: b"Hello".to_vec(),
+        }, 7)));
+
+        // A single-frame masked text message
+        let buf = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let (frame, len) = parse(&buf, true, 1024).unwrap().unwrap();
+        assert_eq!(frame.payload, b"Hello");
+        assert_eq!(len, buf.len());
+
+        // A fragmented unmasked text message
+        let (frame, _) = parse(&[0x01, 0x03, 0x48, 0x65, 0x6c], false, 1024).unwrap().unwrap();
+        assert!(!frame.fin);
+        assert_eq!(frame.opcode, Opcode::Text);
+        let (frame, _) = parse(&[0x80, 0x02, 0x6c, 0x6f], false, 1024).unwrap().unwrap();
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, Opcode::Continuation);
+    }
+
+    #[test]
+    fn test_parse_partial() {
+        let mut buf = Vec::new();
+        encode(Opcode::Binary, true, &[7; 300], Some([1, 2, 3, 4]), &mut buf);
+        for i in 0..buf.len() {
+            assert_eq!(parse(&buf[..i], true, 1024), Ok(None));
+        }
+        let (frame, len) = parse(&buf, true, 1024).unwrap().unwrap();
+        assert_eq!(frame.payload, vec![7; 300]);
+        assert_eq!(len, buf.len());
+    }
+
+    #[test]
+    fn test_encode_lengths() {
+        for &len in &[0, 125, 126, 0xFFFF, 0x10000] {
+            let payload = vec![0xAB; len];
+            let mut buf = Vec::new();
+            encode(Opcode::Binary, true, &payload, None, &mut buf);
+            let (frame, n) = parse(&buf, false, 0x10000).unwrap().unwrap();
+            assert_eq!(frame.payload.len(), len);
+            assert_eq!(n, buf.len());
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        // masking must match the role of the peer
+        assert_eq!(parse(&[0x81, 0x00], true, 1024), Err(Error::Protocol("frame not masked")));
+        assert_eq!(parse(&[0x81, 0x80, 0, 0, 0, 0], false, 1024), Err(Error::Protocol("frame masked")));
+        // control frames can't be fragmented
+        assert_eq!(parse(&[0x09, 0x00], false, 1024), Err(Error::Protocol("bad control frame")));
+        assert_eq!(parse(&[0xC1, 0x00], false, 1024), Err(Error::Protocol("reserved bits set")));
+        assert_eq!(parse(&[0x83, 0x00], false, 1024), Err(Error::Protocol("unknown opcode")));
+        assert_eq!(parse(&[0x82, 0x7E, 0x04, 0x01], false, 1024), Err(Error::TooBig));
+    }
+}
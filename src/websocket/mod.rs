@@ -0,0 +1,640 @@
+//! WebSocket, as in [RFC6455](https://tools.ietf.org/html/rfc6455).
+//!
+//! A WebSocket starts as an HTTP/1.1 request asking to upgrade.
+//!
+//! - On a `Server`, the `server::Handler` checks the request with
+//!   `request_key`, accepts it with `accept` in `on_response`, and returns a
+//!   `WebSocket` from `on_upgrade`.
+//! - On a `Client`, the `client::Handler` asks for it with `request` in
+//!   `on_request`, checks the response with `is_accepted`, and returns a
+//!   `WebSocket` from `on_upgrade`.
+//!
+//! Either way, the `WebSocket` then drives a `websocket::Handler`, which
+//! reacts to each message and returns its desired `Next` action, just as an
+//! HTTP `Handler` does. Pings are answered, fragmented messages are put back
+//! together, and the closing handshake is taken care of.
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+
+use rand;
+use serialize::base64::{ToBase64, STANDARD};
+use unicase::UniCase;
+
+use client;
+use header::{Connection, ConnectionOption, Protocol, ProtocolName, SecWebSocketAccept,
+             SecWebSocketKey, SecWebSocketVersion};
+use header::Upgrade as UpgradeHeader;
+use http::{self, Next, Next_};
+use method::Method;
+use net::Transport;
+use server;
+use status::StatusCode;
+
+use self::frame::{Frame, Opcode};
+
+mod frame;
+mod sha1;
+
+const GUID: &'static [u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only version of the protocol, as sent in `Sec-WebSocket-Version`.
+const VERSION: u8 = 13;
+
+/// Computes the `Sec-WebSocket-Accept` value answering a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut data = key.trim().as_bytes().to_vec();
+    data.extend_from_slice(GUID);
+    sha1::digest(&data).to_base64(STANDARD)
+}
+
+/// Returns the `Sec-WebSocket-Key` of a request opening a WebSocket, or
+/// `None` if the request isn't one.
+pub fn request_key<T>(req: &server::Request<T>) -> Option<String> {
+    if *req.method() != Method::Get {
+        return None;
+    }
+    let headers = req.headers();
+    let upgrade = headers.get::<UpgradeHeader>().map_or(false, |upgrade| {
+        upgrade.0.iter().any(|protocol| protocol.name == ProtocolName::WebSocket)
+    });
+    let connection = headers.get::<Connection>().map_or(false, |connection| {
+        connection.0.iter().any(|option| match *option {
+            ConnectionOption::ConnectionHeader(ref name) => name.eq_ignore_ascii_case("upgrade"),
+            _ => false,
+        })
+    });
+    let version = headers.get::<SecWebSocketVersion>() == Some(&SecWebSocketVersion(VERSION));
+    if upgrade && connection && version {
+        headers.get::<SecWebSocketKey>().map(|key| key.0.clone())
+    } else {
+        None
+    }
+}
+
+/// Makes `res` a `101 Switching Protocols` accepting the WebSocket that was
+/// opened with `key`.
+pub fn accept(res: &mut server::Response, key: &str) {
+    res.set_status(StatusCode::SwitchingProtocols);
+    set_upgrade(res.headers_mut());
+    res.headers_mut().set(SecWebSocketAccept(accept_key(key)));
+}
+
+/// Sets up `req` to open a WebSocket, returning the key to check the
+/// response with.
+pub fn request(req: &mut client::Request) -> String {
+    let nonce: [u8; 16] = rand::random();
+    let key = nonce.to_base64(STANDARD);
+    req.set_method(Method::Get);
+    set_upgrade(req.headers_mut());
+    req.headers_mut().set(SecWebSocketKey(key.clone()));
+    req.headers_mut().set(SecWebSocketVersion(VERSION));
+    key
+}
+
+/// Whether `res` accepts the WebSocket that was opened with `key`.
+pub fn is_accepted(res: &client::Response, key: &str) -> bool {
+    *res.status() == StatusCode::SwitchingProtocols &&
+        res.headers().get::<SecWebSocketAccept>().map_or(false, |accept| accept.0 == accept_key(key))
+}
+
+fn set_upgrade(headers: &mut ::header::Headers) {
+    headers.set(UpgradeHeader(vec![Protocol::new(ProtocolName::WebSocket, None)]));
+    headers.set(Connection(vec![ConnectionOption::ConnectionHeader(UniCase("Upgrade".to_owned()))]));
+}
+
+/// A whole message, put back together if it was fragmented.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// A text message.
+    Text(String),
+    /// A binary message.
+    Binary(Vec<u8>),
+}
+
+/// Queues frames to send to the peer.
+///
+/// Frames are written once the transport is writable.
+#[derive(Debug)]
+pub struct Sender {
+    buf: Vec<u8>,
+    /// Clients mask every frame they send.
+    mask: bool,
+    /// Nothing can be sent after a Close frame.
+    closing: bool,
+}
+
+impl Sender {
+    fn new(mask: bool) -> Sender {
+        Sender {
+            buf: Vec::new(),
+            mask: mask,
+            closing: false,
+        }
+    }
+
+    /// Sends a message.
+    pub fn send(&mut self, msg: Message) {
+        match msg {
+            Message::Text(text) => self.frame(Opcode::Text, text.as_bytes()),
+            Message::Binary(data) => self.frame(Opcode::Binary, &data),
+        }
+    }
+
+    /// Sends a Ping, which the peer answers with a Pong.
+    ///
+    /// Only the first 125 bytes of `data` are sent.
+    pub fn ping(&mut self, data: &[u8]) {
+        let len = ::std::cmp::min(data.len(), 125);
+        self.frame(Opcode::Ping, &data[..len]);
+    }
+
+    /// Starts closing the WebSocket with a status `code`.
+    ///
+    /// Messages are still received until the peer answers, and then the
+    /// connection is ended. Only the first 123 bytes of `reason` are sent.
+    pub fn close(&mut self, code: u16, reason: &str) {
+        let mut len = ::std::cmp::min(reason.len(), 123);
+        while !reason.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut payload = vec![(code >> 8) as u8, code as u8];
+        payload.extend_from_slice(reason[..len].as_bytes());
+        self.frame(Opcode::Close, &payload);
+        self.closing = true;
+    }
+
+    /// Whether a Close frame was sent already.
+    ///
+    /// Nothing else can be sent after it.
+    pub fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    fn frame(&mut self, opcode: Opcode, payload: &[u8]) {
+        if self.closing {
+            debug!("WebSocket is closing, dropping {:?} frame", opcode);
+            return;
+        }
+        let mask = if self.mask {
+            Some(rand::random())
+        } else {
+            None
+        };
+        frame::encode(opcode, true, payload, mask, &mut self.buf);
+    }
+}
+
+/// A trait to react to WebSocket events.
+///
+/// Each event handler returns its desired `Next` action. `Next::end()`
+/// closes the WebSocket normally.
+pub trait Handler<T: Transport> {
+    /// This event occurs first, once the connection switched to WebSocket.
+    fn on_open(&mut self, _ws: &mut Sender) -> Next {
+        Next::read()
+    }
+
+    /// This event occurs each time a whole message has been received.
+    fn on_message(&mut self, ws: &mut Sender, msg: Message) -> Next;
+
+    /// This event occurs after this handler signals `Next::write()`, once
+    /// everything sent before has been written.
+    fn on_writable(&mut self, _ws: &mut Sender) -> Next {
+        Next::read()
+    }
+
+    /// This event occurs when the WebSocket is closing, with the status code
+    /// and reason sent by the peer.
+    ///
+    /// The code is `None` if the peer didn't send one, or went away without
+    /// closing. If the peer broke the protocol, this has the code that was
+    /// sent to it.
+    fn on_close(&mut self, code: Option<u16>, reason: &str) {
+        debug!("default Handler.on_close({:?}, {:?})", code, reason);
+    }
+
+    /// This event occurs whenever an `Error` occurs outside of the other events.
+    ///
+    /// This could IO errors while waiting for events, or a timeout, etc.
+    fn on_error(&mut self, err: ::Error) -> Next {
+        debug!("default Handler.on_error({:?})", err);
+        Next::remove()
+    }
+
+    /// This event occurs when the WebSocket ended, and hands back the transport.
+    fn on_remove(self, _transport: T) where Self: Sized {
+        debug!("default Handler.on_remove");
+    }
+}
+
+/// Drives a WebSocket `Handler` over an upgraded connection.
+///
+/// Return it from the `on_upgrade` of a `server::Handler` or
+/// `client::Handler`.
+pub struct WebSocket<H: Handler<T>, T: Transport> {
+    handler: H,
+    /// Servers receive masked frames, clients don't.
+    server: bool,
+    max_message_size: usize,
+    read_buf: Vec<u8>,
+    sender: Sender,
+    /// The opcode and payload of a fragmented message being received.
+    message: Option<(Opcode, Vec<u8>)>,
+    interest: Next,
+    /// Whether the peer closed, or broke the protocol.
+    done: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<H: Handler<T>, T: Transport> WebSocket<H, T> {
+    /// Creates the server side of a WebSocket.
+    pub fn server(handler: H) -> WebSocket<H, T> {
+        WebSocket::new(handler, true)
+    }
+
+    /// Creates the client side of a WebSocket.
+    pub fn client(handler: H) -> WebSocket<H, T> {
+        WebSocket::new(handler, false)
+    }
+
+    fn new(handler: H, server: bool) -> WebSocket<H, T> {
+        WebSocket {
+            handler: handler,
+            server: server,
+            max_message_size: 16 * 1024 * 1024,
+            read_buf: Vec::new(),
+            sender: Sender::new(!server),
+            message: None,
+            interest: Next::read(),
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the biggest message that can be received.
+    ///
+    /// Bigger messages close the WebSocket with status code 1009.
+    ///
+    /// Default is 16MB.
+    pub fn max_message_size(mut self, val: usize) -> WebSocket<H, T> {
+        self.max_message_size = val;
+        self
+    }
+
+    /// What to ask of the event loop, given the interest of the handler.
+    fn next(&self) -> Next {
+        let (interest, timeout) = http::interest(&self.interest);
+        let writing = !self.sender.buf.is_empty();
+        let next = if let Next_::Remove = interest {
+            return Next::remove();
+        } else if self.done {
+            if writing {
+                Next::write()
+            } else {
+                return Next::end();
+            }
+        } else if self.sender.closing {
+            // waiting for the peer to answer the Close
+            if writing {
+                Next::read_and_write()
+            } else {
+                Next::read()
+            }
+        } else {
+            match (interest, writing) {
+                (Next_::Read, false) => Next::read(),
+                (Next_::Read, true) |
+                (Next_::ReadWrite, _) => Next::read_and_write(),
+                (Next_::Wait, false) => Next::wait(),
+                _ => Next::write(),
+            }
+        };
+        match timeout {
+            Some(dur) => next.timeout(dur),
+            None => next,
+        }
+    }
+
+    fn fail(&mut self, code: u16, reason: &str) {
+        debug!("WebSocket failed: {} {}", code, reason);
+        self.sender.close(code, reason);
+        self.done = true;
+        self.handler.on_close(Some(code), reason);
+    }
+
+    fn set_interest(&mut self, next: Next) {
+        if let (Next_::End, _) = http::interest(&next) {
+            if !self.sender.closing {
+                self.sender.close(1000, "");
+            }
+        }
+        self.interest = next;
+    }
+
+    fn read_frames(&mut self) {
+        let mut pos = 0;
+        while !self.done {
+            let parsed = frame::parse(&self.read_buf[pos..], self.server, self.max_message_size);
+            match parsed {
+                Ok(Some((frame, len))) => {
+                    pos += len;
+                    self.on_frame(frame);
+                },
+                Ok(None) => break,
+                Err(frame::Error::Protocol(msg)) => self.fail(1002, msg),
+                Err(frame::Error::TooBig) => self.fail(1009, "message too big"),
+            }
+        }
+        if self.done {
+            self.read_buf.clear();
+        } else {
+            self.read_buf.drain(..pos);
+        }
+    }
+
+    fn on_frame(&mut self, frame: Frame) {
+        trace!("WebSocket frame {:?}, fin={}, len={}", frame.opcode, frame.fin, frame.payload.len());
+        match frame.opcode {
+            Opcode::Ping => self.sender.frame(Opcode::Pong, &frame.payload),
+            Opcode::Pong => (),
+            Opcode::Close => self.on_close_frame(&frame.payload),
+            Opcode::Text | Opcode::Binary => {
+                if self.message.is_some() {
+                    self.fail(1002, "expected a continuation frame");
+                } else if frame.fin {
+                    self.on_message(frame.opcode, frame.payload);
+                } else {
+                    self.message = Some((frame.opcode, frame.payload));
+                }
+            },
+            Opcode::Continuation => match self.message.take() {
+                Some((opcode, mut payload)) => {
+                    payload.extend_from_slice(&frame.payload);
+                    if payload.len() > self.max_message_size {
+                        self.fail(1009, "message too big");
+                    } else if frame.fin {
+                        self.on_message(opcode, payload);
+                    } else {
+                        self.message = Some((opcode, payload));
+                    }
+                },
+                None => self.fail(1002, "unexpected continuation frame"),
+            },
+        }
+    }
+
+    fn on_message(&mut self, opcode: Opcode, payload: Vec<u8>) {
+        let msg = if opcode == Opcode::Text {
+            match String::from_utf8(payload) {
+                Ok(text) => Message::Text(text),
+                Err(_) => return self.fail(1007, "invalid UTF-8"),
+            }
+        } else {
+            Message::Binary(payload)
+        };
+        let next = self.handler.on_message(&mut self.sender, msg);
+        self.set_interest(next);
+    }
+
+    fn on_close_frame(&mut self, payload: &[u8]) {
+        let (code, reason) = match payload.len() {
+            0 => (None, ""),
+            1 => return self.fail(1002, "bad close frame"),
+            _ => {
+                let code = (payload[0] as u16) << 8 | payload[1] as u16;
+                if !is_valid_close_code(code) {
+                    return self.fail(1002, "bad close code");
+                }
+                match ::std::str::from_utf8(&payload[2..]) {
+                    Ok(reason) => (Some(code), reason),
+                    Err(_) => return self.fail(1007, "invalid UTF-8"),
+                }
+            },
+        };
+        self.handler.on_close(code, reason);
+        if !self.sender.closing {
+            // echo the status code back
+            self.sender.close(code.unwrap_or(1000), "");
+        }
+        self.done = true;
+    }
+}
+
+/// Whether a peer may send `code` in a Close frame, as in RFC6455 7.4.
+///
+/// 1005, 1006 and 1015 only stand in for a missing code locally, and the
+/// rest of 1000-2999 is reserved for the protocol itself.
+fn is_valid_close_code(code: u16) -> bool {
+    match code {
+        1000...1003 | 1007...1014 | 3000...4999 => true,
+        _ => false
+    }
+}
+
+impl<H: Handler<T>, T: Transport> http::Upgrade<T> for WebSocket<H, T> {
+    fn on_start(&mut self, buffered: Vec<u8>) -> Next {
+        self.read_buf = buffered;
+        let next = self.handler.on_open(&mut self.sender);
+        self.set_interest(next);
+        self.read_frames();
+        self.next()
+    }
+
+    fn on_readable(&mut self, transport: &mut T) -> Next {
+        let mut buf = [0; 4096];
+        match transport.read(&mut buf) {
+            Ok(0) => {
+                if !self.done {
+                    self.done = true;
+                    self.handler.on_close(None, "");
+                }
+                return Next::end();
+            },
+            Ok(n) => {
+                self.read_buf.extend_from_slice(&buf[..n]);
+                self.read_frames();
+            },
+            Err(e) => match e.kind() {
+                io::ErrorKind::WouldBlock |
+                io::ErrorKind::Interrupted => (),
+                _ => {
+                    let next = self.handler.on_error(e.into());
+                    self.set_interest(next);
+                }
+            }
+        }
+        self.next()
+    }
+
+    fn on_writable(&mut self, transport: &mut T) -> Next {
+        if !self.sender.buf.is_empty() {
+            match transport.write(&self.sender.buf) {
+                Ok(n) => {
+                    self.sender.buf.drain(..n);
+                },
+                Err(e) => match e.kind() {
+                    io::ErrorKind::WouldBlock |
+                    io::ErrorKind::Interrupted => (),
+                    _ => {
+                        let next = self.handler.on_error(e.into());
+                        self.set_interest(next);
+                    }
+                }
+            }
+        }
+        let wants_write = match http::interest(&self.interest).0 {
+            Next_::Write | Next_::ReadWrite => true,
+            _ => false,
+        };
+        if wants_write && self.sender.buf.is_empty() && !self.sender.closing {
+            let next = self.handler.on_writable(&mut self.sender);
+            self.set_interest(next);
+        }
+        self.next()
+    }
+
+    fn on_wakeup(&mut self, next: Next) -> Next {
+        self.set_interest(next);
+        self.next()
+    }
+
+    fn on_error(&mut self, err: ::Error) -> Next {
+        let next = self.handler.on_error(err);
+        self.set_interest(next);
+        self.next()
+    }
+
+    fn on_remove(self: Box<Self>, transport: T) {
+        let ws = *self;
+        ws.handler.on_remove(transport);
+    }
+}
+
+impl<H: Handler<T>, T: Transport> fmt::Debug for WebSocket<H, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("server", &self.server)
+            .field("sender", &self.sender)
+            .field("interest", &self.interest)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Next, Upgrade};
+    use net::HttpStream;
+    use super::{accept_key, frame, Handler, Message, Sender, WebSocket};
+    use super::frame::Opcode;
+
+    #[test]
+    fn test_accept_key() {
+        // the example in RFC6455
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    struct Echo(Vec<Message>);
+
+    impl Handler<HttpStream> for Echo {
+        fn on_message(&mut self, ws: &mut Sender, msg: Message) -> Next {
+            self.0.push(msg.clone());
+            ws.send(msg);
+            Next::read()
+        }
+    }
+
+    fn client_frame(opcode: Opcode, fin: bool, payload: &[u8], buf: &mut Vec<u8>) {
+        frame::encode(opcode, fin, payload, Some([1, 2, 3, 4]), buf);
+    }
+
+    fn sent(ws: &WebSocket<Echo, HttpStream>) -> Vec<(Opcode, Vec<u8>)> {
+        let mut frames = Vec::new();
+        let mut pos = 0;
+        while let Some((frame, len)) = frame::parse(&ws.sender.buf[pos..], false, 1024).unwrap() {
+            frames.push((frame.opcode, frame.payload));
+            pos += len;
+        }
+        frames
+    }
+
+    #[test]
+    fn test_fragmented_message() {
+        let mut buf = Vec::new();
+        client_frame(Opcode::Text, false, b"Hel", &mut buf);
+        // control frames can come between fragments
+        client_frame(Opcode::Ping, true, b"ping", &mut buf);
+        client_frame(Opcode::Continuation, true, b"lo", &mut buf);
+
+        let mut ws = WebSocket::server(Echo(Vec::new()));
+        Upgrade::<HttpStream>::on_start(&mut ws, buf);
+        assert_eq!(ws.handler.0, vec![Message::Text("Hello".to_owned())]);
+        assert_eq!(sent(&ws), vec![
+            (Opcode::Pong, b"ping".to_vec()),
+            (Opcode::Text, b"Hello".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn test_close_handshake() {
+        let mut buf = Vec::new();
+        client_frame(Opcode::Close, true, &[0x03, 0xE8, b'b', b'y', b'e'], &mut buf);
+        client_frame(Opcode::Text, true, b"too late", &mut buf);
+
+        let mut ws = WebSocket::server(Echo(Vec::new()));
+        Upgrade::<HttpStream>::on_start(&mut ws, buf);
+        assert!(ws.handler.0.is_empty());
+        assert_eq!(sent(&ws), vec![(Opcode::Close, vec![0x03, 0xE8])]);
+        assert!(ws.done);
+    }
+
+    /// The code of the Close frame sent in answer to `payload`.
+    fn close_with(payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        client_frame(Opcode::Close, true, payload, &mut buf);
+
+        let mut ws = WebSocket::server(Echo(Vec::new()));
+        Upgrade::<HttpStream>::on_start(&mut ws, buf);
+        assert!(ws.done);
+        let sent = sent(&ws);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, Opcode::Close);
+        sent[0].1[..2].to_vec()
+    }
+
+    #[test]
+    fn test_close_codes() {
+        assert_eq!(close_with(&[0x03, 0xE9]), vec![0x03, 0xE9]);
+        assert_eq!(close_with(&[0x0F, 0xA0]), vec![0x0F, 0xA0]);
+        // 1005, 1006 and 1015 never go on the wire, and 999 and 2000
+        // aren't codes at all
+        for &code in &[1005u16, 1006, 1015, 999, 2000, 0] {
+            let payload = [(code >> 8) as u8, code as u8];
+            assert!(close_with(&payload) == vec![0x03, 0xEA], "code {}", code);
+        }
+    }
+
+    #[test]
+    fn test_bad_close_payload() {
+        // half a code
+        assert_eq!(close_with(&[0x03]), vec![0x03, 0xEA]);
+        // a reason that isn't UTF-8
+        assert_eq!(close_with(&[0x03, 0xE8, 0xFF, 0xFE]), vec![0x03, 0xEF]);
+    }
+
+    #[test]
+    fn test_unmasked_client_frame() {
+        let mut buf = Vec::new();
+        frame::encode(Opcode::Text, true, b"Hello", None, &mut buf);
+
+        let mut ws = WebSocket::server(Echo(Vec::new()));
+        Upgrade::<HttpStream>::on_start(&mut ws, buf);
+        assert!(ws.handler.0.is_empty());
+        let sent = sent(&ws);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, Opcode::Close);
+        assert_eq!(&sent[0].1[..2], &[0x03, 0xEA]);
+    }
+}
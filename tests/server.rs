@@ -1,4 +1,5 @@
 #![deny(warnings)]
+extern crate flate2;
 extern crate hyper;
 
 use std::net::{TcpStream, SocketAddr};
@@ -7,8 +8,8 @@ use std::sync::mpsc;
 use std::time::Duration;
 
 use hyper::{Next, Encoder, Decoder};
-use hyper::net::{HttpListener, HttpStream};
-use hyper::server::{Server, Handler, Request, Response};
+use hyper::net::{HttpListener, HttpStream, Transport};
+use hyper::server::{Server, Handler, Request, Response, Compress};
 
 struct Serve {
     listening: Option<hyper::server::Listening>,
@@ -46,6 +47,10 @@ impl Serve {
             tx: &self.reply_tx
         }
     }
+
+    fn shutdown(&mut self, grace: Duration) -> hyper::server::Draining {
+        self.listening.take().unwrap().shutdown(grace)
+    }
 }
 
 struct ReplyBuilder<'a> {
@@ -65,6 +70,13 @@ impl<'a> ReplyBuilder<'a> {
         self
     }
 
+    fn trailer(self, name: &'static str, value: &'static str) -> Self {
+        let mut headers = hyper::Headers::new();
+        headers.set_raw(name, value);
+        self.tx.send(Reply::Trailers(headers)).unwrap();
+        self
+    }
+
     fn body<T: AsRef<[u8]>>(self, body: T) {
         self.tx.send(Reply::Body(body.as_ref().into())).unwrap();
     }
@@ -72,7 +84,9 @@ impl<'a> ReplyBuilder<'a> {
 
 impl Drop for Serve {
     fn drop(&mut self) {
-        self.listening.take().unwrap().close();
+        if let Some(listening) = self.listening.take() {
+            listening.close();
+        }
     }
 }
 
@@ -80,12 +94,14 @@ struct TestHandler {
     tx: mpsc::Sender<Msg>,
     reply: Vec<Reply>,
     peeked: Option<Vec<u8>>,
+    trailers: Option<hyper::Headers>,
     timeout: Option<Duration>,
 }
 
 enum Reply {
     Status(hyper::StatusCode),
     Headers(hyper::Headers),
+    Trailers(hyper::Headers),
     Body(Vec<u8>),
 }
 
@@ -138,6 +154,9 @@ impl Handler<HttpStream> for TestHandler {
                     use std::iter::Extend;
                     res.headers_mut().extend(headers.iter());
                 },
+                Reply::Trailers(trailers) => {
+                    self.trailers = Some(trailers);
+                },
                 Reply::Body(body) => {
                     self.peeked = Some(body);
                 },
@@ -154,12 +173,65 @@ impl Handler<HttpStream> for TestHandler {
     fn on_response_writable(&mut self, encoder: &mut Encoder<HttpStream>) -> Next {
         match self.peeked {
             Some(ref body) => {
+                if let Some(trailers) = self.trailers.take() {
+                    encoder.set_trailers(trailers);
+                }
                 encoder.write(body).unwrap();
                 self.next(Next::end())
             },
             None => self.next(Next::end())
         }
     }
+
+    fn on_upgrade(&mut self) -> Option<Box<hyper::Upgrade<HttpStream>>> {
+        Some(Box::new(Echo(Vec::new())))
+    }
+}
+
+/// Echoes back whatever it reads, once a connection switched protocols.
+struct Echo(Vec<u8>);
+
+impl hyper::Upgrade<HttpStream> for Echo {
+    fn on_start(&mut self, buffered: Vec<u8>) -> Next {
+        self.0 = buffered;
+        if self.0.is_empty() {
+            Next::read()
+        } else {
+            Next::write()
+        }
+    }
+
+    fn on_readable(&mut self, transport: &mut HttpStream) -> Next {
+        let mut buf = [0; 1024];
+        match transport.read(&mut buf) {
+            Ok(0) => Next::end(),
+            Ok(n) => {
+                self.0.extend_from_slice(&buf[..n]);
+                Next::write()
+            },
+            Err(e) => match e.kind() {
+                io::ErrorKind::WouldBlock => Next::read(),
+                _ => panic!("test error: {}", e)
+            }
+        }
+    }
+
+    fn on_writable(&mut self, transport: &mut HttpStream) -> Next {
+        match transport.write(&self.0) {
+            Ok(n) => {
+                self.0.drain(..n);
+                if self.0.is_empty() {
+                    Next::read()
+                } else {
+                    Next::write()
+                }
+            },
+            Err(e) => match e.kind() {
+                io::ErrorKind::WouldBlock => Next::write(),
+                _ => panic!("test error: {}", e)
+            }
+        }
+    }
 }
 
 fn serve() -> Serve {
@@ -175,6 +247,20 @@ fn serve_n(n: u32) -> Serve {
 }
 
 fn serve_n_with_timeout(n: u32, dur: Option<Duration>) -> Serve {
+    serve_wrapped(n, dur, |handler| handler)
+}
+
+fn serve_h2c() -> Serve {
+    serve_config(1, None, true, |handler| handler)
+}
+
+fn serve_wrapped<F, H>(n: u32, dur: Option<Duration>, wrap: F) -> Serve
+where F: Fn(TestHandler) -> H + Send + 'static, H: Handler<HttpStream> {
+    serve_config(n, dur, false, wrap)
+}
+
+fn serve_config<F, H>(n: u32, dur: Option<Duration>, h2c: bool, wrap: F) -> Serve
+where F: Fn(TestHandler) -> H + Send + 'static, H: Handler<HttpStream> {
     use std::thread;
 
     let (msg_tx, msg_rx) = mpsc::channel();
@@ -183,17 +269,19 @@ fn serve_n_with_timeout(n: u32, dur: Option<Duration>) -> Serve {
     let addr = "127.0.0.1:0".parse().unwrap();
     let listeners = (0..n).map(|_| HttpListener::bind(&addr).unwrap());
     let (listening, server) = Server::new(listeners)
+        .h2c(h2c)
         .handle(move |_| {
             let mut replies = Vec::new();
             while let Ok(reply) = reply_rx.try_recv() {
                 replies.push(reply);
             }
-            TestHandler {
+            wrap(TestHandler {
                 tx: msg_tx.clone(),
                 timeout: dur,
                 reply: replies,
                 peeked: None,
-            }
+                trailers: None,
+            })
         }).unwrap();
 
 
@@ -286,6 +374,30 @@ fn server_get_chunked_response() {
     assert_eq!(&body[n..], "B\r\nfoo bar baz\r\n0\r\n\r\n");
 }
 
+#[test]
+fn server_chunked_response_trailers() {
+    let server = serve();
+    for &(te, end) in &[("TE: trailers\r\n", "0\r\nGrpc-Status: 0\r\n\r\n"), ("", "0\r\n\r\n")] {
+        server.reply()
+            .status(hyper::Ok)
+            .trailer("Grpc-Status", "0")
+            .body(b"foo bar baz");
+        let mut req = TcpStream::connect(server.addr()).unwrap();
+        write!(req, "\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Connection: close\r\n\
+            {}\
+            \r\n\
+        ", te).unwrap();
+        let mut body = String::new();
+        req.read_to_string(&mut body).unwrap();
+        let n = body.find("\r\n\r\n").unwrap() + 4;
+
+        assert_eq!(&body[n..], format!("B\r\nfoo bar baz\r\n{}", end));
+    }
+}
+
 #[test]
 fn server_post_with_chunked_body() {
     let server = serve();
@@ -398,6 +510,396 @@ fn server_empty_response_chunked_without_calling_write() {
     assert_eq!(lines.next(), None);
 }
 
+#[test]
+fn server_expect_continue() {
+    let server = serve();
+    let mut req = TcpStream::connect(server.addr()).unwrap();
+    req.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    req.write_all(b"\
+        POST / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Expect: 100-continue\r\n\
+        Content-Length: 19\r\n\
+        \r\n\
+    ").unwrap();
+
+    let continued = b"HTTP/1.1 100 Continue\r\n\r\n";
+    let mut buf = [0; 25];
+    req.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &continued[..]);
+
+    req.write_all(b"I'm a good request.").unwrap();
+    let mut response = [0; 256];
+    let n = req.read(&mut response).unwrap();
+    assert!(response[..n].starts_with(b"HTTP/1.1 200 OK\r\n"));
+    assert_eq!(server.body(), b"I'm a good request.");
+}
+
+/// Sends `req` to a new server, and reads the response until it closes.
+fn bad_request(req: &[u8]) -> String {
+    let server = serve();
+    let mut conn = TcpStream::connect(server.addr()).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    conn.write_all(req).unwrap();
+    let mut response = String::new();
+    conn.read_to_string(&mut response).unwrap();
+    assert!(response.contains("Content-Length: 0\r\n"), "{:?}", response);
+    response
+}
+
+#[test]
+fn server_bad_request_400() {
+    let response = bad_request(b"GET / HTTP/1.1\r\nHost exa mple\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?}", response);
+
+    let response = bad_request(b"POST / HTTP/1.1\r\nContent-Length: foo\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?}", response);
+}
+
+#[test]
+fn server_bad_request_413() {
+    let response = bad_request(b"POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{:?}", response);
+}
+
+#[test]
+fn server_bad_request_414() {
+    // the request line alone fills the server's buffer, which holds
+    // 8192 + 4096 * 100 bytes
+    let mut req = b"GET /".to_vec();
+    req.resize(8192 + 4096 * 100, b'a');
+    let response = bad_request(&req);
+    assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"), "{:?}", response);
+}
+
+#[test]
+fn server_bad_request_431() {
+    let mut req = b"GET / HTTP/1.1\r\n".to_vec();
+    for i in 0..200 {
+        write!(req, "X-Header-{}: {}\r\n", i, i).unwrap();
+    }
+    req.extend_from_slice(b"\r\n");
+    let response = bad_request(&req);
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{:?}", response);
+}
+
+#[test]
+fn server_bad_request_505() {
+    let response = bad_request(b"GET / HTTP/2.5\r\nHost: example.domain\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"), "{:?}", response);
+}
+
+#[test]
+fn server_h2c_prior_knowledge() {
+    let server = serve_h2c();
+    let mut req = TcpStream::connect(server.addr()).unwrap();
+    req.write_all(b"\
+        PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\
+        \x00\x00\x00\x04\x00\x00\x00\x00\x00\
+    ").unwrap();
+    let mut head = [0; 9];
+    req.read_exact(&mut head).unwrap();
+    // the server starts with its own SETTINGS frame
+    assert_eq!(head[3], 0x4);
+    assert_eq!(&head[5..], &[0, 0, 0, 0]);
+}
+
+/// An HTTP/2 frame.
+fn h2_frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+    let len = payload.len();
+    let mut frame = vec![(len >> 16) as u8, (len >> 8) as u8, len as u8, kind, flags,
+                         (stream >> 24) as u8, (stream >> 16) as u8, (stream >> 8) as u8, stream as u8];
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Reads the kind, flags, stream and payload of the next HTTP/2 frame.
+fn h2_read_frame<R: Read>(sock: &mut R) -> (u8, u8, u32, Vec<u8>) {
+    let mut head = [0; 9];
+    sock.read_exact(&mut head).unwrap();
+    let len = ((head[0] as usize) << 16) | ((head[1] as usize) << 8) | head[2] as usize;
+    let stream = ((head[5] as u32 & 0x7f) << 24) | ((head[6] as u32) << 16) | ((head[7] as u32) << 8) | head[8] as u32;
+    let mut payload = vec![0; len];
+    sock.read_exact(&mut payload).unwrap();
+    (head[3], head[4], stream, payload)
+}
+
+#[test]
+fn server_h2_request() {
+    let server = serve_h2c();
+    server.reply()
+        .status(hyper::Ok)
+        .header(hyper::header::ContentLength(5))
+        .body(b"hello");
+    let mut req = TcpStream::connect(server.addr()).unwrap();
+    req.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    req.write_all(b"\
+        PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\
+        \x00\x00\x00\x04\x00\x00\x00\x00\x00\
+    ").unwrap();
+    // GET http://example.domain/, with END_STREAM and END_HEADERS
+    req.write_all(&h2_frame(0x1, 0x5, 1, b"\x82\x86\x84\x41\x0eexample.domain")).unwrap();
+
+    let mut headers = None;
+    let mut body = Vec::new();
+    loop {
+        let (kind, flags, stream, payload) = h2_read_frame(&mut req);
+        match kind {
+            0x1 => {
+                assert_eq!(stream, 1);
+                headers = Some(payload);
+            },
+            0x0 => {
+                assert_eq!(stream, 1);
+                body.extend_from_slice(&payload);
+                if flags & 0x1 != 0 {
+                    break;
+                }
+            },
+            _ => ()
+        }
+    }
+    // :status 200, from the static table
+    assert_eq!(headers.expect("expected HEADERS")[0], 0x88);
+    assert_eq!(body, b"hello");
+}
+
+#[test]
+fn server_upgrade() {
+    let server = serve();
+    server.reply()
+        .status(hyper::StatusCode::SwitchingProtocols)
+        .header(hyper::header::Connection(vec!["upgrade".parse().unwrap()]));
+    let mut req = TcpStream::connect(server.addr()).unwrap();
+    req.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: upgrade\r\n\
+        Upgrade: echo\r\n\
+        \r\n\
+        hello\
+    ").unwrap();
+
+    let mut response = Vec::new();
+    let mut buf = [0; 256];
+    while !response.ends_with(b"hello") {
+        let n = req.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed");
+        response.extend_from_slice(&buf[..n]);
+    }
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(!response.contains("Transfer-Encoding"));
+
+    // the transport now belongs to the echo protocol
+    req.write_all(b" world").unwrap();
+    let n = req.read(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b" world");
+}
+
+#[test]
+fn server_websocket_echo() {
+    use hyper::websocket::{self, Message, Sender, WebSocket};
+
+    /// Accepts WebSocket requests.
+    struct Accept(Option<String>);
+
+    impl Handler<HttpStream> for Accept {
+        fn on_request(&mut self, req: Request<HttpStream>) -> Next {
+            self.0 = websocket::request_key(&req);
+            Next::write()
+        }
+        fn on_request_readable(&mut self, _: &mut Decoder<HttpStream>) -> Next {
+            Next::write()
+        }
+        fn on_response(&mut self, res: &mut Response) -> Next {
+            match self.0 {
+                Some(ref key) => websocket::accept(res, key),
+                None => res.set_status(hyper::BadRequest),
+            }
+            Next::end()
+        }
+        fn on_response_writable(&mut self, _: &mut Encoder<HttpStream>) -> Next {
+            Next::end()
+        }
+        fn on_upgrade(&mut self) -> Option<Box<hyper::Upgrade<HttpStream>>> {
+            Some(Box::new(WebSocket::server(EchoMessages)))
+        }
+    }
+
+    struct EchoMessages;
+
+    impl websocket::Handler<HttpStream> for EchoMessages {
+        fn on_message(&mut self, ws: &mut Sender, msg: Message) -> Next {
+            ws.send(msg);
+            Next::read()
+        }
+    }
+
+    /// Opens a WebSocket from the client.
+    struct Open {
+        key: String,
+        tx: mpsc::Sender<Message>,
+    }
+
+    impl hyper::client::Handler<HttpStream> for Open {
+        fn on_request(&mut self, req: &mut hyper::client::Request) -> Next {
+            self.key = websocket::request(req);
+            Next::read()
+        }
+        fn on_request_writable(&mut self, _: &mut Encoder<HttpStream>) -> Next {
+            Next::read()
+        }
+        fn on_response(&mut self, res: hyper::client::Response) -> Next {
+            assert!(websocket::is_accepted(&res, &self.key));
+            Next::end()
+        }
+        fn on_response_readable(&mut self, _: &mut Decoder<HttpStream>) -> Next {
+            Next::end()
+        }
+        fn on_upgrade(&mut self) -> Option<Box<hyper::Upgrade<HttpStream>>> {
+            Some(Box::new(WebSocket::client(Hello(self.tx.clone()))))
+        }
+    }
+
+    /// Sends one message, and closes once it comes back.
+    struct Hello(mpsc::Sender<Message>);
+
+    impl websocket::Handler<HttpStream> for Hello {
+        fn on_open(&mut self, ws: &mut Sender) -> Next {
+            ws.send(Message::Text("hello".to_owned()));
+            Next::read()
+        }
+        fn on_message(&mut self, _: &mut Sender, msg: Message) -> Next {
+            self.0.send(msg).unwrap();
+            Next::end()
+        }
+    }
+
+    let listener = HttpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let (listening, server) = Server::new(listener)
+        .handle(|_| Accept(None))
+        .unwrap();
+    let addr = listening.addrs()[0];
+    ::std::thread::spawn(move || server.run());
+
+    let client = hyper::Client::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+    client.request(format!("http://{}/", addr).parse().unwrap(), Open {
+        key: String::new(),
+        tx: tx,
+    }).unwrap();
+    assert_eq!(rx.recv().unwrap(), Message::Text("hello".to_owned()));
+
+    client.close();
+    listening.close();
+}
+
+#[test]
+fn server_sse_keep_alive() {
+    use hyper::sse::{self, Event, EventReader, EventWriter, KeepAlive};
+
+    /// Sends one event, a keep-alive comment on the first tick, and another
+    /// event on the second.
+    struct Stream {
+        ctrl: hyper::Control,
+        writer: EventWriter,
+        keep_alive: Option<KeepAlive>,
+        ticks: usize,
+    }
+
+    impl Handler<HttpStream> for Stream {
+        fn on_request(&mut self, _: Request<HttpStream>) -> Next {
+            Next::write()
+        }
+        fn on_request_readable(&mut self, _: &mut Decoder<HttpStream>) -> Next {
+            Next::write()
+        }
+        fn on_response(&mut self, res: &mut Response) -> Next {
+            sse::response(res);
+            self.writer.send(&Event::new("one").id("1"));
+            self.keep_alive = Some(KeepAlive::new(self.ctrl.clone(), Duration::from_millis(50)));
+            Next::write()
+        }
+        fn on_response_writable(&mut self, encoder: &mut Encoder<HttpStream>) -> Next {
+            if self.writer.is_empty() {
+                // only the keep-alive wakes up the response
+                self.ticks += 1;
+                if self.ticks == 2 {
+                    self.writer.send(&Event::new("two").id("2"));
+                }
+            }
+            match self.writer.write(encoder) {
+                Ok(_) if self.ticks == 2 && self.writer.is_empty() => {
+                    // no more ticks
+                    drop(self.keep_alive.take());
+                    Next::end()
+                },
+                Ok(next) => next,
+                Err(e) => panic!("writing events: {}", e),
+            }
+        }
+    }
+
+    /// Passes on the events of the stream.
+    struct Listen {
+        reader: EventReader,
+        tx: mpsc::Sender<Event>,
+    }
+
+    impl hyper::client::Handler<HttpStream> for Listen {
+        fn on_request(&mut self, req: &mut hyper::client::Request) -> Next {
+            sse::request(req, None);
+            Next::read()
+        }
+        fn on_request_writable(&mut self, _: &mut Encoder<HttpStream>) -> Next {
+            Next::read()
+        }
+        fn on_response(&mut self, res: hyper::client::Response) -> Next {
+            assert_eq!(res.status(), &hyper::Ok);
+            Next::read()
+        }
+        fn on_response_readable(&mut self, decoder: &mut Decoder<HttpStream>) -> Next {
+            match self.reader.read(decoder) {
+                Ok(events) => {
+                    for event in events {
+                        self.tx.send(event).unwrap();
+                    }
+                    Next::read()
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Next::read(),
+                Err(_) => Next::end(),
+            }
+        }
+    }
+
+    let listener = HttpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let (listening, server) = Server::new(listener)
+        .handle(|ctrl| Stream {
+            ctrl: ctrl,
+            writer: EventWriter::new(),
+            keep_alive: None,
+            ticks: 0,
+        })
+        .unwrap();
+    let addr = listening.addrs()[0];
+    ::std::thread::spawn(move || server.run());
+
+    let client = hyper::Client::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+    client.request(format!("http://{}/", addr).parse().unwrap(), Listen {
+        reader: EventReader::new(),
+        tx: tx,
+    }).unwrap();
+    assert_eq!(rx.recv().unwrap(), Event::new("one").id("1"));
+    // the comment sent in between is skipped by the reader
+    assert_eq!(rx.recv().unwrap(), Event::new("two").id("2"));
+
+    client.close();
+    listening.close();
+}
+
 #[test]
 fn server_keep_alive() {
     extern crate env_logger;
@@ -479,3 +981,234 @@ fn server_get_with_body_three_listeners() {
         assert_eq!(server.body(), comparison);
     }
 }
+
+#[test]
+fn server_compress_response() {
+    use flate2::read::GzDecoder;
+
+    let server = serve_wrapped(1, None, |handler| Compress::new(handler).min_size(64));
+    let text = (0..100).map(|_| "I'm a response that is long enough to be worth compressing. ").collect::<String>();
+    server.reply()
+        .status(hyper::Ok)
+        .header(hyper::header::ContentLength(text.len() as u64))
+        .body(&text);
+    let mut req = TcpStream::connect(server.addr()).unwrap();
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Accept-Encoding: deflate;q=0.5, gzip\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut res = Vec::new();
+    req.read_to_end(&mut res).unwrap();
+    let n = res.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let head = String::from_utf8(res[..n].to_vec()).unwrap();
+    assert!(head.contains("Content-Encoding: gzip\r\n"), "{}", head);
+    assert!(head.contains("Vary: Accept-Encoding\r\n"), "{}", head);
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{}", head);
+    assert!(!head.contains("Content-Length"), "{}", head);
+
+    // join the chunks
+    let mut body = Vec::new();
+    let mut chunks = &res[n..];
+    loop {
+        let line = chunks.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size = usize::from_str_radix(::std::str::from_utf8(&chunks[..line]).unwrap(), 16).unwrap();
+        if size == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunks[line + 2..line + 2 + size]);
+        chunks = &chunks[line + 2 + size + 2..];
+    }
+    assert!(body.len() < text.len());
+    let mut decoded = String::new();
+    GzDecoder::new(&body[..]).unwrap().read_to_string(&mut decoded).unwrap();
+    assert_eq!(decoded, text);
+
+    // too short to be worth it
+    server.reply()
+        .status(hyper::Ok)
+        .header(hyper::header::ContentLength(11))
+        .body(b"foo bar baz");
+    let mut req = TcpStream::connect(server.addr()).unwrap();
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Accept-Encoding: gzip\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut res = String::new();
+    req.read_to_string(&mut res).unwrap();
+    assert!(!res.contains("Content-Encoding"), "{}", res);
+    assert!(res.ends_with("\r\n\r\nfoo bar baz"), "{}", res);
+}
+
+#[test]
+fn server_graceful_shutdown() {
+    let mut server = serve();
+    let addr = *server.addr();
+    server.reply()
+        .status(hyper::Ok)
+        .header(hyper::header::ContentLength(4))
+        .body(b"done");
+    let mut req = TcpStream::connect(addr).unwrap();
+    req.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    req.write_all(b"\
+        POST / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Content-Length: 10\r\n\
+        \r\n\
+        hello\
+    ").unwrap();
+    // the request is in progress once part of its body was read
+    match server.msg_rx.recv() {
+        Ok(Msg::Chunk(chunk)) => assert_eq!(chunk, b"hello"),
+        Err(e) => panic!("expected chunk, actual: {:?}", e)
+    }
+
+    let draining = server.shutdown(Duration::from_secs(5));
+    req.write_all(b"world").unwrap();
+    let mut res = String::new();
+    req.read_to_string(&mut res).unwrap();
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    assert!(res.contains("Connection: close\r\n"), "{:?}", res);
+    assert!(res.ends_with("\r\n\r\ndone"), "{:?}", res);
+
+    draining.wait();
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn server_h2c_prior_knowledge_disabled() {
+    let server = serve();
+    let mut req = TcpStream::connect(server.addr()).unwrap();
+    req.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    req.write_all(b"\
+        PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\
+        \x00\x00\x00\x04\x00\x00\x00\x00\x00\
+    ").unwrap();
+    // read as a request for an HTTP version the server doesn't speak
+    let mut res = String::new();
+    req.read_to_string(&mut res).unwrap();
+    assert!(res.starts_with("HTTP/1.1 505 "), "{:?}", res);
+}
+
+#[test]
+fn server_graceful_shutdown_h2() {
+    use std::time::Instant;
+
+    let mut server = serve_h2c();
+    let mut req = TcpStream::connect(server.addr()).unwrap();
+    req.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    req.write_all(b"\
+        PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\
+        \x00\x00\x00\x04\x00\x00\x00\x00\x00\
+    ").unwrap();
+    let mut head = [0; 9];
+    req.read_exact(&mut head).unwrap();
+    assert_eq!(head[3], 0x4);
+    let len = ((head[0] as usize) << 16) | ((head[1] as usize) << 8) | head[2] as usize;
+    req.read_exact(&mut vec![0; len]).unwrap();
+
+    let start = Instant::now();
+    let draining = server.shutdown(Duration::from_secs(30));
+    // an idle h2 connection gets a GOAWAY and is closed, without waiting
+    // out the grace period
+    let mut frames = Vec::new();
+    req.read_to_end(&mut frames).unwrap();
+    let mut kinds = Vec::new();
+    let mut rest = &frames[..];
+    while rest.len() >= 9 {
+        let len = ((rest[0] as usize) << 16) | ((rest[1] as usize) << 8) | rest[2] as usize;
+        kinds.push(rest[3]);
+        rest = &rest[9 + len..];
+    }
+    assert!(kinds.contains(&0x7), "expected GOAWAY, frames: {:?}", kinds);
+
+    draining.wait();
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+/// Answers every request with "hello".
+struct Hello;
+
+impl<T: Transport> Handler<T> for Hello {
+    fn on_request(&mut self, _: Request<T>) -> Next {
+        Next::write()
+    }
+    fn on_request_readable(&mut self, _: &mut Decoder<T>) -> Next {
+        Next::write()
+    }
+    fn on_response(&mut self, res: &mut Response) -> Next {
+        res.headers_mut().set(hyper::header::ContentLength(5));
+        Next::write()
+    }
+    fn on_response_writable(&mut self, encoder: &mut Encoder<T>) -> Next {
+        encoder.write(b"hello").unwrap();
+        Next::end()
+    }
+}
+
+#[test]
+fn server_handle_threads() {
+    let listener = HttpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let (listening, server) = Server::new(listener)
+        .keep_alive(false)
+        .handle_threads(3, || |_| Hello)
+        .unwrap();
+    let addr = listening.addrs()[0];
+    ::std::thread::spawn(move || server.run());
+
+    for _ in 0..6 {
+        let mut req = TcpStream::connect(addr).unwrap();
+        req.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        req.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").unwrap();
+        let mut res = String::new();
+        req.read_to_string(&mut res).unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+        assert!(res.ends_with("\r\n\r\nhello"), "{:?}", res);
+    }
+
+    // every loop stops, and none accepts anymore
+    listening.shutdown(Duration::from_secs(5)).wait();
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn server_unix_socket() {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    // unique, so tests running at the same time don't share a socket
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let name = format!("hyper-server-unix-socket-{}-{}.sock", now.as_secs(), now.subsec_nanos());
+    let path = ::std::env::temp_dir().join(name);
+    let _ = ::std::fs::remove_file(&path);
+    let (listening, server) = Server::unix(&path).unwrap()
+        .handle(|_| Hello)
+        .unwrap();
+    assert!(listening.addrs().is_empty());
+    ::std::thread::spawn(move || server.run());
+
+    let mut req = ::std::os::unix::net::UnixStream::connect(&path).unwrap();
+    req.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: localhost\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut res = String::new();
+    req.read_to_string(&mut res).unwrap();
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    assert!(res.ends_with("\r\n\r\nhello"), "{:?}", res);
+
+    listening.close();
+    let _ = ::std::fs::remove_file(&path);
+}